@@ -0,0 +1,148 @@
+use std::env;
+
+use dt_lib::codepage::Codepage;
+use dt_lib::error::Error as ArgError;
+
+#[derive(Debug)]
+pub enum Command {
+    // +name: add an object module
+    Add(String),
+
+    // -name: remove a module
+    Remove(String),
+
+    // -+name: replace a module with the object of the same name
+    Replace(String),
+
+    // *name: extract a module to an object file, or every module if no
+    // name is given
+    Extract(Option<String>),
+
+    // -*name: extract a module and then remove it
+    ExtractRemove(String),
+}
+
+#[derive(Debug)]
+pub struct Args {
+    pub libname: String,
+    pub commands: Vec<Command>,
+    pub codepage: Codepage,
+
+    // The page size to repack the library with, if it's to be repacked;
+    // None inside means the smallest which will do (-p min)
+    pub page_size: Option<Option<usize>>,
+
+    pub case_sensitive: bool,
+    pub list: bool,
+    pub verify: bool,
+    pub rebuild: bool,
+
+    // A library to compare this one against (-d)
+    pub diff: Option<String>,
+
+    args: env::Args,
+    arg: Option<String>,
+}
+
+impl Args {
+    fn new() -> Args {
+        Args{
+            libname: "".to_string(),
+            commands: Vec::new(),
+            codepage: Codepage::default(),
+            page_size: None,
+            case_sensitive: false,
+            list: false,
+            verify: false,
+            rebuild: false,
+            diff: None,
+            args: env::args(),
+            arg: None,
+        }
+    }
+
+    fn next(&mut self) {
+        self.arg = self.args.next();
+    }
+
+    fn value(&mut self, flag: &str) -> Result<String, ArgError> {
+        self.next();
+        match self.arg {
+            Some(ref value) => Ok(value.clone()),
+            None => Err(ArgError::new(&format!("flag {} requires a value", flag))),
+        }
+    }
+
+    fn command(arg: &str) -> Result<Command, ArgError> {
+        let (op, name) = arg.split_at(arg.find(|c| !"+-*".contains(c)).unwrap_or(arg.len()));
+
+        match op {
+            "*" if name.is_empty() => Ok(Command::Extract(None)),
+            _ if name.is_empty() => Err(ArgError::new(&format!("invalid command {}", arg))),
+            "*" => Ok(Command::Extract(Some(name.to_string()))),
+            "-*" | "*-" => Ok(Command::ExtractRemove(name.to_string())),
+            "+" => Ok(Command::Add(name.to_string())),
+            "-" => Ok(Command::Remove(name.to_string())),
+            "-+" | "+-" => Ok(Command::Replace(name.to_string())),
+            _ => Err(ArgError::new(&format!("invalid command {}", arg))),
+        }
+    }
+
+    // lib [flags] library [commands]
+    //
+    // Flags come before the library name, so that after it a leading `-`
+    // is free to mean a command.
+    //
+    pub fn parse() -> Result<Args, ArgError> {
+        let mut args = Args::new();
+
+        // skip program name
+        args.next();
+
+        // flags
+        loop {
+            args.next();
+
+            match args.arg.as_deref() {
+                Some(flag) => if !flag.starts_with('-') {
+                    break
+                } else {
+                    match flag {
+                        "-c" => args.codepage = args.value("-c")?.as_str().try_into()?,
+                        "-p" => {
+                            let value = args.value("-p")?;
+                            let size = match value.as_str() {
+                                "min" => None,
+                                _ => Some(value.parse().map_err(|_| ArgError::new(&format!("invalid page size {}", value)))?),
+                            };
+                            args.page_size = Some(size);
+                        },
+                        "-s" => args.case_sensitive = true,
+                        "-l" => args.list = true,
+                        "-v" => args.verify = true,
+                        "-r" => args.rebuild = true,
+                        "-d" => args.diff = Some(args.value("-d")?),
+                        _ => return Err(ArgError::new(&format!("invalid flag {}", flag))),
+                    }
+                },
+                None => break,
+            }
+        }
+
+        match args.arg {
+            Some(ref name) => args.libname = name.clone(),
+            None => return Err(ArgError::new("missing library name")),
+        }
+
+        loop {
+            args.next();
+
+            match args.arg.take() {
+                Some(arg) => args.commands.push(Self::command(&arg)?),
+                None => break,
+            }
+        }
+
+        Ok(args)
+    }
+}
@@ -0,0 +1,231 @@
+mod args;
+
+use std::path::Path;
+
+use dt_lib::error::Error as AppError;
+use dt_lib::libfile;
+use dt_lib::libcheck::*;
+use dt_lib::libdiff::*;
+use dt_lib::liblist::*;
+use dt_lib::libwriter::*;
+use dt_lib::objfile::ParseOptions;
+
+use crate::args::{Args, Command};
+
+// A file name with `ext` added if it doesn't have an extension
+//
+fn with_extension(name: &str, ext: &str) -> String {
+    if Path::new(name).extension().is_some() {
+        name.to_string()
+    } else {
+        format!("{}.{}", name, ext)
+    }
+}
+
+// The module name LIB gives an object file: its base name
+//
+fn module_name(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+fn read(path: &str) -> Result<Vec<u8>, AppError> {
+    std::fs::read(path).map_err(|err| AppError::new(&format!("{}: {}", path, err)))
+}
+
+fn member(name: &str, options: &ParseOptions) -> Result<LibMember, AppError> {
+    let path = with_extension(name, "obj");
+    let obj = read(&path)?;
+    LibMember::from_obj(&module_name(&path), &obj, options).map_err(|err| err.in_module(&path))
+}
+
+// Write members out as object files, named as LibWriter::file_names
+// says
+//
+fn extract(writer: &LibWriter, name: Option<&str>) -> Result<(), AppError> {
+    let files = writer.file_names();
+    let indices: Vec<usize> = match name {
+        Some(name) => match writer.find(&module_name(name)) {
+            Some(index) => vec![index],
+            None => return Err(AppError::new(&format!("module {} is not in the library", module_name(name)))),
+        },
+        None => (0..writer.members.len()).collect(),
+    };
+
+    for index in indices {
+        println!("extracting {} to {}", writer.members[index].name, files[index]);
+        std::fs::write(&files[index], &writer.members[index].image)
+            .map_err(|err| AppError::new(&format!("{}: {}", files[index], err)))?;
+    }
+
+    Ok(())
+}
+
+// Print the library the way LIB's listing file does: an index of every
+// public, then each module with where it is and what it defines
+//
+fn print_listing(libname: &str, listing: &LibListing) {
+    println!("LIBRARY {} (page size {}, {:?} format)", libname, listing.page_size, listing.flavor);
+    println!();
+
+    for (symbol, module) in listing.symbols() {
+        println!("{:.<32}{}", format!("{} ", symbol), module);
+    }
+
+    for module in &listing.modules {
+        println!();
+        println!("{:<16} Offset: {:08x}H  Length: {:08x}H  Pages: {:04x}-{:04x}",
+            module.name, module.offset, module.length, module.first_page, module.last_page);
+        if let Some(source) = &module.source {
+            println!("{:<16} Source: {}", "", source);
+        }
+        for public in &module.publics {
+            println!("    {}", public);
+        }
+    }
+}
+
+// Print what changed going from `oldname` to `newname`: modules added
+// (+), removed (-) and changed (*), with the symbols which came and went
+//
+fn print_diff(oldname: &str, newname: &str, diff: &LibDiff) {
+    println!("comparing {} to {}", oldname, newname);
+
+    for name in &diff.added {
+        println!("+ {}", name);
+    }
+    for name in &diff.removed {
+        println!("- {}", name);
+    }
+    for change in &diff.changed {
+        println!("* {} (length {:08x}H -> {:08x}H)", change.name, change.old_length, change.new_length);
+        for public in &change.publics_added {
+            println!("    + public {}", public);
+        }
+        for public in &change.publics_removed {
+            println!("    - public {}", public);
+        }
+        for ext in &change.externs_added {
+            println!("    + extern {}", ext);
+        }
+        for ext in &change.externs_removed {
+            println!("    - extern {}", ext);
+        }
+    }
+
+    if diff.is_empty() {
+        println!("no differences");
+    }
+}
+
+fn lib() -> Result<(), AppError> {
+    let args = Args::parse()?;
+    let libname = with_extension(&args.libname, "lib");
+    let options = ParseOptions{ codepage: args.codepage, ..Default::default() };
+    let mut changed = args.rebuild;
+
+    let mut image = if Path::new(&libname).exists() { Some(read(&libname)?) } else { None };
+
+    let mut writer = if let Some(image) = &image {
+        let mut lib = libfile::Parser::new(image)?;
+        lib.codepage = args.codepage;
+
+        if args.verify {
+            let report = check_library(&lib);
+            for problem in &report.problems {
+                println!("{}", problem);
+            }
+            println!("{}: {} module(s), {} integrity problem(s)", libname, report.modules, report.problems.len());
+
+            let problems = verify_dictionary(&lib, &options)?;
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            println!("{}: {} dictionary problem(s){}", libname, problems.len(),
+                if problems.is_empty() || args.rebuild { "" } else { "; use -r to rebuild the dictionary" });
+        }
+
+        LibWriter::load(&lib, &options)?
+    } else {
+        println!("creating library {}", libname);
+        changed = true;
+        LibWriter{ codepage: args.codepage, ..LibWriter::new() }
+    };
+
+    let old_page_size = writer.page_size;
+    if let Some(page_size) = args.page_size {
+        writer.page_size = page_size;
+        changed = true;
+    }
+
+    if args.case_sensitive {
+        writer.case_sensitive = true;
+        changed = true;
+    }
+
+    for command in &args.commands {
+        match command {
+            Command::Add(name) => writer.add(member(name, &options)?)?,
+            Command::Remove(name) => {
+                if let Err(err) = writer.remove(&module_name(name)) {
+                    println!("warning: {}; ignored", err);
+                }
+            },
+            Command::Replace(name) => {
+                if !writer.replace(member(name, &options)?) {
+                    println!("warning: module {} was not in the library; added", module_name(name));
+                }
+            },
+            Command::Extract(name) => {
+                extract(&writer, name.as_deref())?;
+                continue;
+            },
+            Command::ExtractRemove(name) => {
+                extract(&writer, Some(name))?;
+                writer.remove(&module_name(name))?;
+            },
+        }
+        changed = true;
+    }
+
+    if changed {
+        for dup in writer.duplicates()? {
+            println!("warning: {} is defined in {}; redefinition in {} ignored", dup.symbol, dup.module, dup.ignored);
+        }
+
+        let written = writer.write()?;
+        let page_size = libfile::LibHeader::parse(&written)?.page_size;
+        if let (Some(_), Some(old_page_size)) = (args.page_size, old_page_size) {
+            println!("repacked {} from page size {} to {}", libname, old_page_size, page_size);
+        }
+        std::fs::write(&libname, &written)?;
+        image = Some(written);
+    }
+
+    if let (Some(diff), Some(image)) = (&args.diff, &image) {
+        let oldname = with_extension(diff, "lib");
+        let old_image = read(&oldname)?;
+        let mut old = libfile::Parser::new(&old_image)?;
+        old.codepage = args.codepage;
+        let mut lib = libfile::Parser::new(image)?;
+        lib.codepage = args.codepage;
+        print_diff(&oldname, &libname, &diff_libraries(&old, &lib, &options)?);
+    }
+
+    if let (true, Some(image)) = (args.list, &image) {
+        let mut lib = libfile::Parser::new(image)?;
+        lib.codepage = args.codepage;
+        print_listing(&libname, &list_library(&lib, &options)?);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = lib() {
+        println!("{}", err);
+        std::process::exit(1);
+    }
+}
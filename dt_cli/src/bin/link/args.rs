@@ -0,0 +1,447 @@
+use std::env;
+use std::vec;
+
+use dt_lib::codepage::Codepage;
+use dt_lib::diagnostics::Severity;
+use dt_lib::objfile::Combine;
+use dt_lib::error::Error as ArgError;
+
+#[derive(Debug)]
+pub struct Args {
+    pub objects: Vec<String>,
+    pub libraries: Vec<String>,
+
+    // The overlay each object goes in, by its place in `objects`, or 0 for
+    // the root. Objects given in parentheses make up an overlay, as in
+    // LINK's a+(b+c)+(d); and the interrupt calls into them are made with
+    // (-ovlint N, or /OVERLAYINTERRUPT:N).
+    pub overlays: Vec<u16>,
+    pub overlay_interrupt: Option<u8>,
+
+    // The EXE to write; by default the first object's name with .exe
+    pub output: Option<String>,
+
+    pub codepage: Codepage,
+
+    // The symbol to start at, overriding the main module's start address
+    pub entry: Option<String>,
+
+    // Only warn about publics defined more than once (-allowdup)
+    pub allow_duplicates: bool,
+
+    // Segments or classes to put first, in order (-order A,B,...), and
+    // whether to order as DOSSEG does (-dosseg)
+    pub ordering: Vec<String>,
+    pub dosseg: bool,
+
+    // Directories to look for libraries in (-L), before those in the LIB
+    // environment variable
+    pub library_paths: Vec<String>,
+
+    // -nodefaultlib ignores every default library, and -nodefaultlib:NAME
+    // just the one named
+    pub no_default_libraries: bool,
+    pub ignore_libraries: Vec<String>,
+
+    // Write a .SYM file of the program's symbols next to the EXE (-sym)
+    pub sym: bool,
+
+    // Compress the image, as /EXEPACK does (-exepack)
+    pub exepack: bool,
+
+    // Header tuning: the most memory to ask for past the image, in
+    // paragraphs (-maxalloc N, or /CPARMAXALLOC:N); loading the program
+    // high (-high, or /HIGH); and filling in the checksum (-checksum)
+    pub max_alloc: Option<u16>,
+    pub load_high: bool,
+    pub checksum: bool,
+
+    // The byte to fill alignment gaps with (-fill N), rather than zero
+    pub fill: u8,
+
+    // How to combine SEGDEFs with combine codes 4 and 7 (-combine4 and
+    // -combine7, each public, private or common) where not as public
+    pub combine_4: Option<Combine>,
+    pub combine_7: Option<Combine>,
+
+    // The map file to write (-map, or the map file field); with /MAP and
+    // no name, the EXE's name with .map
+    pub map: Option<String>,
+    pub default_map: bool,
+
+    // Put each module's line numbers in the map (-lines, or /LINENUMBERS),
+    // which makes a map even if none was asked for
+    pub line_numbers: bool,
+
+    // Append a cross-reference of the publics to the map (-xref, or
+    // /XREF), which likewise makes a map
+    pub xref: bool,
+
+    // LINK options this linker doesn't have, which are ignored
+    pub ignored: Vec<String>,
+
+    // What to make of each kind of problem, by its code: -Wno-CODE ignores
+    // it and -Werror=CODE makes it an error. -Werror makes every warning
+    // an error.
+    pub severities: Vec<(String, Severity)>,
+    pub warnings_are_errors: bool,
+
+    // A control script placing segments and symbols at fixed addresses
+    // (-script FILE). One which sets an origin has a flat binary written,
+    // with .bin, rather than an EXE.
+    pub script: Option<String>,
+
+    // Only resolve symbols and lay out the segments, printing the segment
+    // table and what's unresolved, and write nothing (-dryrun)
+    pub dry_run: bool,
+
+    // Trace the link: -v for the modules taken from libraries and where
+    // segments went, -vv for every fixup as well
+    pub verbose: u8,
+
+    args: vec::IntoIter<String>,
+    arg: Option<String>,
+
+    // The overlay being given, if a parenthesis is open, and how many
+    // there have been
+    overlay: Option<u16>,
+    overlay_count: u16,
+}
+
+impl Args {
+    fn new() -> Args {
+        Args{
+            objects: Vec::new(),
+            libraries: Vec::new(),
+            overlays: Vec::new(),
+            overlay_interrupt: None,
+            output: None,
+            codepage: Codepage::default(),
+            entry: None,
+            allow_duplicates: false,
+            ordering: Vec::new(),
+            dosseg: false,
+            library_paths: Vec::new(),
+            no_default_libraries: false,
+            ignore_libraries: Vec::new(),
+            sym: false,
+            exepack: false,
+            max_alloc: None,
+            load_high: false,
+            checksum: false,
+            fill: 0,
+            combine_4: None,
+            combine_7: None,
+            map: None,
+            default_map: false,
+            line_numbers: false,
+            xref: false,
+            ignored: Vec::new(),
+            severities: Vec::new(),
+            warnings_are_errors: false,
+            script: None,
+            dry_run: false,
+            verbose: 0,
+            args: Vec::new().into_iter(),
+            arg: None,
+            overlay: None,
+            overlay_count: 0,
+        }
+    }
+
+    fn next(&mut self) {
+        self.arg = self.args.next();
+    }
+
+    fn value(&mut self, flag: &str) -> Result<String, ArgError> {
+        self.next();
+        match self.arg {
+            Some(ref value) => Ok(value.clone()),
+            None => Err(ArgError::new(&format!("flag {} requires a value", flag))),
+        }
+    }
+
+    // A number, in decimal or with 0x in hex
+    //
+    fn number(value: &str, what: &str) -> Result<u16, ArgError> {
+        let number = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => value.parse::<u16>(),
+        };
+        number.map_err(|_| ArgError::new(&format!("invalid {} {}", what, value)))
+    }
+
+    fn byte(value: &str, what: &str) -> Result<u8, ArgError> {
+        u8::try_from(Self::number(value, what)?)
+            .map_err(|_| ArgError::new(&format!("invalid {} {}", what, value)))
+    }
+
+    // How a combine code is to be taken, for -combine4 and -combine7
+    //
+    fn combine(value: &str, raw: u8) -> Result<Combine, ArgError> {
+        match value {
+            "public" => Ok(Combine::Public{ raw }),
+            "private" => Ok(Combine::Private),
+            "common" => Ok(Combine::Common),
+            _ => Err(ArgError::new(&format!("combine code {} can't be taken as {}; use public, private or common", raw, value))),
+        }
+    }
+
+    fn interrupt(value: &str) -> Result<u8, ArgError> {
+        Self::byte(value, "overlay interrupt")
+    }
+
+    // An object, which may open or close an overlay with a parenthesis
+    //
+    fn object(&mut self, word: &str) -> Result<(), ArgError> {
+        let mut word = word;
+
+        if let Some(rest) = word.strip_prefix('(') {
+            if self.overlay.is_some() {
+                return Err(ArgError::new("overlays can't be nested"));
+            }
+            self.overlay_count += 1;
+            self.overlay = Some(self.overlay_count);
+            word = rest;
+        }
+
+        let closes = word.ends_with(')');
+        if closes {
+            if self.overlay.is_none() {
+                return Err(ArgError::new("unmatched ) in object list"));
+            }
+            word = &word[..word.len() - 1];
+        }
+
+        if !word.is_empty() {
+            self.objects.push(word.to_string());
+            self.overlays.push(self.overlay.unwrap_or(0));
+        }
+
+        if closes {
+            self.overlay = None;
+        }
+        Ok(())
+    }
+
+    // The objects were all given, so any overlay must have been closed
+    //
+    fn check_objects(&self) -> Result<(), ArgError> {
+        if self.overlay.is_some() {
+            return Err(ArgError::new("overlay is missing its closing )"));
+        }
+        if self.objects.is_empty() {
+            return Err(ArgError::new("no object files given"));
+        }
+        Ok(())
+    }
+
+    // link [flags] file...
+    //
+    // Files ending in .lib are libraries to search; anything else is an
+    // object, with .obj added if it has no extension. Objects in
+    // parentheses go in an overlay.
+    //
+    // Or, as MS LINK takes them, if there are no - flags and there's a
+    // response file or a separator:
+    //
+    // link objs[,exefile[,mapfile[,libs]]][;] [/options]
+    //
+    pub fn parse() -> Result<Args, ArgError> {
+        let argv: Vec<String> = env::args().skip(1).collect();
+
+        let link_style = !argv.iter().any(|arg| arg.starts_with('-')) &&
+            argv.iter().any(|arg| arg.starts_with('@') || arg.contains([',', ';', '+']));
+        if link_style {
+            return Args::parse_link_style(&argv);
+        }
+
+        let mut args = Args::new();
+        args.args = argv.into_iter();
+
+        loop {
+            args.next();
+
+            match args.arg.take() {
+                Some(flag) if flag.starts_with('-') => match flag.as_str() {
+                    "-o" => args.output = Some(args.value("-o")?),
+                    "-c" => args.codepage = args.value("-c")?.as_str().try_into()?,
+                    "-L" => {
+                        let path = args.value("-L")?;
+                        args.library_paths.push(path);
+                    },
+                    "-entry" => args.entry = Some(args.value("-entry")?),
+                    "-allowdup" => args.allow_duplicates = true,
+                    "-order" => {
+                        let names = args.value("-order")?;
+                        args.ordering.extend(names.split(',').filter(|name| !name.is_empty()).map(|name| name.to_string()));
+                    },
+                    "-dosseg" => args.dosseg = true,
+                    "-sym" => args.sym = true,
+                    "-exepack" => args.exepack = true,
+                    "-maxalloc" => args.max_alloc = Some(Self::number(&args.value("-maxalloc")?, "paragraph count")?),
+                    "-high" => args.load_high = true,
+                    "-checksum" => args.checksum = true,
+                    "-combine4" => args.combine_4 = Some(Self::combine(&args.value("-combine4")?, 4)?),
+                    "-combine7" => args.combine_7 = Some(Self::combine(&args.value("-combine7")?, 7)?),
+                    "-fill" => args.fill = Self::byte(&args.value("-fill")?, "fill byte")?,
+                    "-ovlint" => args.overlay_interrupt = Some(Self::interrupt(&args.value("-ovlint")?)?),
+                    "-map" => args.map = Some(args.value("-map")?),
+                    "-lines" => args.line_numbers = true,
+                    "-xref" => args.xref = true,
+                    "-nodefaultlib" => args.no_default_libraries = true,
+                    "-Werror" => args.warnings_are_errors = true,
+                    "-script" => args.script = Some(args.value("-script")?),
+                    "-dryrun" => args.dry_run = true,
+                    "-v" => args.verbose = args.verbose.max(1),
+                    "-vv" => args.verbose = 2,
+                    _ if flag.starts_with("-Werror=") => args.severities.push((flag["-Werror=".len()..].to_string(), Severity::Error)),
+                    _ if flag.starts_with("-Wno-") => args.severities.push((flag["-Wno-".len()..].to_string(), Severity::Ignored)),
+                    _ if flag.starts_with("-nodefaultlib:") => args.ignore_libraries.push(flag["-nodefaultlib:".len()..].to_string()),
+                    _ if flag.starts_with("-L") => args.library_paths.push(flag[2..].to_string()),
+                    _ => return Err(ArgError::new(&format!("invalid flag {}", flag))),
+                },
+                Some(file) if file.to_ascii_lowercase().ends_with(".lib") => args.libraries.push(file),
+                Some(file) => args.object(&file)?,
+                None => break,
+            }
+        }
+
+        args.check_objects()?;
+        Ok(args)
+    }
+
+    // A response file, as text to go on the command line. Each line
+    // answers one of LINK's prompts, so ends a field, unless it ends with
+    // + to carry on onto the next line.
+    //
+    fn response_file(path: &str) -> Result<String, ArgError> {
+        let text = std::fs::read_to_string(path).map_err(|err| ArgError::new(&format!("{}: {}", path, err)))?;
+        let mut fields = String::new();
+
+        for line in text.lines().map(|line| line.trim()) {
+            fields.push_str(line);
+            if !line.ends_with('+') {
+                fields.push(',');
+            }
+        }
+
+        Ok(fields)
+    }
+
+    // A LINK option, which may be abbreviated to as little as `min`
+    // letters, and the value after a colon if there is one
+    //
+    fn option<'a>(option: &'a str, name: &str, min: usize) -> Option<Option<&'a str>> {
+        let (given, value) = match option.split_once(':') {
+            Some((given, value)) => (given, Some(value)),
+            None => (option, None),
+        };
+
+        match given.len() >= min && name.starts_with(&given.to_ascii_uppercase()) {
+            true => Some(value),
+            false => None,
+        }
+    }
+
+    fn link_option(&mut self, option: &str) -> Result<(), ArgError> {
+        if Self::option(option, "MAP", 1).is_some() {
+            self.default_map = true;
+        } else if Self::option(option, "LINENUMBERS", 2).is_some() {
+            self.line_numbers = true;
+        } else if Self::option(option, "XREF", 1).is_some() {
+            self.xref = true;
+        } else if let Some(value) = Self::option(option, "OVERLAYINTERRUPT", 1) {
+            let value = value.ok_or_else(|| ArgError::new("/OVERLAYINTERRUPT needs a number"))?;
+            self.overlay_interrupt = Some(Self::interrupt(value)?);
+        } else if let Some(value) = Self::option(option, "CPARMAXALLOC", 2) {
+            let value = value.ok_or_else(|| ArgError::new("/CPARMAXALLOC needs a number"))?;
+            self.max_alloc = Some(Self::number(value, "paragraph count")?);
+        } else if Self::option(option, "HIGH", 1).is_some() {
+            self.load_high = true;
+        } else if Self::option(option, "EXEPACK", 1).is_some() {
+            self.exepack = true;
+        } else if Self::option(option, "DOSSEG", 2).is_some() {
+            self.dosseg = true;
+        } else if let Some(value) = Self::option(option, "NODEFAULTLIBRARYSEARCH", 3) {
+            match value {
+                Some(name) => self.ignore_libraries.push(name.to_string()),
+                None => self.no_default_libraries = true,
+            }
+        } else {
+            self.ignored.push(format!("/{}", option));
+        }
+        Ok(())
+    }
+
+    fn parse_link_style(argv: &[String]) -> Result<Args, ArgError> {
+        let mut args = Args::new();
+        let mut text = String::new();
+
+        for arg in argv {
+            match arg.strip_prefix('@') {
+                Some(path) => text.push_str(&Self::response_file(path)?),
+                None => text.push_str(arg),
+            }
+            text.push(' ');
+        }
+
+        // a ; takes the defaults for the rest of the fields, but options
+        // may still follow it
+        //
+        let (text, rest) = text.split_once(';').unwrap_or((&text, ""));
+
+        for word in rest.split(['+', ' ', '\t', ',', ';']).filter(|word| !word.is_empty()) {
+            match word.strip_prefix('/') {
+                Some(option) => args.link_option(option)?,
+                None => args.ignored.push(word.to_string()),
+            }
+        }
+
+        for (index, field) in text.split(',').enumerate() {
+            let mut files = Vec::new();
+
+            for word in field.split(['+', ' ', '\t']).filter(|word| !word.is_empty()) {
+                match word.strip_prefix('/') {
+                    Some(option) => args.link_option(option)?,
+                    None if index == 0 => args.object(word)?,
+                    None => files.push(word.to_string()),
+                }
+            }
+
+            match index {
+                0 => (),
+                1 => args.output = files.into_iter().next(),
+                2 => args.map = files.into_iter().next().filter(|name| !name.eq_ignore_ascii_case("nul") && !name.to_ascii_lowercase().starts_with("nul.")),
+                3 => args.libraries.extend(files),
+                _ => args.ignored.extend(files),
+            }
+        }
+
+        args.check_objects()?;
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn link_style(text: &str) -> Args {
+        Args::parse_link_style(&[text.to_string()]).unwrap()
+    }
+
+    #[test]
+    fn test_link_style_semicolon() {
+        let args = link_style("a.obj;/map");
+        assert_eq!(args.objects, vec!["a.obj"]);
+        assert!(args.default_map);
+        assert_eq!(args.output, None);
+
+        let args = link_style("a+b,prog;  /ex /li  extra");
+        assert_eq!(args.objects, vec!["a", "b"]);
+        assert_eq!(args.output, Some("prog".to_string()));
+        assert!(args.exepack && args.line_numbers);
+        assert_eq!(args.ignored, vec!["extra"]);
+    }
+}
@@ -0,0 +1,305 @@
+mod args;
+
+use std::fmt::Write;
+use std::path::Path;
+
+use dt_lib::error::Diagnostic;
+use dt_lib::error::Error as AppError;
+use dt_lib::exepack;
+use dt_lib::linker::Linker;
+use dt_lib::linkmap::*;
+use dt_lib::linkscript::LinkScript;
+use dt_lib::mzexe;
+use dt_lib::objfile::ParseOptions;
+
+use crate::args::Args;
+
+// A file name with `ext` added if it doesn't have an extension
+//
+fn with_extension(name: &str, ext: &str) -> String {
+    if Path::new(name).extension().is_some() {
+        name.to_string()
+    } else {
+        format!("{}.{}", name, ext)
+    }
+}
+
+fn read(path: &str) -> Result<Vec<u8>, AppError> {
+    std::fs::read(path).map_err(|err| AppError::new(&format!("{}: {}", path, err)))
+}
+
+// Find a default library the way MS LINK does: as named, then in each
+// -L directory, then in each directory of the LIB environment variable
+//
+fn find_library(name: &str, args: &Args) -> Result<Option<(String, Vec<u8>)>, AppError> {
+    let file = with_extension(name, "lib");
+    let lib_env = std::env::var("LIB").unwrap_or_default();
+    let dirs = args.library_paths.iter()
+        .map(|dir| dir.as_str())
+        .chain(lib_env.split(';').filter(|dir| !dir.is_empty()));
+
+    let candidates = std::iter::once(file.clone())
+        .chain(dirs.map(|dir| Path::new(dir).join(&file).to_string_lossy().to_string()));
+
+    for path in candidates {
+        if Path::new(&path).is_file() {
+            let image = read(&path)?;
+            return Ok(Some((path, image)));
+        }
+    }
+
+    Ok(None)
+}
+
+// The segment table which starts a map file
+//
+fn segment_table(segments: &[MapSegment]) -> String {
+    let mut text = String::new();
+
+    writeln!(text).unwrap();
+    writeln!(text, " Start  Stop   Length Name                   Class").unwrap();
+    for segment in segments {
+        let stop = (segment.start + segment.length).max(segment.start + 1) - 1;
+        let line = format!(" {:05X}H {:05X}H {:05X}H {:<22} {:<15}", segment.start, stop, segment.length, segment.name, segment.class);
+        match segment.overlay {
+            0 => writeln!(text, "{}", line.trim_end()).unwrap(),
+            overlay => writeln!(text, "{} Overlay {}", line, overlay).unwrap(),
+        }
+    }
+
+    text
+}
+
+// The map file, laid out as LINK lays it out: segments, groups, the
+// publics sorted by name and then by address, line numbers if they were
+// asked for, the entry point, and then the cross-reference if it was
+//
+fn map_text(map: &LinkMap, line_numbers: bool, xref: bool) -> String {
+    let mut text = segment_table(&map.segments);
+
+    if !map.groups.is_empty() {
+        writeln!(text).unwrap();
+        writeln!(text, " Origin   Group").unwrap();
+        for (name, frame) in &map.groups {
+            writeln!(text, " {:04X}:0   {}", frame, name).unwrap();
+        }
+    }
+
+    for (title, publics) in [("Name", map.publics_by_name()), ("Value", map.publics_by_value())] {
+        writeln!(text).unwrap();
+        writeln!(text, "  Address         Publics by {}", title).unwrap();
+        writeln!(text).unwrap();
+        for public in publics {
+            let abs = if public.absolute { "Abs" } else { "" };
+            writeln!(text, " {:04X}:{:04X}  {:<5} {}", public.frame, public.offset, abs, public.name).unwrap();
+        }
+    }
+
+    if line_numbers {
+        for block in &map.lines {
+            writeln!(text).unwrap();
+            writeln!(text, "Line numbers for {}({}) segment {}", block.origin, block.source, block.segment).unwrap();
+            writeln!(text).unwrap();
+            for row in block.lines.chunks(4) {
+                for (line, frame, offset) in row {
+                    write!(text, " {:5} {:04X}:{:04X}", line, frame, offset).unwrap();
+                }
+                writeln!(text).unwrap();
+            }
+        }
+    }
+
+    if let Some((cs, ip)) = map.entry {
+        writeln!(text).unwrap();
+        writeln!(text, "Program entry point at {:04X}:{:04X}", cs, ip).unwrap();
+    }
+
+    if xref {
+        writeln!(text).unwrap();
+        writeln!(text, " Symbol                         Defined in           Referenced by").unwrap();
+        writeln!(text).unwrap();
+        for entry in map.xref_by_name() {
+            let line = format!(" {:<30} {:<20} {}", entry.name, entry.defined, entry.references.join(" "));
+            writeln!(text, "{}", line.trim_end()).unwrap();
+        }
+    }
+
+    text
+}
+
+// Print the trace and the problems the link has come up with so far, and
+// fail if any of the problems were errors
+//
+fn report(linker: &mut Linker) -> Result<(), AppError> {
+    for line in linker.trace.drain(..) {
+        println!("{}", line);
+    }
+    for report in linker.diagnostics.take() {
+        println!("{}", report);
+    }
+
+    match linker.diagnostics.has_errors() {
+        true => Err(AppError::new("link failed; no output written")),
+        false => Ok(()),
+    }
+}
+
+fn link() -> Result<(), AppError> {
+    let args = Args::parse()?;
+
+    let options = ParseOptions{ codepage: args.codepage, ..Default::default() };
+    let mut linker = Linker::new(options);
+    for (code, severity) in &args.severities {
+        linker.diagnostics.set_severity(code, *severity);
+    }
+    linker.diagnostics.set_warnings_are_errors(args.warnings_are_errors);
+    linker.verbose = args.verbose;
+
+    // whatever's left to report if the link fails part way
+    //
+    let result = build(&args, &mut linker);
+    report(&mut linker).ok();
+    if let Some(summary) = linker.diagnostics.summary() {
+        println!("{}", summary);
+    }
+    result
+}
+
+// The EXE file, packed and with its header tuned as asked
+//
+fn build_exe(args: &Args, linker: &mut Linker) -> Result<Vec<u8>, AppError> {
+    let mut exe = linker.build_exe()?;
+
+    if args.exepack {
+        match exepack::pack(&exe) {
+            Some(packed) => exe = packed,
+            None => linker.diagnostics.warn("exepack", Diagnostic::new("packing would not make the program smaller; not packed")),
+        }
+    }
+
+    if let Some(paragraphs) = args.max_alloc {
+        exe.set_max_alloc(paragraphs);
+    }
+    if args.load_high {
+        exe.load_high();
+    }
+
+    let mut bytes = exe.write()?;
+    if args.checksum {
+        mzexe::set_checksum(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+fn build(args: &Args, linker: &mut Linker) -> Result<(), AppError> {
+    for option in &args.ignored {
+        linker.diagnostics.warn("option", Diagnostic::new(&format!("{} ignored", option)));
+    }
+
+    if let Some(path) = &args.script {
+        let text = std::fs::read_to_string(path).map_err(|err| AppError::new(&format!("{}: {}", path, err)))?;
+        linker.add_script(LinkScript::parse(&text).map_err(|err| err.in_module(path))?);
+    }
+
+    linker.entry = args.entry.clone();
+    linker.allow_duplicates = args.allow_duplicates;
+    linker.ordering = args.ordering.clone();
+    linker.dosseg = args.dosseg;
+    linker.no_default_libraries = args.no_default_libraries;
+    linker.ignore_libraries = args.ignore_libraries.clone();
+    linker.fill = args.fill;
+    if let Some(combine) = &args.combine_4 {
+        linker.combine_4 = combine.clone();
+    }
+    if let Some(combine) = &args.combine_7 {
+        linker.combine_7 = combine.clone();
+    }
+
+    if let Some(interrupt) = args.overlay_interrupt {
+        linker.overlay_interrupt = interrupt;
+    }
+
+    for (name, overlay) in args.objects.iter().zip(&args.overlays) {
+        let path = with_extension(name, "obj");
+        linker.add_overlay(&path, read(&path)?, *overlay)?;
+    }
+
+    for name in &args.libraries {
+        let (path, image) = find_library(name, args)?.ok_or_else(|| AppError::new(&format!("cannot find library {}", name)))?;
+        linker.add_library(&path, image)?;
+    }
+
+    linker.resolve_with_defaults(|name| find_library(name, args))?;
+
+    let unresolved = linker.unresolved();
+    for symbol in &unresolved {
+        linker.diagnostics.error("unresolved", Diagnostic::new(&symbol.to_string()));
+    }
+
+    // a dry run lays the program out even if it won't link, so the
+    // segments can be seen along with what's wrong
+    //
+    if args.dry_run {
+        linker.pass1()?;
+        print!("{}", segment_table(&map_segments(linker)));
+    }
+
+    if !unresolved.is_empty() {
+        report(linker).ok();
+        return Err(AppError::new(&format!("{} unresolved external(s)", unresolved.len())));
+    }
+
+    if !args.allow_duplicates {
+        let duplicates = linker.duplicates();
+        for duplicate in &duplicates {
+            linker.diagnostics.error("duplicate", Diagnostic::new(&duplicate.to_string()));
+        }
+        if !duplicates.is_empty() {
+            report(linker).ok();
+            return Err(AppError::new(&format!("{} public symbol(s) defined more than once; use -allowdup to link anyway", duplicates.len())));
+        }
+    }
+    report(linker)?;
+
+    if args.dry_run {
+        return Ok(());
+    }
+
+    // a program linked at an origin is written as it's to be in memory
+    //
+    let (bytes, ext) = match linker.script.origin {
+        Some(_) => (linker.build_binary()?, "bin"),
+        None => (build_exe(args, linker)?, "exe"),
+    };
+    report(linker)?;
+
+    let output = match &args.output {
+        Some(output) => with_extension(output, ext),
+        None => Path::new(&args.objects[0]).with_extension(ext).to_string_lossy().to_string(),
+    };
+    std::fs::write(&output, bytes).map_err(|err| AppError::new(&format!("{}: {}", output, err)))?;
+
+    let map = match (&args.map, args.default_map || args.line_numbers || args.xref) {
+        (Some(map), _) => Some(with_extension(map, "map")),
+        (None, true) => Some(Path::new(&output).with_extension("map").to_string_lossy().to_string()),
+        (None, false) => None,
+    };
+    if let Some(map) = map {
+        std::fs::write(&map, map_text(&link_map(linker)?, args.line_numbers, args.xref)).map_err(|err| AppError::new(&format!("{}: {}", map, err)))?;
+    }
+
+    if args.sym {
+        let path = Path::new(&output);
+        let module = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().to_ascii_uppercase());
+        let sym = path.with_extension("sym").to_string_lossy().to_string();
+        std::fs::write(&sym, linker.sym_file(&module)?.write()?).map_err(|err| AppError::new(&format!("{}: {}", sym, err)))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = link() {
+        println!("{}", err);
+        std::process::exit(1);
+    }
+}
@@ -1,16 +1,29 @@
 use std::env;
 
+use dt_lib::codepage::Codepage;
 use dt_lib::error::Error as ArgError;
+use dt_lib::objfile::ChecksumPolicy;
 
 #[derive(Debug)]
 pub enum Operation {
     List,
+    Lint,
+    Info,
 }
 
 #[derive(Debug)]
 pub struct Args {
     pub op: Operation,
     pub libname: String,
+
+    // The one library module to dump, by name or as `#n` for the n'th
+    pub module: Option<String>,
+
+    pub codepage: Codepage,
+    pub checksum: ChecksumPolicy,
+    pub strict: bool,
+    pub merge_comdats: bool,
+    pub recover: bool,
     
     args: env::Args,
     arg: Option<String>,
@@ -21,6 +34,12 @@ impl Args {
         Args{ 
             op: Operation::List,
             libname: "".to_string(),
+            module: None,
+            codepage: Codepage::default(),
+            checksum: ChecksumPolicy::default(),
+            strict: false,
+            merge_comdats: false,
+            recover: false,
             args: env::args(),
             arg: None,
         }
@@ -30,6 +49,14 @@ impl Args {
         self.arg = self.args.next(); 
     }
 
+    fn value(&mut self, flag: &str) -> Result<String, ArgError> {
+        self.next();
+        match self.arg {
+            Some(ref value) => Ok(value.clone()),
+            None => Err(ArgError::new(&format!("flag {} requires a value", flag))),
+        }
+    }
+
     pub fn parse() -> Result<Args, ArgError> {
         let mut args = Args::new();
         
@@ -46,6 +73,13 @@ impl Args {
                 } else {
                     match flag {
                         "-t" => args.op = Operation::List,
+                        "-l" => args.op = Operation::Lint,
+                        "-i" => args.op = Operation::Info,
+                        "-k" => args.checksum = args.value("-k")?.as_str().try_into()?,
+                        "-s" => args.strict = true,
+                        "-m" => args.merge_comdats = true,
+                        "-r" => args.recover = true,
+                        "-c" => args.codepage = args.value("-c")?.as_str().try_into()?,
                         _ => return Err(ArgError::new(&format!("invalid flag {}", flag))),
                     }
                             },
@@ -58,6 +92,9 @@ impl Args {
             None => return Err(ArgError::new("missing library name")),
         }
 
+        args.next();
+        args.module = args.arg.take();
+
         Ok(args)
     }
 }
\ No newline at end of file
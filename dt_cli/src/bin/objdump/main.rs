@@ -2,11 +2,14 @@ mod args;
 
 use std::str;
 
+use dt_lib::error::Diagnostic;
 use dt_lib::error::Error as AppError;
 use dt_lib::objfile::*;
 use dt_lib::libfile;
+use dt_lib::validate::validate;
+use dt_lib::modinfo::module_info;
 
-use crate::args::Args;
+use crate::args::{Args, Operation};
 
 struct Objdump {
     lnames: Vec<String>,
@@ -52,12 +55,20 @@ impl Objdump {
 
     fn segname(&self, seg: &Segdef) -> String {
         format!("{}.{}.{}",
-            self.opt_lname(seg.class),
             self.opt_lname(seg.name),
+            self.opt_lname(seg.class),
             self.opt_lname(seg.overlay)
         )
     }
 
+    fn segindex(&self, index: usize) -> String {
+        if index >= self.segments.len() {
+            "invalid-segment".to_string()
+        } else {
+            self.segname(&self.segments[index])
+        }
+    }
+
     fn groupname(&self, index: usize) -> &str {
         if index >= self.groups.len() {
             "invalid-group"
@@ -77,11 +88,17 @@ impl Objdump {
     fn segdef(&mut self, segs: &[Segdef]) -> Result<(), AppError> {
         println!("SEGDEF");
         for seg in segs.iter() {
-            print!("{:5} {} {:?} {:?}",
+            let combine = match seg.combine {
+                Combine::Public{ raw } if raw != 2 => format!("Public({})", raw),
+                Combine::Public{ .. } => "Public".to_string(),
+                ref combine => format!("{:?}", combine),
+            };
+
+            print!("{:5} {} {:?} {}",
                 self.segments.len(),
                 self.segname(seg),
                 seg.align,
-                seg.combine,
+                combine,
             );
             
             if let Some(abs) = &seg.abs {
@@ -100,12 +117,19 @@ impl Objdump {
         Ok(())
     }
 
-    fn grpdef(&mut self, name: usize, segs: &Vec<usize>) -> Result<(), AppError> {
+    fn grpdef(&mut self, name: usize, components: &[GrpComponent]) -> Result<(), AppError> {
         println!("GRPDEF {}", self.lname(name));
 
-        for segidx in segs.iter() {
-            let seg = &self.segments[*segidx];
-            println!("      {}", self.segname(seg)); 
+        for component in components.iter() {
+            match component {
+                GrpComponent::Segdef{ index } => println!("      {}", self.segindex(*index)),
+                GrpComponent::Extdef{ index } => println!("      EXTERN {}", self.externname(*index)),
+                GrpComponent::SegClassOverlay{ name, class, overlay } =>
+                    println!("      {}.{}.{}", self.lname(*class), self.lname(*name), self.lname(*overlay)),
+                GrpComponent::Ltl{ data, max_length, length } =>
+                    println!("      LTL Data={:02x} Max-Length={:04x} Length={:04x}", data, max_length, length),
+                GrpComponent::Absolute{ frame, offset } => println!("      Frame={:04x}:{:02x}", frame, offset),
+            }
         }
 
         self.groups.push(self.lname(name).to_string());
@@ -134,8 +158,7 @@ impl Objdump {
         }
 
         if let Some(seg) = seg {
-            let seg = &self.segments[seg];
-            print!(" SEG={}", self.segname(seg));
+            print!(" SEG={}", self.segindex(seg));
         }
 
         if let Some(frame) = frame {
@@ -205,7 +228,47 @@ impl Objdump {
             Coment::Libmod{ name} => println!("  Libmod '{}'", name),
             Coment::LinkPassSeparator => println!("  Link pass separator"),
             Coment::WeakExtern{ externs } => self.coment_weak_extern(externs)?,
+            Coment::LazyExtern{ externs } => {
+                println!("  Lazy externs");
+                self.coment_weak_extern(externs)?
+            },
+            Coment::DosVersion{ version } => println!("  DOS version {}", version),
+            Coment::EasyOmf{ text } => println!("  Easy OMF-386 '{}'", text),
             Coment::User{ text } => println!("  User '{}'", text),
+            Coment::Impdef{ internal, module, entry } => match entry {
+                ImportEntry::Ordinal(ordinal) => println!("  Import '{}' from {} @{}", internal, module, ordinal),
+                ImportEntry::Name(name) => println!("  Import '{}' from {} '{}'", internal, module, name),
+            },
+            Coment::Expdef{ flags, exported, internal, ordinal } => {
+                print!("  Export '{}'", exported);
+                if !internal.is_empty() {
+                    print!(" = '{}'", internal);
+                }
+                if let Some(ordinal) = ordinal {
+                    print!(" @{}", ordinal);
+                }
+                if (flags & 0x40) != 0 {
+                    print!(" RESIDENTNAME");
+                }
+                if (flags & 0x20) != 0 {
+                    print!(" NODATA");
+                }
+                println!(" Parameter-Words={}", flags & 0x1f);
+            },
+            Coment::Incdef{ extdef_delta, linnum_delta } => 
+                println!("  Incremental EXTDEF-Delta={} LINNUM-Delta={}", extdef_delta, linnum_delta),
+            Coment::Lnkdir{ flags, pcode_version, cv_version } =>
+                println!("  Linker directives Flags={:02x} PCode-Version={} CV-Version={}", flags, pcode_version, cv_version),
+            Coment::ExeStr{ text } => println!("  Executable string '{}'", text),
+            Coment::IncErr => println!("  Incremental compilation error"),
+            Coment::Dependency{ name, timestamp } => println!("  Dependency '{}' {}", name, timestamp),
+            Coment::DependencyEnd => println!("  End of dependencies"),
+            Coment::NoPad{ segs } => {
+                println!("  No padding");
+                for seg in segs.iter() {
+                    println!("    {}", self.segindex(*seg));
+                }
+            },
 
             _ => println!("  Unknown comment class {:02x}", header.comclass),
         }
@@ -267,17 +330,44 @@ impl Objdump {
     }
 
     fn ledata(&self, seg: usize, offset: u32, data: &[u8]) -> Result<(), AppError> {
-        let seg = &self.segments[seg];
-        println!("LEDATA {}", self.segname(seg));
+        println!("LEDATA {}", self.segindex(seg));
         Self::hexdump(data, offset as usize);
     
         Ok(())
     }
 
-    fn lidata(&self, seg: usize, offset: u32, data: &[u8]) -> Result<(), AppError> {
-        let seg = &self.segments[seg];
-        println!("LIDATA {}", self.segname(seg));
-        Self::hexdump(data, offset as usize);
+    fn iterated_block(block: &IteratedBlock, depth: usize) {
+        let indent = "  ".repeat(depth);
+        match &block.content {
+            IteratedContent::Data(data) => {
+                print!("{}{} x", indent, block.repeat);
+                for by in data.iter() {
+                    print!(" {:02x}", by);
+                }
+                println!();
+            },
+            IteratedContent::Blocks(blocks) => {
+                println!("{}{} x", indent, block.repeat);
+                for block in blocks.iter() {
+                    Self::iterated_block(block, depth + 1);
+                }
+            },
+        }
+    }
+
+    fn lidata(&self, seg: usize, offset: u32, blocks: &[IteratedBlock]) -> Result<(), AppError> {
+        println!("LIDATA {} Offset={:08x}", self.segindex(seg), offset);
+        for block in blocks.iter() {
+            Self::iterated_block(block, 1);
+        }
+
+        // Anything bigger than the real mode address space isn't worth
+        // dumping
+        //
+        match expand_iterated(blocks, 0x100000) {
+            Ok(data) => Self::hexdump(&data, offset as usize),
+            Err(err) => println!("  {}", err),
+        }
     
         Ok(())
     }
@@ -292,7 +382,17 @@ impl Objdump {
     }
 
     fn bakpat(&self, seg: usize, location: BakpatLocation, fixups: &[BakpatFixup]) -> Result<(), AppError> {
-        println!("BAKPAT {} {:?}", self.segname(&self.segments[seg]), location);
+        println!("BAKPAT {} {:?}", self.segindex(seg), location);
+
+        for fixup in fixups {
+            println!("      Offset {:08x} Value {:08x}", fixup.offset, fixup.value);
+        }
+
+        Ok(())
+    }
+
+    fn nbkpat(&self, name: usize, location: BakpatLocation, fixups: &[BakpatFixup]) -> Result<(), AppError> {
+        println!("NBKPAT {} {:?}", self.lname(name), location);
 
         for fixup in fixups {
             println!("      Offset {:08x} Value {:08x}", fixup.offset, fixup.value);
@@ -309,7 +409,7 @@ impl Objdump {
                 FixupSubrecord::TargetThread{ method, thread, index } => {
                     print!("      TARGET THREAD {} {:?} ", thread, method);
                     match method {
-                        TargetMethod::Segdef => print!("{}", self.segname(&self.segments[*index])),
+                        TargetMethod::Segdef => print!("{}", self.segindex(*index)),
                         TargetMethod::Grpdef => print!("{}", self.groupname(*index)),
                         TargetMethod::Extdef => print!("{}", self.externname(*index)),
                         _ => (),
@@ -320,7 +420,7 @@ impl Objdump {
                     print!("      FRAME THREAD {} {:?} ", thread, method);
                     if let Some(index) = index {
                         match method {
-                            FrameMethod::Segdef => print!("{}", self.segname(&self.segments[*index])),
+                            FrameMethod::Segdef => print!("{}", self.segindex(*index)),
                             FrameMethod::Grpdef => print!("{}", self.groupname(*index)),
                             FrameMethod::Extdef => print!("{}", self.externname(*index)),
                             _ => (),
@@ -346,7 +446,7 @@ impl Objdump {
                             //
                             // TODO should refactor to put index into FrameMethod enum
                             //
-                            FrameMethod::Segdef => print!("FRAME SEG {} ", self.segname(&self.segments[fixup.frame_datum.unwrap()])),
+                            FrameMethod::Segdef => print!("FRAME SEG {} ", self.segindex(fixup.frame_datum.unwrap())),
                             FrameMethod::Grpdef => print!("FRAME GROUP {} ", self.groupname(fixup.frame_datum.unwrap())),
                             FrameMethod::Extdef => print!("FRAME EXTERN {} ", self.externname(fixup.frame_datum.unwrap())),
                             FrameMethod::Target => print!("FRAME=TARGET "),
@@ -363,7 +463,7 @@ impl Objdump {
                             TargetMethod::Extdef | TargetMethod::ExtdefNoDisplacement =>
                                 print!("TARGET EXTERN {} ", self.externname(fixup.target_datum.unwrap())),
                             TargetMethod::Segdef | TargetMethod::SegdefNoDisplacement =>
-                                print!("TARGET SEG {} ", self.segname(&self.segments[fixup.target_datum.unwrap()])),
+                                print!("TARGET SEG {} ", self.segindex(fixup.target_datum.unwrap())),
                             TargetMethod::Grpdef | TargetMethod::GrpdefNoDisplacement =>
                                 print!("TARGET GROUP {} ", self.groupname(fixup.target_datum.unwrap())),
                         }
@@ -377,6 +477,34 @@ impl Objdump {
         Ok(())
     }
 
+    fn linnum(&self, group: Option<usize>, seg: usize, lines: &[LineNumber]) -> Result<(), AppError> {
+        print!("LINNUM");
+        if let Some(group) = group {
+            print!(" GRP={}", self.groupname(group));
+        }
+        println!(" SEG={}", self.segindex(seg));
+
+        for line in lines {
+            println!("      Line {:5} Offset {:08x}", line.line, line.offset);
+        }
+
+        Ok(())
+    }
+
+    fn linsym(&self, flags: u8, name: usize, lines: &[LineNumber]) -> Result<(), AppError> {
+        print!("LINSYM {}", self.lname(name));
+        if (flags & 0x01) != 0 {
+            print!(" Continuation");
+        }
+        println!();
+
+        for line in lines {
+            println!("      Line {:5} Offset {:08x}", line.line, line.offset);
+        }
+
+        Ok(())
+    }
+
     fn alias(&self, aliases: &[Alias]) -> Result<(), AppError> {
         println!("ALIAS");
 
@@ -427,43 +555,87 @@ impl Objdump {
         }
 
         if let Some(seg) = comdat.base_seg {
-            println!("  Segment {}", self.segname(&self.segments[seg]));
+            println!("  Segment {}", self.segindex(seg));
         }
 
         if let Some(frame) = comdat.base_frame {
             println!("  Frame {:04x}", frame);
         }
 
-        Self::hexdump(&comdat.data, comdat.offset as usize);
+        for block in comdat.blocks.iter() {
+            Self::iterated_block(block, 1);
+        }
+
+        match comdat.expand(0x100000) {
+            Ok(data) => Self::hexdump(&data, comdat.offset as usize),
+            Err(err) => println!("  {}", err),
+        }
         
         Ok(())
     }
 
 }
 
-fn dump_one_object(obj: &[u8]) -> Result<(), AppError> {
-    let mut obj = Parser::new(&obj);
+// Name a library member the way classic linkers do, e.g. "CRTLIB.LIB(fopen)"
+//
+fn member_name(libname: Option<&str>, module: Option<&str>) -> Option<String> {
+    match (libname, module) {
+        (Some(libname), Some(module)) => Some(format!("{}({})", libname, module)),
+        (Some(libname), None) => Some(libname.to_string()),
+        (None, module) => module.map(|module| module.to_string()),
+    }
+}
+
+fn print_diagnostics(diagnostics: &[Diagnostic], libname: Option<&str>) {
+    for diag in diagnostics.iter() {
+        let module = member_name(libname, diag.module.as_deref());
+        println!("{}", Diagnostic{ module, ..diag.clone() });
+    }
+}
+
+fn dump_one_object(obj: &[u8], options: &ParseOptions, libname: Option<&str>) -> Result<(), AppError> {
+    let mut obj = Parser::with_options(obj, options.clone());
     let mut objdump = Objdump::new();
     loop {
-        match obj.next()? {
+        let record = obj.next().map_err(|mut err| {
+            err.module = member_name(libname, err.module.as_deref());
+            err
+        })?;
+
+        match record {
             Record::THEADR{ name } => println!("THEADER {}", name),
-            Record::MODEND{ main, start_address } => objdump.modend(main, start_address)?,
+            Record::LHEADR{ name } => println!("LHEADER {}", name),
+            Record::MODEND{ main, start_address, .. } => objdump.modend(main, start_address)?,
             Record::LNAMES{ names } => objdump.lnames(&names)?,
             Record::SEGDEF{ segs } => objdump.segdef(&segs)?,
-            Record::GRPDEF{ name, segs } => objdump.grpdef(name, &segs)?,
+            Record::GRPDEF{ name, components } => objdump.grpdef(name, &components)?,
             Record::EXTDEF{ externs } => objdump.extdef(&externs)?,
             Record::PUBDEF{ group, seg, frame, publics} => objdump.pubdef(group, seg, frame, &publics, false)?,
             Record::LPUBDEF{ group, seg, frame, publics} => objdump.pubdef(group, seg, frame, &publics, true)?,
             Record::COMENT{ header, coment } => objdump.coment(header, &coment)?,
             Record::LEDATA{ seg, offset, data } => objdump.ledata(seg, offset, &data)?,
-            Record::LIDATA{ seg, offset, data } => objdump.lidata(seg, offset, &data)?,
+            Record::LIDATA{ seg, offset, blocks } => objdump.lidata(seg, offset, &blocks)?,
             Record::BAKPAT{ seg, location, fixups} => objdump.bakpat(seg, location, &fixups)?,
+            Record::NBKPAT{ name, location, fixups} => objdump.nbkpat(name, location, &fixups)?,
             Record::FIXUPP{ fixups} => objdump.fixupp(&fixups)?,
             Record::COMDEF{ commons } => objdump.comdef(&commons)?,
             Record::LEXTDEF{ externs } => objdump.extdef(&externs)?,
             Record::ALIAS{ aliases } => objdump.alias(&aliases)?,
             Record::CEXTDEF{ externs } => objdump.cextdef(&externs)?,
             Record::COMDAT{ comdat } => objdump.comdat(&comdat)?,
+            Record::LINNUM{ group, seg, lines } => objdump.linnum(group, seg, &lines)?,
+            Record::LINSYM{ flags, name, lines } => objdump.linsym(flags, name, &lines)?,
+            Record::LIBHDR{ page_size, dict_offset, dict_blocks, flags } => 
+                println!("LIBHDR Page-Size={} Dictionary={:08x} Blocks={} Flags={:02x}", page_size, dict_offset, dict_blocks, flags),
+            Record::LIBEND => println!("LIBEND"),
+            Record::INTEL{ kind, data } => {
+                println!("{}", kind.name());
+                Objdump::hexdump(&data, 0);
+            },
+            Record::BORLAND{ rectype, data } => {
+                println!("BORLAND ${:02x}", rectype);
+                Objdump::hexdump(&data, 0);
+            },
             Record::None => break,
             x => { 
                 println!("record {:x?}", x)
@@ -471,29 +643,94 @@ fn dump_one_object(obj: &[u8]) -> Result<(), AppError> {
         }
     }
 
+    print_diagnostics(obj.diagnostics(), libname);
+
+    Ok(())
+}
+
+fn lint_one_object(obj: &[u8], options: &ParseOptions, libname: Option<&str>) -> Result<(), AppError> {
+    let diagnostics = validate(obj, options).map_err(|mut err| {
+        err.module = member_name(libname, err.module.as_deref());
+        err
+    })?;
+
+    print_diagnostics(&diagnostics, libname);
+
+    Ok(())
+}
+
+fn info_one_object(obj: &[u8], options: &ParseOptions, libname: Option<&str>) -> Result<(), AppError> {
+    let info = module_info(obj, options).map_err(|mut err| {
+        err.module = member_name(libname, err.module.as_deref());
+        err
+    })?;
+
+    let bits = match (info.uses16, info.uses32) {
+        (true, true) => "mixed 16/32",
+        (false, true) => "32",
+        _ => "16",
+    };
+
+    println!("MODULE {}", member_name(libname, info.name.as_deref()).unwrap_or_default());
+    println!("  Records: {}-bit", bits);
+    if let Some(translator) = &info.translator {
+        println!("  Translator: {}", translator);
+    }
+    if let Some(model) = &info.memory_model {
+        println!("  Memory-Model: {}", model);
+    }
+    for lib in info.default_libraries.iter() {
+        println!("  Default-Library: {}", lib);
+    }
+    println!("  Debug-Info: {}", if info.debug_info { "yes" } else { "no" });
+    for (rectype, count) in info.record_counts.iter() {
+        println!("  ${:02x} x {}", rectype, count);
+    }
+
     Ok(())
 }
 
 fn objdump() -> Result<(), AppError> {
     let args = Args::parse()?;
-    let obj = std::fs::read(args.libname)?;
+    let obj = std::fs::read(&args.libname)?;
+    let options = ParseOptions{
+        codepage: args.codepage,
+        checksum: args.checksum,
+        merge_comdats: args.merge_comdats,
+        strict: args.strict,
+        recover: args.recover,
+    };
+
+    let process = match args.op {
+        Operation::List => dump_one_object,
+        Operation::Lint => lint_one_object,
+        Operation::Info => info_one_object,
+    };
 
     if libfile::Parser::is_lib(&obj) {
         println!("FILE IS A LIBRARY");
         let mut lib = libfile::Parser::new(&obj)?;
-        let mut obj = lib.first_obj()?;
-
-        loop {
-            match obj {
-                None => break,
-                Some(obj) => dump_one_object(obj)?,
-            }
-
-            obj = lib.next_obj()?;
-            println!("--------------------");
+        lib.codepage = args.codepage;
+
+        match &args.module {
+            Some(name) => {
+                let module = match name.strip_prefix('#') {
+                    Some(index) => {
+                        let index = index.parse().map_err(|_| AppError::new(&format!("{} is not a module number", name)))?;
+                        lib.get_module(index)?
+                    },
+                    None => lib.get_module_by_name(name)?,
+                };
+                let module = module.ok_or_else(|| AppError::new(&format!("module {} is not in the library", name)))?;
+                process(module.image, &options, Some(&args.libname))?;
+            },
+            None => for module in lib.modules() {
+                process(module?.image, &options, Some(&args.libname))?;
+                println!("--------------------");
+            },
         }
     } else {
-        dump_one_object(&obj)?;
+        process(&obj, &options, None)?;
     }
 
     Ok(())
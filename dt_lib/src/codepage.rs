@@ -0,0 +1,160 @@
+use crate::error::Error as CodepageError;
+
+// Text decoding for the names and strings embedded in OMF objects and
+// libraries. DOS toolchains wrote whatever bytes the source had, which
+// in practice means an OEM code page rather than UTF-8.
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub enum Codepage {
+    #[default]
+    Cp437,
+    Cp850,
+    Latin1,
+    Utf8,
+}
+
+impl TryFrom<&str> for Codepage {
+    type Error = CodepageError;
+
+    fn try_from(val: &str) -> Result<Self, Self::Error> {
+        match val.to_lowercase().as_str() {
+            "cp437" | "437" => Ok(Codepage::Cp437),
+            "cp850" | "850" => Ok(Codepage::Cp850),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Codepage::Latin1),
+            "utf8" | "utf-8" => Ok(Codepage::Utf8),
+
+            val => Err(CodepageError::new(&format!("unknown code page {}", val))),
+        }
+    }
+}
+
+// The upper halves (0x80..0xff) of the OEM code pages; the lower half of
+// each is plain ASCII.
+//
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+const CP850_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '®', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À', '©', '╣', '║', '╗', '╝', '¢', '¥', '┐',
+    '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã', '╚', '╔', '╩', '╦', '╠', '═', '╬', '¤',
+    'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î', 'Ï', '┘', '┌', '█', '▄', '¦', 'Ì', '▀',
+    'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ', 'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´',
+    '\u{ad}', '±', '‗', '¾', '¶', '§', '÷', '¸', '°', '¨', '·', '¹', '³', '²', '■', '\u{a0}',
+];
+
+impl Codepage {
+    fn high_half(&self) -> Option<&'static [char; 128]> {
+        match self {
+            Codepage::Cp437 => Some(&CP437_HIGH),
+            Codepage::Cp850 => Some(&CP850_HIGH),
+            _ => None,
+        }
+    }
+
+    // Decode raw bytes into a string. Only strict UTF-8 can fail; every
+    // other code page maps all 256 byte values.
+    //
+    pub fn decode(&self, bytes: &[u8]) -> Result<String, CodepageError> {
+        match self {
+            Codepage::Utf8 => String::from_utf8(bytes.to_vec())
+                .map_err(|err| CodepageError::new(&format!("invalid UTF-8 text: {}", err))),
+            Codepage::Latin1 => Ok(bytes.iter().map(|by| *by as char).collect()),
+            _ => {
+                let high = self.high_half().unwrap();
+                Ok(bytes.iter().map(|by| if *by < 0x80 { *by as char } else { high[(*by - 0x80) as usize] }).collect())
+            },
+        }
+    }
+
+    // The inverse of decode, for matching names against the raw bytes in
+    // a file. Fails if a character has no encoding in the code page.
+    //
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, CodepageError> {
+        if *self == Codepage::Utf8 {
+            return Ok(text.as_bytes().to_vec());
+        }
+
+        text.chars().map(|ch| {
+            let byte = match self.high_half() {
+                _ if (ch as u32) < 0x80 => Some(ch as u8),
+                None => u8::try_from(ch as u32).ok(),
+                Some(high) => high.iter().position(|hi| *hi == ch).map(|pos| (pos + 0x80) as u8),
+            };
+
+            byte.ok_or_else(|| CodepageError::new(&format!("'{}' cannot be encoded in {:?}", ch, self)))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ascii_decodes_the_same_everywhere() {
+        for cp in [Codepage::Cp437, Codepage::Cp850, Codepage::Latin1, Codepage::Utf8] {
+            assert_eq!(cp.decode(b"_main").unwrap(), "_main");
+        }
+    }
+
+    #[test]
+    fn test_cp437_decodes_high_bytes() {
+        let bytes = [0x80, 0x9b, 0xe1, 0xff];
+        assert_eq!(Codepage::Cp437.decode(&bytes).unwrap(), "Ç¢ß\u{a0}");
+    }
+
+    #[test]
+    fn test_cp850_differs_from_cp437() {
+        let bytes = [0x9b, 0xb5];
+        assert_eq!(Codepage::Cp850.decode(&bytes).unwrap(), "øÁ");
+    }
+
+    #[test]
+    fn test_latin1_maps_bytes_directly() {
+        let bytes = [0x41, 0xe9];
+        assert_eq!(Codepage::Latin1.decode(&bytes).unwrap(), "Aé");
+    }
+
+    #[test]
+    fn test_codepage_names_parse() {
+        assert_eq!(Codepage::try_from("CP850").unwrap(), Codepage::Cp850);
+        assert_eq!(Codepage::try_from("latin1").unwrap(), Codepage::Latin1);
+        assert!(Codepage::try_from("ebcdic").is_err());
+    }
+
+    #[test]
+    fn test_encode_inverts_decode() {
+        let bytes: Vec<u8> = (0x20..=0xff).collect();
+        for cp in [Codepage::Cp437, Codepage::Cp850, Codepage::Latin1] {
+            let text = cp.decode(&bytes).unwrap();
+            assert_eq!(cp.encode(&text).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_encode_rejects_unmappable_chars() {
+        assert!(Codepage::Cp437.encode("€").is_err());
+        assert!(Codepage::Latin1.encode("Ç").is_ok());
+        assert!(Codepage::Latin1.encode("═").is_err());
+    }
+
+    #[test]
+    fn test_utf8_rejects_invalid_bytes() {
+        let bytes = [0x41, 0x80];
+        assert!(Codepage::Utf8.decode(&bytes).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::Diagnostic;
+
+// How seriously a problem is taken. An ignored one isn't reported at all;
+// an error fails the link once it's been reported.
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Severity {
+    Ignored,
+    Warning,
+    Error,
+}
+
+// A problem, with the short code it's known by, which is what -W flags
+// name it with
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Report {
+    pub code: &'static str,
+    pub severity: Severity,
+    pub diagnostic: Diagnostic,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(module) = &self.diagnostic.module {
+            write!(f, "{}: ", module)?;
+        }
+        if let Some(offset) = self.diagnostic.offset {
+            write!(f, "{:08x}: ", offset)?;
+        }
+
+        let severity = match self.severity {
+            Severity::Error => "error",
+            _ => "warning",
+        };
+        write!(f, "{} [{}]: {}", severity, self.code, self.diagnostic.details)
+    }
+}
+
+// Collects the problems a link runs into. Each kind of problem has a
+// severity of its own, which can be changed by code, and every warning
+// can be made an error. Reports wait here until they're taken to be
+// printed; the counts are of everything reported.
+//
+#[derive(Debug)]
+#[derive(Default)]
+pub struct Diagnostics {
+    pending: Vec<Report>,
+    severities: HashMap<String, Severity>,
+    warnings_are_errors: bool,
+
+    pub warnings: usize,
+    pub errors: usize,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    // Give problems with `code` a severity of their own
+    //
+    pub fn set_severity(&mut self, code: &str, severity: Severity) {
+        self.severities.insert(code.to_ascii_lowercase(), severity);
+    }
+
+    pub fn set_warnings_are_errors(&mut self, on: bool) {
+        self.warnings_are_errors = on;
+    }
+
+    // The severity a problem of `code` gets, where it's ordinarily
+    // `severity`. An error can be made a warning by code, but not by
+    // default.
+    //
+    pub fn severity(&self, code: &str, severity: Severity) -> Severity {
+        match self.severities.get(&code.to_ascii_lowercase()) {
+            Some(severity) => *severity,
+            None if severity == Severity::Warning && self.warnings_are_errors => Severity::Error,
+            None => severity,
+        }
+    }
+
+    // Report a problem which is ordinarily a warning
+    //
+    pub fn warn(&mut self, code: &'static str, diagnostic: Diagnostic) {
+        self.report(code, Severity::Warning, diagnostic);
+    }
+
+    // Report a problem which is ordinarily an error
+    //
+    pub fn error(&mut self, code: &'static str, diagnostic: Diagnostic) {
+        self.report(code, Severity::Error, diagnostic);
+    }
+
+    fn report(&mut self, code: &'static str, severity: Severity, diagnostic: Diagnostic) {
+        let severity = self.severity(code, severity);
+        match severity {
+            Severity::Ignored => return,
+            Severity::Warning => self.warnings += 1,
+            Severity::Error => self.errors += 1,
+        }
+        self.pending.push(Report{ code, severity, diagnostic });
+    }
+
+    // The reports which haven't been taken yet, oldest first
+    //
+    pub fn pending(&self) -> &[Report] {
+        &self.pending
+    }
+
+    pub fn take(&mut self) -> Vec<Report> {
+        std::mem::take(&mut self.pending)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors > 0
+    }
+
+    // How many warnings and errors there were, if there were any
+    //
+    pub fn summary(&self) -> Option<String> {
+        match (self.warnings, self.errors) {
+            (0, 0) => None,
+            (warnings, errors) => Some(format!("{} warning(s), {} error(s)", warnings, errors)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_severities() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.set_severity("NOSTACK", Severity::Ignored);
+        diagnostics.set_severity("dup", Severity::Warning);
+
+        diagnostics.warn("nostack", Diagnostic::new("no stack segment"));
+        diagnostics.warn("nostart", Diagnostic::new("no starting address"));
+        diagnostics.error("dup", Diagnostic::new("_x is defined twice").in_module("a.obj"));
+        assert_eq!((diagnostics.warnings, diagnostics.errors), (2, 0));
+
+        let reports: Vec<String> = diagnostics.take().iter().map(|report| report.to_string()).collect();
+        assert_eq!(reports, vec![
+            "warning [nostart]: no starting address",
+            "a.obj: warning [dup]: _x is defined twice",
+        ]);
+        assert!(diagnostics.pending().is_empty());
+
+        // -Werror makes every other warning an error
+        //
+        diagnostics.set_warnings_are_errors(true);
+        diagnostics.warn("nostart", Diagnostic::with_offset("no starting address", 0x10));
+        diagnostics.warn("nostack", Diagnostic::new("no stack segment"));
+        assert!(diagnostics.has_errors());
+        assert_eq!(diagnostics.pending()[0].to_string(), "00000010: error [nostart]: no starting address");
+        assert_eq!(diagnostics.summary(), Some("2 warning(s), 1 error(s)".to_string()));
+    }
+}
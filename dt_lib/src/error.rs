@@ -6,12 +6,19 @@ use std::io;
 pub struct Error {
     pub details: String,
     pub offset: Option<usize>,
+
+    // The module the error came from, e.g. "CRTLIB.LIB(fopen)"
+    pub module: Option<String>,
 }
 
 // Format error for display
 //
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(module) = &self.module {
+            write!(f, "{}: ", module)?;
+        }
+
         match self.offset {
             Some(offset) => write!(f, "{:08x}: {}", offset, self.details),
             None =>         write!(f, "{}", self.details),
@@ -28,6 +35,7 @@ impl Error {
         Error {
             details: details.to_string(),
             offset: None,
+            module: None,
         }
     }
 
@@ -35,6 +43,7 @@ impl Error {
         Error {
             details: details.to_string(),
             offset: Some(offset),
+            module: None,
         }
     }
 
@@ -42,13 +51,70 @@ impl Error {
         Error{
             details: "record is truncated".to_string(),
             offset: None,
+            module: None,
         }
     }
 
+    // Attribute the error to a module, unless something more specific
+    // already has
+    //
+    pub fn in_module(mut self, module: &str) -> Error {
+        self.module.get_or_insert_with(|| module.to_string());
+        self
+    }
+
 }
 
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
         Error::new(&format!("{}", err))
     }
-}
\ No newline at end of file
+}
+
+// A non-fatal problem noticed while parsing, kept so the caller can
+// report it after the fact
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Diagnostic {
+    pub details: String,
+    pub offset: Option<usize>,
+    pub module: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(module) = &self.module {
+            write!(f, "{}: ", module)?;
+        }
+
+        match self.offset {
+            Some(offset) => write!(f, "{:08x}: warning: {}", offset, self.details),
+            None =>         write!(f, "warning: {}", self.details),
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn new(details: &str) -> Diagnostic {
+        Diagnostic {
+            details: details.to_string(),
+            offset: None,
+            module: None,
+        }
+    }
+
+    pub fn with_offset(details: &str, offset: usize) -> Diagnostic {
+        Diagnostic {
+            details: details.to_string(),
+            offset: Some(offset),
+            module: None,
+        }
+    }
+
+    pub fn in_module(mut self, module: &str) -> Diagnostic {
+        self.module.get_or_insert_with(|| module.to_string());
+        self
+    }
+}
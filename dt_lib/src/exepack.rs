@@ -0,0 +1,467 @@
+use crate::linker::Relocation;
+use crate::mzexe::MzExe;
+
+// An EXE packed the way /EXEPACK packs one. The load image is compressed
+// with run-length encoding and followed by a header, the stub which
+// unpacks it, and the relocations. DOS starts the stub, which moves
+// itself out of the way, expands the image in place from the top down,
+// relocates it and then jumps to the program's real entry point.
+//
+// The header is at the start of the stub's segment:
+//
+// 00  real IP          08  real SP
+// 02  real CS          0A  real SS
+// 04  load segment     0C  paragraphs of unpacked image
+// 06  size of header,  0E  skip length, always 1
+//     stub and         10  signature "RB"
+//     relocations
+//
+const HEADER_SIZE: usize = 0x12;
+const SIGNATURE: u16 = 0x4252;
+
+// The compressed image is read backwards from its end, past any padding
+// bytes of FF. Each command is a byte ending a count word: a fill, with
+// the byte to fill with before the count, or a copy, with the bytes to
+// copy before the count. The bottom bit marks the last command; anything
+// below it was left as it is.
+//
+const FILL: u8 = 0xb0;
+const COPY: u8 = 0xb2;
+const LAST: u8 = 0x01;
+const PADDING: u8 = 0xff;
+
+// The stub keeps 64K-16 bytes of room below its pointers before reading
+// each command, so no command may be longer than that less the command
+// itself. A run shorter than MIN_FILL costs more as a fill than as part
+// of a copy.
+//
+const MAX_COUNT: usize = 0xffe0;
+const MIN_FILL: usize = 8;
+
+// Room for the stub's stack, above its relocated copy
+//
+const STACK_SIZE: usize = 0x100;
+
+// The unpacking stub, which follows the header. The stack pointer it
+// sets once it has moved, the operand of the MOV SP at 003B, is filled
+// in when the file is packed.
+//
+const STACK_PATCH: usize = 0x3b - HEADER_SIZE;
+
+const STUB: [u8; STUB_SIZE] = [
+    // start:
+    0x8b, 0xe8,                             // mov bp, ax
+    0x8c, 0xc0,                             // mov ax, es
+    0x05, 0x10, 0x00,                       // add ax, 0010h
+    0x0e,                                   // push cs
+    0x1f,                                   // pop ds
+    0xa3, 0x04, 0x00,                       // mov [0004], ax
+    0x03, 0x06, 0x0c, 0x00,                 // add ax, [000C]
+    0x8e, 0xc0,                             // mov es, ax
+    0x8b, 0x0e, 0x06, 0x00,                 // mov cx, [0006]
+    0x8b, 0xf1,                             // mov si, cx
+    0x4e,                                   // dec si
+    0x8b, 0xfe,                             // mov di, si
+    0xfd,                                   // std
+    0xf3, 0xa4,                             // rep movsb
+    0x50,                                   // push ax
+    0xb8, 0x36, 0x00,                       // mov ax, relocated
+    0x50,                                   // push ax
+    0xcb,                                   // retf
+    // relocated:
+    0x8c, 0xc8,                             // mov ax, cs
+    0x8e, 0xd0,                             // mov ss, ax
+    0xbc, 0x00, 0x00,                       // mov sp, STACK
+    0x8c, 0xdb,                             // mov bx, ds
+    0x4b,                                   // dec bx
+    0x8e, 0xdb,                             // mov ds, bx
+    0xbe, 0x0f, 0x00,                       // mov si, 000Fh
+    0x8c, 0xc3,                             // mov bx, es
+    0x4b,                                   // dec bx
+    0x8e, 0xc3,                             // mov es, bx
+    0xbf, 0x0f, 0x00,                       // mov di, 000Fh
+    // pad:
+    0xac,                                   // lodsb
+    0x3c, 0xff,                             // cmp al, 0FFh
+    0x74, 0xfb,                             // je pad
+    0x46,                                   // inc si
+    // next:
+    0xb1, 0x04,                             // mov cl, 4
+    0x8b, 0xc6,                             // mov ax, si
+    0xd3, 0xe8,                             // shr ax, cl
+    0x8c, 0xdb,                             // mov bx, ds
+    0x03, 0xc3,                             // add ax, bx
+    0x83, 0xe6, 0x0f,                       // and si, 000Fh
+    0x3d, 0x00, 0x10,                       // cmp ax, 1000h
+    0x73, 0x08,                             // jae big_si
+    0xd3, 0xe0,                             // shl ax, cl
+    0x03, 0xf0,                             // add si, ax
+    0x33, 0xc0,                             // xor ax, ax
+    0xeb, 0x06,                             // jmp short norm_si
+    // big_si:
+    0x2d, 0xff, 0x0f,                       // sub ax, 0FFFh
+    0x83, 0xc6, 0xf0,                       // add si, 0FFF0h
+    // norm_si:
+    0x8e, 0xd8,                             // mov ds, ax
+    0x8b, 0xc7,                             // mov ax, di
+    0xd3, 0xe8,                             // shr ax, cl
+    0x8c, 0xc3,                             // mov bx, es
+    0x03, 0xc3,                             // add ax, bx
+    0x83, 0xe7, 0x0f,                       // and di, 000Fh
+    0x3d, 0x00, 0x10,                       // cmp ax, 1000h
+    0x73, 0x08,                             // jae big_di
+    0xd3, 0xe0,                             // shl ax, cl
+    0x03, 0xf8,                             // add di, ax
+    0x33, 0xc0,                             // xor ax, ax
+    0xeb, 0x06,                             // jmp short norm_di
+    // big_di:
+    0x2d, 0xff, 0x0f,                       // sub ax, 0FFFh
+    0x83, 0xc7, 0xf0,                       // add di, 0FFF0h
+    // norm_di:
+    0x8e, 0xc0,                             // mov es, ax
+    0xac,                                   // lodsb
+    0x8a, 0xd0,                             // mov dl, al
+    0x4e,                                   // dec si
+    0xad,                                   // lodsw
+    0x8b, 0xc8,                             // mov cx, ax
+    0x46,                                   // inc si
+    0x8a, 0xc2,                             // mov al, dl
+    0x24, 0xfe,                             // and al, 0FEh
+    0x3c, 0xb0,                             // cmp al, 0B0h
+    0x75, 0x05,                             // jne copy
+    0xac,                                   // lodsb
+    0xf3, 0xaa,                             // rep stosb
+    0xeb, 0x06,                             // jmp short check
+    // copy:
+    0x3c, 0xb2,                             // cmp al, 0B2h
+    0x75, 0x50,                             // jne corrupt
+    0xf3, 0xa4,                             // rep movsb
+    // check:
+    0xf6, 0xc2, 0x01,                       // test dl, 1
+    0x74, 0x9e,                             // jz next
+    0x0e,                                   // push cs
+    0x1f,                                   // pop ds
+    0xbe, 0x26, 0x01,                       // mov si, relocs
+    0xfc,                                   // cld
+    0x8b, 0x16, 0x04, 0x00,                 // mov dx, [0004]
+    0x33, 0xdb,                             // xor bx, bx
+    // part:
+    0xad,                                   // lodsw
+    0x8b, 0xc8,                             // mov cx, ax
+    0xe3, 0x0e,                             // jcxz next_part
+    0x8b, 0xc3,                             // mov ax, bx
+    0x03, 0xc2,                             // add ax, dx
+    0x8e, 0xc0,                             // mov es, ax
+    // entry:
+    0xad,                                   // lodsw
+    0x8b, 0xf8,                             // mov di, ax
+    0x26, 0x01, 0x15,                       // add es:[di], dx
+    0xe2, 0xf8,                             // loop entry
+    // next_part:
+    0x81, 0xc3, 0x00, 0x10,                 // add bx, 1000h
+    0x75, 0xe7,                             // jnz part
+    0x8b, 0x1e, 0x0a, 0x00,                 // mov bx, [000A]
+    0x03, 0xda,                             // add bx, dx
+    0x8b, 0x0e, 0x08, 0x00,                 // mov cx, [0008]
+    0xa1, 0x02, 0x00,                       // mov ax, [0002]
+    0x03, 0xc2,                             // add ax, dx
+    0xa3, 0x02, 0x00,                       // mov [0002], ax
+    0x83, 0xea, 0x10,                       // sub dx, 0010h
+    0x8e, 0xc2,                             // mov es, dx
+    0x8e, 0xda,                             // mov ds, dx
+    0x8e, 0xd3,                             // mov ss, bx
+    0x8b, 0xe1,                             // mov sp, cx
+    0x8b, 0xc5,                             // mov ax, bp
+    0x2e, 0xff, 0x2e, 0x00, 0x00,           // jmp far cs:[0000]
+    // corrupt:
+    0x0e,                                   // push cs
+    0x1f,                                   // pop ds
+    0xb4, 0x09,                             // mov ah, 09h
+    0xba, 0x0c, 0x01,                       // mov dx, message
+    0xcd, 0x21,                             // int 21h
+    0xb8, 0xff, 0x4c,                       // mov ax, 4CFFh
+    0xcd, 0x21,                             // int 21h
+    // message:
+    // db "packed image is corrupt", 13, 10, "$"
+    0x70, 0x61, 0x63, 0x6b, 0x65, 0x64, 0x20, 0x69, 0x6d, 0x61, 0x67, 0x65, 0x20, 0x69, 0x73, 0x20, 0x63, 0x6f, 0x72, 0x72, 0x75, 0x70, 0x74, 0x0d, 0x0a, 0x24,
+    // relocs:
+];
+const STUB_SIZE: usize = 0x114;
+
+// A command, covering `len` bytes of the image from `start`
+//
+#[derive(Debug)]
+struct Command {
+    fill: bool,
+    start: usize,
+    len: usize,
+}
+
+impl Command {
+    // How many bytes the command takes up in the packed image
+    //
+    fn packed_len(&self) -> usize {
+        match self.fill {
+            true => 4,
+            false => self.len + 3,
+        }
+    }
+}
+
+// Split the image into runs to fill and the bytes between them to copy
+//
+fn commands(image: &[u8]) -> Vec<Command> {
+    let mut commands = Vec::new();
+    let mut copy_from = 0;
+    let mut pos = 0;
+
+    let mut push = |fill: bool, start: usize, end: usize| {
+        for start in (start..end).step_by(MAX_COUNT) {
+            commands.push(Command{ fill, start, len: (end - start).min(MAX_COUNT) });
+        }
+    };
+
+    while pos < image.len() {
+        let run = image[pos..].iter().take_while(|byte| **byte == image[pos]).count();
+        if run >= MIN_FILL {
+            push(false, copy_from, pos);
+            push(true, pos, pos + run);
+            copy_from = pos + run;
+        }
+        pos += run;
+    }
+    push(false, copy_from, image.len());
+
+    commands
+}
+
+// Pack an EXE, if packing makes it smaller. The image is padded to whole
+// paragraphs, which is how the stub expands it. Overlays are left as they
+// are.
+//
+pub fn pack(exe: &MzExe) -> Option<MzExe> {
+    let paragraphs = exe.image.len().div_ceil(16);
+    let mut image = exe.image.clone();
+    image.resize(paragraphs * 16, 0);
+
+    let commands = commands(&image);
+
+    // Expanding in place from the top down only works if every command
+    // writes above what's still to be read: below any command, the packed
+    // bytes must be no more than the bytes they unpack to. Where that
+    // fails, the commands up to the lowest point are left unpacked.
+    //
+    let mut balance = 0isize;
+    let mut lowest = (0, 0);
+    for (index, command) in commands.iter().enumerate().take(commands.len().saturating_sub(1)) {
+        balance += command.len as isize - command.packed_len() as isize;
+        if balance <= lowest.1 {
+            lowest = (index + 1, balance);
+        }
+    }
+
+    let stream = &commands[lowest.0..];
+    let unpacked = stream.first().map_or(image.len(), |command| command.start);
+
+    let mut data = image[..unpacked].to_vec();
+    for (index, command) in stream.iter().enumerate() {
+        let end = command.start + command.len;
+        match command.fill {
+            true => data.push(image[command.start]),
+            false => data.extend(&image[command.start..end]),
+        }
+        data.extend((command.len as u16).to_le_bytes());
+        data.push(if command.fill { FILL } else { COPY } | if index == 0 { LAST } else { 0 });
+    }
+
+    if stream.is_empty() || data.len() > image.len() {
+        return None;
+    }
+
+    data.resize(data.len().div_ceil(16) * 16, PADDING);
+    let header_paragraph = data.len() / 16;
+
+    // The relocations, as a count and offsets for each 64K of the image
+    //
+    let mut parts = vec![Vec::new(); 16];
+    for reloc in &exe.relocations {
+        let linear = reloc.segment as usize * 16 + reloc.offset as usize;
+        parts.get_mut(linear >> 16)?.push(linear as u16);
+    }
+
+    let mut relocations = Vec::new();
+    for part in parts {
+        relocations.extend((part.len() as u16).to_le_bytes());
+        relocations.extend(part.iter().flat_map(|offset| offset.to_le_bytes()));
+    }
+
+    let size = HEADER_SIZE + STUB_SIZE + relocations.len();
+    let stub_paragraphs = size.div_ceil(16);
+    let sp = stub_paragraphs * 16 + STACK_SIZE;
+
+    let mut stub = STUB;
+    stub[STACK_PATCH..STACK_PATCH + 2].copy_from_slice(&(sp as u16).to_le_bytes());
+
+    let header = [exe.ip, exe.cs, 0, size as u16, exe.sp, exe.ss, paragraphs as u16, 1, SIGNATURE];
+    data.extend(header.iter().flat_map(|word| word.to_le_bytes()));
+    data.extend(stub);
+    data.extend(relocations);
+
+    // The stub and its stack are moved to the end of the unpacked image,
+    // and the program needs its own memory past that
+    //
+    let needed = (paragraphs + exe.min_alloc as usize).max(paragraphs + stub_paragraphs + STACK_SIZE / 16);
+    let loaded = data.len().div_ceil(16);
+    let min_alloc = needed.saturating_sub(loaded).min(0xffff) as u16;
+
+    if data.len() >= exe.image.len() + exe.relocations.len() * 4 {
+        return None;
+    }
+
+    Some(MzExe{
+        image: data,
+        relocations: Vec::new(),
+        min_alloc,
+        max_alloc: exe.max_alloc.max(min_alloc),
+        ss: header_paragraph as u16,
+        sp: sp as u16,
+        cs: header_paragraph as u16,
+        ip: HEADER_SIZE as u16,
+        overlays: exe.overlays.clone(),
+    })
+}
+
+// Unpack an EXE packed by `pack`, the way the stub does, giving back the
+// image and its relocations
+//
+pub fn unpack(exe: &MzExe) -> Option<(Vec<u8>, Vec<Relocation>)> {
+    let header = exe.cs as usize * 16;
+    let word = |at: usize| exe.image.get(at..at + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+
+    if word(header + 0x10)? != SIGNATURE {
+        return None;
+    }
+
+    // src and dest are one past the next byte to read and to write, both
+    // in the one buffer, as they are in memory
+    //
+    let mut image = exe.image[..header].to_vec();
+    image.resize(word(header + 0x0c)? as usize * 16, 0);
+
+    let mut src = header;
+    while src > 0 && image[src - 1] == PADDING {
+        src -= 1;
+    }
+    let mut dest = image.len();
+
+    loop {
+        if src < 4 {
+            return None;
+        }
+        let command = image[src - 1];
+        let count = u16::from_le_bytes([image[src - 3], image[src - 2]]) as usize;
+        src -= 3;
+
+        if count > dest {
+            return None;
+        }
+
+        // nothing may be written over what's still to be read
+        //
+        match command & !LAST {
+            FILL if dest - count >= src - 1 => {
+                src -= 1;
+                let byte = image[src];
+                image[dest - count..dest].fill(byte);
+                dest -= count;
+            },
+            COPY if dest >= src && count <= src => for _ in 0..count {
+                src -= 1;
+                dest -= 1;
+                image[dest] = image[src];
+            },
+            _ => return None,
+        }
+
+        if command & LAST != 0 {
+            break;
+        }
+    }
+
+    let mut relocations = Vec::new();
+    let mut at = header + HEADER_SIZE + STUB_SIZE;
+    for part in 0..16 {
+        let count = word(at)?;
+        at += 2;
+        for _ in 0..count {
+            let linear = part * 0x10000 + word(at)? as usize;
+            relocations.push(Relocation{ segment: (linear >> 4) as u16, offset: (linear & 0xf) as u16 });
+            at += 2;
+        }
+    }
+
+    Some((image, relocations))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn exe(image: Vec<u8>, relocations: Vec<Relocation>) -> MzExe {
+        MzExe{ image, relocations, min_alloc: 0x40, max_alloc: 0xffff, ss: 0x20, sp: 0x100, cs: 0, ip: 0, overlays: Vec::new() }
+    }
+
+    // Bytes which don't repeat, as code mostly doesn't
+    //
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn test_pack_round_trip() {
+        let mut image = noise(0x123, 1);
+        image.extend(vec![0; 0x2000]);
+        image.extend(noise(0x40, 2));
+        image.extend(vec![0x90; 0x10]);
+        image.extend(noise(5, 3));
+        image.extend(vec![0xcc; 0x18000]);
+        image.extend(noise(0x11, 4));
+
+        let relocations = vec![Relocation{ segment: 0, offset: 3 }, Relocation{ segment: 0x1234, offset: 0x10 }];
+        let packed = pack(&exe(image.clone(), relocations)).unwrap();
+        assert!(packed.image.len() < 0x800);
+        assert!(packed.relocations.is_empty());
+        assert_eq!((packed.cs, packed.ip), (packed.ss, HEADER_SIZE as u16));
+
+        let (unpacked, relocations) = unpack(&packed).unwrap();
+        image.resize(image.len().div_ceil(16) * 16, 0);
+        assert_eq!(unpacked, image);
+        assert_eq!(relocations, vec![Relocation{ segment: 0, offset: 3 }, Relocation{ segment: 0x1235, offset: 0 }]);
+
+        // the program gets the memory it asked for past its image
+        //
+        let loaded = packed.image.len().div_ceil(16) + packed.min_alloc as usize;
+        assert!(loaded >= image.len() / 16 + 0x40);
+    }
+
+    #[test]
+    fn test_pack_leaves_start_unpacked() {
+        // a copy at the bottom would be overwritten by the fill above it
+        //
+        let mut image = noise(0x30, 5);
+        image.extend(vec![0; 0x1000]);
+
+        let packed = pack(&exe(image.clone(), Vec::new())).unwrap();
+        assert_eq!(&packed.image[..0x30], &image[..0x30]);
+        assert_eq!(unpack(&packed).unwrap().0, image);
+    }
+
+    #[test]
+    fn test_pack_incompressible() {
+        assert!(pack(&exe(noise(0x1000, 6), Vec::new())).is_none());
+    }
+}
@@ -1,4 +1,23 @@
+pub mod codepage;
 pub mod error;
+pub mod diagnostics;
 pub mod objfile;
 pub mod libfile;
-
+pub mod libwriter;
+pub mod liblist;
+pub mod libcheck;
+pub mod libdiff;
+pub mod validate;
+pub mod modinfo;
+pub mod objwriter;
+pub mod objbuilder;
+pub mod objpatch;
+pub mod transform;
+pub mod thunks;
+pub mod omfgen;
+pub mod linker;
+pub mod mzexe;
+pub mod symfile;
+pub mod linkscript;
+pub mod linkmap;
+pub mod exepack;
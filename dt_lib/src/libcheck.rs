@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::Error as LibError;
+use crate::libfile::Parser;
+use crate::libwriter::LibMember;
+use crate::objfile::ParseOptions;
+
+// A disagreement between a library's dictionary and its modules
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum DictProblem {
+    // A public which looking up through the dictionary doesn't find
+    Missing{ symbol: String, module: String },
+
+    // A public the dictionary leads to a page where no module defining
+    // it starts
+    WrongModule{ symbol: String, page: usize },
+
+    // A dictionary entry for a name which no module defines
+    Stale{ symbol: String, page: usize },
+}
+
+impl fmt::Display for DictProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DictProblem::Missing{ symbol, module } => write!(f, "{} (defined in {}) is not in the dictionary", symbol, module),
+            DictProblem::WrongModule{ symbol, page } => write!(f, "{} is entered at page {:04x}, which doesn't define it", symbol, page),
+            DictProblem::Stale{ symbol, page } => write!(f, "{} is entered at page {:04x} but no module defines it", symbol, page),
+        }
+    }
+}
+
+// Cross-check the dictionary against the publics the modules actually
+// define. Every public must be found by a dictionary lookup, at a module
+// which defines it (with duplicates, any one of them will do), and every
+// dictionary entry must name something which is defined. Module name
+// entries are checked if they're there, but not required, since not
+// every librarian writes them.
+//
+pub fn verify_dictionary(lib: &Parser, options: &ParseOptions) -> Result<Vec<DictProblem>, LibError> {
+    let key = |name: &str| if lib.case_sensitive { name.to_string() } else { name.to_ascii_lowercase() };
+    let mut defined: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut publics = Vec::new();
+
+    let members = lib.map_modules(|module| {
+        let member = LibMember::from_obj(&module.name, module.image, options)
+            .map_err(|err| err.in_module(&module.name))?;
+        Ok((module, member))
+    })?;
+
+    for (module, member) in members {
+        defined.entry(key(&format!("{}!", module.name))).or_default().push(module.page);
+        for public in member.publics {
+            defined.entry(key(&public)).or_default().push(module.page);
+            publics.push((public, module.name.clone()));
+        }
+    }
+
+    let mut problems = Vec::new();
+
+    for (symbol, module) in publics {
+        match lib.find_symbol_obj(&symbol)? {
+            None => problems.push(DictProblem::Missing{ symbol, module }),
+            Some(page) if !defined[&key(&symbol)].contains(&page) => problems.push(DictProblem::WrongModule{ symbol, page }),
+            Some(_) => (),
+        }
+    }
+
+    for entry in lib.dictionary()? {
+        match defined.get(&key(&entry.name)) {
+            None => problems.push(DictProblem::Stale{ symbol: entry.name, page: entry.page }),
+            Some(pages) if entry.is_module() && !pages.contains(&entry.page) => {
+                problems.push(DictProblem::WrongModule{ symbol: entry.name, page: entry.page });
+            },
+            Some(_) => (),
+        }
+    }
+
+    Ok(problems)
+}
+
+// Something wrong with the way a library's modules are laid out
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum IntegrityProblem {
+    // A module which doesn't start on a page boundary, so no dictionary
+    // entry can point at it
+    Misaligned{ module: String, offset: usize },
+
+    // A module which runs into the next module, the dictionary or the
+    // end of the file before its MODEND
+    NoModend{ module: String, offset: usize },
+
+    // A record whose bytes don't sum to zero, and whose checksum byte
+    // isn't zero to say there's no checksum
+    BadChecksum{ module: String, offset: usize },
+
+    // Something other than zeros where there should only be padding
+    BadPadding{ offset: usize },
+}
+
+impl fmt::Display for IntegrityProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityProblem::Misaligned{ module, offset } => write!(f, "{:08x}: module {} does not start on a page boundary", offset, module),
+            IntegrityProblem::NoModend{ module, offset } => write!(f, "{:08x}: module {} has no MODEND", offset, module),
+            IntegrityProblem::BadChecksum{ module, offset } => write!(f, "{:08x}: record in module {} has a bad checksum", offset, module),
+            IntegrityProblem::BadPadding{ offset } => write!(f, "{:08x}: padding is not all zeros", offset),
+        }
+    }
+}
+
+// What check_library found: how many modules there are, and what's
+// wrong with them, in the order they're stored
+//
+#[derive(Debug)]
+pub struct IntegrityReport {
+    pub modules: usize,
+    pub problems: Vec<IntegrityProblem>,
+}
+
+impl IntegrityReport {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+const THEADR: u8 = 0x80;
+const LHEADR: u8 = 0x82;
+const COMENT: u8 = 0x88;
+const MODEND: u8 = 0x8a;
+const MODEND32: u8 = 0x8b;
+const LIBMOD: u8 = 0xa3;
+const LIB_END: u8 = 0xf1;
+
+// Check the structure of a library, short of what's in the records:
+// that every module starts on a page boundary and ends in a MODEND,
+// that every record's checksum is right, and that there's nothing but
+// zeros between the modules and in the end record before the
+// dictionary. Unlike walking the modules with the parser, this carries
+// on past a problem, so everything wrong is reported at once.
+//
+pub fn check_library(lib: &Parser) -> IntegrityReport {
+    let image = lib.image;
+    let end = lib.modules_end().min(image.len());
+    let mut report = IntegrityReport{ modules: 0, problems: Vec::new() };
+    let mut ptr = lib.pagesize;
+
+    while ptr < end {
+        match image[ptr] {
+            // WLIB may leave whole pages of zeros between modules, so
+            // padding doesn't have to stop at the next page
+            //
+            0 => ptr = image[ptr..end].iter().position(|&byte| byte != 0).map_or(end, |n| ptr + n),
+            THEADR | LHEADR => {
+                report.modules += 1;
+                ptr = check_module(lib, ptr, end, &mut report.problems);
+            },
+            LIB_END => {
+                check_lib_end(lib, ptr, &mut report.problems);
+                break;
+            },
+            _ => {
+                report.problems.push(IntegrityProblem::BadPadding{ offset: ptr });
+                ptr = (ptr + lib.pagesize) & !(lib.pagesize - 1);
+            },
+        }
+    }
+
+    report
+}
+
+// Check the records of the module at `start`, returning where whatever
+// follows it starts
+//
+fn check_module(lib: &Parser, start: usize, end: usize, problems: &mut Vec<IntegrityProblem>) -> usize {
+    let image = lib.image;
+    let mut ptr = start;
+    let mut theadr = None;
+    let mut libmod = None;
+    let mut bad_checksums = Vec::new();
+    let mut modend = false;
+
+    while ptr < end {
+        let rectype = image[ptr];
+
+        // a new module, padding or the end record before a MODEND
+        //
+        if ptr != start && matches!(rectype, THEADR | LHEADR | LIB_END | 0) {
+            break;
+        }
+
+        let reclen = match image.get(ptr + 1..ptr + 3) {
+            Some(len) => u16::from_le_bytes([len[0], len[1]]) as usize,
+            None => {
+                ptr = end;
+                break;
+            },
+        };
+
+        let Some(record) = image.get(ptr..ptr + 3 + reclen).filter(|_| ptr + 3 + reclen <= end) else {
+            ptr = end;
+            break;
+        };
+
+        let body = &record[3..(record.len() - 1).max(3)];
+        match rectype {
+            THEADR | LHEADR => theadr = name(body),
+            COMENT if body.get(1) == Some(&LIBMOD) => libmod = name(&body[2..]),
+            _ => (),
+        }
+
+        let sum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if sum != 0 && record.last() != Some(&0) {
+            bad_checksums.push(ptr);
+        }
+
+        ptr += record.len();
+
+        if rectype == MODEND || rectype == MODEND32 {
+            modend = true;
+            break;
+        }
+    }
+
+    let module = libmod.or(theadr)
+        .map(|name| lib.codepage.decode(name).unwrap_or_else(|_| String::from_utf8_lossy(name).to_string()))
+        .unwrap_or_default();
+
+    if !start.is_multiple_of(lib.pagesize) {
+        problems.push(IntegrityProblem::Misaligned{ module: module.clone(), offset: start });
+    }
+
+    for offset in bad_checksums {
+        problems.push(IntegrityProblem::BadChecksum{ module: module.clone(), offset });
+    }
+
+    if !modend {
+        problems.push(IntegrityProblem::NoModend{ module, offset: start });
+    }
+
+    ptr
+}
+
+// The end record pads out to the dictionary, which it must reach
+// exactly, with zeros
+//
+fn check_lib_end(lib: &Parser, ptr: usize, problems: &mut Vec<IntegrityProblem>) {
+    let image = lib.image;
+    let record_end = image.get(ptr + 1..ptr + 3)
+        .map(|len| ptr + 3 + u16::from_le_bytes([len[0], len[1]]) as usize);
+
+    match record_end {
+        Some(record_end) if record_end <= image.len() && (lib.dictblocks == 0 || record_end == lib.dictoffset) => {
+            if let Some(at) = image[ptr + 3..record_end].iter().position(|&byte| byte != 0) {
+                problems.push(IntegrityProblem::BadPadding{ offset: ptr + 3 + at });
+            }
+        },
+        _ => problems.push(IntegrityProblem::BadPadding{ offset: ptr }),
+    }
+}
+
+fn name(body: &[u8]) -> Option<&[u8]> {
+    body.split_first().and_then(|(len, rest)| rest.get(..*len as usize))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::libwriter::LibWriter;
+    use crate::objbuilder::*;
+
+    fn library() -> Vec<u8> {
+        let mut writer = LibWriter::new();
+
+        for (module, publics) in [("a", vec!["_one", "_two"]), ("b", vec!["_three"])] {
+            let mut builder = ObjBuilder::new(module);
+            let text = builder.segment(SegmentSpec::new("_TEXT", "CODE"));
+            for (i, name) in publics.iter().enumerate() {
+                builder.public(name, text, i as u32);
+            }
+            builder.data(text, 0, &[0xc3, 0xc3]).unwrap();
+            writer.add(LibMember::from_obj(module, &builder.build().unwrap(), &ParseOptions::default()).unwrap()).unwrap();
+        }
+
+        writer.write().unwrap()
+    }
+
+    // The offset in the image of the dictionary entry for `name`
+    //
+    fn entry(image: &[u8], name: &[u8]) -> usize {
+        let lib = Parser::new(image).unwrap();
+        let dict = &image[lib.dictoffset..];
+        let at = dict.windows(name.len() + 1)
+            .position(|window| window[0] as usize == name.len() && &window[1..] == name)
+            .unwrap();
+        lib.dictoffset + at
+    }
+
+    #[test]
+    fn test_written_library_verifies() {
+        let image = library();
+        let lib = Parser::new(&image).unwrap();
+        assert_eq!(verify_dictionary(&lib, &ParseOptions::default()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_wrong_page() {
+        let mut image = library();
+        let at = entry(&image, b"_three");
+        image[at + 7] = 0x01;
+
+        let lib = Parser::new(&image).unwrap();
+        assert_eq!(verify_dictionary(&lib, &ParseOptions::default()).unwrap(), vec![
+            DictProblem::WrongModule{ symbol: "_three".to_string(), page: 1 },
+        ]);
+    }
+
+    #[test]
+    fn test_renamed_entry() {
+        let mut image = library();
+        let at = entry(&image, b"_two");
+        image[at + 4] = b'x';
+
+        let lib = Parser::new(&image).unwrap();
+        let problems = verify_dictionary(&lib, &ParseOptions::default()).unwrap();
+        assert_eq!(problems, vec![
+            DictProblem::Missing{ symbol: "_two".to_string(), module: "a".to_string() },
+            DictProblem::Stale{ symbol: "_twx".to_string(), page: 1 },
+        ]);
+        assert_eq!(format!("{}", problems[1]), "_twx is entered at page 0001 but no module defines it");
+    }
+
+    #[test]
+    fn test_written_library_is_intact() {
+        let image = library();
+        let report = check_library(&Parser::new(&image).unwrap());
+        assert_eq!(report.modules, 2);
+        assert!(report.is_clean(), "report has {:?}", report.problems);
+    }
+
+    #[test]
+    fn test_integrity_problems() {
+        let mut image = library();
+        let (a, b) = {
+            let lib = Parser::new(&image).unwrap();
+            let modules: Vec<(usize, usize)> = lib.modules().map(|module| {
+                let module = module.unwrap();
+                (module.offset, module.len())
+            }).collect();
+            (modules[0], modules[1])
+        };
+
+        // break the THEADR checksum of a, scribble in the padding after
+        // it, and turn b's MODEND into a comment (with its checksum fixed
+        // to match)
+        //
+        let theadr_end = a.0 + 3 + image[a.0 + 1] as usize;
+        image[theadr_end - 1] ^= 0x01;
+        image[a.0 + a.1 + 1] = 0x55;
+        image[b.0 + b.1 - 5] = COMENT;
+        image[b.0 + b.1 - 1] = image[b.0 + b.1 - 1].wrapping_add(MODEND - COMENT);
+
+        let report = check_library(&Parser::new(&image).unwrap());
+        assert_eq!(report.modules, 2);
+        assert_eq!(report.problems, vec![
+            IntegrityProblem::BadChecksum{ module: "a".to_string(), offset: a.0 },
+            IntegrityProblem::BadPadding{ offset: a.0 + a.1 + 1 },
+            IntegrityProblem::NoModend{ module: "b".to_string(), offset: b.0 },
+        ]);
+        assert_eq!(format!("{}", report.problems[2]), format!("{:08x}: module b has no MODEND", b.0));
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use crate::error::Error as LibError;
+use crate::libfile::{self, LibModule, Parser};
+use crate::objfile::{self, ParseOptions, Record};
+
+// How a module present in both libraries differs between them. The
+// symbol lists are sorted, and may all be empty if only the code or data
+// changed.
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ModuleChange {
+    pub name: String,
+    pub old_length: usize,
+    pub new_length: usize,
+
+    pub publics_added: Vec<String>,
+    pub publics_removed: Vec<String>,
+    pub externs_added: Vec<String>,
+    pub externs_removed: Vec<String>,
+}
+
+// Everything which differs between two libraries. Modules are matched
+// by name, ignoring case; added and changed modules are in the order
+// the new library stores them, removed ones in the order of the old.
+//
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub struct LibDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<ModuleChange>,
+}
+
+impl LibDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// The names a module asks for from elsewhere
+//
+fn module_externs(obj: &[u8], options: &ParseOptions) -> Result<Vec<String>, LibError> {
+    let mut parser = objfile::Parser::with_options(obj, options.clone());
+    let mut externs = Vec::new();
+
+    loop {
+        match parser.next()? {
+            Record::None | Record::MODEND{ .. } => break,
+            Record::EXTDEF{ externs: defs } => externs.extend(defs.into_iter().map(|ext| ext.name)),
+            _ => (),
+        }
+    }
+
+    Ok(externs)
+}
+
+// What's in `new` and not in `old`, and what's in `old` and not in
+// `new`, both sorted
+//
+fn set_difference(mut old: Vec<String>, mut new: Vec<String>) -> (Vec<String>, Vec<String>) {
+    old.sort();
+    old.dedup();
+    new.sort();
+    new.dedup();
+
+    let added = new.iter().filter(|name| old.binary_search(name).is_err()).cloned().collect();
+    let removed = old.iter().filter(|name| new.binary_search(name).is_err()).cloned().collect();
+    (added, removed)
+}
+
+fn compare_modules(old: &LibModule, new: &LibModule, options: &ParseOptions) -> Result<Option<ModuleChange>, LibError> {
+    if old.image == new.image {
+        return Ok(None);
+    }
+
+    let symbols = |module: &LibModule| -> Result<(Vec<String>, Vec<String>), LibError> {
+        let publics = libfile::module_publics(module.image, options).map_err(|err| err.in_module(&module.name))?;
+        let externs = module_externs(module.image, options).map_err(|err| err.in_module(&module.name))?;
+        Ok((publics, externs))
+    };
+
+    let (old_publics, old_externs) = symbols(old)?;
+    let (new_publics, new_externs) = symbols(new)?;
+    let (publics_added, publics_removed) = set_difference(old_publics, new_publics);
+    let (externs_added, externs_removed) = set_difference(old_externs, new_externs);
+
+    Ok(Some(ModuleChange{
+        name: new.name.clone(),
+        old_length: old.len(),
+        new_length: new.len(),
+        publics_added,
+        publics_removed,
+        externs_added,
+        externs_removed,
+    }))
+}
+
+// Compare two libraries module by module, and the symbols of the modules
+// which have changed
+//
+pub fn diff_libraries(old: &Parser, new: &Parser, options: &ParseOptions) -> Result<LibDiff, LibError> {
+    let old_modules = old.modules().collect::<Result<Vec<_>, LibError>>()?;
+    let new_modules = new.modules().collect::<Result<Vec<_>, LibError>>()?;
+
+    let by_name: HashMap<String, &LibModule> = old_modules.iter()
+        .map(|module| (module.name.to_ascii_lowercase(), module))
+        .collect();
+    let mut diff = LibDiff::default();
+
+    for module in &new_modules {
+        match by_name.get(&module.name.to_ascii_lowercase()) {
+            None => diff.added.push(module.name.clone()),
+            Some(old_module) => diff.changed.extend(compare_modules(old_module, module, options)?),
+        }
+    }
+
+    diff.removed = old_modules.iter()
+        .filter(|old_module| !new_modules.iter().any(|module| module.name.eq_ignore_ascii_case(&old_module.name)))
+        .map(|module| module.name.clone())
+        .collect();
+
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::libwriter::{LibMember, LibWriter};
+    use crate::objbuilder::*;
+
+    fn member(module: &str, publics: &[&str], externs: &[&str], code: &[u8]) -> LibMember {
+        let mut builder = ObjBuilder::new(module);
+        let text = builder.segment(SegmentSpec::new("_TEXT", "CODE"));
+        for (i, name) in publics.iter().enumerate() {
+            builder.public(name, text, i as u32);
+        }
+        for name in externs {
+            builder.external(name);
+        }
+        builder.data(text, 0, code).unwrap();
+        LibMember::from_obj(module, &builder.build().unwrap(), &ParseOptions::default()).unwrap()
+    }
+
+    fn library(members: Vec<LibMember>) -> Vec<u8> {
+        let mut writer = LibWriter::new();
+        for member in members {
+            writer.add(member).unwrap();
+        }
+        writer.write().unwrap()
+    }
+
+    #[test]
+    fn test_identical_libraries() {
+        let image = library(vec![member("a", &["_a"], &[], &[0xc3])]);
+        let lib = Parser::new(&image).unwrap();
+        assert!(diff_libraries(&lib, &lib, &ParseOptions::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_diff_libraries() {
+        let old = library(vec![
+            member("a", &["_a"], &[], &[0xc3]),
+            member("b", &["_b", "_b2"], &["_a"], &[0xc3]),
+            member("c", &["_c"], &[], &[0xc3]),
+        ]);
+        let new = library(vec![
+            member("A", &["_a"], &[], &[0xc3]),
+            member("b", &["_b", "_b3"], &["_puts"], &[0xc3]),
+            member("c", &["_c"], &[], &[0x90, 0xc3]),
+            member("d", &["_d"], &[], &[0xc3]),
+        ]);
+        let old = Parser::new(&old).unwrap();
+        let new = Parser::new(&new).unwrap();
+
+        let diff = diff_libraries(&old, &new, &ParseOptions::default()).unwrap();
+        assert_eq!(diff.added, vec!["d".to_string()]);
+        assert!(diff.removed.is_empty());
+
+        // a's THEADR and LIBMOD change case, so its image does too
+        //
+        let names: Vec<&str> = diff.changed.iter().map(|change| change.name.as_str()).collect();
+        assert_eq!(names, vec!["A", "b", "c"]);
+
+        let b = &diff.changed[1];
+        assert_eq!(b.publics_added, vec!["_b3".to_string()]);
+        assert_eq!(b.publics_removed, vec!["_b2".to_string()]);
+        assert_eq!(b.externs_added, vec!["_puts".to_string()]);
+        assert_eq!(b.externs_removed, vec!["_a".to_string()]);
+
+        let c = &diff.changed[2];
+        assert!(c.publics_added.is_empty() && c.externs_removed.is_empty());
+        assert_eq!(c.new_length, c.old_length + 1);
+
+        let diff = diff_libraries(&new, &old, &ParseOptions::default()).unwrap();
+        assert_eq!(diff.removed, vec!["d".to_string()]);
+    }
+}
@@ -1,6 +1,99 @@
+use std::cell::OnceCell;
 use std::cmp::{max};
 
+use crate::codepage::Codepage;
 use crate::error::Error as LibError;
+use crate::objfile::{self, Coment, ParseOptions, Record};
+
+// The library header record, which takes up the whole first page
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct LibHeader {
+    // Modules start on multiples of the page size, which is a power of two
+    pub page_size: usize,
+
+    // Where the dictionary starts and how many 512-byte blocks it has
+    pub dict_offset: usize,
+    pub dict_blocks: usize,
+
+    pub flags: u8,
+}
+
+impl LibHeader {
+    // Dictionary lookups are case-sensitive
+    pub const CASE_SENSITIVE: u8 = 0x01;
+
+    const MIN_PAGE_SIZE: usize = 16;
+    const MAX_PAGE_SIZE: usize = 32768;
+
+    pub fn parse(image: &[u8]) -> Result<LibHeader, LibError> {
+        if !Parser::is_lib(image) {
+            return Err(LibError::new("image is not a library"));
+        }
+
+        let page_size = Parser::uint(&image[1..3]) + 3;
+        let dict_offset = Parser::uint(&image[3..7]);
+        let dict_blocks = Parser::uint(&image[7..9]);
+        let flags = image[9];
+
+        if !page_size.is_power_of_two() || !(Self::MIN_PAGE_SIZE..=Self::MAX_PAGE_SIZE).contains(&page_size) {
+            return Err(LibError::new(&format!("library page size {} is not a power of two from 16 to 32768", page_size)));
+        }
+
+        Ok(LibHeader{ page_size, dict_offset, dict_blocks, flags })
+    }
+
+    pub fn case_sensitive(&self) -> bool {
+        (self.flags & Self::CASE_SENSITIVE) != 0
+    }
+
+    // The size of the dictionary in bytes
+    //
+    pub fn dict_size(&self) -> usize {
+        self.dict_blocks * LIB_BLOCK_SIZE
+    }
+}
+
+// A name in the library dictionary and the page of the module defining
+// it
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct DictEntry {
+    pub name: String,
+    pub page: usize,
+}
+
+impl DictEntry {
+    // Module names are entered in the dictionary with a trailing `!`,
+    // which can't appear in a symbol
+    //
+    pub fn is_module(&self) -> bool {
+        self.name.ends_with('!')
+    }
+}
+
+// Which librarian a library looks to have been written by. Microsoft LIB
+// closes the modules with a library end record (F1H) padding out to the
+// dictionary and names each module in a LIBMOD comment; Borland TLIB
+// leaves the gap as plain padding and always writes an extended
+// dictionary; Watcom WLIB writes the end record but no LIBMODs, and may
+// leave out the dictionary entirely. Parsing copes with all of them;
+// this is for reporting and for callers which want to mimic the
+// original.
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum LibFlavor {
+    Microsoft,
+    Borland,
+    Watcom,
+    Unknown,
+}
 
 pub struct ExtDict {
     pub entries: usize,
@@ -9,40 +102,322 @@ pub struct ExtDict {
 
 pub struct Parser<'a> {
     pub image: &'a [u8],
-    pub ptr: usize,
-    pub start: usize,
-    pub next: usize,
     pub pagesize: usize,
     pub dictoffset: usize,
     pub dictblocks: usize,
+
+    // Whether dictionary lookups match case. This starts out as the
+    // header's flag says, but a linker may override it (see ignore_case).
     pub case_sensitive: bool,
+
     pub edict: Option<ExtDict>,
+
+    // Everything in the header record, flags included
+    pub header: LibHeader,
+
+    // How symbol names are stored in the dictionary
+    pub codepage: Codepage,
+}
+
+// A module stored in a library. `name` comes from the LIBMOD comment
+// if there is one (librarians add it, since the THEADR often names the
+// source file), else from the THEADR.
+//
+#[derive(Debug)]
+pub struct LibModule<'a> {
+    pub name: String,
+
+    // Where the module starts, as the page number the dictionary uses
+    // and as a byte offset into the library
+    pub page: usize,
+    pub offset: usize,
+
+    // The module's records, THEADR through MODEND
+    pub image: &'a [u8],
+
+    // Both names the module carries, for callers which care where
+    // `name` came from: the short name from the LIBMOD comment, and
+    // the THEADR's, which is often a source path
+    pub libmod: Option<String>,
+    pub theadr: Option<String>,
+}
+
+impl<'a> LibModule<'a> {
+    pub fn len(&self) -> usize {
+        self.image.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.image.is_empty()
+    }
+}
+
+// A handle on a library module which hasn't been read yet. Making one
+// from a dictionary hit costs nothing; the module's records are parsed
+// the first time they're asked for, and kept.
+//
+#[derive(Debug)]
+pub struct LazyModule<'a> {
+    pub page: usize,
+    pub offset: usize,
+
+    // The library from the start of the module on; parsing stops at the
+    // module's MODEND
+    image: &'a [u8],
+    records: OnceCell<Vec<Record>>,
+}
+
+impl<'a> LazyModule<'a> {
+    pub fn is_loaded(&self) -> bool {
+        self.records.get().is_some()
+    }
+
+    // The module's records, THEADR through MODEND, parsing them if this
+    // is the first time
+    //
+    pub fn records(&self, options: &ParseOptions) -> Result<&[Record], LibError> {
+        if let Some(records) = self.records.get() {
+            return Ok(records);
+        }
+
+        let mut parser = objfile::Parser::with_options(self.image, options.clone());
+        let mut records = Vec::new();
+
+        loop {
+            // errors are placed in the library, not the module
+            //
+            let record = parser.next().map_err(|mut err| {
+                err.offset = err.offset.map(|offset| offset + self.offset);
+                err
+            })?;
+            match record {
+                Record::None => return Err(LibError::with_offset("library module has no MODEND", self.offset)),
+                Record::MODEND{ .. } => {
+                    records.push(record);
+                    break;
+                },
+                _ => records.push(record),
+            }
+        }
+
+        Ok(self.records.get_or_init(|| records))
+    }
+
+    // The module's name, from its LIBMOD comment or else its THEADR
+    //
+    pub fn name(&self, options: &ParseOptions) -> Result<String, LibError> {
+        let records = self.records(options)?;
+
+        let libmod = records.iter().find_map(|record| match record {
+            Record::COMENT{ coment: Coment::Libmod{ name }, .. } => Some(name),
+            _ => None,
+        });
+        let theadr = records.iter().find_map(|record| match record {
+            Record::THEADR{ name } | Record::LHEADR{ name } => Some(name),
+            _ => None,
+        });
+
+        Ok(libmod.or(theadr).cloned().unwrap_or_default())
+    }
 }
 
-struct LibHash {
-    block_x: u16,
-    block_d: u16,
-    bucket_x: u16,
-    bucket_d: u16,
+// The names a module puts in a library dictionary: its PUBDEFs and
+// public COMDATs. Stops at MODEND.
+//
+pub fn module_publics(obj: &[u8], options: &ParseOptions) -> Result<Vec<String>, LibError> {
+    let options = ParseOptions{ merge_comdats: false, ..options.clone() };
+    let mut parser = objfile::Parser::with_options(obj, options);
+    let mut lnames: Vec<String> = Vec::new();
+    let mut publics = Vec::new();
+
+    loop {
+        match parser.next()? {
+            Record::None | Record::MODEND{ .. } => break,
+            Record::LNAMES{ names } => lnames.extend(names),
+            Record::PUBDEF{ publics: defs, .. } => publics.extend(defs.into_iter().map(|public| public.name)),
+            Record::COMDAT{ comdat } if !comdat.local() && !comdat.continuation() => {
+                if let Some(name) = lnames.get(comdat.name.wrapping_sub(1)) {
+                    publics.push(name.clone());
+                }
+            },
+            _ => (),
+        }
+    }
+
+    Ok(publics)
+}
+
+// Iterates the modules of a library. Iteration stops at the dictionary,
+// at padding in front of it, or after the first error.
+//
+pub struct Modules<'p, 'a> {
+    lib: &'p Parser<'a>,
+    ptr: usize,
+    done: bool,
+}
+
+impl<'p, 'a> Modules<'p, 'a> {
+    fn module(&mut self) -> Result<LibModule<'a>, LibError> {
+        let lib = self.lib;
+        let image = lib.image;
+        let start = self.ptr;
+        let mut ptr = start;
+        let mut theadr = None;
+        let mut libmod = None;
+
+        loop {
+            let header = image.get(ptr..ptr + 3)
+                .ok_or_else(|| LibError::with_offset("library module is truncated", ptr))?;
+            let rectype = header[0];
+            let reclen = Parser::uint(&header[1..3]);
+
+            // the body doesn't include the checksum byte
+            //
+            let body = image.get(ptr + 3..ptr + 3 + reclen)
+                .and_then(|body| body.split_last())
+                .map(|(_, body)| body)
+                .ok_or_else(|| LibError::with_offset("library module is truncated", ptr))?;
+
+            match rectype {
+                Parser::THEADR | Parser::LHEADR if theadr.is_none() => theadr = Some(Self::name(body, ptr)?),
+                Parser::COMENT if body.get(1) == Some(&Parser::LIBMOD) => libmod = Some(Self::name(&body[2..], ptr)?),
+                _ => (),
+            }
+
+            ptr += 3 + reclen;
+
+            if rectype == Parser::MODEND || rectype == Parser::MODEND32 {
+                break;
+            }
+        }
+
+        if ptr > lib.modules_end() {
+            return Err(LibError::with_offset("object file ran over into dictionary", start));
+        }
+
+        self.ptr = (ptr + lib.pagesize - 1) & !(lib.pagesize - 1);
+
+        let libmod = libmod.map(|name| lib.codepage.decode(name)).transpose()?;
+        let theadr = theadr.map(|name| lib.codepage.decode(name)).transpose()?;
+        let name = libmod.clone().or_else(|| theadr.clone()).unwrap_or_default();
+
+        Ok(LibModule{
+            name,
+            page: start / lib.pagesize,
+            offset: start,
+            image: &image[start..ptr],
+            libmod,
+            theadr,
+        })
+    }
+
+    fn name(body: &[u8], offset: usize) -> Result<&[u8], LibError> {
+        body.split_first()
+            .and_then(|(len, rest)| rest.get(..*len as usize))
+            .ok_or_else(|| LibError::with_offset("library module name is truncated", offset))
+    }
 }
 
-const LIB_BLOCK_SIZE: usize = 512;
-const LIB_NBUCKETS: usize = 37;
+impl<'p, 'a> Iterator for Modules<'p, 'a> {
+    type Item = Result<LibModule<'a>, LibError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let lib = self.lib;
+        let end = lib.modules_end();
+
+        // WLIB may leave whole pages of zeros between modules
+        //
+        while !self.done && self.ptr < end && lib.image.get(self.ptr) == Some(&0) {
+            self.ptr = (self.ptr + lib.pagesize) & !(lib.pagesize - 1);
+        }
+
+        // If there is no THEADR here, then we've run into padding between
+        // the object files and the dictionary
+        //
+        if self.done || self.ptr >= end || !matches!(lib.image.get(self.ptr), Some(&Parser::THEADR) | Some(&Parser::LHEADR)) {
+            return None;
+        }
+
+        let module = self.module();
+        self.done = module.is_err();
+        Some(module)
+    }
+}
+
+// Where a name goes in a dictionary of a given number of blocks: the
+// block and bucket to try first, and the steps to take through blocks
+// and buckets when that one is taken
+//
+#[derive(Debug)]
+pub struct LibHash {
+    pub block_x: u16,
+    pub block_d: u16,
+    pub bucket_x: u16,
+    pub bucket_d: u16,
+}
+
+impl LibHash {
+    fn rotr(x: u16, bits: usize) -> u16 {
+        ((x) << (16-bits)) | ((x) >> bits)
+    }
+
+    fn rotl(x: u16, bits: usize) -> u16 {
+        ((x) << bits) | ((x) >> (16-bits))
+    }
+
+    pub fn new(name: &[u8], blocks: usize) -> LibHash {
+        const BLANK: u16 = 0x20u16;
+        let mut len = name.len() as u16;
+
+        let mut block_x = len | BLANK;
+        let mut bucket_d = block_x;
+        let mut block_d = 0;
+        let mut bucket_x = 0;
+
+        let mut pb = 0;
+        let mut pe = name.len();
+
+        loop {
+            pe -= 1;
+            let cback = name[pe] as u16 | BLANK;
+            bucket_x = Self::rotr(bucket_x, 2) ^ cback;
+            block_d = Self::rotl(block_d, 2) ^ cback;
+            len -= 1;
+            if len == 0 {
+                break;
+            }
+            let cfront = name[pb] as u16 | BLANK;
+            pb += 1;
+            block_x = Self::rotl(block_x, 2) ^ cfront;
+            bucket_d = Self::rotr(bucket_d, 2) ^ cfront;
+        }
+
+        LibHash{
+            block_x: block_x % blocks as u16,
+            bucket_d: max(bucket_d % LIB_NBUCKETS as u16, 1),
+            block_d: max(block_d % blocks as u16, 1),
+            bucket_x: bucket_x % LIB_NBUCKETS as u16,
+        }
+    }
+}
+
+pub const LIB_BLOCK_SIZE: usize = 512;
+pub const LIB_NBUCKETS: usize = 37;
+
+// The byte after the buckets holds the next free word in the block, or
+// this if the block has no room left
+pub const LIB_BLOCK_FULL: u8 = 0xff;
 
 impl<'a> Parser<'a> {
     const MIN_HEADER_LENGTH: usize = 10;
     const LIB_HEADER: u8 = 0xf0;
+    const THEADR: u8 = 0x80;
+    const LHEADR: u8 = 0x82;
+    const COMENT: u8 = 0x88;
     const MODEND: u8 = 0x8a;
     const MODEND32: u8 = 0x8b;
-
-    fn err(&self, err: &str) -> LibError {
-        LibError::with_offset(err, self.start)
-    }
-
-    fn endrec(&self) -> usize {
-        // record end does not include checksum byte
-        self.next - 1
-    }
+    const LIBMOD: u8 = 0xa3;
+    const LIB_END: u8 = 0xf1;
 
     fn uint(data: &[u8]) -> usize {
         let bytes = data.len();
@@ -56,33 +431,17 @@ impl<'a> Parser<'a> {
         value
     }
 
-    fn next_uint(&mut self, size: usize) -> Result<usize, LibError> {
-        if self.ptr + size > self.endrec() {
-            Err(self.err("next_uint: record is truncated"))
-        } else {
-            let value = Self::uint(&self.image[self.ptr..self.ptr+size]);
-            self.ptr += size;
-            Ok(value)
-        }
-    }
-
     pub fn is_lib(image: &[u8]) -> bool {
         image.len() >= Self::MIN_HEADER_LENGTH && image[0] == Self::LIB_HEADER 
     }
 
     pub fn new(image: &'a [u8]) -> Result<Parser<'a>, LibError> {
-        if !Self::is_lib(&image) {
-            return Err(LibError::new("image is not a library"));
-        }
-        
-        let next = image.len();
+        let header = LibHeader::parse(image)?;
 
-        let reclen = Self::uint(&image[1..3]);
-        let dictoffset = Self::uint(&image[3..7]);
-        let dictblocks = Self::uint(&image[7..9]);
-        let flags = image[9];
+        let dictoffset = header.dict_offset;
+        let dictblocks = header.dict_blocks;
 
-        if dictoffset >= image.len() {
+        if dictblocks != 0 && dictoffset >= image.len() {
             return Err(LibError::new("library is corrupt (no or invalid dictionary)"));
         }
 
@@ -92,12 +451,16 @@ impl<'a> Parser<'a> {
         const EDICT_HEADER_TYPE: u8 = 0xf2;
 
         let edict = if edict_start + EDICT_HEADER_LEN <= image.len() && image[edict_start] == EDICT_HEADER_TYPE {
-            let len = Self::uint(&image[edict_start+1..edict_start+3]);
             let entries = Self::uint(&image[edict_start+3..edict_start+5]);
             let offset = edict_start + EDICT_HEADER_LEN;
-            
-            // NB length includes 16-bit entries word which is before edict_offset
-            if offset - 2 + len < image.len() {
+
+            // The length (which includes the 16-bit entries word before
+            // edict_offset) isn't checked: TLIB pads the library out past
+            // the extended dictionary, so it needn't end the file, and a
+            // truncated dependency list is reported when it's read. The
+            // module table at least must be there.
+            //
+            if offset + entries * 4 > image.len() {
                 None
             } else {
                 Some(ExtDict{ offset, entries })
@@ -108,158 +471,257 @@ impl<'a> Parser<'a> {
 
         Ok(Parser {
             image,
-            ptr: 0,
-            start: 0,
-            next,
-            pagesize: reclen + 3,
+            pagesize: header.page_size,
             dictoffset,
             dictblocks,
-            case_sensitive: (flags & 0x01) != 0,
+            case_sensitive: header.case_sensitive(),
             edict,
+            header,
+            codepage: Codepage::default(),
         })
     }
 
-    pub fn first_obj(&mut self) -> Result<Option<&[u8]>, LibError> {
-        self.ptr = self.pagesize;
-        let start = self.ptr;
-
-        loop {
-            let rectype = self.next_uint(1)? as u8;
-            let reclen = self.next_uint(2)?;
+    // The modules in the library, in the order they're stored
+    //
+    pub fn modules(&self) -> Modules<'_, 'a> {
+        Modules{ lib: self, ptr: self.pagesize, done: false }
+    }
 
-            self.ptr += reclen;
+    // Run `f` over every module, with the results in the order the
+    // modules are stored. Finding the modules only means stepping over
+    // record headers, so that's done first; with the `parallel` feature
+    // the real work in `f` is then spread over threads.
+    //
+    pub fn map_modules<T, F>(&self, f: F) -> Result<Vec<T>, LibError>
+    where
+        T: Send,
+        F: Fn(LibModule<'a>) -> Result<T, LibError> + Send + Sync,
+    {
+        let modules = self.modules().collect::<Result<Vec<_>, LibError>>()?;
 
-            if rectype == Self::MODEND || rectype == Self::MODEND32 {
-                break;
-            }
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            modules.into_par_iter().map(f).collect()
         }
 
-        if self.ptr > self.dictoffset {
-            return Err(self.err("object file ran over into dictionary"));
-        }
+        #[cfg(not(feature = "parallel"))]
+        modules.into_iter().map(f).collect()
+    }
+
+    // The module starting at `page`, as the dictionary gives it
+    //
+    pub fn module_at_page(&self, page: usize) -> Result<LibModule<'a>, LibError> {
+        let offset = self.lazy_module(page)?.offset;
+        Modules{ lib: self, ptr: offset, done: false }.module()
+    }
 
-        let end = self.ptr;
-        self.ptr = (self.ptr + self.pagesize - 1) & !(self.pagesize - 1);
+    // A handle on the module at `page`, which is read only when needed
+    //
+    pub fn lazy_module(&self, page: usize) -> Result<LazyModule<'a>, LibError> {
+        let offset = page * self.pagesize;
 
-        Ok(Some(&self.image[start..end]))
+        if page == 0 || offset >= self.modules_end() || !matches!(self.image.get(offset), Some(&Self::THEADR) | Some(&Self::LHEADR)) {
+            return Err(LibError::new(&format!("no library module starts at page {:04x}", page)));
+        }
+
+        Ok(LazyModule{ page, offset, image: &self.image[offset..self.modules_end()], records: OnceCell::new() })
     }
 
-    pub fn next_obj(&mut self) -> Result<Option<&[u8]>, LibError> {
-        let start = self.ptr;
+    // The module the dictionary says defines `name`, without reading it
+    //
+    pub fn find_symbol_lazy(&self, name: &str) -> Result<Option<LazyModule<'a>>, LibError> {
+        match self.find_symbol_obj(name)? {
+            Some(page) => Ok(Some(self.lazy_module(page)?)),
+            None => Ok(None),
+        }
+    }
 
-        if start >= self.dictoffset {
-            return Ok(None);
+    // The module defining `name`: straight from the dictionary if there is
+    // one, else by reading the publics of each module in turn
+    //
+    pub fn find_symbol(&self, name: &str, options: &ParseOptions) -> Result<Option<LibModule<'a>>, LibError> {
+        if self.dictblocks != 0 {
+            return match self.find_symbol_obj(name)? {
+                Some(page) => Ok(Some(self.module_at_page(page)?)),
+                None => Ok(None),
+            };
         }
 
-        // Check: if there is no THEADER here, then we've run into padding 
-        // between the object files and the dictionary
-        //
-        if self.image[start] != 0x80 {
-            return Ok(None);
+        for module in self.modules() {
+            let module = module?;
+            let publics = module_publics(module.image, options).map_err(|err| err.in_module(&module.name))?;
+            let found = publics.iter().any(|public| match self.case_sensitive {
+                true => public == name,
+                false => public.eq_ignore_ascii_case(name),
+            });
+
+            if found {
+                return Ok(Some(module));
+            }
         }
 
-        loop {
-            let rectype = self.next_uint(1)? as u8;
-            let reclen = self.next_uint(2)?;
+        Ok(None)
+    }
 
-            self.ptr += reclen;
+    // The module `index` places into the library, counting from zero
+    //
+    pub fn get_module(&self, index: usize) -> Result<Option<LibModule<'a>>, LibError> {
+        self.modules().nth(index).transpose()
+    }
 
-            if rectype == Self::MODEND || rectype == Self::MODEND32 {
-                break;
+    // The module called `name`. Librarians enter module names in the
+    // dictionary with a `!` after them, so that's tried first; not all of
+    // them do, though, so failing that the modules are searched in turn.
+    //
+    pub fn get_module_by_name(&self, name: &str) -> Result<Option<LibModule<'a>>, LibError> {
+        let named = |module: &LibModule| match self.case_sensitive {
+            true => module.name == name,
+            false => module.name.eq_ignore_ascii_case(name),
+        };
+
+        if self.dictblocks != 0 {
+            if let Some(page) = self.find_symbol_obj(&format!("{}!", name))? {
+                let module = self.module_at_page(page)?;
+                if named(&module) {
+                    return Ok(Some(module));
+                }
             }
         }
 
-        if self.ptr > self.dictoffset {
-            return Err(self.err("object file ran over into dictionary"));
+        for module in self.modules() {
+            let module = module?;
+            if named(&module) {
+                return Ok(Some(module));
+            }
         }
 
-        let end = self.ptr;
-        self.ptr = (self.ptr + self.pagesize - 1) & !(self.pagesize - 1);
+        Ok(None)
+    }
+
+    // Override how lookups treat case, as LINK's /IGNORECASE and
+    // /NOIGNORECASE do; None goes back to the library's own flag. The
+    // dictionary hash ignores case either way, so only the final name
+    // comparison changes.
+    //
+    pub fn ignore_case(&mut self, ignore: Option<bool>) {
+        self.case_sensitive = match ignore {
+            Some(ignore) => !ignore,
+            None => self.header.case_sensitive(),
+        };
+    }
 
-        Ok(Some(&self.image[start..end]))
+    // Where the modules end: at the dictionary, or if there isn't one
+    // (WLIB can leave it out), at the end of the image
+    //
+    pub fn modules_end(&self) -> usize {
+        if self.dictblocks == 0 {
+            self.image.len()
+        } else {
+            self.dictoffset
+        }
     }
 
-    fn to_ascii(s: &str) -> Result<&[u8], LibError>
+    // Tell which librarian wrote the library by what follows the last
+    // module, and whether the modules were given LIBMOD comments
+    //
+    pub fn flavor(&self) -> Result<LibFlavor, LibError> {
+        let mut end = self.pagesize;
+        let mut libmod = false;
+
+        for module in self.modules() {
+            let module = module?;
+            end = (module.offset + module.len() + self.pagesize - 1) & !(self.pagesize - 1);
+            libmod |= module.libmod.is_some();
+        }
+
+        Ok(match (self.image.get(end), &self.edict) {
+            (Some(&Self::LIB_END), _) if libmod => LibFlavor::Microsoft,
+            (Some(&Self::LIB_END), _) => LibFlavor::Watcom,
+            (_, Some(_)) => LibFlavor::Borland,
+            _ if self.dictblocks == 0 => LibFlavor::Watcom,
+            _ => LibFlavor::Unknown,
+        })
+    }
+
+    // Names in the dictionary are stored as the raw bytes from the
+    // objects, so encode the name the same way before hashing it.
+    //
+    fn to_dict_name(&self, s: &str) -> Result<Vec<u8>, LibError>
     {
-        let bytes = s.as_bytes();
+        let bytes = self.codepage.encode(s)?;
         
-        if bytes.iter().any(|s| *s < 0x20 || *s > 0x7e) {
-            return Err(LibError::new("names must be ASCII"));
+        if bytes.is_empty() || bytes.len() > 0xff || bytes.iter().any(|s| *s < 0x20) {
+            return Err(LibError::new("names must be 1 to 255 printable characters"));
         }
 
         Ok(bytes)
     }
 
-    fn rotr(x: u16, bits: usize) -> u16 {
-        ((x) << (16-bits)) | ((x) >> bits)
-    }
+    // One 512-byte block of the dictionary
+    //
+    fn dict_block(&self, block: usize) -> Result<&[u8], LibError> {
+        let offset = self.dictoffset + block * LIB_BLOCK_SIZE;
 
-    fn rotl(x: u16, bits: usize) -> u16 {
-        ((x) << bits) | ((x) >> (16-bits))
+        self.image.get(offset..offset + LIB_BLOCK_SIZE)
+            .ok_or_else(|| LibError::with_offset("library dictionary is truncated", offset))
     }
 
-    fn hash(&self, name: &[u8]) -> LibHash {
-        const BLANK: u16 = 0x20u16;
-        let mut len = name.len() as u16;
-
-        let mut block_x = len | BLANK;
-        let mut bucket_d = block_x;
-        let mut block_d = 0;
-        let mut bucket_x;
-
-        let mut pb = 0;
-        let mut pe = name.len();
-
-        loop {
-            pe -= 1;
-            let cback = name[pe] as u16 | BLANK;
-            bucket_x = Self::rotr(block_x, 2) ^ cback;
-            block_d = Self::rotl(block_d, 2) ^ cback;
-            len -= 1;
-            if len == 0 {
-                break;
-            }
-            let cfront = name[pb] as u16 | BLANK;
-            pb += 1;
-            block_x = Self::rotl(block_x, 2) ^ cfront;
-            bucket_d = Self::rotr(bucket_d, 2) ^ cfront;
+    // The entry a bucket points at, as the raw name and the page of the
+    // module which defines it, or None if the bucket is empty
+    //
+    fn dict_entry<'b>(&self, buf: &'b [u8], block: usize, bucket: usize) -> Result<Option<(&'b [u8], usize)>, LibError> {
+        let idx = 2 * buf[bucket] as usize;
+        if idx == 0 {
+            return Ok(None);
         }
 
-        LibHash{
-            block_x: block_x % self.dictblocks as u16,
-            bucket_d: max(bucket_d % LIB_NBUCKETS as u16, 1),
-            block_d: max(block_d % self.dictblocks as u16, 1),
-            bucket_x: bucket_x % LIB_NBUCKETS as u16,
+        let len = buf[idx] as usize;
+        match buf.get(idx + 1..idx + 3 + len) {
+            Some(entry) => Ok(Some((&entry[..len], Self::uint(&entry[len..])))),
+            None => Err(LibError::with_offset(
+                "library dictionary entry runs off the end of its block",
+                self.dictoffset + block * LIB_BLOCK_SIZE + idx,
+            )),
         }
     }
 
+    fn dict_names_match(&self, name: &[u8], entry: &[u8]) -> bool {
+        if self.case_sensitive {
+            name == entry
+        } else {
+            name.eq_ignore_ascii_case(entry)
+        }
+    }
 
+    // The page of the module which defines `name`, found by hashing
+    // straight to its dictionary block and bucket. An empty bucket in a
+    // block which isn't full means the name isn't there; otherwise the
+    // search goes on to the next bucket and then the next block.
+    //
     pub fn find_symbol_obj(&self, name: &str) -> Result<Option<usize>, LibError> {
-        let ascname = Self::to_ascii(name)?;
-        let hash = self.hash(ascname);
+        if self.dictblocks == 0 {
+            return Ok(None);
+        }
+
+        let dictname = self.to_dict_name(name)?;
+        let hash = LibHash::new(&dictname, self.dictblocks);
 
         let mut block = hash.block_x as usize;
         let bucket = hash.bucket_x as usize;
 
         loop {
             let mut b0 = bucket;
-            let offset = self.dictoffset + block * LIB_BLOCK_SIZE;
-            let buf = &self.image[offset..offset+LIB_BLOCK_SIZE];
-
+            let buf = self.dict_block(block)?;
 
             loop {
-                let mut idx = 2 * buf[b0 as usize] as usize;
-                let len = buf[idx] as usize;
-                idx += 1;
-
-                let thisname = &buf[idx..idx+len];
-                if ascname == thisname {
-                    idx += len;
-                    let modindex = Self::uint(&buf[idx..idx+2]) as u16;
-                    
-                    return Ok(Some(modindex as usize));
+                match self.dict_entry(buf, block, b0)? {
+                    Some((thisname, page)) if self.dict_names_match(&dictname, thisname) => return Ok(Some(page)),
+                    Some(_) => (),
+                    None if buf[LIB_NBUCKETS] != LIB_BLOCK_FULL => return Ok(None),
+                    None => (),
                 }
-                
+
                 b0 = (b0 + hash.bucket_d as usize) % LIB_NBUCKETS;
                 if b0 == bucket {
                     break;
@@ -275,6 +737,27 @@ impl<'a> Parser<'a> {
         Ok(None)
     }
 
+    // Every entry in the dictionary, in block and bucket order. Along
+    // with the public symbols this includes an entry for each module,
+    // named for the module with a trailing `!`.
+    //
+    pub fn dictionary(&self) -> Result<Vec<DictEntry>, LibError> {
+        let mut entries = Vec::new();
+
+        for block in 0..self.dictblocks {
+            let buf = self.dict_block(block)?;
+
+            for bucket in 0..LIB_NBUCKETS {
+                if let Some((name, page)) = self.dict_entry(buf, block, bucket)? {
+                    let name = self.codepage.decode(name)?;
+                    entries.push(DictEntry{ name, page });
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
     pub fn find_module_index(&self, modpage: usize) -> Option<usize> {
         match &self.edict {
             None => None,
@@ -296,12 +779,16 @@ impl<'a> Parser<'a> {
                     let index = index - 1;    
                     let mut offset = Self::uint(&data[index*4+2..index*4+4]);
                     let mut deps = Vec::new();
-                    while offset <= data.len() - 2 {
+                    while offset + 2 <= data.len() {
                         let next = Self::uint(&data[offset..offset+2]);
                         if next == 0 {
                             return Ok(Some(deps));
                         }
 
+                        if next > edict.entries {
+                            return Err(LibError::with_offset("library extended dictionary has a bad module index", edict.offset + offset));
+                        }
+
                         // convert dep index to dep page# of module
                         let next = (next - 1) * 4;
                         let next = Self::uint(&data[next..next+2]);
@@ -309,7 +796,7 @@ impl<'a> Parser<'a> {
                         offset += 2;
                     }
 
-                    Err(self.err("library extended dictionary is truncated"))
+                    Err(LibError::with_offset("library extended dictionary is truncated", edict.offset))
                 },
                 None => Ok(None),
             }
@@ -348,6 +835,36 @@ mod test {
         };
     }
 
+    #[test]
+    fn test_header_fields() {
+        let mut bytes = shortlib();
+
+        match LibHeader::parse(&bytes) {
+            Ok(header) => {
+                assert_eq!(header, LibHeader{ page_size: 16, dict_offset: 0x400, dict_blocks: 2, flags: 0x00 });
+                assert!(!header.case_sensitive());
+                assert_eq!(header.dict_size(), 0x400);
+            },
+            Err(x) => assert!(false, "parser returned {}", x),
+        }
+
+        bytes[9] = LibHeader::CASE_SENSITIVE;
+        let parser = Parser::new(&bytes).unwrap();
+        assert_eq!(parser.header.flags, 0x01);
+        assert!(parser.case_sensitive);
+    }
+
+    #[test]
+    fn test_header_rejects_bad_page_size() {
+        let mut bytes = shortlib();
+        bytes[1] = 24 - 3;
+        assert!(LibHeader::parse(&bytes).is_err());
+        assert!(Parser::new(&bytes).is_err());
+
+        bytes[1] = 8 - 3;
+        assert!(LibHeader::parse(&bytes).is_err());
+    }
+
     #[test]
     fn test_is_lib_false_too_short() {
         let bytes = [
@@ -387,7 +904,7 @@ mod test {
     }
 
     #[test]
-    fn test_first_obj_succeeds() {
+    fn test_first_module_succeeds() {
         let bytes = vec![
             0xf0, 16-3, 0, 0x30, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x80, 0x05, 0x00, 0x03, 0x41, 0x42, 0x43, 0x00,
@@ -405,14 +922,20 @@ mod test {
         let parser = Parser::new(&bytes);
         match parser {
             Err(x) => assert!(false, "parser returned error {}", x),
-            Ok(mut parser) => {
+            Ok(parser) => {
                 assert_eq!(parser.pagesize, 16);
                 assert_eq!(parser.dictoffset, 48);
                 assert_eq!(parser.dictblocks, 3);
 
-                match parser.first_obj() {
-                    Ok(fo) =>assert_eq!(fo, Some(&bytes[16..29])),
-                    Err(e) => assert!(false, "parser failed on first object {}", e),
+                match parser.modules().next() {
+                    Some(Ok(module)) => {
+                        assert_eq!(module.name, "ABC");
+                        assert_eq!(module.page, 1);
+                        assert_eq!(module.offset, 16);
+                        assert_eq!(module.len(), 13);
+                        assert_eq!(module.image, &bytes[16..29]);
+                    },
+                    x => assert!(false, "parser failed on first object {:?}", x),
                 }
 
             },
@@ -420,7 +943,7 @@ mod test {
     }
 
     #[test]
-    fn test_next_obj_succeeds() {
+    fn test_next_module_succeeds() {
         let bytes = vec![
             0xf0, 16-3, 0, 0x30, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x80, 0x05, 0x00, 0x03, 0x41, 0x42, 0x43, 0x00,
@@ -437,17 +960,22 @@ mod test {
         assert!(Parser::is_lib(&bytes));
         let parser = Parser::new(&bytes);
         assert!(parser.is_ok());
-        let mut parser = parser.unwrap();
-        assert!(parser.first_obj().is_ok());
+        let parser = parser.unwrap();
+        let mut modules = parser.modules();
+        assert!(matches!(modules.next(), Some(Ok(_))));
         
-        match parser.next_obj() {
-            Ok(no) =>assert_eq!(no, Some(&bytes[32..32+13])),
-            Err(e) => assert!(false, "parser failed on first object {}", e),
+        match modules.next() {
+            Some(Ok(module)) => {
+                assert_eq!(module.name, "DEF");
+                assert_eq!(module.page, 2);
+                assert_eq!(module.image, &bytes[32..32+13]);
+            },
+            x => assert!(false, "parser failed on second object {:?}", x),
         };
     }
 
     #[test]
-    fn test_next_obj_succeeds_on_end_of_modules() {
+    fn test_modules_end_at_padding() {
         let bytes = vec![
             0xf0, 0x0d, 0x00, 0x20, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x80, 0x05, 0x00, 0x03, 0x41, 0x42, 0x43, 0x00, 0x8a, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
@@ -459,13 +987,47 @@ mod test {
         assert!(Parser::is_lib(&bytes));
         let parser = Parser::new(&bytes);
         assert!(parser.is_ok());
-        let mut parser = parser.unwrap();
-        assert!(parser.first_obj().is_ok());
-        
-        match parser.next_obj() {
-            Ok(no) =>assert_eq!(no, None),
-            Err(e) => assert!(false, "parser failed on first object {}", e),
-        };
+        let parser = parser.unwrap();
+        let mut modules = parser.modules();
+        assert!(matches!(modules.next(), Some(Ok(_))));
+        assert!(modules.next().is_none());
+    }
+
+    #[test]
+    fn test_module_names_prefer_libmod() {
+        let bytes = shortlib();
+        let parser = Parser::new(&bytes).unwrap();
+
+        let modules: Vec<(String, usize)> = parser.modules()
+            .map(|module| module.map(|module| (module.name, module.page)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(modules, vec![("hello".to_string(), 0x0001), ("main".to_string(), 0x001b)]);
+
+        let module = parser.get_module(0).unwrap().unwrap();
+        assert_eq!(module.libmod.as_deref(), Some("hello"));
+        assert_eq!(module.theadr.as_deref(), Some("hello.c"));
+    }
+
+    #[test]
+    fn test_truncated_module_stops_iteration() {
+        let bytes = vec![
+            0xf0, 16-3, 0, 0x30, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x80, 0x05, 0x00, 0x03, 0x41, 0x42, 0x43, 0x00,
+            0x8a, 0x40, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+            0x80, 0x05, 0x00, 0x03, 0x44, 0x45, 0x46, 0x00,
+            0x8a, 0x02, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+
+            // stub dict
+            0
+        ];
+
+        let parser = Parser::new(&bytes).unwrap();
+        let mut modules = parser.modules();
+        assert!(matches!(modules.next(), Some(Err(_))));
+        assert!(modules.next().is_none());
     }
 
     #[test]
@@ -483,6 +1045,158 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_find_symbol_obj_case() {
+        let mut bytes = shortlib();
+        let parser = Parser::new(&bytes).unwrap();
+        assert!(matches!(parser.find_symbol_obj("_MAIN"), Ok(Some(0x001b))));
+        assert!(matches!(parser.find_symbol_obj("_puts"), Ok(None)));
+
+        bytes[9] = LibHeader::CASE_SENSITIVE;
+        let parser = Parser::new(&bytes).unwrap();
+        assert!(matches!(parser.find_symbol_obj("_MAIN"), Ok(None)));
+        assert!(matches!(parser.find_symbol_obj("_main"), Ok(Some(0x001b))));
+    }
+
+    #[test]
+    fn test_ignore_case_overrides_flag() {
+        let mut bytes = shortlib();
+        bytes[9] = LibHeader::CASE_SENSITIVE;
+        let mut parser = Parser::new(&bytes).unwrap();
+
+        parser.ignore_case(Some(true));
+        assert!(matches!(parser.find_symbol_obj("_MAIN"), Ok(Some(0x001b))));
+
+        parser.ignore_case(None);
+        assert!(matches!(parser.find_symbol_obj("_MAIN"), Ok(None)));
+
+        let bytes = shortlib();
+        let mut parser = Parser::new(&bytes).unwrap();
+        parser.ignore_case(Some(false));
+        assert!(matches!(parser.find_symbol_obj("_MAIN"), Ok(None)));
+        assert!(matches!(parser.find_symbol_obj("_main"), Ok(Some(0x001b))));
+    }
+
+    #[test]
+    fn test_find_symbol_returns_module() {
+        let bytes = shortlib();
+        let parser = Parser::new(&bytes).unwrap();
+
+        match parser.find_symbol("_main", &ParseOptions::default()) {
+            Ok(Some(module)) => {
+                assert_eq!(module.name, "main");
+                assert_eq!(module.offset, 0x1b0);
+            },
+            x => assert!(false, "find_symbol returned {:?}", x),
+        }
+        assert!(matches!(parser.find_symbol("_puts", &ParseOptions::default()), Ok(None)));
+        assert!(parser.module_at_page(0x0002).is_err());
+    }
+
+    #[test]
+    fn test_map_modules_keeps_order() {
+        let bytes = shortlib();
+        let parser = Parser::new(&bytes).unwrap();
+
+        let names = parser.map_modules(|module| Ok(module.name)).unwrap();
+        assert_eq!(names, vec!["hello".to_string(), "main".to_string()]);
+
+        let err = parser.map_modules(|module| match module.page {
+            0x001b => Err(LibError::new("no")),
+            _ => Ok(()),
+        });
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_get_module() {
+        let bytes = shortlib();
+        let parser = Parser::new(&bytes).unwrap();
+
+        match parser.get_module(1) {
+            Ok(Some(module)) => assert_eq!((module.name.as_str(), module.offset), ("main", 0x1b0)),
+            x => assert!(false, "get_module returned {:?}", x),
+        }
+        assert!(matches!(parser.get_module(2), Ok(None)));
+
+        match parser.get_module_by_name("HELLO") {
+            Ok(Some(module)) => assert_eq!((module.name.as_str(), module.page), ("hello", 0x0001)),
+            x => assert!(false, "get_module_by_name returned {:?}", x),
+        }
+        assert!(matches!(parser.get_module_by_name("puts"), Ok(None)));
+    }
+
+    #[test]
+    fn test_lazy_module_parses_on_demand() {
+        let bytes = shortlib();
+        let parser = Parser::new(&bytes).unwrap();
+        let options = ParseOptions::default();
+
+        let module = parser.find_symbol_lazy("_main").unwrap().unwrap();
+        assert_eq!((module.page, module.offset), (0x001b, 0x1b0));
+        assert!(!module.is_loaded());
+
+        assert_eq!(module.name(&options).unwrap(), "main");
+        assert!(module.is_loaded());
+
+        let records = module.records(&options).unwrap();
+        assert!(matches!(records.first(), Some(Record::THEADR{ .. })));
+        assert!(matches!(records.last(), Some(Record::MODEND{ .. })));
+
+        assert!(matches!(parser.find_symbol_lazy("_puts"), Ok(None)));
+        assert!(parser.lazy_module(0x0002).is_err());
+    }
+
+    #[test]
+    fn test_find_symbol_without_dictionary() {
+        let mut bytes = shortlib();
+        bytes[7] = 0;
+        let parser = Parser::new(&bytes[..0x400]).unwrap();
+
+        match parser.find_symbol("_MAIN", &ParseOptions::default()) {
+            Ok(Some(module)) => assert_eq!(module.name, "main"),
+            x => assert!(false, "find_symbol returned {:?}", x),
+        }
+        assert!(matches!(parser.find_symbol("_puts", &ParseOptions::default()), Ok(None)));
+    }
+
+    #[test]
+    fn test_dictionary_entries() {
+        let bytes = shortlib();
+        let parser = Parser::new(&bytes).unwrap();
+
+        match parser.dictionary() {
+            Ok(entries) => {
+                let entries: Vec<(&str, usize, bool)> = entries.iter()
+                    .map(|entry| (entry.name.as_str(), entry.page, entry.is_module()))
+                    .collect();
+                assert_eq!(entries, vec![
+                    ("_hello", 0x0001, false),
+                    ("main!", 0x001b, true),
+                    ("_main", 0x001b, false),
+                    ("hello!", 0x0001, true),
+                ]);
+            },
+            Err(x) => assert!(false, "parser returned {}", x),
+        }
+    }
+
+    #[test]
+    fn test_dictionary_truncated() {
+        let bytes = shortlib();
+        let parser = Parser::new(&bytes[..0x500]).unwrap();
+        assert!(parser.dictionary().is_err());
+    }
+
+    #[test]
+    fn test_find_symbol_accepts_codepage_names() {
+        let bytes = shortlib();
+        let parser = Parser::new(&bytes).unwrap();
+
+        assert!(matches!(parser.find_symbol_obj("_grüße"), Ok(None)));
+        assert!(parser.find_symbol_obj("_€").is_err());
+    }
+
     #[test]
     fn test_find_edict_succeeds() {
         let bytes = shortlib();
@@ -502,6 +1216,60 @@ mod test {
     }
 
 
+    #[test]
+    fn test_flavor() {
+        let mut bytes = shortlib();
+        assert_eq!(Parser::new(&bytes).unwrap().flavor().unwrap(), LibFlavor::Microsoft);
+
+        // TLIB leaves no end record, and pads after the extended
+        // dictionary
+        //
+        bytes[0x340..0x343].fill(0);
+        bytes.extend([0; 9]);
+
+        let parser = Parser::new(&bytes).unwrap();
+        assert_eq!(parser.flavor().unwrap(), LibFlavor::Borland);
+        assert_eq!(parser.modules().count(), 2);
+        assert!(matches!(parser.find_module_dependencies(0x001b), Ok(Some(deps)) if deps == vec![0x0001]));
+
+        assert_eq!(Parser::new(&bytes[..EDICT_START]).unwrap().flavor().unwrap(), LibFlavor::Unknown);
+    }
+
+    #[test]
+    fn test_watcom_layout() {
+        // no dictionary, a page of zeros between the modules, an LHEADR,
+        // no LIBMODs, and an end record
+        //
+        let bytes = vec![
+            0xf0, 16-3, 0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x80, 0x05, 0x00, 0x03, 0x41, 0x42, 0x43, 0x00,
+            0x8a, 0x02, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x82, 0x05, 0x00, 0x03, 0x44, 0x45, 0x46, 0x00,
+            0x8a, 0x02, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+            0xf1, 0x01, 0x00, 0x00,
+        ];
+
+        let parser = Parser::new(&bytes).unwrap();
+        let modules: Vec<(String, usize)> = parser.modules()
+            .map(|module| module.map(|module| (module.name, module.page)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(modules, vec![("ABC".to_string(), 1), ("DEF".to_string(), 3)]);
+        assert_eq!(parser.flavor().unwrap(), LibFlavor::Watcom);
+        assert!(matches!(parser.find_symbol_obj("ABC!"), Ok(None)));
+        assert!(matches!(parser.dictionary(), Ok(entries) if entries.is_empty()));
+    }
+
+    #[test]
+    fn test_truncated_edict_table_is_ignored() {
+        let bytes = shortlib();
+        let parser = Parser::new(&bytes[..EDICT_OFFSET + 4]).unwrap();
+        assert!(parser.edict.is_none());
+    }
+
     #[test]
     fn test_find_no_edict_succeeds() {
         let bytes = shortlib();
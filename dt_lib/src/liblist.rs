@@ -0,0 +1,133 @@
+use std::cmp::Ordering;
+
+use crate::error::Error as LibError;
+use crate::libfile::{LibFlavor, Parser};
+use crate::libwriter::LibMember;
+use crate::objfile::ParseOptions;
+
+// One module of a library listing
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ModuleListing {
+    pub name: String,
+
+    // The THEADR name, if it's not the same as `name` (which is the
+    // LIBMOD name when there is one)
+    pub source: Option<String>,
+
+    pub offset: usize,
+    pub length: usize,
+
+    // The pages the module occupies, first and last inclusive
+    pub first_page: usize,
+    pub last_page: usize,
+
+    pub publics: Vec<String>,
+}
+
+// What LIB puts in a listing file: every module with where it lives and
+// what it defines
+//
+#[derive(Debug)]
+pub struct LibListing {
+    pub page_size: usize,
+    pub flavor: LibFlavor,
+    pub modules: Vec<ModuleListing>,
+}
+
+// Names are listed case-insensitively, as LIB does, with case breaking
+// ties so the order is stable
+//
+fn by_name(a: &str, b: &str) -> Ordering {
+    a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase()).then_with(|| a.cmp(b))
+}
+
+impl LibListing {
+    // Every public paired with the module defining it, sorted by name
+    //
+    pub fn symbols(&self) -> Vec<(&str, &str)> {
+        let mut symbols: Vec<(&str, &str)> = self.modules.iter()
+            .flat_map(|module| module.publics.iter().map(move |public| (public.as_str(), module.name.as_str())))
+            .collect();
+
+        symbols.sort_by(|a, b| by_name(a.0, b.0).then_with(|| by_name(a.1, b.1)));
+        symbols
+    }
+}
+
+pub fn list_library(lib: &Parser, options: &ParseOptions) -> Result<LibListing, LibError> {
+    let modules = lib.map_modules(|module| {
+        let member = LibMember::from_obj(&module.name, module.image, options)
+            .map_err(|err| err.in_module(&module.name))?;
+
+        let mut publics = member.publics;
+        publics.sort_by(|a, b| by_name(a, b));
+
+        let length = module.len();
+        let source = module.theadr.filter(|theadr| *theadr != module.name);
+        Ok(ModuleListing{
+            name: module.name,
+            source,
+            offset: module.offset,
+            length,
+            first_page: module.page,
+            last_page: (module.offset + length.max(1) - 1) / lib.pagesize,
+            publics,
+        })
+    })?;
+
+    Ok(LibListing{ page_size: lib.pagesize, flavor: lib.flavor()?, modules })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::libwriter::LibWriter;
+    use crate::objbuilder::*;
+
+    fn member(module: &str, publics: &[&str], size: usize) -> LibMember {
+        sourced_member(module, module, publics, size)
+    }
+
+    // A member whose THEADR names the source file `source`
+    //
+    fn sourced_member(module: &str, source: &str, publics: &[&str], size: usize) -> LibMember {
+        let mut builder = ObjBuilder::new(source);
+        let text = builder.segment(SegmentSpec::new("_TEXT", "CODE"));
+        for (i, name) in publics.iter().enumerate() {
+            builder.public(name, text, i as u32);
+        }
+        let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+        builder.data(text, 0, &data).unwrap();
+        LibMember::from_obj(module, &builder.build().unwrap(), &ParseOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_list_library() {
+        let mut writer = LibWriter::new();
+        writer.add(member("b", &["_zed", "_Alpha"], 0x10)).unwrap();
+        writer.add(member("a", &["_beta"], 0x100)).unwrap();
+        writer.add(sourced_member("c", "src\\c.c", &["_gamma"], 0x10)).unwrap();
+        let image = writer.write().unwrap();
+        let lib = Parser::new(&image).unwrap();
+
+        let listing = list_library(&lib, &ParseOptions::default()).unwrap();
+        assert_eq!(listing.page_size, 16);
+        assert_eq!(listing.flavor, LibFlavor::Microsoft);
+
+        let b = &listing.modules[0];
+        assert_eq!((b.name.as_str(), b.offset, b.first_page), ("b", 16, 1));
+        assert_eq!(b.last_page, (b.offset + b.length - 1) / 16);
+        assert_eq!(b.publics, vec!["_Alpha".to_string(), "_zed".to_string()]);
+
+        let a = &listing.modules[1];
+        assert_eq!(a.first_page, b.last_page + 1);
+        assert!(a.last_page > a.first_page + 0x0f);
+
+        assert_eq!(b.source, None);
+        assert_eq!(listing.modules[2].source.as_deref(), Some("src\\c.c"));
+
+        assert_eq!(listing.symbols(), vec![("_Alpha", "b"), ("_beta", "a"), ("_gamma", "c"), ("_zed", "b")]);
+    }
+}
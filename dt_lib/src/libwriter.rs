@@ -0,0 +1,598 @@
+use std::collections::HashMap;
+
+use crate::codepage::Codepage;
+use crate::error::Error as LibError;
+use crate::libfile::{self, LibHash, LibHeader, LIB_BLOCK_FULL, LIB_BLOCK_SIZE, LIB_NBUCKETS};
+use crate::objfile::*;
+use crate::objwriter::*;
+
+const LIB_HEADER: u8 = 0xf0;
+const LIB_END: u8 = 0xf1;
+const COMENT: u8 = 0x88;
+const LIBMOD: u8 = 0xa3;
+
+// The first free word in an empty dictionary block, just past the
+// buckets and the free word byte itself
+const LIB_BLOCK_FIRST_FREE: u8 = LIB_NBUCKETS.div_ceil(2) as u8;
+
+// A raw dictionary name, and the member (or page) it points to
+type DictName = (Vec<u8>, usize);
+
+// One module of a library being written, and the public names it goes
+// into the dictionary under
+//
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct LibMember {
+    pub name: String,
+    pub image: Vec<u8>,
+    pub publics: Vec<String>,
+}
+
+impl LibMember {
+    // Make a library member from an object module. The module keeps the
+    // name in its LIBMOD comment if it has one, else is given `name` in a
+    // new LIBMOD just after the THEADR, the way LIB does. Anything after
+    // the MODEND is dropped.
+    //
+    pub fn from_obj(name: &str, obj: &[u8], options: &ParseOptions) -> Result<LibMember, LibError> {
+        let options = ParseOptions{ merge_comdats: false, ..options.clone() };
+        let codepage = options.codepage;
+        let mut parser = Parser::with_options(obj, options.clone());
+        let mut libmod = None;
+        let mut theadr_end = None;
+
+        let end = loop {
+            let parsed = parser.next_parsed()?;
+            let next = parsed.offset + parsed.raw.len();
+
+            match &parsed.record {
+                Record::None => return Err(LibError::new(&format!("object module {} has no MODEND", name))),
+                Record::THEADR{ .. } | Record::LHEADR{ .. } if theadr_end.is_none() => theadr_end = Some(next),
+                Record::COMENT{ coment: Coment::Libmod{ name }, .. } => libmod = Some(name.clone()),
+                Record::MODEND{ .. } => break next,
+                _ => (),
+            }
+        };
+
+        let publics = libfile::module_publics(&obj[..end], &options)?;
+
+        let image = match (&libmod, theadr_end) {
+            (None, Some(at)) => {
+                let mut body = RecordBody::with_codepage(codepage);
+                body.byte(0x00).byte(LIBMOD).str(name)?;
+
+                let mut writer = RecordWriter::new();
+                writer.record(COMENT, body.bytes())?;
+
+                [&obj[..at], writer.bytes(), &obj[at..end]].concat()
+            },
+            _ => obj[..end].to_vec(),
+        };
+
+        Ok(LibMember{ name: libmod.unwrap_or_else(|| name.to_string()), image, publics })
+    }
+}
+
+// A public defined by more than one member. As with LIB, the first
+// definition goes in the dictionary and later ones are left out.
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Duplicate {
+    pub symbol: String,
+    pub module: String,
+    pub ignored: String,
+}
+
+// Builds a library image from a list of members, laying the modules out
+// on page boundaries and generating the dictionary from their publics.
+//
+#[derive(Debug)]
+pub struct LibWriter {
+    pub members: Vec<LibMember>,
+
+    // The page size to use, or None for the smallest one which can
+    // address every module
+    pub page_size: Option<usize>,
+
+    pub case_sensitive: bool,
+    pub codepage: Codepage,
+}
+
+impl Default for LibWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LibWriter {
+    const MIN_PAGE_SIZE: usize = 16;
+    const MAX_PAGE_SIZE: usize = 32768;
+
+    pub fn new() -> LibWriter {
+        LibWriter{ members: Vec::new(), page_size: None, case_sensitive: false, codepage: Codepage::default() }
+    }
+
+    // Start from the members of an existing library, keeping its page
+    // size and flags. The dictionary is rebuilt from the modules rather
+    // than trusted.
+    //
+    pub fn load(lib: &libfile::Parser, options: &ParseOptions) -> Result<LibWriter, LibError> {
+        let members = lib.map_modules(|module| {
+            LibMember::from_obj(&module.name, module.image, options)
+                .map_err(|err| err.in_module(&module.name))
+        })?;
+
+        Ok(LibWriter{
+            members,
+            page_size: Some(lib.header.page_size),
+            case_sensitive: lib.header.case_sensitive(),
+            codepage: lib.codepage,
+        })
+    }
+
+    // The index of the member called `name`. Module names, like the DOS
+    // file names they come from, are never case sensitive.
+    //
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.members.iter().position(|member| member.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn add(&mut self, member: LibMember) -> Result<(), LibError> {
+        if self.find(&member.name).is_some() {
+            return Err(LibError::new(&format!("module {} is already in the library", member.name)));
+        }
+
+        self.members.push(member);
+        Ok(())
+    }
+
+    // Take the member called `name` out of the library
+    //
+    pub fn remove(&mut self, name: &str) -> Result<LibMember, LibError> {
+        match self.find(name) {
+            Some(index) => Ok(self.members.remove(index)),
+            None => Err(LibError::new(&format!("module {} is not in the library", name))),
+        }
+    }
+
+    // Put `member` in place of the member with the same name, or add it
+    // if there isn't one. Returns whether a member was replaced.
+    //
+    pub fn replace(&mut self, member: LibMember) -> bool {
+        match self.find(&member.name) {
+            Some(index) => {
+                self.members[index] = member;
+                true
+            },
+            None => {
+                self.members.push(member);
+                false
+            },
+        }
+    }
+
+    // A file name to extract each member to, in member order. Names come
+    // from the module name with any directory and extension dropped (a
+    // THEADR name is often the source path) and anything which won't go
+    // in a DOS file name replaced; members which would still collide get
+    // a numbered suffix.
+    //
+    pub fn file_names(&self) -> Vec<String> {
+        let mut used: Vec<String> = Vec::new();
+
+        self.members.iter()
+            .map(|member| {
+                let base = member.name.rsplit(['\\', '/', ':']).next().unwrap_or("");
+                let stem = match base.rfind('.') {
+                    Some(dot) if dot > 0 => &base[..dot],
+                    _ => base,
+                };
+                let stem: String = stem.chars()
+                    .map(|c| if c.is_ascii_alphanumeric() || "_-$~!#".contains(c) { c } else { '_' })
+                    .collect();
+                let stem = if stem.is_empty() { "module".to_string() } else { stem };
+
+                let mut name = format!("{}.obj", stem);
+                let mut suffix = 2;
+                while used.iter().any(|other| other.eq_ignore_ascii_case(&name)) {
+                    name = format!("{}_{}.obj", stem, suffix);
+                    suffix += 1;
+                }
+
+                used.push(name.clone());
+                name
+            })
+            .collect()
+    }
+
+    fn dict_key(&self, name: &[u8]) -> Vec<u8> {
+        if self.case_sensitive {
+            name.to_vec()
+        } else {
+            name.to_ascii_lowercase()
+        }
+    }
+
+    // Every dictionary entry as the raw name and the index of the member
+    // it points to, along with the publics left out as duplicates
+    //
+    fn symbols(&self) -> Result<(Vec<DictName>, Vec<Duplicate>), LibError> {
+        let mut symbols = Vec::new();
+        let mut defined: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut duplicates = Vec::new();
+
+        for (index, member) in self.members.iter().enumerate() {
+            let module = format!("{}!", member.name);
+
+            for name in std::iter::once(&module).chain(member.publics.iter()) {
+                let bytes = self.codepage.encode(name)?;
+                if bytes.is_empty() || bytes.len() > 0xff || bytes.iter().any(|b| *b < 0x20) {
+                    return Err(LibError::new(&format!("{} can't go in the dictionary (names must be 1 to 255 printable characters)", name)));
+                }
+
+                match defined.get(&self.dict_key(&bytes)) {
+                    Some(first) if *first == index => (),
+                    Some(first) => duplicates.push(Duplicate{
+                        symbol: name.clone(),
+                        module: self.members[*first].name.clone(),
+                        ignored: member.name.clone(),
+                    }),
+                    None => {
+                        defined.insert(self.dict_key(&bytes), index);
+                        symbols.push((bytes, index));
+                    },
+                }
+            }
+        }
+
+        Ok((symbols, duplicates))
+    }
+
+    // Publics which more than one member defines
+    //
+    pub fn duplicates(&self) -> Result<Vec<Duplicate>, LibError> {
+        Ok(self.symbols()?.1)
+    }
+
+    fn pages(&self, page_size: usize) -> Option<Vec<usize>> {
+        let mut offset = page_size;
+        let mut pages = Vec::new();
+
+        for member in &self.members {
+            let page = offset / page_size;
+            if page > 0xffff {
+                return None;
+            }
+
+            pages.push(page);
+            offset += member.image.len().div_ceil(page_size) * page_size;
+        }
+
+        Some(pages)
+    }
+
+    fn layout(&self) -> Result<(usize, Vec<usize>), LibError> {
+        match self.page_size {
+            Some(size) => {
+                if !size.is_power_of_two() || !(Self::MIN_PAGE_SIZE..=Self::MAX_PAGE_SIZE).contains(&size) {
+                    return Err(LibError::new(&format!("library page size {} is not a power of two from 16 to 32768", size)));
+                }
+
+                self.pages(size)
+                    .map(|pages| (size, pages))
+                    .ok_or_else(|| LibError::new(&format!("library is too big for a page size of {}", size)))
+            },
+            None => {
+                let mut size = Self::MIN_PAGE_SIZE;
+                loop {
+                    if let Some(pages) = self.pages(size) {
+                        return Ok((size, pages));
+                    }
+                    if size == Self::MAX_PAGE_SIZE {
+                        return Err(LibError::new("library is too big for any page size"));
+                    }
+                    size *= 2;
+                }
+            },
+        }
+    }
+
+    fn is_prime(n: usize) -> bool {
+        n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d))
+    }
+
+    // Put one name in the dictionary, probing blocks and buckets in the
+    // order a lookup will. A block the name doesn't fit in is marked full
+    // so lookups know to keep going past it.
+    //
+    fn insert(blocks: &mut [[u8; LIB_BLOCK_SIZE]], name: &[u8], page: usize) -> bool {
+        let hash = LibHash::new(name, blocks.len());
+        let size = (name.len() + 3).div_ceil(2) * 2;
+        let mut block = hash.block_x as usize;
+
+        for _ in 0..blocks.len() {
+            let buf = &mut blocks[block];
+            let mut bucket = hash.bucket_x as usize;
+
+            for _ in 0..LIB_NBUCKETS {
+                if buf[bucket] == 0 {
+                    let free = 2 * buf[LIB_NBUCKETS] as usize;
+
+                    if buf[LIB_NBUCKETS] == LIB_BLOCK_FULL || free + size > LIB_BLOCK_SIZE {
+                        buf[LIB_NBUCKETS] = LIB_BLOCK_FULL;
+                        break;
+                    }
+
+                    buf[free] = name.len() as u8;
+                    buf[free + 1..free + 1 + name.len()].copy_from_slice(name);
+                    buf[free + 1 + name.len()..free + 3 + name.len()].copy_from_slice(&(page as u16).to_le_bytes());
+                    buf[bucket] = (free / 2) as u8;
+
+                    let next = free + size;
+                    buf[LIB_NBUCKETS] = if next >= LIB_BLOCK_SIZE { LIB_BLOCK_FULL } else { (next / 2) as u8 };
+                    return true;
+                }
+
+                bucket = (bucket + hash.bucket_d as usize) % LIB_NBUCKETS;
+            }
+
+            buf[LIB_NBUCKETS] = LIB_BLOCK_FULL;
+            block = (block + hash.block_d as usize) % blocks.len();
+        }
+
+        false
+    }
+
+    // Build the dictionary in the smallest prime number of blocks which
+    // will hold every name. Hashing may still leave a block overfull, in
+    // which case the next prime up is tried.
+    //
+    fn dictionary(symbols: &[DictName]) -> Result<Vec<[u8; LIB_BLOCK_SIZE]>, LibError> {
+        let bytes: usize = symbols.iter().map(|(name, _)| (name.len() + 3).div_ceil(2) * 2).sum();
+        let usable = LIB_BLOCK_SIZE - 2 * LIB_BLOCK_FIRST_FREE as usize;
+        let mut count = bytes.div_ceil(usable).max(symbols.len().div_ceil(LIB_NBUCKETS)).max(2);
+
+        while count <= 0xffff {
+            if Self::is_prime(count) {
+                let mut blocks = vec![[0u8; LIB_BLOCK_SIZE]; count];
+                for block in blocks.iter_mut() {
+                    block[LIB_NBUCKETS] = LIB_BLOCK_FIRST_FREE;
+                }
+
+                if symbols.iter().all(|(name, page)| Self::insert(&mut blocks, name, *page)) {
+                    return Ok(blocks);
+                }
+            }
+            count += 1;
+        }
+
+        Err(LibError::new("library dictionary is too big"))
+    }
+
+    // Write the library: the header page, each module on a page
+    // boundary, the end record padding out to a dictionary block, and
+    // the dictionary.
+    //
+    pub fn write(&self) -> Result<Vec<u8>, LibError> {
+        let (page_size, pages) = self.layout()?;
+        let (symbols, _) = self.symbols()?;
+        let mut image = vec![0u8; page_size];
+
+        for (member, page) in self.members.iter().zip(pages.iter()) {
+            image.resize(page * page_size, 0);
+            image.extend_from_slice(&member.image);
+        }
+        image.resize(image.len().div_ceil(page_size) * page_size, 0);
+
+        let dict_offset = (image.len() + 4).div_ceil(LIB_BLOCK_SIZE) * LIB_BLOCK_SIZE;
+        let reclen = dict_offset - image.len() - 3;
+        image.push(LIB_END);
+        image.extend_from_slice(&(reclen as u16).to_le_bytes());
+        image.resize(dict_offset, 0);
+
+        let symbols: Vec<DictName> = symbols.into_iter()
+            .map(|(name, index)| (name, pages[index]))
+            .collect();
+        let blocks = Self::dictionary(&symbols)?;
+        for block in &blocks {
+            image.extend_from_slice(block);
+        }
+
+        let dict_offset = u32::try_from(dict_offset).map_err(|_| LibError::new("library is too big"))?;
+        let flags = if self.case_sensitive { LibHeader::CASE_SENSITIVE } else { 0 };
+
+        image[0] = LIB_HEADER;
+        image[1..3].copy_from_slice(&((page_size - 3) as u16).to_le_bytes());
+        image[3..7].copy_from_slice(&dict_offset.to_le_bytes());
+        image[7..9].copy_from_slice(&(blocks.len() as u16).to_le_bytes());
+        image[9] = flags;
+
+        Ok(image)
+    }
+}
+
+// Rewrite a library with its modules on `page_size` boundaries, or on
+// the smallest pages which will hold them if that's None, with a
+// dictionary rebuilt to match
+//
+pub fn repack(lib: &libfile::Parser, page_size: Option<usize>, options: &ParseOptions) -> Result<Vec<u8>, LibError> {
+    let mut writer = LibWriter::load(lib, options)?;
+    writer.page_size = page_size;
+    writer.write()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::objbuilder::*;
+
+    fn object(module: &str, publics: &[&str]) -> Vec<u8> {
+        let mut builder = ObjBuilder::new(&format!("{}.c", module));
+        let text = builder.segment(SegmentSpec::new("_TEXT", "CODE"));
+        for (i, name) in publics.iter().enumerate() {
+            builder.public(name, text, i as u32);
+        }
+        builder.data(text, 0, &[0x90; 0x40]).unwrap();
+        builder.build().unwrap()
+    }
+
+    fn member(module: &str, publics: &[&str]) -> LibMember {
+        LibMember::from_obj(module, &object(module, publics), &ParseOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_member_gets_libmod() {
+        let member = member("hello", &["_hello"]);
+        assert_eq!(member.name, "hello");
+        assert_eq!(member.publics, vec!["_hello".to_string()]);
+
+        let mut parser = Parser::new(&member.image);
+        assert!(matches!(parser.next(), Ok(Record::THEADR{ .. })));
+        match parser.next() {
+            Ok(Record::COMENT{ coment: Coment::Libmod{ name }, .. }) => assert_eq!(name, "hello"),
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+
+        // an existing LIBMOD is kept
+        //
+        let again = LibMember::from_obj("other", &member.image, &ParseOptions::default()).unwrap();
+        assert_eq!(again.name, "hello");
+        assert_eq!(again.image, member.image);
+    }
+
+    #[test]
+    fn test_write_library() {
+        let mut writer = LibWriter::new();
+        writer.add(member("hello", &["_hello", "_greet"])).unwrap();
+        writer.add(member("main", &["_main"])).unwrap();
+        assert!(writer.add(member("MAIN", &[])).is_err());
+
+        let image = writer.write().unwrap();
+        let lib = libfile::Parser::new(&image).unwrap();
+        assert_eq!(lib.header.page_size, 16);
+        assert_eq!(lib.header.dict_offset % LIB_BLOCK_SIZE, 0);
+
+        let modules: Vec<(String, usize)> = lib.modules()
+            .map(|module| module.map(|module| (module.name, module.page)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(modules.len(), 2);
+        assert_eq!(modules[0], ("hello".to_string(), 1));
+
+        for (symbol, module) in [("_hello", 0), ("_greet", 0), ("_main", 1)] {
+            assert_eq!(lib.find_symbol_obj(symbol).unwrap(), Some(modules[module].1));
+        }
+        assert_eq!(lib.find_symbol_obj("_puts").unwrap(), None);
+        assert_eq!(lib.dictionary().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_remove_and_replace() {
+        let mut writer = LibWriter::new();
+        writer.add(member("a", &["_a"])).unwrap();
+        writer.add(member("b", &["_b"])).unwrap();
+        writer.add(member("c", &["_c"])).unwrap();
+
+        assert_eq!(writer.remove("B").unwrap().name, "b");
+        assert!(writer.remove("b").is_err());
+
+        assert!(writer.replace(member("a", &["_a", "_a2"])));
+        assert!(!writer.replace(member("d", &["_d"])));
+
+        let names: Vec<&str> = writer.members.iter().map(|member| member.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "c", "d"]);
+
+        let image = writer.write().unwrap();
+        let lib = libfile::Parser::new(&image).unwrap();
+        assert_eq!(lib.find_symbol_obj("_b").unwrap(), None);
+        assert_eq!(lib.find_symbol_obj("_a2").unwrap(), Some(1));
+        assert!(lib.find_symbol_obj("_d").unwrap().is_some());
+
+        let pages: Vec<usize> = lib.modules().map(|module| module.unwrap().offset % lib.pagesize).collect();
+        assert_eq!(pages, vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn test_file_names() {
+        let mut writer = LibWriter::new();
+        for name in ["hello", "C:\\SRC\\HELLO.C", "../lib/x.y.c", "a b", ".c"] {
+            writer.members.push(LibMember{ name: name.to_string(), image: Vec::new(), publics: Vec::new() });
+        }
+
+        assert_eq!(writer.file_names(), vec!["hello.obj", "HELLO_2.obj", "x_y.obj", "a_b.obj", "_c.obj"]);
+    }
+
+    #[test]
+    fn test_duplicates_keep_the_first() {
+        let mut writer = LibWriter::new();
+        writer.add(member("a", &["_x"])).unwrap();
+        writer.add(member("b", &["_X"])).unwrap();
+
+        assert_eq!(writer.duplicates().unwrap(), vec![
+            Duplicate{ symbol: "_X".to_string(), module: "a".to_string(), ignored: "b".to_string() },
+        ]);
+
+        let image = writer.write().unwrap();
+        let lib = libfile::Parser::new(&image).unwrap();
+        assert_eq!(lib.find_symbol_obj("_X").unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_large_dictionary() {
+        let names: Vec<String> = (0..500).map(|i| format!("_symbol_number_{}", i)).collect();
+        let publics: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+
+        let mut writer = LibWriter::new();
+        writer.add(member("big", &publics)).unwrap();
+        let image = writer.write().unwrap();
+        let lib = libfile::Parser::new(&image).unwrap();
+
+        assert!(lib.dictblocks > 2);
+        for name in &names {
+            assert_eq!(lib.find_symbol_obj(name).unwrap(), Some(1));
+        }
+    }
+
+    #[test]
+    fn test_reload_library() {
+        let mut writer = LibWriter::new();
+        writer.page_size = Some(64);
+        writer.case_sensitive = true;
+        writer.add(member("hello", &["_hello"])).unwrap();
+        let image = writer.write().unwrap();
+
+        let lib = libfile::Parser::new(&image).unwrap();
+        let reloaded = LibWriter::load(&lib, &ParseOptions::default()).unwrap();
+        assert_eq!(reloaded.page_size, Some(64));
+        assert!(reloaded.case_sensitive);
+        assert_eq!(reloaded.members[0].publics, vec!["_hello".to_string()]);
+        assert_eq!(reloaded.write().unwrap(), image);
+    }
+
+    #[test]
+    fn test_repack() {
+        let mut writer = LibWriter::new();
+        writer.add(member("a", &["_a"])).unwrap();
+        writer.add(member("b", &["_b"])).unwrap();
+        let image = writer.write().unwrap();
+        let options = ParseOptions::default();
+
+        let repacked = repack(&libfile::Parser::new(&image).unwrap(), Some(512), &options).unwrap();
+        let lib = libfile::Parser::new(&repacked).unwrap();
+        assert_eq!(lib.pagesize, 512);
+        match lib.find_symbol("_b", &options) {
+            Ok(Some(module)) => {
+                assert_eq!(module.offset, 1024);
+                assert_eq!(module.image, &writer.members[1].image[..]);
+            },
+            x => assert!(false, "find_symbol returned {:?}", x),
+        }
+
+        let smallest = repack(&lib, None, &options).unwrap();
+        assert_eq!(smallest, image);
+        assert!(repack(&lib, Some(100), &options).is_err());
+    }
+}
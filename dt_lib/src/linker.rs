@@ -0,0 +1,3933 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::diagnostics::Diagnostics;
+use crate::error::Diagnostic;
+use crate::error::Error as LinkError;
+use crate::libfile;
+use crate::linkscript::LinkScript;
+use crate::mzexe::MzExe;
+use crate::symfile::{SymFile, SymSegment};
+use crate::objfile::*;
+
+// An object module read into the link, with its records kept in order
+// along with where each one starts in the object and its record type.
+//
+// A module is read in two passes. Pass 1 reads the definitions: names,
+// segments, groups and symbols, which is all it takes to resolve symbols
+// and lay out the program. If the module marks where they end with a link
+// pass separator COMENT, pass 1 stops there, and the data, fixups and
+// MODEND after it aren't read until pass 2 builds the image.
+//
+#[derive(Debug)]
+pub struct Module {
+    pub name: String,
+
+    // Where the module came from, for messages: the object file's name,
+    // or LIBRARY(module) for a library member
+    pub origin: String,
+
+    pub records: Vec<(usize, u8, Record)>,
+    pub lnames: Vec<String>,
+    pub segdefs: Vec<Segdef>,
+    pub grpdefs: Vec<(usize, Vec<GrpComponent>)>,
+
+    // Where each SEGDEF went in the output, once segments are combined,
+    // and the output group each GRPDEF stands for
+    pub placements: Vec<Placement>,
+    pub groups: Vec<usize>,
+
+    // The symbol each external index refers to. EXTDEF, LEXTDEF, COMDEF
+    // and CEXTDEF all number their names in one sequence, from 1.
+    pub externs: Vec<SymbolId>,
+
+    // Where pass 1 stopped in the object, and the object, if it stopped
+    // at a link pass separator and the rest hasn't been read yet
+    pub deferred: Option<(ParserState, Vec<u8>)>,
+
+    // The overlay the module's code goes in, or 0 for the root
+    pub overlay: u16,
+}
+
+// Pass on the problems the parser tolerated in a module as warnings
+// about it, under where the module came from rather than the name it
+// gives itself
+//
+fn forward_diagnostics(parser: &Parser, origin: &str, diagnostics: &mut Diagnostics) {
+    for diagnostic in parser.diagnostics() {
+        diagnostics.warn("object", Diagnostic{ module: None, ..diagnostic.clone() }.in_module(origin));
+    }
+}
+
+impl Module {
+    // Pass 1: read the module up to the link pass separator, or all of it
+    // if there isn't one
+    //
+    // The object is only kept if there's a pass 2 to read it in. What the
+    // parser lets go by is reported to `diagnostics`.
+    //
+    pub fn parse(origin: &str, obj: Cow<'_, [u8]>, options: &ParseOptions, diagnostics: &mut Diagnostics) -> Result<Module, LinkError> {
+        let mut parser = Parser::with_options(&obj, options.clone());
+        let mut records = Vec::new();
+        let mut lnames = Vec::new();
+        let mut segdefs = Vec::new();
+        let mut grpdefs = Vec::new();
+        let mut state = None;
+
+        loop {
+            let parsed = parser.next_parsed().map_err(|err| err.in_module(origin))?;
+            let separator = matches!(parsed.record, Record::COMENT{ coment: Coment::LinkPassSeparator, .. });
+
+            match parsed.record {
+                Record::None => break,
+                Record::LNAMES{ ref names } => lnames.extend(names.iter().cloned()),
+                Record::SEGDEF{ ref segs } => segdefs.extend(segs.iter().cloned()),
+                Record::GRPDEF{ name, ref components } => grpdefs.push((name, components.clone())),
+                _ => (),
+            }
+
+            records.push((parsed.offset, parsed.rectype, parsed.record));
+
+            if separator {
+                state = Some(parser.state());
+                break;
+            }
+        }
+
+        forward_diagnostics(&parser, origin, diagnostics);
+        let name = parser.module().unwrap_or(origin).to_string();
+        let deferred = state.map(|state| (state, obj.into_owned()));
+
+        Ok(Module{
+            name,
+            origin: origin.to_string(),
+            records,
+            lnames,
+            segdefs,
+            grpdefs,
+            placements: Vec::new(),
+            groups: Vec::new(),
+            externs: Vec::new(),
+            deferred,
+            overlay: 0,
+        })
+    }
+
+    // Pass 2: read the rest of the module, after the link pass separator.
+    // The parser picks up where pass 1 left it, with what it had seen to
+    // check indices against.
+    //
+    pub fn read_deferred(&mut self, options: &ParseOptions, diagnostics: &mut Diagnostics) -> Result<(), LinkError> {
+        let (state, obj) = match self.deferred.take() {
+            Some(deferred) => deferred,
+            None => return Ok(()),
+        };
+
+        let mut parser = Parser::resume(&obj, options.clone(), state);
+        loop {
+            let parsed = parser.next_parsed().map_err(|err| err.in_module(&self.origin))?;
+            match parsed.record {
+                Record::None => break,
+                record => self.records.push((parsed.offset, parsed.rectype, record)),
+            }
+        }
+
+        forward_diagnostics(&parser, &self.origin, diagnostics);
+        Ok(())
+    }
+
+    // An LNAMES entry, by its 1-based index
+    //
+    pub fn lname(&self, index: usize) -> Result<&str, LinkError> {
+        self.lnames.get(index.wrapping_sub(1))
+            .map(|name| name.as_str())
+            .ok_or_else(|| LinkError::new(&format!("LNAMES index {} is out of range", index)).in_module(&self.origin))
+    }
+
+    pub fn segdef(&self, index: usize) -> Result<&Segdef, LinkError> {
+        self.segdefs.get(index.wrapping_sub(1))
+            .ok_or_else(|| LinkError::new(&format!("segment index {} is out of range", index)).in_module(&self.origin))
+    }
+
+    // The segment and class names of a SEGDEF. Either may be left out,
+    // which makes it empty.
+    //
+    pub fn segment_names(&self, segdef: &Segdef) -> Result<(String, String), LinkError> {
+        let name = |index: Option<usize>| index.map_or(Ok(""), |index| self.lname(index)).map(|name| name.to_string());
+        Ok((name(segdef.name)?, name(segdef.class)?))
+    }
+
+    // The symbol an external index (from 1) refers to
+    //
+    pub fn external(&self, index: usize) -> Result<SymbolId, LinkError> {
+        self.externs.get(index.wrapping_sub(1))
+            .copied()
+            .ok_or_else(|| LinkError::new(&format!("external index {} is out of range", index)).in_module(&self.origin))
+    }
+}
+
+pub type SymbolId = usize;
+
+// A PUBDEF or LPUBDEF of a symbol: `offset` from the base its record
+// gives, which is a segment (in a group, if `group` is given) or, with no
+// segment, the absolute `frame`
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct PublicDef {
+    pub module: usize,
+    pub group: Option<usize>,
+    pub seg: Option<usize>,
+    pub frame: Option<u16>,
+    pub offset: u32,
+    pub local: bool,
+}
+
+// A COMDEF of a communal variable, `length` bytes long. Far ones are
+// given as a number of elements of some size, but the parser has
+// already multiplied them out.
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct CommonDef {
+    pub module: usize,
+    pub far: bool,
+    pub length: usize,
+}
+
+// A module declaring a symbol external, and the offset of the record
+// which does it
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Reference {
+    pub module: usize,
+    pub offset: usize,
+    pub local: bool,
+}
+
+// Everything the link knows about one name. The first public definition
+// is the one which counts; any more are kept so they can be reported.
+//
+#[derive(Debug)]
+pub struct Symbol {
+    pub name: String,
+
+    // The module a local (LPUBDEF or LEXTDEF) symbol belongs to; only that
+    // module can see it
+    pub module: Option<usize>,
+
+    pub publics: Vec<PublicDef>,
+    pub commons: Vec<CommonDef>,
+    pub references: Vec<Reference>,
+}
+
+impl Symbol {
+    pub fn definition(&self) -> Option<&PublicDef> {
+        self.publics.first()
+    }
+
+    // Defined by a public, or by a communal definition which will have
+    // space allocated for it if nothing else defines it
+    //
+    pub fn is_defined(&self) -> bool {
+        !self.publics.is_empty() || !self.commons.is_empty()
+    }
+}
+
+// A symbol which nothing defines, with each module which refers to it and
+// the offset in the module of the record which does
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Unresolved {
+    pub name: String,
+    pub references: Vec<(String, usize)>,
+}
+
+impl fmt::Display for Unresolved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unresolved external {}", self.name)?;
+        for (origin, offset) in &self.references {
+            write!(f, "\n    referenced by {} at {:08x}", origin, offset)?;
+        }
+        Ok(())
+    }
+}
+
+// A public defined by more than one module. The first definition is the
+// one which is used; `others` are the modules which define it again.
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Duplicate {
+    pub name: String,
+    pub first: String,
+    pub others: Vec<String>,
+}
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is defined in {} and {}", self.name, self.first, self.others.join(", "))
+    }
+}
+
+// Every symbol defined or referenced by the modules in the link, in the
+// order they were first seen. Each module has a scope of its own for its
+// local symbols, so that two modules' statics of the same name are two
+// symbols; a local name hides nothing in the global scope.
+//
+#[derive(Debug)]
+#[derive(Default)]
+pub struct SymbolTable {
+    pub symbols: Vec<Symbol>,
+    index: HashMap<String, SymbolId>,
+    locals: HashMap<(usize, String), SymbolId>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    pub fn id(&self, name: &str) -> Option<SymbolId> {
+        self.index.get(name).copied()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.id(name).map(|id| &self.symbols[id])
+    }
+
+    // A module's local symbol
+    //
+    pub fn local(&self, module: usize, name: &str) -> Option<&Symbol> {
+        self.locals.get(&(module, name.to_string())).map(|id| &self.symbols[*id])
+    }
+
+    fn new_symbol(&mut self, name: &str, module: Option<usize>) -> SymbolId {
+        self.symbols.push(Symbol{ name: name.to_string(), module, publics: Vec::new(), commons: Vec::new(), references: Vec::new() });
+        self.symbols.len() - 1
+    }
+
+    // The symbol called `name`, created if it hasn't been seen yet
+    //
+    pub fn intern(&mut self, name: &str) -> SymbolId {
+        if let Some(id) = self.id(name) {
+            return id;
+        }
+
+        let id = self.new_symbol(name, None);
+        self.index.insert(name.to_string(), id);
+        id
+    }
+
+    // The local symbol `name` of module `module`, created if need be
+    //
+    pub fn intern_local(&mut self, module: usize, name: &str) -> SymbolId {
+        if let Some(id) = self.locals.get(&(module, name.to_string())) {
+            return *id;
+        }
+
+        let id = self.new_symbol(name, Some(module));
+        self.locals.insert((module, name.to_string()), id);
+        id
+    }
+
+    // Symbols which are referenced but which nothing defines
+    //
+    pub fn undefined(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(|symbol| !symbol.references.is_empty() && !symbol.is_defined())
+    }
+
+    // Enter the definitions and references of module number `index`, and
+    // number its externals
+    //
+    pub fn add_module(&mut self, index: usize, module: &mut Module) -> Result<(), LinkError> {
+        let mut externs = Vec::new();
+
+        for (offset, _, record) in &module.records {
+            let offset = *offset;
+
+            match record {
+                Record::PUBDEF{ group, seg, frame, publics } | Record::LPUBDEF{ group, seg, frame, publics } => {
+                    let local = matches!(record, Record::LPUBDEF{ .. });
+                    for public in publics {
+                        let id = match local {
+                            true => self.intern_local(index, &public.name),
+                            false => self.intern(&public.name),
+                        };
+                        self.symbols[id].publics.push(PublicDef{
+                            module: index,
+                            group: *group,
+                            seg: *seg,
+                            frame: *frame,
+                            offset: public.offset,
+                            local,
+                        });
+                    }
+                },
+                Record::EXTDEF{ externs: names } | Record::LEXTDEF{ externs: names } => {
+                    let local = matches!(record, Record::LEXTDEF{ .. });
+                    for ext in names {
+                        let id = match local {
+                            true => self.intern_local(index, &ext.name),
+                            false => self.intern(&ext.name),
+                        };
+                        self.symbols[id].references.push(Reference{ module: index, offset, local });
+                        externs.push(id);
+                    }
+                },
+                Record::CEXTDEF{ externs: names } => for ext in names {
+                    let id = self.intern(module.lname(ext.name)?);
+                    self.symbols[id].references.push(Reference{ module: index, offset, local: false });
+                    externs.push(id);
+                },
+                Record::COMDEF{ commons } => for common in commons {
+                    let id = self.intern(&common.name);
+                    self.symbols[id].commons.push(Self::common(index, common, offset)?);
+                    externs.push(id);
+                },
+                _ => (),
+            }
+        }
+
+        module.externs = externs;
+        Ok(())
+    }
+
+    // Data types 0x01 to 0x5f are Borland segment indices, which are
+    // near
+    //
+    fn common(module: usize, common: &Comdef, offset: usize) -> Result<CommonDef, LinkError> {
+        match common.datatype {
+            0x61 => Ok(CommonDef{ module, far: true, length: common.length }),
+            0x62 | 0x01..=0x5f => Ok(CommonDef{ module, far: false, length: common.length }),
+            datatype => Err(LinkError::with_offset(&format!("COMDEF {} has unknown data type ${:02x}", common.name, datatype), offset)),
+        }
+    }
+}
+
+// Where one module's SEGDEF went: into output segment `segment`,
+// starting `offset` bytes in
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Placement {
+    pub segment: usize,
+    pub offset: u64,
+}
+
+// A module's part of an output segment
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Contribution {
+    pub module: usize,
+    pub segdef: usize,
+    pub offset: u64,
+    pub length: u64,
+}
+
+// A segment of the output, made of every SEGDEF with its name and class
+// which the combine types allow to be put together
+//
+#[derive(Debug)]
+pub struct Segment {
+    pub name: String,
+    pub class: String,
+    pub combine: Combine,
+
+    // The strictest alignment any of the pieces asks for, which the
+    // segment as a whole must start on
+    pub align: u64,
+
+    pub length: u64,
+    pub contributions: Vec<Contribution>,
+
+    // The segment's address in the image, once it's been laid out
+    pub base: u64,
+
+    // Where an absolute segment is in memory. It's addressed from its own
+    // frame, and takes no space in the image.
+    pub absolute: Option<AbsoluteSeg>,
+
+    // The overlay the segment is in, or 0 for the root
+    pub overlay: u16,
+
+    // The frame the segment is addressed from, where that isn't the
+    // paragraph it starts in: the overlay area's, for a segment of an
+    // overlay, or one a control script gives
+    pub addressed_from: Option<u16>,
+}
+
+impl Segment {
+    // The paragraph the segment is addressed from, and how far into it
+    // the segment starts. A segment above 1M, or below the frame it's
+    // addressed from, has no frame at all.
+    //
+    pub fn frame(&self) -> Result<u16, LinkError> {
+        match (&self.absolute, self.addressed_from) {
+            (Some(abs), _) => Ok(abs.frame),
+            (None, Some(frame)) => Ok(frame),
+            (None, None) => u16::try_from(self.base / 16)
+                .map_err(|_| LinkError::new(&format!("segment {} ({}) is above 1M", self.name, self.class))),
+        }
+    }
+
+    pub fn frame_offset(&self) -> Result<u64, LinkError> {
+        let frame = self.frame()?;
+        self.base.checked_sub(frame as u64 * 16)
+            .ok_or_else(|| LinkError::new(&format!("segment {} ({}) is below its frame {:04X}", self.name, self.class, frame)))
+    }
+}
+
+// A frame, and whether it's fixed in memory (that of an absolute segment
+// or symbol) rather than moving with the program when DOS loads it
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Frame {
+    pub paragraph: u16,
+    pub fixed: bool,
+}
+
+// A fixup worked out to where it points: the frame its target is
+// addressed from, and the target's address in the image. `record` is
+// the index in its module's records of the data record it patches.
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ResolvedFixup {
+    pub record: usize,
+    pub data_offset: usize,
+    pub location: FixupLocation,
+    pub is_seg_relative: bool,
+    pub frame: u16,
+    pub fixed_frame: bool,
+    pub target: u64,
+
+    // The symbol the target is, if it's an external
+    pub symbol: Option<SymbolId>,
+
+    // The overlay the target is in, or 0 for the root
+    pub overlay: u16,
+}
+
+// A word of the image which holds a paragraph, and so must have the
+// load segment added to it when DOS loads the program: an MZ relocation
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Relocation {
+    pub segment: u16,
+    pub offset: u16,
+}
+
+// The program as it will be loaded, from the start of its first segment,
+// and the words in it which need relocating. An overlay's image is loaded
+// at `base`, the start of the overlay area, and its relocations are from
+// there. `loaded` is how far into the data the data records reach.
+//
+#[derive(Debug)]
+pub struct Image {
+    pub base: u64,
+    pub data: Vec<u8>,
+    pub relocations: Vec<Relocation>,
+    pub loaded: usize,
+}
+
+impl Image {
+    // Add `value` to the little-endian field of `size` bytes at `at`,
+    // throwing away any carry out of it. The object's data may already
+    // hold part of the value, as MASM leaves some displacements there.
+    //
+    fn add(&mut self, at: u64, size: usize, value: u64) {
+        let at = (at - self.base) as usize;
+        let field = &mut self.data[at..at + size];
+        let mut carry = value;
+
+        for byte in field.iter_mut() {
+            let sum = *byte as u64 + (carry & 0xff);
+            *byte = sum as u8;
+            carry = (carry >> 8) + (sum >> 8);
+        }
+    }
+
+    // Drop the zeros at the end of the image, which needn't be stored as
+    // DOS can be asked for the memory instead, returning how many
+    // paragraphs that is. Zeros a data record put there and relocated
+    // words stay, as a program may count on them being in the file.
+    //
+    pub fn trim(&mut self) -> u16 {
+        let length = self.data.len();
+        let relocated = self.relocations.iter()
+            .map(|reloc| reloc.segment as usize * 16 + reloc.offset as usize + 2)
+            .max()
+            .unwrap_or(0);
+        let used = self.data.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+
+        self.data.truncate(used.max(relocated).max(self.loaded).min(length));
+        (length - self.data.len()).div_ceil(16) as u16
+    }
+}
+
+// Follow iterated data through its encoding, each block being a repeat
+// count and block count (`header` bytes together) and then either more
+// blocks or a length byte and literal data, to find the expanded offset
+// of the `size` bytes at `at` in the record. `enc` is how far into the
+// record `blocks` start, and `exp` how far into the expanded data.
+//
+fn find_in_iterated(blocks: &[IteratedBlock], header: usize, enc: &mut usize, mut exp: u64, repeated: bool, at: usize, size: usize) -> Option<u64> {
+    for block in blocks {
+        let repeated = repeated || block.repeat != 1;
+        *enc += header;
+
+        match &block.content {
+            IteratedContent::Data(data) => {
+                *enc += 1;
+                if !repeated && at >= *enc && at + size <= *enc + data.len() {
+                    return Some(exp + (at - *enc) as u64);
+                }
+                *enc += data.len();
+            },
+            IteratedContent::Blocks(inner) => {
+                if let Some(found) = find_in_iterated(inner, header, enc, exp, repeated, at, size) {
+                    return Some(found);
+                }
+            },
+        }
+
+        exp += block.expanded_len();
+    }
+
+    None
+}
+
+// The four target and four frame threads of a module. Threads are set
+// by FIXUPP subrecords, and last until they're set again or the module
+// ends, so a thread set in one FIXUPP may be used in the next.
+//
+#[derive(Default)]
+struct Threads {
+    targets: [Option<(TargetMethod, usize)>; 4],
+    frames: [Option<(FrameMethod, Option<usize>)>; 4],
+}
+
+// A group of output segments which are addressed from one frame
+//
+#[derive(Debug)]
+pub struct Group {
+    pub name: String,
+    pub segments: Vec<usize>,
+
+    // The paragraph the group is addressed from: that of its lowest
+    // segment, once the segments have been laid out
+    pub frame: u16,
+}
+
+// The boundary a SEGDEF's alignment asks for. An absolute segment has
+// no alignment of its own; it's treated as a paragraph.
+//
+pub fn alignment(align: &Align) -> u64 {
+    match align {
+        Align::Byte => 1,
+        Align::Word => 2,
+        Align::Dword => 4,
+        Align::Paragraph | Align::Absolute => 16,
+        Align::Page => 256,
+    }
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    value.div_ceil(align) * align
+}
+
+// What a MemoryModel COMENT says a module was compiled for: its memory
+// model and its processor, as far as it says. Other letters, such as O
+// for optimized, don't matter to the link.
+//
+pub fn memory_model(text: &str) -> (Option<&'static str>, Option<&'static str>) {
+    let model = text.chars().find_map(|c| match c {
+        's' => Some("small"),
+        'm' => Some("medium"),
+        'c' => Some("compact"),
+        'l' => Some("large"),
+        'h' => Some("huge"),
+        _ => None,
+    });
+    let cpu = text.chars().find_map(|c| match c {
+        '0' => Some("8086"),
+        '1' => Some("80186"),
+        '2' => Some("80286"),
+        '3' => Some("80386"),
+        _ => None,
+    });
+    (model, cpu)
+}
+
+// How libraries are told apart: by file name without the directory or
+// extension, ignoring case, so that SLIBCE and c:\lib\slibce.lib are the
+// same library
+//
+pub fn library_key(name: &str) -> String {
+    let base = name.rsplit(['/', '\\', ':']).next().unwrap_or(name);
+    let stem = match base.rfind('.') {
+        Some(dot) if dot > 0 => &base[..dot],
+        _ => base,
+    };
+    stem.to_ascii_lowercase()
+}
+
+// A library to search for modules defining what the objects leave
+// undefined
+//
+pub struct Library {
+    pub name: String,
+    pub image: Vec<u8>,
+
+    // The pages of the modules already taken into the link
+    loaded: HashSet<usize>,
+}
+
+// Calls into overlays are made with INT 3Fh, as MS LINK makes them, and
+// the overlay manager finds where to load overlays from this symbol
+//
+pub const OVERLAY_INTERRUPT: u8 = 0x3f;
+pub const OVERLAY_BASE: &str = "$$OVLBASE";
+
+// The modules in the link and the symbols they define and use.
+//
+// The same inputs always link to the same bytes. Everything which decides
+// the output is kept in the order it was added, and the hash tables here
+// are only looked things up in, never walked.
+//
+pub struct Linker {
+    pub options: ParseOptions,
+    pub modules: Vec<Module>,
+    pub symbols: SymbolTable,
+    pub libraries: Vec<Library>,
+    pub segments: Vec<Segment>,
+    pub groups: Vec<Group>,
+
+    // The segments in the order they're laid out in the image
+    pub order: Vec<usize>,
+
+    // What a control script asks for: where the image starts, segments
+    // and groups at addresses of their own, and memory to keep clear
+    pub script: LinkScript,
+
+    // How SEGDEFs with combine codes 4 and 7 are combined. The spec has
+    // them public, as MS LINK does, but some older tools used them
+    // otherwise: as private or as common, say.
+    pub combine_4: Combine,
+    pub combine_7: Combine,
+
+    // Segment names or classes to put first, in this order, ahead of any
+    // other ordering; and whether to order segments as DOSSEG does even
+    // if no module asks for it
+    pub ordering: Vec<String>,
+    pub dosseg: bool,
+
+    // Problems found along the way, to be reported as the link goes. Which
+    // of them are warnings and which errors is up to the caller.
+    pub diagnostics: Diagnostics,
+
+    // How much of what the link does to note in `trace`: at 1, the modules
+    // taken from libraries and where each segment went; at 2, how every
+    // fixup was resolved as well
+    pub verbose: u8,
+    pub trace: Vec<String>,
+
+    // The symbol to start the program at, rather than the main module's
+    // start address
+    pub entry: Option<String>,
+
+    // Warn about publics defined more than once, using the first
+    // definition, rather than failing the link
+    pub allow_duplicates: bool,
+
+    // Default libraries not to search: all of them, or those named
+    pub no_default_libraries: bool,
+    pub ignore_libraries: Vec<String>,
+
+    // The byte to pad the gaps alignment leaves between segments, and
+    // between the pieces of a segment, with
+    pub fill: u8,
+
+    // The interrupt calls into overlays are made through, and the module
+    // which holds the overlay area, once there are overlays
+    pub overlay_interrupt: u8,
+    pub overlay_area: Option<usize>,
+
+    // The default libraries already asked for, by library_key
+    requested: HashSet<String>,
+}
+
+impl Linker {
+    pub fn new(options: ParseOptions) -> Linker {
+        Linker{
+            options,
+            modules: Vec::new(),
+            symbols: SymbolTable::new(),
+            libraries: Vec::new(),
+            segments: Vec::new(),
+            groups: Vec::new(),
+            order: Vec::new(),
+            script: LinkScript::default(),
+            combine_4: Combine::Public{ raw: 4 },
+            combine_7: Combine::Public{ raw: 7 },
+            ordering: Vec::new(),
+            dosseg: false,
+            diagnostics: Diagnostics::new(),
+            verbose: 0,
+            trace: Vec::new(),
+            entry: None,
+            allow_duplicates: false,
+            no_default_libraries: false,
+            ignore_libraries: Vec::new(),
+            fill: 0,
+            overlay_interrupt: OVERLAY_INTERRUPT,
+            overlay_area: None,
+            requested: HashSet::new(),
+        }
+    }
+
+    fn parse_library<'a>(&self, image: &'a [u8]) -> Result<libfile::Parser<'a>, LinkError> {
+        let mut lib = libfile::Parser::new(image)?;
+        lib.codepage = self.options.codepage;
+        Ok(lib)
+    }
+
+    // Add a library to be searched. Libraries are searched in the order
+    // they're added.
+    //
+    pub fn add_library(&mut self, name: &str, image: Vec<u8>) -> Result<(), LinkError> {
+        self.parse_library(&image).map_err(|err| err.in_module(name))?;
+        self.libraries.push(Library{ name: name.to_string(), image, loaded: HashSet::new() });
+        Ok(())
+    }
+
+    // The module of library `index` which defines `name`, as its origin
+    // and its object, unless it's already been loaded
+    //
+    fn find_in_library(&mut self, index: usize, name: &str) -> Result<Option<(String, Vec<u8>)>, LinkError> {
+        let library = &self.libraries[index];
+        let lib = self.parse_library(&library.image)?;
+
+        let (page, found) = match lib.find_symbol(name, &self.options).map_err(|err| err.in_module(&library.name))? {
+            Some(module) if !library.loaded.contains(&module.page) => {
+                (module.page, (format!("{}({})", library.name, module.name), module.image.to_vec()))
+            },
+            _ => return Ok(None),
+        };
+
+        self.libraries[index].loaded.insert(page);
+        Ok(Some(found))
+    }
+
+    // Search the libraries for modules defining whatever is undefined,
+    // and add them to the link. A module which is added may need more
+    // symbols, so this goes on until a search finds nothing new. Returns
+    // the number of modules added.
+    //
+    pub fn resolve(&mut self) -> Result<usize, LinkError> {
+        let mut added = 0;
+
+        loop {
+            let mut undefined: Vec<String> = self.symbols.undefined()
+                .filter(|symbol| symbol.module.is_none())
+                .map(|symbol| symbol.name.clone())
+                .collect();
+            let before = added;
+
+            // the entry point may need to come from a library too
+            //
+            if let Some(entry) = &self.entry {
+                if !self.symbols.get(entry).is_some_and(|symbol| symbol.is_defined()) {
+                    undefined.push(entry.clone());
+                }
+            }
+
+            for name in undefined {
+                // a module added for an earlier name may define this one
+                //
+                if self.symbols.get(&name).is_some_and(|symbol| symbol.is_defined()) {
+                    continue;
+                }
+
+                for index in 0..self.libraries.len() {
+                    if let Some((origin, obj)) = self.find_in_library(index, &name)? {
+                        if self.verbose >= 1 {
+                            self.trace.push(format!("{} loaded for {}", origin, name));
+                        }
+                        self.add_object(&origin, obj)?;
+                        added += 1;
+                        break;
+                    }
+                }
+            }
+
+            if added == before {
+                return Ok(added);
+            }
+        }
+    }
+
+    // The default libraries the modules name, in the order they name them,
+    // which haven't been asked for or added already and aren't ignored
+    //
+    pub fn default_libraries(&self) -> Vec<String> {
+        if self.no_default_libraries {
+            return Vec::new();
+        }
+
+        let mut names: Vec<String> = Vec::new();
+        let records = self.modules.iter().flat_map(|module| module.records.iter());
+
+        for (_, _, record) in records {
+            if let Record::COMENT{ coment: Coment::DefaultLibrary{ name }, .. } = record {
+                let key = library_key(name);
+                let seen = self.requested.contains(&key) ||
+                    self.libraries.iter().any(|library| library_key(&library.name) == key) ||
+                    self.ignore_libraries.iter().any(|ignored| library_key(ignored) == key) ||
+                    names.iter().any(|name| library_key(name) == key);
+
+                if !seen {
+                    names.push(name.clone());
+                }
+            }
+        }
+
+        names
+    }
+
+    // Search libraries as `resolve` does, also taking in the default
+    // libraries the modules ask for, as MS LINK does once the libraries
+    // it was given are exhausted. `open` finds a library by the name a
+    // module gives, returning its path and contents, or None if there's
+    // no such library, which is warned about.
+    //
+    pub fn resolve_with_defaults<F>(&mut self, mut open: F) -> Result<usize, LinkError>
+    where
+        F: FnMut(&str) -> Result<Option<(String, Vec<u8>)>, LinkError>
+    {
+        let mut added = self.resolve()?;
+
+        loop {
+            let names = self.default_libraries();
+            if names.is_empty() {
+                return Ok(added);
+            }
+
+            for name in names {
+                self.requested.insert(library_key(&name));
+                match open(&name)? {
+                    Some((path, image)) => self.add_library(&path, image)?,
+                    None => self.diagnostics.warn("nolib", Diagnostic::new(&format!("cannot find library {}", name))),
+                }
+            }
+
+            added += self.resolve()?;
+        }
+    }
+
+    // Add an object to the link, returning its module number. The object
+    // may be lent or given; one with a link pass separator is kept for
+    // pass 2, which it's cheaper to be given.
+    //
+    pub fn add_object<'o>(&mut self, origin: &str, obj: impl Into<Cow<'o, [u8]>>) -> Result<usize, LinkError> {
+        self.add_overlay(origin, obj, 0)
+    }
+
+    // Add an object whose code goes in overlay `overlay`, or in the root
+    // if that's 0. Only code segments, those whose class ends in CODE, are
+    // overlaid; the rest of the module goes in the root.
+    //
+    pub fn add_overlay<'o>(&mut self, origin: &str, obj: impl Into<Cow<'o, [u8]>>, overlay: u16) -> Result<usize, LinkError> {
+        if overlay != 0 && self.overlay_area.is_none() {
+            self.add_overlay_area();
+        }
+
+        let mut module = Module::parse(origin, obj.into(), &self.options, &mut self.diagnostics)?;
+        let index = self.modules.len();
+        module.overlay = overlay;
+
+        self.symbols.add_module(index, &mut module)?;
+        self.modules.push(module);
+        Ok(index)
+    }
+
+    // The overlay area, where each overlay is loaded in turn, as a module
+    // of its own with one segment. It's laid out with the code, and as
+    // long as the longest overlay. Its start is the public $$OVLBASE, for
+    // the overlay manager to load overlays at.
+    //
+    fn add_overlay_area(&mut self) {
+        let index = self.modules.len();
+        let id = self.symbols.intern(OVERLAY_BASE);
+        self.symbols.symbols[id].publics.push(PublicDef{ module: index, group: None, seg: Some(1), frame: None, offset: 0, local: false });
+
+        self.modules.push(Module{
+            name: "overlays".to_string(),
+            origin: "overlay area".to_string(),
+            records: Vec::new(),
+            lnames: ["OVERLAY_AREA", "CODE"].iter().map(|name| name.to_string()).collect(),
+            segdefs: vec![Segdef{ name: Some(1), class: Some(2), align: Align::Paragraph, combine: Combine::Private, ..Segdef::empty() }],
+            grpdefs: Vec::new(),
+            placements: Vec::new(),
+            groups: Vec::new(),
+            externs: Vec::new(),
+            deferred: None,
+            overlay: 0,
+        });
+        self.overlay_area = Some(index);
+    }
+
+    // Link as a control script asks. The symbols it defines are publics
+    // of a module of their own, as if an object had defined them.
+    //
+    pub fn add_script(&mut self, script: LinkScript) {
+        let index = self.modules.len();
+        for (name, frame, offset) in &script.symbols {
+            let id = self.symbols.intern(name);
+            self.symbols.symbols[id].publics.push(PublicDef{ module: index, group: None, seg: None, frame: Some(*frame), offset: *offset as u32, local: false });
+        }
+
+        self.modules.push(Module{
+            name: "script".to_string(),
+            origin: "control script".to_string(),
+            records: Vec::new(),
+            lnames: Vec::new(),
+            segdefs: Vec::new(),
+            grpdefs: Vec::new(),
+            placements: Vec::new(),
+            groups: Vec::new(),
+            externs: Vec::new(),
+            deferred: None,
+            overlay: 0,
+        });
+        self.script = script;
+    }
+
+    // How many overlays there are
+    //
+    pub fn overlay_count(&self) -> u16 {
+        self.modules.iter().map(|module| module.overlay).max().unwrap_or(0)
+    }
+
+    // Give space to the communal variables nothing else defines, as a
+    // module of their own which defines them all. Each gets the largest
+    // size it's declared with. Near ones go in c_common, in DGROUP; if any
+    // declaration is near, the variable must be, as near references can
+    // only reach DGROUP. Far ones are packed into FAR_BSS segments of up
+    // to 64K each.
+    //
+    pub fn allocate_communals(&mut self) -> Result<(), LinkError> {
+        let index = self.modules.len();
+        let mut near_length = 0;
+        let mut far_lengths: Vec<u64> = Vec::new();
+        let mut publics = Vec::new();
+
+        for (id, symbol) in self.symbols.symbols.iter().enumerate() {
+            if !symbol.publics.is_empty() || symbol.commons.is_empty() {
+                continue;
+            }
+
+            let length = symbol.commons.iter().map(|common| common.length as u64).max().unwrap_or(0);
+            let align = if length > 1 { 2 } else { 1 };
+
+            let (seg, group, offset) = if symbol.commons.iter().any(|common| !common.far) {
+                let offset = align_up(near_length, align);
+                near_length = offset + length;
+                (1, Some(1), offset)
+            } else {
+                if length > 0x10000 {
+                    return Err(LinkError::new(&format!("far communal variable {} is larger than 64K", symbol.name)));
+                }
+
+                let fits = far_lengths.last().is_some_and(|used| align_up(*used, align) + length <= 0x10000);
+                if !fits {
+                    far_lengths.push(0);
+                }
+                let used = far_lengths.last_mut().unwrap();
+                let offset = align_up(*used, align);
+                *used = offset + length;
+                (far_lengths.len() + 1, None, offset)
+            };
+
+            publics.push((id, PublicDef{ module: index, group, seg: Some(seg), frame: None, offset: offset as u32, local: false }));
+        }
+
+        if publics.is_empty() {
+            return Ok(());
+        }
+
+        if near_length > 0x10000 {
+            return Err(LinkError::new(&format!("near communal variables need {} bytes, more than 64K", near_length)));
+        }
+
+        let segdef = |name, class, align, combine, length| Segdef{ name: Some(name), class: Some(class), align, combine, length, ..Segdef::empty() };
+        let mut segdefs = vec![segdef(1, 2, Align::Word, Combine::Public{ raw: 2 }, near_length)];
+        segdefs.extend(far_lengths.iter().map(|length| segdef(4, 4, Align::Paragraph, Combine::Private, *length)));
+
+        for (id, public) in publics {
+            self.symbols.symbols[id].publics.push(public);
+        }
+
+        self.modules.push(Module{
+            name: "communals".to_string(),
+            origin: "communal variables".to_string(),
+            records: Vec::new(),
+            lnames: ["c_common", "BSS", "DGROUP", "FAR_BSS"].iter().map(|name| name.to_string()).collect(),
+            segdefs,
+            grpdefs: vec![(3, vec![GrpComponent::Segdef{ index: 1 }])],
+            placements: Vec::new(),
+            groups: Vec::new(),
+            externs: Vec::new(),
+            deferred: None,
+            overlay: 0,
+        });
+
+        Ok(())
+    }
+
+    // Put the SEGDEFs of every module into output segments. SEGDEFs with
+    // the same name and class go together, depending on their combine
+    // type: public and stack pieces are put one after another, each on
+    // its own alignment, and common pieces all start at the beginning,
+    // the segment being as long as the longest. Private SEGDEFs (and
+    // absolute ones, which have a place of their own) are never combined.
+    //
+    pub fn combine_segments(&mut self) -> Result<(), LinkError> {
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut by_name: HashMap<(String, String, u16), usize> = HashMap::new();
+
+        let combine_as = |combine: &Combine| match combine {
+            Combine::Public{ raw: 4 } => self.combine_4.clone(),
+            Combine::Public{ raw: 7 } => self.combine_7.clone(),
+            combine => combine.clone(),
+        };
+
+        for (index, module) in self.modules.iter_mut().enumerate() {
+            let mut placements = Vec::new();
+
+            for (segdef_index, segdef) in module.segdefs.iter().enumerate() {
+                let (name, class) = module.segment_names(segdef)?;
+                let align = alignment(&segdef.align);
+                let combine = combine_as(&segdef.combine);
+                let private = combine == Combine::Private || segdef.align == Align::Absolute;
+
+                // segments of different overlays are never combined
+                //
+                let overlay = match class.ends_with("CODE") && segdef.align != Align::Absolute {
+                    true => module.overlay,
+                    false => 0,
+                };
+
+                let existing = match private {
+                    true => None,
+                    false => by_name.get(&(name.clone(), class.clone(), overlay)).copied(),
+                };
+
+                let segment = match existing {
+                    Some(segment) => {
+                        let kind = |combine: &Combine| std::mem::discriminant(combine);
+                        if kind(&segments[segment].combine) != kind(&combine) {
+                            return Err(LinkError::new(&format!(
+                                "segment {} ({}) has combine type {} here but {} elsewhere",
+                                name, class, segdef.combine.raw(), segments[segment].combine.raw()
+                            )).in_module(&module.origin));
+                        }
+                        segment
+                    },
+                    None => {
+                        segments.push(Segment{
+                            name: name.clone(),
+                            class: class.clone(),
+                            combine: combine.clone(),
+                            align,
+                            length: 0,
+                            contributions: Vec::new(),
+                            base: 0,
+                            absolute: segdef.abs.clone().filter(|_| segdef.align == Align::Absolute),
+                            overlay,
+                            addressed_from: None,
+                        });
+                        if !private {
+                            by_name.insert((name, class, overlay), segments.len() - 1);
+                        }
+                        segments.len() - 1
+                    },
+                };
+
+                let segment_ref = &mut segments[segment];
+                let offset = match combine {
+                    Combine::Common => 0,
+                    _ => align_up(segment_ref.length, align),
+                };
+
+                segment_ref.align = segment_ref.align.max(align);
+                segment_ref.length = segment_ref.length.max(offset + segdef.length);
+                segment_ref.contributions.push(Contribution{ module: index, segdef: segdef_index + 1, offset, length: segdef.length });
+                placements.push(Placement{ segment, offset });
+            }
+
+            module.placements = placements;
+        }
+
+        self.segments = segments;
+        Ok(())
+    }
+
+    // Gather the GRPDEFs of every module into output groups. A group is
+    // everything any module puts in it; a segment may be named by many
+    // modules, but it's only in the group once.
+    //
+    pub fn combine_groups(&mut self) -> Result<(), LinkError> {
+        let mut groups: Vec<Group> = Vec::new();
+
+        for module in self.modules.iter_mut() {
+            let mut indices = Vec::new();
+
+            for (name, components) in &module.grpdefs {
+                let name = module.lname(*name)?.to_string();
+                let group = match groups.iter().position(|group| group.name == name) {
+                    Some(group) => group,
+                    None => {
+                        groups.push(Group{ name: name.clone(), segments: Vec::new(), frame: 0 });
+                        groups.len() - 1
+                    },
+                };
+
+                for component in components {
+                    let segment = match component {
+                        GrpComponent::Segdef{ index } => module.placements.get(index.wrapping_sub(1))
+                            .map(|placement| placement.segment)
+                            .ok_or_else(|| LinkError::new(&format!("group {} has segment index {} out of range", name, index)).in_module(&module.origin))?,
+                        _ => return Err(LinkError::new(&format!("group {} has a component which isn't a segment", name)).in_module(&module.origin)),
+                    };
+
+                    if !groups[group].segments.contains(&segment) {
+                        groups[group].segments.push(segment);
+                    }
+                }
+
+                indices.push(group);
+            }
+
+            module.groups = indices;
+        }
+
+        self.groups = groups;
+        Ok(())
+    }
+
+    // Give each group the lowest frame any of its segments is addressed
+    // from, which every segment in it must be reachable from with a 16-bit
+    // offset
+    //
+    pub fn frame_groups(&mut self) -> Result<(), LinkError> {
+        for group in self.groups.iter_mut() {
+            let segments = group.segments.iter().map(|segment| &self.segments[*segment]);
+
+            let frames = segments.clone().map(|segment| segment.frame()).collect::<Result<Vec<u16>, LinkError>>()?;
+            let frame = frames.into_iter().min().map_or(0, u64::from);
+            let end = segments.map(|segment| segment.base + segment.length).max().unwrap_or(0);
+
+            if end - frame * 16 > 0x10000 {
+                return Err(LinkError::new(&format!("group {} is larger than 64K ({} bytes)", group.name, end - frame * 16)));
+            }
+
+            group.frame = u16::try_from(frame)
+                .map_err(|_| LinkError::new(&format!("group {} is above 1M", group.name)))?;
+        }
+
+        Ok(())
+    }
+
+    // The order segments go in the image. By default, segments of a class
+    // are together, classes in the order they were first seen, and
+    // segments in a class in the order they were first seen. DOSSEG
+    // reorders that, and `ordering` reorders the result. Absolute segments
+    // aren't in the image at all.
+    //
+    fn segment_order(&self) -> Vec<usize> {
+        let mut classes: Vec<&str> = Vec::new();
+        for segment in self.segments.iter().filter(|segment| segment.absolute.is_none()) {
+            if !classes.contains(&segment.class.as_str()) {
+                classes.push(&segment.class);
+            }
+        }
+
+        let mut order: Vec<usize> = classes.iter()
+            .flat_map(|class| (0..self.segments.len())
+                .filter(|index| self.segments[*index].absolute.is_none() && self.segments[*index].class == *class))
+            .collect();
+
+        if self.dosseg || self.wants_dosseg() {
+            order.sort_by_key(|index| self.dosseg_rank(*index));
+        }
+
+        if !self.ordering.is_empty() {
+            order.sort_by_key(|index| {
+                let segment = &self.segments[*index];
+                let by_name = self.ordering.iter().position(|name| name.eq_ignore_ascii_case(&segment.name));
+                let by_class = self.ordering.iter().position(|name| name.eq_ignore_ascii_case(&segment.class));
+                by_name.or(by_class).unwrap_or(self.ordering.len())
+            });
+        }
+
+        order
+    }
+
+    // Whether any module has a DOSSEG COMENT
+    //
+    fn wants_dosseg(&self) -> bool {
+        self.modules.iter()
+            .flat_map(|module| module.records.iter())
+            .any(|(_, _, record)| matches!(record, Record::COMENT{ coment: Coment::DosSeg, .. }))
+    }
+
+    // Where DOSSEG puts a segment: code (classes ending in CODE) first,
+    // then everything outside DGROUP, then DGROUP with BEGDATA first and
+    // BSS and STACK last
+    //
+    fn dosseg_rank(&self, index: usize) -> usize {
+        let segment = &self.segments[index];
+        let in_dgroup = self.groups.iter().any(|group| group.name == "DGROUP" && group.segments.contains(&index));
+
+        match segment.class.as_str() {
+            class if class.ends_with("CODE") => 0,
+            _ if !in_dgroup => 1,
+            "BEGDATA" => 2,
+            "BSS" => 4,
+            "STACK" => 5,
+            _ => 3,
+        }
+    }
+
+    // Give every segment its place in the image, one after another on
+    // their alignments, and then frame the groups around them. A segment
+    // is addressed from the paragraph it starts in, so must fit in 64K
+    // from there. A control script may have the image start elsewhere
+    // than 0, put some segments at addresses of their own, and keep the
+    // rest out of ranges it reserves.
+    //
+    pub fn layout(&mut self) -> Result<(), LinkError> {
+        let order = self.segment_order();
+        let origin = self.script.origin;
+        let mut base = origin.map_or(0, |origin| origin.linear);
+
+        for segment in self.segments.iter_mut() {
+            if let Some(abs) = &segment.absolute {
+                segment.base = abs.frame as u64 * 16 + abs.offset as u64;
+                if abs.offset as u64 + segment.length > 0x10000 {
+                    return Err(LinkError::new(&format!("segment {} ({}) is larger than 64K", segment.name, segment.class)));
+                }
+            }
+        }
+
+        let placed = self.place_segments(&order)?;
+        let mut taken: Vec<(u64, u64)> = self.script.reserved.clone();
+        taken.extend(placed.iter().map(|index| (self.segments[*index].base, self.segments[*index].base + self.segments[*index].length)));
+
+        // each overlay's segments are laid out from the start of the
+        // overlay area, which is as long as the longest overlay
+        //
+        let mut overlays = vec![0; self.overlay_count() as usize + 1];
+        let mut area_align = 16;
+        for index in &order {
+            let segment = &mut self.segments[*index];
+            if segment.overlay != 0 {
+                let end = &mut overlays[segment.overlay as usize];
+                segment.base = align_up(*end, segment.align);
+                *end = segment.base + segment.length;
+                area_align = area_align.max(segment.align);
+            }
+        }
+
+        let area = self.overlay_area.map(|module| self.modules[module].placements[0].segment);
+        if let Some(area) = area {
+            self.segments[area].length = overlays.iter().copied().max().unwrap_or(0);
+            self.segments[area].align = area_align;
+        }
+
+        for index in &order {
+            let segment = &mut self.segments[*index];
+            if segment.overlay != 0 || placed.contains(index) {
+                continue;
+            }
+
+            // skip over what's been placed or reserved, until the segment
+            // fits in between
+            //
+            segment.base = align_up(base, segment.align);
+            while let Some((_, end)) = taken.iter().find(|(start, end)| segment.base < *end && segment.base + segment.length.max(1) > *start) {
+                segment.base = align_up(*end, segment.align);
+            }
+            segment.addressed_from = origin.and_then(|origin| origin.frame);
+
+            base = segment.base + segment.length;
+        }
+
+        if let Some(area) = area {
+            let start = self.segments[area].base;
+            let frame = self.segments[area].frame().map_err(|err| self.segment_error(area, err))?;
+
+            for segment in self.segments.iter_mut().filter(|segment| segment.overlay != 0) {
+                segment.base += start;
+                segment.addressed_from = Some(frame);
+            }
+
+            for (overlay, length) in overlays.iter().enumerate().skip(1) {
+                if *length > 0x10000 {
+                    return Err(LinkError::new(&format!("overlay {} is larger than 64K ({} bytes)", overlay, length)));
+                }
+            }
+        }
+
+        // nothing past 1M can be addressed, as its frame wouldn't fit in
+        // 16 bits, and nothing more than 64K from its frame
+        //
+        for index in &order {
+            let segment = &self.segments[*index];
+            let end = segment.base.checked_add(segment.length);
+            if segment.absolute.is_none() && end.is_none_or(|end| end > 0x100000) {
+                let details = format!("segment {} ({}) runs past 1M", segment.name, segment.class);
+                return Err(self.segment_error(*index, LinkError::new(&details)));
+            }
+
+            let offset = segment.frame_offset().map_err(|err| self.segment_error(*index, err))?;
+            if segment.overlay == 0 && offset + segment.length > 0x10000 {
+                let details = match segment.addressed_from {
+                    Some(frame) => format!("segment {} ({}) is out of reach of frame {:04X}", segment.name, segment.class, frame),
+                    None => format!("segment {} ({}) is larger than 64K", segment.name, segment.class),
+                };
+                return Err(self.segment_error(*index, LinkError::new(&details)));
+            }
+        }
+
+        self.order = order;
+        self.frame_groups()
+    }
+
+    // An error about an output segment, put down to the first module with
+    // a piece of it
+    //
+    fn segment_error(&self, segment: usize, err: LinkError) -> LinkError {
+        match self.segments[segment].contributions.first() {
+            Some(piece) => err.in_module(&self.modules[piece.module].origin),
+            None => err,
+        }
+    }
+
+    // Put the segments the control script places where it says, returning
+    // which they are. A group's segments go one after another in layout
+    // order, the first at the address given. None may be in an overlay,
+    // below the origin, or on top of anything else placed or reserved.
+    //
+    fn place_segments(&mut self, order: &[usize]) -> Result<Vec<usize>, LinkError> {
+        let mut placed: Vec<usize> = Vec::new();
+
+        for (name, at) in &self.script.places {
+            let segments: Vec<usize> = match self.groups.iter().find(|group| group.name.eq_ignore_ascii_case(name)) {
+                Some(group) => order.iter().copied().filter(|index| group.segments.contains(index)).collect(),
+                None => order.iter().copied().filter(|index| self.segments[*index].name.eq_ignore_ascii_case(name)).collect(),
+            };
+            if segments.is_empty() {
+                return Err(LinkError::new(&format!("script places {}, which is not a segment or group in the image", name)));
+            }
+
+            let mut base = at.linear;
+            for (nth, index) in segments.into_iter().enumerate() {
+                let segment = &self.segments[index];
+                let describe = || format!("segment {} ({})", segment.name, segment.class);
+
+                if nth > 0 {
+                    base = align_up(base, segment.align);
+                }
+
+                if placed.contains(&index) {
+                    return Err(LinkError::new(&format!("{} is placed more than once", describe())));
+                }
+                if segment.overlay != 0 {
+                    return Err(LinkError::new(&format!("{} is in an overlay, so can't be placed", describe())));
+                }
+                if base % segment.align != 0 {
+                    return Err(LinkError::new(&format!("{} can't be placed at {:05X}; it's aligned on {}", describe(), base, segment.align)));
+                }
+                if self.script.origin.is_some_and(|origin| base < origin.linear) {
+                    return Err(LinkError::new(&format!("{} is placed below the origin", describe())));
+                }
+
+                let end = base + segment.length;
+                let overlaps = |start: u64, stop: u64| base < stop && end.max(base + 1) > start;
+                if self.script.reserved.iter().any(|(start, stop)| overlaps(*start, *stop)) ||
+                    placed.iter().any(|other| overlaps(self.segments[*other].base, self.segments[*other].base + self.segments[*other].length)) {
+                    return Err(LinkError::new(&format!("{} overlaps something else placed or reserved at {:05X}", describe(), base)));
+                }
+
+                let segment = &mut self.segments[index];
+                segment.base = base;
+                segment.addressed_from = at.frame;
+                placed.push(index);
+                base = end;
+            }
+        }
+
+        Ok(placed)
+    }
+
+    // How long the image is, up to the end of its last segment
+    //
+    pub fn image_length(&self) -> u64 {
+        self.segments.iter()
+            .filter(|segment| segment.absolute.is_none())
+            .map(|segment| segment.base + segment.length)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // The segment a module's SEGDEF (from 1) went into, and where in the
+    // segment its piece starts
+    //
+    pub fn placement(&self, module: usize, segdef: usize) -> Result<&Placement, LinkError> {
+        let module = &self.modules[module];
+        module.placements.get(segdef.wrapping_sub(1))
+            .ok_or_else(|| LinkError::new(&format!("segment index {} is out of range", segdef)).in_module(&module.origin))
+    }
+
+    // The frame and offset of `offset` into a module's SEGDEF, once the
+    // segments are laid out
+    //
+    pub fn segdef_address(&self, module: usize, segdef: usize, offset: u64) -> Result<(u16, u64), LinkError> {
+        let placement = self.placement(module, segdef)?;
+        let segment = &self.segments[placement.segment];
+        Ok((segment.frame()?, segment.frame_offset()? + placement.offset + offset))
+    }
+
+    // The overlay a module's SEGDEF went into
+    //
+    fn segdef_overlay(&self, module: usize, segdef: usize) -> Result<u16, LinkError> {
+        Ok(self.segments[self.placement(module, segdef)?.segment].overlay)
+    }
+
+    // The overlay a symbol is defined in
+    //
+    fn symbol_overlay(&self, id: SymbolId) -> Result<u16, LinkError> {
+        match self.symbols.symbols[id].definition() {
+            Some(PublicDef{ module, seg: Some(seg), .. }) => self.segdef_overlay(*module, *seg),
+            _ => Ok(0),
+        }
+    }
+
+    fn segdef_frame(&self, module: usize, segdef: usize) -> Result<Frame, LinkError> {
+        let segment = &self.segments[self.placement(module, segdef)?.segment];
+        Ok(Frame{ paragraph: segment.frame()?, fixed: segment.absolute.is_some() })
+    }
+
+    // The output group a module's GRPDEF (from 1) went into
+    //
+    pub fn group(&self, module: usize, grpdef: usize) -> Result<&Group, LinkError> {
+        let module = &self.modules[module];
+        module.groups.get(grpdef.wrapping_sub(1))
+            .map(|group| &self.groups[*group])
+            .ok_or_else(|| LinkError::new(&format!("group index {} is out of range", grpdef)).in_module(&module.origin))
+    }
+
+    // Where a symbol's definition is: the frame it's addressed from (its
+    // group's, if the PUBDEF gives one) and its address in the image
+    //
+    pub fn symbol_address(&self, id: SymbolId) -> Result<(u16, u64), LinkError> {
+        self.symbol_location(id).map(|(frame, linear)| (frame.paragraph, linear))
+    }
+
+    fn symbol_location(&self, id: SymbolId) -> Result<(Frame, u64), LinkError> {
+        let symbol = &self.symbols.symbols[id];
+        let def = match symbol.definition() {
+            Some(def) => def,
+            None if !symbol.commons.is_empty() => return Err(LinkError::new(&format!("communal variable {} has no space allocated", symbol.name))),
+            None => return Err(LinkError::new(&format!("{} is undefined", symbol.name))),
+        };
+
+        let (frame, linear) = match (def.seg, def.frame) {
+            (Some(seg), _) => {
+                let (_, offset) = self.segdef_address(def.module, seg, def.offset as u64)?;
+                let frame = self.segdef_frame(def.module, seg)?;
+                (frame, frame.paragraph as u64 * 16 + offset)
+            },
+            (None, Some(frame)) => (Frame{ paragraph: frame, fixed: true }, frame as u64 * 16 + def.offset as u64),
+            (None, None) => return Err(LinkError::new(&format!("{} has neither a segment nor a frame", symbol.name))),
+        };
+
+        match def.group {
+            Some(group) => Ok((Frame{ paragraph: self.group(def.module, group)?.frame, fixed: false }, linear)),
+            None => Ok((frame, linear)),
+        }
+    }
+
+    // The frame a target is addressed from and its address. Only the low
+    // two bits of the method say what the target is; the P bit has
+    // already decided whether there was a displacement to read.
+    //
+    fn target(&self, module: usize, method: TargetMethod, datum: usize, displacement: u32) -> Result<(Frame, u64), LinkError> {
+        let (frame, linear) = match method.raw() & 3 {
+            0 => {
+                let (_, offset) = self.segdef_address(module, datum, 0)?;
+                let frame = self.segdef_frame(module, datum)?;
+                (frame, frame.paragraph as u64 * 16 + offset)
+            },
+            1 => {
+                let frame = self.group(module, datum)?.frame;
+                (Frame{ paragraph: frame, fixed: false }, frame as u64 * 16)
+            },
+            _ => self.symbol_location(self.modules[module].external(datum)?)?,
+        };
+
+        Ok((frame, linear + displacement as u64))
+    }
+
+    // The frame a fixup's frame method gives. `previous` is the SEGDEF of
+    // the data record being fixed up, and `target` the target's frame.
+    //
+    fn frame(&self, module: usize, method: FrameMethod, datum: Option<usize>, previous: usize, target: Frame) -> Result<Frame, LinkError> {
+        let datum = || datum.ok_or_else(|| LinkError::new(&format!("frame method {:?} needs an index", method)));
+
+        match method {
+            FrameMethod::Segdef => self.segdef_frame(module, datum()?),
+            FrameMethod::Grpdef => Ok(Frame{ paragraph: self.group(module, datum()?)?.frame, fixed: false }),
+            FrameMethod::Extdef => Ok(self.symbol_location(self.modules[module].external(datum()?)?)?.0),
+            FrameMethod::PreviousDataRecord => self.segdef_frame(module, previous),
+            FrameMethod::Target => Ok(target),
+        }
+    }
+
+    fn resolve_fixup(&self, module: usize, fixup: &Fixup, threads: &Threads, record: usize, seg: usize) -> Result<ResolvedFixup, LinkError> {
+        let (tmethod, tdatum) = match (fixup.target_method, fixup.target_datum, fixup.target_thread) {
+            (Some(method), Some(datum), _) => (method, datum),
+            (_, _, Some(thread)) => threads.targets[thread]
+                .ok_or_else(|| LinkError::new(&format!("fixup uses target thread {} before it is set", thread)))?,
+            _ => return Err(LinkError::new("fixup has no target")),
+        };
+
+        let (fmethod, fdatum) = match (fixup.frame_method, fixup.frame_thread) {
+            (Some(method), _) => (method, fixup.frame_datum),
+            (None, Some(thread)) => threads.frames[thread]
+                .ok_or_else(|| LinkError::new(&format!("fixup uses frame thread {} before it is set", thread)))?,
+            (None, None) => return Err(LinkError::new("fixup has no frame")),
+        };
+
+        let (target_frame, target) = self.target(module, tmethod, tdatum, fixup.target_displacement)?;
+        let symbol = match tmethod.raw() & 3 {
+            2 => Some(self.modules[module].external(tdatum)?),
+            _ => None,
+        };
+        let overlay = match (tmethod.raw() & 3, symbol) {
+            (0, _) => self.segdef_overlay(module, tdatum)?,
+            (_, Some(id)) => self.symbol_overlay(id)?,
+            _ => 0,
+        };
+        let frame = self.frame(module, fmethod, fdatum, seg, target_frame)?;
+
+        Ok(ResolvedFixup{
+            record,
+            data_offset: fixup.data_offset,
+            location: fixup.location,
+            is_seg_relative: fixup.is_seg_relative,
+            frame: frame.paragraph,
+            fixed_frame: frame.fixed,
+            target,
+            symbol,
+            overlay,
+        })
+    }
+
+    // Work out the frame and target of every fixup in a module, once the
+    // segments are laid out. Each FIXUPP patches the LEDATA or LIDATA
+    // before it.
+    //
+    pub fn resolve_fixups(&self, module: usize) -> Result<Vec<ResolvedFixup>, LinkError> {
+        let mut threads = Threads::default();
+        let mut previous = None;
+        let mut resolved = Vec::new();
+        let origin = &self.modules[module].origin;
+
+        for (index, (offset, _, record)) in self.modules[module].records.iter().enumerate() {
+            match record {
+                Record::LEDATA{ seg, .. } | Record::LIDATA{ seg, .. } => previous = Some((index, *seg)),
+                Record::COMDAT{ .. } => previous = None,
+                Record::FIXUPP{ fixups } => for fixup in fixups {
+                    match fixup {
+                        FixupSubrecord::TargetThread{ method, thread, index } => threads.targets[*thread] = Some((*method, *index)),
+                        FixupSubrecord::FrameThread{ method, thread, index } => threads.frames[*thread] = Some((*method, *index)),
+                        FixupSubrecord::Fixup{ fixup } => {
+                            let fixed = match previous {
+                                Some((record, seg)) => self.resolve_fixup(module, fixup, &threads, record, seg),
+                                None => Err(LinkError::new(&format!("fixup at {:04x} does not follow a data record", fixup.data_offset))),
+                            };
+                            resolved.push(fixed.map_err(|err| LinkError{ offset: Some(*offset), ..err }.in_module(origin))?);
+                        },
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    // The frame and offset of a LEDATA or LIDATA record's data
+    //
+    fn data_address(&self, module: usize, record: usize) -> Result<(u16, u64), LinkError> {
+        match &self.modules[module].records[record].2 {
+            Record::LEDATA{ seg, offset, .. } | Record::LIDATA{ seg, offset, .. } => self.segdef_address(module, *seg, *offset as u64),
+            _ => Err(LinkError::new("fixup does not follow a data record")),
+        }
+    }
+
+    // The overlay a LEDATA or LIDATA record's data goes in
+    //
+    fn data_overlay(&self, module: usize, record: usize) -> Result<u16, LinkError> {
+        match &self.modules[module].records[record].2 {
+            Record::LEDATA{ seg, .. } | Record::LIDATA{ seg, .. } => self.segdef_overlay(module, *seg),
+            _ => Err(LinkError::new("fixup does not follow a data record")),
+        }
+    }
+
+    // Copy one of a module's data records into the image, or the window
+    // of it, that its segment is in, expanding iterated data
+    //
+    fn load_data(&self, module: usize, index: usize, image: &mut Image) -> Result<(), LinkError> {
+        let origin = &self.modules[module].origin;
+        let (offset, _, record) = &self.modules[module].records[index];
+        let (seg, start) = match record {
+            Record::LEDATA{ seg, offset, .. } | Record::LIDATA{ seg, offset, .. } => (*seg, *offset as u64),
+            _ => return Ok(()),
+        };
+
+        let room = self.modules[module].segdef(seg)?.length.saturating_sub(start);
+        let expanded;
+        let data = match record {
+            Record::LIDATA{ blocks, .. } => {
+                expanded = expand_iterated(blocks, room).map_err(|err| LinkError::with_offset(&err.details, *offset).in_module(origin))?;
+                &expanded
+            },
+            Record::LEDATA{ data, .. } => data,
+            _ => unreachable!(),
+        };
+
+        if data.len() as u64 > room {
+            return Err(LinkError::with_offset("data record runs past the end of its segment", *offset).in_module(origin));
+        }
+
+        let (frame, at) = self.data_address(module, index)?;
+        let at = (frame as u64 * 16 + at - image.base) as usize;
+        image.data[at..at + data.len()].copy_from_slice(data);
+        image.loaded = image.loaded.max(at + data.len());
+        Ok(())
+    }
+
+    // Where a fixup lands in the data its record describes. A LIDATA
+    // fixup's offset is into the record as encoded; it can only be applied
+    // to literal bytes which are written out once, as there's no saying
+    // which copy of a repeated block is meant.
+    //
+    fn fixup_data_offset(&self, module: usize, fixup: &ResolvedFixup) -> Result<u64, LinkError> {
+        match &self.modules[module].records[fixup.record] {
+            (_, rectype, Record::LIDATA{ blocks, .. }) => {
+                let header = if rectype & 1 != 0 { 6 } else { 4 };
+                find_in_iterated(blocks, header, &mut 0, 0, false, fixup.data_offset, fixup.location.size())
+                    .ok_or_else(|| LinkError::new(&format!("fixup at {:04x} is inside a repeated block of iterated data", fixup.data_offset)))
+            },
+            _ => Ok(fixup.data_offset as u64),
+        }
+    }
+
+    // Patch the bytes a fixup covers. A segment-relative fixup puts in the
+    // target's offset from its frame, and a self-relative one its distance
+    // from the end of the fixup; both take the frame for any paragraph
+    // part, which is also listed to be relocated, from `base`, the start
+    // of the image the fixup is in. `image` may be just a window of it.
+    //
+    fn apply_fixup(&self, module: usize, fixup: &ResolvedFixup, image: &mut Image, base: u64) -> Result<(), LinkError> {
+        let (frame, offset) = self.data_address(module, fixup.record)?;
+        let offset = offset + self.fixup_data_offset(module, fixup)?;
+        let at = frame as u64 * 16 + offset;
+
+        let overlay = self.data_overlay(module, fixup.record)?;
+
+        // the parser only warns about a fixup which runs past its data, so
+        // it mustn't be let write past that, or past the image
+        //
+        let end = fixup.data_offset + fixup.location.size();
+        let past_record = match &self.modules[module].records[fixup.record].2 {
+            Record::LEDATA{ data, .. } => end > data.len(),
+            _ => false,
+        };
+        if past_record || at < image.base || at + fixup.location.size() as u64 > image.base + image.data.len() as u64 {
+            return Err(LinkError::new(&format!("fixup at {:04x}..{:04x} runs past the end of its data record", fixup.data_offset, end)));
+        }
+
+        if fixup.overlay != 0 && fixup.overlay != overlay {
+            return self.overlay_call(fixup, at, image);
+        }
+
+        // a program linked at an origin is never relocated, so has no
+        // relocation table to find the fixup's place in
+        //
+        let relocatable = self.script.origin.is_none();
+        let (frame, offset) = match frame.checked_sub((base / 16) as u16) {
+            Some(frame) => (frame, offset),
+            None if !relocatable => (0, 0),
+            None => return Err(LinkError::new("fixup is outside its overlay")),
+        };
+
+        let value = match fixup.is_seg_relative {
+            true => fixup.target.wrapping_sub(fixup.frame as u64 * 16),
+            false => match fixup.location {
+                FixupLocation::Byte | FixupLocation::Word | FixupLocation::LoaderWord |
+                FixupLocation::Offset32 | FixupLocation::LoaderOffset32 => fixup.target.wrapping_sub(at + fixup.location.size() as u64),
+                location => return Err(LinkError::new(&format!("self-relative fixup can't have location {:?}", location))),
+            },
+        };
+
+        self.check_fixup(module, fixup, at, value as i64)?;
+
+        let relocate = |image: &mut Image, disp: u64| {
+            image.add(at + disp, 2, fixup.frame as u64);
+            if relocatable && !fixup.fixed_frame {
+                image.relocations.push(Relocation{ segment: frame, offset: (offset + disp) as u16 });
+            }
+        };
+
+        match fixup.location {
+            FixupLocation::Byte => image.add(at, 1, value),
+            FixupLocation::HighOrderByte => image.add(at, 1, value >> 8),
+            FixupLocation::Word | FixupLocation::LoaderWord => image.add(at, 2, value),
+            FixupLocation::Offset32 | FixupLocation::LoaderOffset32 => image.add(at, 4, value),
+            FixupLocation::Selector => relocate(image, 0),
+            FixupLocation::LongPointer => {
+                image.add(at, 2, value);
+                relocate(image, 2);
+            },
+            FixupLocation::Pointer48 => {
+                image.add(at, 4, value);
+                relocate(image, 4);
+            },
+        }
+
+        Ok(())
+    }
+
+    // Turn a far call into another overlay into a call to the overlay
+    // manager. The 5 bytes of CALL FAR target become
+    //
+    //   INT 3Fh
+    //   DB overlay
+    //   DW offset of the target from the overlay area
+    //
+    // and the manager, having loaded the overlay, goes on to the target
+    // and returns past them. Nothing but a call can reach overlaid code
+    // from outside its overlay, as there'd be no telling if it was loaded.
+    //
+    fn overlay_call(&self, fixup: &ResolvedFixup, at: u64, image: &mut Image) -> Result<(), LinkError> {
+        let target = match fixup.symbol {
+            Some(id) => self.symbols.symbols[id].name.clone(),
+            None => format!("{:05x}", fixup.target),
+        };
+
+        let opcode = (at - image.base).checked_sub(1).map(|at| image.data[at as usize]);
+        if fixup.location != FixupLocation::LongPointer || !fixup.is_seg_relative || opcode != Some(0x9a) {
+            return Err(LinkError::new(&format!("reference to {} in overlay {} from outside it is not a far call", target, fixup.overlay)));
+        }
+
+        let overlay = u8::try_from(fixup.overlay).map_err(|_| LinkError::new(&format!("overlay {} can't be called; there are more than 255", fixup.overlay)))?;
+        let offset = fixup.target.checked_sub(fixup.frame as u64 * 16)
+            .filter(|offset| *offset <= 0xffff)
+            .ok_or_else(|| LinkError::new(&format!("call to {} is out of reach of the overlay area", target)))?;
+
+        let call = (at - image.base) as usize - 1;
+        image.data[call..call + 5].copy_from_slice(&[0xcd, self.overlay_interrupt, overlay, offset as u8, (offset >> 8) as u8]);
+        Ok(())
+    }
+
+    // The frame and offset a MODEND start address gives. It's worked out
+    // like a fixup, but there's no data record for a frame to come from.
+    //
+    fn start_address(&self, module: usize, start: &StartAddress) -> Result<(u16, u16), LinkError> {
+        let (tmethod, tdatum) = match (start.tmethod()?, start.target_datum) {
+            (Some(method), Some(datum)) => (method, datum),
+            _ => return Err(LinkError::new("start address target uses a thread")),
+        };
+
+        let (target_frame, target) = self.target(module, tmethod, tdatum, start.target_disp.unwrap_or(0))?;
+        let frame = match start.fmethod()? {
+            Some(FrameMethod::PreviousDataRecord) => return Err(LinkError::new("start address frame can't be the previous data record")),
+            Some(method) => self.frame(module, method, start.frame_datum, 0, target_frame)?.paragraph,
+            None => return Err(LinkError::new("start address frame uses a thread")),
+        };
+
+        match target.checked_sub(frame as u64 * 16) {
+            Some(ip) if ip <= 0xffff => Ok((frame, ip as u16)),
+            _ => Err(LinkError::new(&format!("start address is out of reach of frame {:04x}", frame))),
+        }
+    }
+
+    // The program's entry point as CS:IP: the `entry` symbol if one was
+    // given, or else the start address of the main module, the one whose
+    // MODEND claims it's the main program. Only one module may.
+    //
+    pub fn entry_point(&self) -> Result<Option<(u16, u16)>, LinkError> {
+        if let Some(name) = &self.entry {
+            let id = self.symbols.id(name).ok_or_else(|| LinkError::new(&format!("entry point {} is undefined", name)))?;
+            let (frame, linear) = self.symbol_address(id)?;
+            return match linear.checked_sub(frame as u64 * 16) {
+                Some(ip) if ip <= 0xffff => Ok(Some((frame, ip as u16))),
+                _ => Err(LinkError::new(&format!("entry point {} is out of reach of frame {:04x}", name, frame))),
+            };
+        }
+
+        let mut entry = None;
+        let mut main: Option<&str> = None;
+
+        for (index, module) in self.modules.iter().enumerate() {
+            for (offset, _, record) in &module.records {
+                if let Record::MODEND{ main: true, start_address: Some(start), .. } = record {
+                    if let Some(main) = main {
+                        return Err(LinkError::new(&format!("{} and {} both have a start address", main, module.origin)));
+                    }
+
+                    main = Some(&module.origin);
+                    entry = Some(self.start_address(index, start)
+                        .map_err(|err| LinkError{ offset: Some(*offset), ..err }.in_module(&module.origin))?);
+                }
+            }
+        }
+
+        Ok(entry)
+    }
+
+    // The initial SS:SP: the top of the first stack segment
+    //
+    pub fn stack(&self) -> Option<(u16, u16)> {
+        self.segments.iter()
+            .find(|segment| segment.combine == Combine::Stack)
+            .and_then(|segment| Some((segment.frame().ok()?, (segment.frame_offset().ok()? + segment.length) as u16)))
+    }
+
+    // The symbols still undefined once the libraries have been searched,
+    // in the order they were first seen
+    //
+    pub fn unresolved(&self) -> Vec<Unresolved> {
+        self.symbols.undefined()
+            .map(|symbol| Unresolved{
+                name: match symbol.module {
+                    Some(module) => format!("{} (local to {})", symbol.name, self.modules[module].origin),
+                    None => symbol.name.clone(),
+                },
+                references: symbol.references.iter()
+                    .map(|reference| (self.modules[reference.module].origin.clone(), reference.offset))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    // Publics which more than one module defines. A communal definition
+    // alongside a public one isn't a duplicate: the public is used.
+    //
+    pub fn duplicates(&self) -> Vec<Duplicate> {
+        self.symbols.symbols.iter()
+            .filter_map(|symbol| {
+                let mut defs = symbol.publics.iter().filter(|def| !def.local);
+                let first = defs.next()?;
+                let others: Vec<String> = defs.map(|def| self.modules[def.module].origin.clone()).collect();
+
+                match others.is_empty() {
+                    true => None,
+                    false => Some(Duplicate{ name: symbol.name.clone(), first: self.modules[first.module].origin.clone(), others }),
+                }
+            })
+            .collect()
+    }
+
+    // Make sure every symbol is defined, and defined once unless that's
+    // allowed
+    //
+    fn check_symbols(&mut self) -> Result<(), LinkError> {
+        let unresolved = self.symbols.undefined().count();
+        if unresolved > 0 {
+            return Err(LinkError::new(&format!("{} unresolved external(s)", unresolved)));
+        }
+
+        let duplicates = self.duplicates();
+        if !duplicates.is_empty() && !self.allow_duplicates {
+            return Err(LinkError::new(&format!("{} public symbol(s) defined more than once", duplicates.len())));
+        }
+        for duplicate in duplicates {
+            self.diagnostics.warn("duplicate", Diagnostic::new(&format!("{}; using the first definition", duplicate)));
+        }
+        Ok(())
+    }
+
+    // Link the program as a flat binary image, as it's to be in memory
+    // from the control script's origin on: a ROM, a boot sector, or the
+    // like. Nothing will relocate it, so its frames are used as they are.
+    //
+    pub fn build_binary(&mut self) -> Result<Vec<u8>, LinkError> {
+        if self.script.origin.is_none() {
+            return Err(LinkError::new("a binary image needs an origin"));
+        }
+        if self.overlay_count() > 0 {
+            return Err(LinkError::new("a binary image can't have overlays"));
+        }
+
+        self.check_symbols()?;
+        self.pass1()?;
+        Ok(self.pass2()?.remove(0).data)
+    }
+
+    // Link the modules into an EXE. Libraries should already have been
+    // searched with `resolve`, and nothing may be left undefined or, unless
+    // it's allowed, defined twice.
+    //
+    pub fn build_exe(&mut self) -> Result<MzExe, LinkError> {
+        if self.script.origin.is_some() {
+            return Err(LinkError::new("a program linked at an origin can't be an EXE"));
+        }
+
+        self.check_symbols()?;
+        self.pass1()?;
+        let mut images = self.pass2()?;
+        let mut image = images.remove(0);
+        let min_alloc = image.trim();
+
+        let overlays = images.into_iter()
+            .map(|overlay| MzExe{
+                image: overlay.data,
+                relocations: overlay.relocations,
+                min_alloc: 0,
+                max_alloc: 0,
+                ss: 0,
+                sp: 0,
+                cs: 0,
+                ip: 0,
+                overlays: Vec::new(),
+            })
+            .collect();
+
+        let (cs, ip) = self.entry_point()?.unwrap_or_else(|| {
+            self.diagnostics.warn("nostart", Diagnostic::new("no starting address"));
+            (0, 0)
+        });
+        let (ss, sp) = self.stack().unwrap_or_else(|| {
+            self.diagnostics.warn("nostack", Diagnostic::new("no stack segment"));
+            (0, 0)
+        });
+
+        Ok(MzExe{
+            image: image.data,
+            relocations: image.relocations,
+            min_alloc,
+            max_alloc: 0xffff,
+            ss,
+            sp,
+            cs,
+            ip,
+            overlays,
+        })
+    }
+
+    // The public symbols of the linked program for a .SYM file, named
+    // `module`. Symbols are grouped by the frame they're addressed from,
+    // under the name of the group or segment that frame belongs to;
+    // absolute symbols in the first 64K are constants.
+    //
+    pub fn sym_file(&self, module: &str) -> Result<SymFile, LinkError> {
+        let mut constants = Vec::new();
+        let mut segments: Vec<SymSegment> = Vec::new();
+
+        for (id, symbol) in self.symbols.symbols.iter().enumerate() {
+            let def = match symbol.definition() {
+                Some(def) if symbol.module.is_none() => def,
+                _ => continue,
+            };
+
+            let (frame, linear) = self.symbol_address(id)?;
+            if def.seg.is_none() && frame == 0 && linear <= 0xffff {
+                constants.push((symbol.name.clone(), linear as u16));
+                continue;
+            }
+
+            let value = (linear - frame as u64 * 16) as u32;
+            match segments.iter_mut().find(|segment| segment.frame == frame) {
+                Some(segment) => segment.symbols.push((symbol.name.clone(), value)),
+                None => segments.push(SymSegment{ name: self.frame_name(frame), frame, symbols: vec![(symbol.name.clone(), value)] }),
+            }
+        }
+
+        segments.sort_by_key(|segment| segment.frame);
+
+        Ok(SymFile{
+            module: module.to_string(),
+            entry_segment: self.entry_point()?.map_or(0, |(cs, _)| cs),
+            constants,
+            segments,
+        })
+    }
+
+    // What to call a frame: the group addressed from it, or else the first
+    // segment which is
+    //
+    fn frame_name(&self, frame: u16) -> String {
+        self.groups.iter()
+            .find(|group| group.frame == frame && !group.segments.is_empty())
+            .map(|group| group.name.clone())
+            .or_else(|| self.segments.iter().find(|segment| segment.frame().is_ok_and(|at| at == frame)).map(|segment| segment.name.clone()))
+            .unwrap_or_else(|| format!("{:04x}", frame))
+    }
+
+    // Warn if the modules weren't all compiled for the same memory model,
+    // or the same processor, as far as their MemoryModel COMENTs say. Such
+    // a program tends to crash in ways that are hard to trace back to it.
+    // Each model is given with the first module compiled for it.
+    //
+    fn check_memory_models(&mut self) {
+        let mut models: Vec<(&str, &str)> = Vec::new();
+        let mut cpus: Vec<(&str, &str)> = Vec::new();
+
+        for module in &self.modules {
+            let text = module.records.iter().find_map(|(_, _, record)| match record {
+                Record::COMENT{ coment: Coment::MemoryModel{ text }, .. } => Some(text),
+                _ => None,
+            });
+            let (model, cpu) = text.map_or((None, None), |text| memory_model(text));
+
+            for (found, seen) in [(model, &mut models), (cpu, &mut cpus)] {
+                if let Some(found) = found {
+                    if !seen.iter().any(|(name, _)| *name == found) {
+                        seen.push((found, &module.origin));
+                    }
+                }
+            }
+        }
+
+        let list = |seen: &[(&str, &str)]| seen.iter().map(|(name, origin)| format!("{} ({})", origin, name)).collect::<Vec<_>>().join(", ");
+        if models.len() > 1 {
+            let details = format!("modules are compiled for different memory models: {}", list(&models));
+            self.diagnostics.warn("model", Diagnostic::new(&details));
+        }
+        if cpus.len() > 1 {
+            let details = format!("modules are compiled for different processors: {}", list(&cpus));
+            self.diagnostics.warn("cpu", Diagnostic::new(&details));
+        }
+    }
+
+    // Pass 1 of the link proper, once the symbols are resolved: decide
+    // where everything goes. Only the definitions in each module are
+    // needed for this.
+    //
+    pub fn pass1(&mut self) -> Result<(), LinkError> {
+        self.check_memory_models();
+        self.allocate_communals()?;
+        self.combine_segments()?;
+        self.combine_groups()?;
+        self.layout()?;
+
+        if self.verbose >= 1 {
+            self.trace_layout();
+        }
+        Ok(())
+    }
+
+    // Note where each segment went, in layout order, and the pieces of
+    // modules it was combined from
+    //
+    fn trace_layout(&mut self) {
+        for index in &self.order {
+            let segment = &self.segments[*index];
+            let overlay = match segment.overlay {
+                0 => String::new(),
+                overlay => format!(" overlay {}", overlay),
+            };
+            self.trace.push(format!(
+                "segment {} ({}) at {:05X}, length {:X}, align {}{}",
+                segment.name, segment.class, segment.base, segment.length, segment.align, overlay
+            ));
+
+            for contribution in &segment.contributions {
+                self.trace.push(format!(
+                    "    {:05X} length {:X} from {}",
+                    segment.base + contribution.offset, contribution.length, self.modules[contribution.module].origin
+                ));
+            }
+        }
+    }
+
+    // Note how each fixup of every module was resolved: where it is, what
+    // it points to and the frame it's addressed from
+    //
+    fn trace_fixups(&mut self, fixups: &[Vec<ResolvedFixup>]) -> Result<(), LinkError> {
+        for (module, fixups) in fixups.iter().enumerate() {
+            for fixup in fixups {
+                let (frame, offset) = self.data_address(module, fixup.record)?;
+                let target = match fixup.symbol {
+                    Some(id) => format!("{} at {:05X}", self.symbols.symbols[id].name, fixup.target),
+                    None => format!("{:05X}", fixup.target),
+                };
+                let kind = match fixup.is_seg_relative {
+                    true => "",
+                    false => " self-relative",
+                };
+
+                self.trace.push(format!(
+                    "{}: fixup at {:04X}:{:04X} {:?}{} to {} from frame {:04X}",
+                    self.modules[module].origin, frame, offset + fixup.data_offset as u64, fixup.location, kind, target, fixup.frame
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Make sure near calls and jumps to a symbol are made from a frame the
+    // symbol is addressed from. One which isn't is a near call to a far
+    // procedure, which returns with RETF, and so crashes the program.
+    //
+    fn check_near_calls(&mut self, fixups: &[Vec<ResolvedFixup>]) -> Result<(), LinkError> {
+        let mut problems = Vec::new();
+
+        for (module, fixups) in fixups.iter().enumerate() {
+            for fixup in fixups {
+                let id = match fixup.symbol {
+                    Some(id) if !fixup.is_seg_relative => id,
+                    _ => continue,
+                };
+                let target = match self.symbols.symbols[id].definition() {
+                    Some(PublicDef{ module, seg: Some(seg), .. }) => self.placement(*module, *seg)?.segment,
+                    _ => continue,
+                };
+                let seg = match &self.modules[module].records[fixup.record].2 {
+                    Record::LEDATA{ seg, .. } | Record::LIDATA{ seg, .. } => self.placement(module, *seg)?.segment,
+                    _ => continue,
+                };
+                if target == seg {
+                    continue;
+                }
+
+                // the code may be addressed from its own segment's frame or
+                // from that of any group it's in, which is fine if the
+                // symbol is in the group too
+                //
+                let (frame, _) = self.symbol_address(id)?;
+                let mut groups = self.groups.iter().filter(|group| group.segments.contains(&seg));
+                if self.segments[seg].frame()? == frame || groups.any(|group| group.frame == frame || group.segments.contains(&target)) {
+                    continue;
+                }
+
+                let (data_frame, offset) = self.data_address(module, fixup.record)?;
+                let at = data_frame as u64 * 16 + offset + fixup.data_offset as u64;
+                let details = format!("near call to far symbol {} from {}", self.symbols.symbols[id].name, self.symbol_before(seg, at));
+                problems.push(Diagnostic::new(&details).in_module(&self.modules[module].origin));
+            }
+        }
+
+        for problem in problems {
+            self.diagnostics.error("nearfar", problem);
+        }
+        Ok(())
+    }
+
+    // The public nearest below `at` in an output segment, to say where in
+    // the program something is, or the segment and offset if there is none
+    //
+    fn symbol_before(&self, segment: usize, at: u64) -> String {
+        let mut best: Option<(u64, &str)> = None;
+
+        for (id, symbol) in self.symbols.symbols.iter().enumerate() {
+            let in_segment = match symbol.definition() {
+                Some(PublicDef{ module, seg: Some(seg), .. }) => self.placement(*module, *seg).is_ok_and(|placement| placement.segment == segment),
+                _ => false,
+            };
+            if let (true, Ok((_, linear))) = (in_segment, self.symbol_address(id)) {
+                if linear <= at && best.is_none_or(|(best, _)| linear > best) {
+                    best = Some((linear, &symbol.name));
+                }
+            }
+        }
+
+        match best {
+            Some((linear, name)) if linear == at => name.to_string(),
+            Some((linear, name)) => format!("{}+{:x}", name, at - linear),
+            None => format!("{}:{:04x}", self.segments[segment].name, at - self.segments[segment].base),
+        }
+    }
+
+    // Pass 2: read what pass 1 left of each module, its data and fixups,
+    // and build the images from it. The fixups are resolved once, for
+    // the trace and the checks as well as the images.
+    //
+    pub fn pass2(&mut self) -> Result<Vec<Image>, LinkError> {
+        for module in &mut self.modules {
+            module.read_deferred(&self.options, &mut self.diagnostics)?;
+        }
+
+        let fixups = self.resolve_all_fixups()?;
+        if self.verbose >= 2 {
+            self.trace_fixups(&fixups)?;
+        }
+        self.check_near_calls(&fixups)?;
+        self.assemble(&fixups, cfg!(feature = "parallel"))
+    }
+
+    // Every module's fixups, resolved, by module
+    //
+    fn resolve_all_fixups(&self) -> Result<Vec<Vec<ResolvedFixup>>, LinkError> {
+        (0..self.modules.len()).map(|module| self.resolve_fixups(module)).collect()
+    }
+
+    // Make sure what a fixup puts in fits. An offset must be reachable from
+    // its frame, and a self-relative fixup must also be made from inside
+    // the frame, and a byte one reach no further than a short jump can.
+    //
+    fn check_fixup(&self, module: usize, fixup: &ResolvedFixup, at: u64, value: i64) -> Result<(), LinkError> {
+        let base = fixup.frame as i64 * 16;
+        let from_frame = fixup.target as i64 - base;
+        let (limit, range) = match fixup.location {
+            FixupLocation::Offset32 | FixupLocation::LoaderOffset32 | FixupLocation::Pointer48 => (0xffff_ffff, i32::MIN as i64..=i32::MAX as i64),
+            FixupLocation::Byte if !fixup.is_seg_relative => (0xffff, -0x80..=0x7f),
+            _ => (0xffff, -0xffff..=0xffff),
+        };
+
+        let problem = if fixup.location != FixupLocation::Selector && !(0..=limit).contains(&from_frame) {
+            Some(format!("target {:05x} is outside its frame {:04x}", fixup.target, fixup.frame))
+        } else if !fixup.is_seg_relative && !(0..=limit).contains(&(at as i64 - base)) {
+            Some(format!("self-relative fixup is outside the target's frame {:04x}", fixup.frame))
+        } else if !fixup.is_seg_relative && !range.contains(&value) {
+            Some(format!("displacement {} does not fit", value))
+        } else {
+            None
+        };
+
+        let problem = match problem {
+            Some(problem) => problem,
+            None => return Ok(()),
+        };
+
+        // say where the fixup is, as segment:offset, and what it refers to
+        //
+        let seg = match &self.modules[module].records[fixup.record].2 {
+            Record::LEDATA{ seg, .. } | Record::LIDATA{ seg, .. } => *seg,
+            _ => return Err(LinkError::new("fixup does not follow a data record")),
+        };
+        let segment = &self.segments[self.placement(module, seg)?.segment];
+        let target = match fixup.symbol {
+            Some(id) => self.symbols.symbols[id].name.clone(),
+            None => format!("{:05x}", fixup.target),
+        };
+
+        Err(LinkError::new(&format!(
+            "fixup overflow at {}:{:04x} ({:?} fixup to {}): {}",
+            segment.name, at - segment.base, fixup.location, target, problem
+        )))
+    }
+
+    // Build the program from every module's data, with the fixups
+    // applied. The segments must have been laid out.
+    //
+    pub fn build_image(&self) -> Result<Image, LinkError> {
+        Ok(self.build_images()?.remove(0))
+    }
+
+    // Fill what no module's piece of a segment covers with `fill`. An
+    // image is all pieces and gaps up to the end of its last segment.
+    //
+    fn fill_gaps(&self, images: &mut [Image]) {
+        for (overlay, image) in images.iter_mut().enumerate() {
+            let mut pieces: Vec<(u64, u64)> = self.segments.iter()
+                .filter(|segment| segment.absolute.is_none() && segment.overlay as usize == overlay)
+                .flat_map(|segment| segment.contributions.iter()
+                    .map(|piece| (segment.base + piece.offset, segment.base + piece.offset + piece.length)))
+                .collect();
+            pieces.sort();
+
+            let mut at = image.base;
+            for (start, end) in pieces.into_iter().chain([(image.base + image.data.len() as u64, 0)]) {
+                if start > at {
+                    image.data[(at - image.base) as usize..(start - image.base) as usize].fill(self.fill);
+                }
+                at = at.max(end);
+            }
+        }
+    }
+
+    // Build the program and its overlays, the root program first and then
+    // each overlay in turn
+    //
+    pub fn build_images(&self) -> Result<Vec<Image>, LinkError> {
+        self.assemble(&self.resolve_all_fixups()?, cfg!(feature = "parallel"))
+    }
+
+    // Build the images, each as windows of segments which don't overlap
+    // those of any other window, if `split`, or else as a whole. Each
+    // window is built by itself, with its data loaded and fixed up a
+    // module at a time as they'd be over the whole image, so the windows
+    // can be built on threads of their own; the relocations are put back
+    // in the order the fixups came in afterwards.
+    //
+    fn assemble(&self, fixups: &[Vec<ResolvedFixup>], split: bool) -> Result<Vec<Image>, LinkError> {
+        let base = self.script.origin.map_or(0, |origin| origin.linear);
+        let mut images = vec![Image{ base, data: vec![0; (self.image_length().max(base) - base) as usize], relocations: Vec::new(), loaded: 0 }];
+
+        for overlay in 1..=self.overlay_count() {
+            let segments = self.segments.iter().filter(|segment| segment.overlay == overlay);
+            let frames = segments.clone().map(|segment| segment.frame()).collect::<Result<Vec<u16>, LinkError>>()?;
+            let base = frames.into_iter().min().map_or(0, |frame| frame as u64 * 16);
+            let end = segments.map(|segment| segment.base + segment.length).max().unwrap_or(base);
+            images.push(Image{ base, data: vec![0; (end - base) as usize], relocations: Vec::new(), loaded: 0 });
+        }
+
+        if self.fill != 0 {
+            self.fill_gaps(&mut images);
+        }
+
+        let mut windows = self.windows(&images, split);
+        let window_of = self.record_windows(&windows)?;
+
+        let build = |window: &mut Window| self.build_window(window, &images[window.overlay], &window_of, fixups);
+
+        #[cfg(feature = "parallel")]
+        let results: Vec<_> = {
+            use rayon::prelude::*;
+            windows.par_iter_mut().map(build).collect()
+        };
+
+        #[cfg(not(feature = "parallel"))]
+        let results: Vec<_> = windows.iter_mut().map(build).collect();
+
+        // of the windows which failed, say what the whole image would
+        // have failed on first
+        //
+        let mut relocations = Vec::new();
+        let mut failed: Option<(Step, LinkError)> = None;
+        for (window, result) in windows.iter().zip(results) {
+            match result {
+                Ok(tagged) => relocations.extend(tagged.into_iter().map(|(step, relocation)| (window.overlay, step, relocation))),
+                Err((at, err)) => if failed.as_ref().is_none_or(|(first, _)| at < *first) {
+                    failed = Some((at, err));
+                },
+            }
+        }
+        if let Some((_, err)) = failed {
+            return Err(err);
+        }
+
+        for window in windows {
+            let image = &mut images[window.overlay];
+            let at = (window.image.base - image.base) as usize;
+            image.data[at..at + window.image.data.len()].copy_from_slice(&window.image.data);
+            if window.image.loaded != 0 {
+                image.loaded = image.loaded.max(at + window.image.loaded);
+            }
+        }
+
+        relocations.sort_by_key(|(overlay, step, _)| (*overlay, *step));
+        for (overlay, _, relocation) in relocations {
+            images[overlay].relocations.push(relocation);
+        }
+
+        Ok(images)
+    }
+
+    // Cut the images into windows. Segments which overlap go in the same
+    // window, so no two windows write the same bytes.
+    //
+    fn windows(&self, images: &[Image], split: bool) -> Vec<Window> {
+        let mut windows: Vec<Window> = Vec::new();
+
+        for (overlay, image) in images.iter().enumerate() {
+            let start = image.base;
+            let end = image.base + image.data.len() as u64;
+
+            let mut segments: Vec<usize> = (0..self.segments.len())
+                .filter(|segment| self.segments[*segment].absolute.is_none() && self.segments[*segment].overlay as usize == overlay)
+                .collect();
+            segments.sort_by_key(|segment| self.segments[*segment].base);
+
+            let first = windows.len();
+            for segment in segments {
+                let segment_start = self.segments[segment].base.clamp(start, end);
+                let segment_end = (self.segments[segment].base + self.segments[segment].length).clamp(start, end);
+
+                match windows[first..].last_mut() {
+                    Some(window) if !split || segment_start < window.end => {
+                        window.end = window.end.max(segment_end);
+                        window.segments.push(segment);
+                    },
+                    _ => windows.push(Window{ id: windows.len(), overlay, segments: vec![segment], start: segment_start, end: segment_end, image: Image{ base: 0, data: Vec::new(), relocations: Vec::new(), loaded: 0 } }),
+                }
+            }
+        }
+
+        for window in &mut windows {
+            let image = &images[window.overlay];
+            let at = (window.start - image.base) as usize;
+            window.image = Image{ base: window.start, data: image.data[at..at + (window.end - window.start) as usize].to_vec(), relocations: Vec::new(), loaded: 0 };
+        }
+
+        windows
+    }
+
+    // The window each data record of each module goes in, by record
+    //
+    fn record_windows(&self, windows: &[Window]) -> Result<Vec<Vec<Option<usize>>>, LinkError> {
+        let mut window_of = vec![None; self.segments.len()];
+        for (index, window) in windows.iter().enumerate() {
+            for segment in &window.segments {
+                window_of[*segment] = Some(index);
+            }
+        }
+
+        self.modules.iter().enumerate().map(|(module, m)| {
+            m.records.iter().map(|(offset, _, record)| match record {
+                Record::LEDATA{ seg, .. } | Record::LIDATA{ seg, .. } => {
+                    let segment = self.placement(module, *seg)?.segment;
+                    if self.segments[segment].absolute.is_some() {
+                        return Err(LinkError::with_offset("data for an absolute segment can't be loaded", *offset).in_module(&m.origin));
+                    }
+                    Ok(window_of[segment])
+                },
+                _ => Ok(None),
+            }).collect()
+        }).collect()
+    }
+
+    // Load a window's data and apply its fixups, a module at a time. The
+    // relocations come back tagged with the step of the fixup they're
+    // from, and a failure with the step it happened at.
+    //
+    fn build_window(&self, window: &mut Window, image: &Image, window_of: &[Vec<Option<usize>>], fixups: &[Vec<ResolvedFixup>]) -> Result<Vec<(Step, Relocation)>, (Step, LinkError)> {
+        let mut relocations = Vec::new();
+
+        for (module, fixups) in fixups.iter().enumerate() {
+            let here = |record: usize| window_of[module][record] == Some(window.id);
+
+            for index in (0..self.modules[module].records.len()).filter(|index| here(*index)) {
+                self.load_data(module, index, &mut window.image).map_err(|err| ((module, 0, index), err))?;
+            }
+
+            for (order, fixup) in fixups.iter().enumerate().filter(|(_, fixup)| here(fixup.record)) {
+                let before = window.image.relocations.len();
+                self.apply_fixup(module, fixup, &mut window.image, image.base).map_err(|err| {
+                    let offset = self.modules[module].records[fixup.record].0;
+                    ((module, 1, order), LinkError{ offset: Some(offset), ..err }.in_module(&self.modules[module].origin))
+                })?;
+                relocations.extend(window.image.relocations.drain(before..).map(|relocation| ((module, 1, order), relocation)));
+            }
+        }
+
+        Ok(relocations)
+    }
+}
+
+// How far into building a whole image something happens: the module,
+// whether its data is being loaded (0) or fixed up (1), and the record or
+// fixup
+//
+type Step = (usize, usize, usize);
+
+// A stretch of one of the images, from `start` to `end`, with the
+// segments in it, and its own copy of the data there
+//
+struct Window {
+    id: usize,
+    overlay: usize,
+    segments: Vec<usize>,
+    start: u64,
+    end: u64,
+    image: Image,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codepage::Codepage;
+    use crate::libwriter::{LibMember, LibWriter};
+    use crate::objwriter::{encode_record, RecordWriter};
+    use crate::omfgen::compile;
+
+    // An object made of `records`, for what omfgen can't express
+    //
+    fn raw_object(records: &[(u8, Record)]) -> Vec<u8> {
+        let mut writer = RecordWriter::new();
+        for (rectype, record) in records {
+            writer.record(*rectype, &encode_record(*rectype, record, Codepage::default()).unwrap()).unwrap();
+        }
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn test_symbol_table() {
+        let mut linker = Linker::new(ParseOptions::default());
+
+        let main = compile("
+            module main
+            segment _TEXT CODE
+            extern _puts _helper
+            public _main _TEXT 0
+            data _TEXT 0 c3
+        ").unwrap();
+        let helper = compile("
+            module helper
+            segment _TEXT CODE
+            public _helper _TEXT 4
+            data _TEXT 0 90 90 90 90 c3
+        ").unwrap();
+
+        assert_eq!(linker.add_object("main.obj", &main).unwrap(), 0);
+        assert_eq!(linker.add_object("helper.obj", &helper).unwrap(), 1);
+        assert_eq!(linker.modules[0].name, "main");
+
+        let symbols = &linker.symbols;
+        let helper = symbols.get("_helper").unwrap();
+        assert_eq!(helper.definition(), Some(&PublicDef{ module: 1, group: None, seg: Some(1), frame: None, offset: 4, local: false }));
+        assert_eq!(helper.references.len(), 1);
+        assert_eq!(helper.references[0].module, 0);
+
+        let undefined: Vec<&str> = symbols.undefined().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(undefined, vec!["_puts"]);
+
+        let externs: Vec<&str> = linker.modules[0].externs.iter().map(|id| symbols.symbols[*id].name.as_str()).collect();
+        assert_eq!(externs, vec!["_puts", "_helper"]);
+    }
+
+    #[test]
+    fn test_resolve_from_libraries() {
+        let mut writer = LibWriter::new();
+        for (name, text) in [
+            ("puts", "segment _TEXT CODE\nextern _write\npublic _puts _TEXT 0\ndata _TEXT 0 c3"),
+            ("write", "segment _TEXT CODE\npublic _write _TEXT 0\ndata _TEXT 0 c3"),
+            ("unused", "segment _TEXT CODE\npublic _unused _TEXT 0\ndata _TEXT 0 c3"),
+        ] {
+            let obj = compile(&format!("module {}\n{}", name, text)).unwrap();
+            writer.add(LibMember::from_obj(name, &obj, &ParseOptions::default()).unwrap()).unwrap();
+        }
+
+        let main = compile("
+            segment _TEXT CODE
+            extern _puts _exit
+            public _main _TEXT 0
+            data _TEXT 0 c3
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("main.obj", &main).unwrap();
+        linker.add_library("C.LIB", writer.write().unwrap()).unwrap();
+        assert_eq!(linker.resolve().unwrap(), 2);
+
+        let origins: Vec<&str> = linker.modules.iter().map(|module| module.origin.as_str()).collect();
+        assert_eq!(origins, vec!["main.obj", "C.LIB(puts)", "C.LIB(write)"]);
+
+        let undefined: Vec<&str> = linker.symbols.undefined().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(undefined, vec!["_exit"]);
+        assert_eq!(linker.resolve().unwrap(), 0);
+
+        assert!(linker.add_library("BAD.LIB", main).is_err());
+    }
+
+    #[test]
+    fn test_default_libraries() {
+        let default_library = |name: &str| (0x88, Record::COMENT{
+            header: ComentHeader{ comtype: 0x40, comclass: 0x9f },
+            coment: Coment::DefaultLibrary{ name: name.to_string() },
+        });
+
+        let puts = raw_object(&[
+            (0x80, Record::THEADR{ name: "puts".to_string() }),
+            default_library("DOS"),
+            (0x8c, Record::EXTDEF{ externs: vec![Extern{ name: "_write".to_string(), typeidx: 0 }] }),
+            (0x90, Record::PUBDEF{ group: None, seg: None, frame: Some(0), publics: vec![Public{ name: "_puts".to_string(), offset: 0, typeidx: 0 }] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+        let write = compile("module write\nsegment _TEXT CODE\npublic _write _TEXT 0\ndata _TEXT 0 c3").unwrap();
+        let library = |name: &str, obj: &[u8]| {
+            let mut writer = LibWriter::new();
+            writer.add(LibMember::from_obj(name, obj, &ParseOptions::default()).unwrap()).unwrap();
+            writer.write().unwrap()
+        };
+
+        let main = raw_object(&[
+            (0x80, Record::THEADR{ name: "main".to_string() }),
+            default_library("SLIBCE"),
+            default_library("MISSING"),
+            default_library("slibce"),
+            (0x8c, Record::EXTDEF{ externs: vec![Extern{ name: "_puts".to_string(), typeidx: 0 }] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("main.obj", &main).unwrap();
+        assert_eq!(linker.default_libraries(), vec!["SLIBCE".to_string(), "MISSING".to_string()]);
+
+        let mut opened = Vec::new();
+        let added = linker.resolve_with_defaults(|name| {
+            opened.push(name.to_string());
+            Ok(match name {
+                "SLIBCE" => Some(("lib/SLIBCE.LIB".to_string(), library("puts", &puts))),
+                "DOS" => Some(("lib/DOS.LIB".to_string(), library("write", &write))),
+                _ => None,
+            })
+        }).unwrap();
+
+        assert_eq!(added, 2);
+        assert_eq!(opened, vec!["SLIBCE", "MISSING", "DOS"]);
+        let nolib = linker.diagnostics.pending().iter().find(|report| report.code == "nolib").unwrap();
+        assert_eq!(nolib.diagnostic.details, "cannot find library MISSING");
+        assert_eq!(linker.symbols.undefined().count(), 0);
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("main.obj", &main).unwrap();
+        linker.ignore_libraries.push("missing.lib".to_string());
+        assert_eq!(linker.default_libraries(), vec!["SLIBCE".to_string()]);
+        linker.no_default_libraries = true;
+        assert!(linker.default_libraries().is_empty());
+
+        assert_eq!(library_key("C:\\LIB\\SLIBCE.LIB"), "slibce");
+        assert_eq!(library_key("lib/dos"), "dos");
+    }
+
+    #[test]
+    fn test_combine_segments() {
+        let a = compile("
+            module a
+            segment _TEXT CODE align=byte combine=public length=3
+            segment _DATA DATA align=word combine=public length=5
+            segment COMM DATA combine=common length=10
+            segment _PRIV CODE combine=private length=1
+            segment STACK STACK combine=stack length=100
+        ").unwrap();
+        let b = compile("
+            module b
+            segment _DATA DATA align=word combine=public length=3
+            segment _TEXT CODE align=para combine=public length=2
+            segment COMM DATA combine=common length=20
+            segment _PRIV CODE combine=private length=1
+            segment STACK STACK combine=stack length=100
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &a).unwrap();
+        linker.add_object("b.obj", &b).unwrap();
+        linker.combine_segments().unwrap();
+
+        let segments: Vec<(&str, u64, u64)> = linker.segments.iter()
+            .map(|segment| (segment.name.as_str(), segment.align, segment.length))
+            .collect();
+        assert_eq!(segments, vec![
+            ("_TEXT", 16, 0x12),
+            ("_DATA", 2, 9),
+            ("COMM", 16, 0x20),
+            ("_PRIV", 16, 1),
+            ("STACK", 16, 0x200),
+            ("_PRIV", 16, 1),
+        ]);
+
+        assert_eq!(linker.modules[1].placements, vec![
+            Placement{ segment: 1, offset: 6 },
+            Placement{ segment: 0, offset: 0x10 },
+            Placement{ segment: 2, offset: 0 },
+            Placement{ segment: 5, offset: 0 },
+            Placement{ segment: 4, offset: 0x100 },
+        ]);
+        assert_eq!(linker.segments[0].contributions[1], Contribution{ module: 1, segdef: 2, offset: 0x10, length: 2 });
+    }
+
+    #[test]
+    fn test_groups() {
+        let a = compile("
+            segment _DATA DATA length=10
+            segment CONST CONST length=10
+            group DGROUP _DATA CONST
+        ").unwrap();
+        let b = compile("
+            segment _BSS BSS length=10
+            segment _DATA DATA length=10
+            group DGROUP _BSS _DATA
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &a).unwrap();
+        linker.add_object("b.obj", &b).unwrap();
+        linker.combine_segments().unwrap();
+        linker.combine_groups().unwrap();
+
+        assert_eq!(linker.groups.len(), 1);
+        assert_eq!(linker.groups[0].segments, vec![0, 1, 2]);
+        assert_eq!(linker.modules[1].groups, vec![0]);
+
+        for (segment, base) in linker.segments.iter_mut().zip([0x120, 0x150, 0x160]) {
+            segment.base = base;
+        }
+        linker.frame_groups().unwrap();
+        assert_eq!(linker.groups[0].frame, 0x12);
+
+        linker.segments[2].base = 0x10120;
+        let err = linker.frame_groups().unwrap_err();
+        assert_eq!(err.details, "group DGROUP is larger than 64K (65552 bytes)");
+    }
+
+    #[test]
+    fn test_layout() {
+        let a = compile("
+            segment _TEXT CODE align=byte length=3
+            segment _DATA DATA align=word length=5
+            segment CONST CONST align=byte length=1
+            group DGROUP _DATA CONST
+        ").unwrap();
+        let b = compile("
+            segment _TEXT2 CODE align=page length=0x10
+            segment _DATA DATA align=word length=3
+            segment _BSS BSS align=para length=4
+            group DGROUP _DATA _BSS
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &a).unwrap();
+        linker.add_object("b.obj", &b).unwrap();
+        linker.combine_segments().unwrap();
+        linker.combine_groups().unwrap();
+        linker.layout().unwrap();
+
+        let layout: Vec<(&str, u64)> = linker.order.iter()
+            .map(|index| (linker.segments[*index].name.as_str(), linker.segments[*index].base))
+            .collect();
+        assert_eq!(layout, vec![("_TEXT", 0), ("_TEXT2", 0x100), ("_DATA", 0x110), ("CONST", 0x119), ("_BSS", 0x120)]);
+        assert_eq!(linker.image_length(), 0x124);
+        assert_eq!(linker.groups[0].frame, 0x11);
+
+        assert_eq!(linker.segdef_address(1, 2, 1).unwrap(), (0x11, 7));
+        assert_eq!(linker.segdef_address(0, 3, 0).unwrap(), (0x11, 9));
+        assert!(linker.segdef_address(1, 4, 0).is_err());
+    }
+
+    #[test]
+    fn test_segment_ordering() {
+        let obj = compile("
+            segment _DATA DATA
+            segment STACK STACK combine=stack length=10
+            segment _BSS BSS length=10
+            segment FAR_DATA FAR_DATA length=1
+            segment _TEXT CODE length=1
+            segment NULL BEGDATA length=1
+            segment CONST CONST length=1
+            group DGROUP NULL _DATA CONST _BSS STACK
+            data _DATA 0 01
+        ").unwrap();
+
+        let order = |dosseg: bool, ordering: &[&str]| {
+            let mut linker = Linker::new(ParseOptions::default());
+            linker.add_object("a.obj", &obj).unwrap();
+            linker.dosseg = dosseg;
+            linker.ordering = ordering.iter().map(|name| name.to_string()).collect();
+            linker.combine_segments().unwrap();
+            linker.combine_groups().unwrap();
+            linker.layout().unwrap();
+            linker.order.iter().map(|index| linker.segments[*index].name.clone()).collect::<Vec<String>>()
+        };
+
+        assert_eq!(order(false, &[]), vec!["_DATA", "STACK", "_BSS", "FAR_DATA", "_TEXT", "NULL", "CONST"]);
+        assert_eq!(order(true, &[]), vec!["_TEXT", "FAR_DATA", "NULL", "_DATA", "CONST", "_BSS", "STACK"]);
+        assert_eq!(order(true, &["const", "FAR_DATA", "_DATA"]), vec!["CONST", "FAR_DATA", "_DATA", "_TEXT", "NULL", "_BSS", "STACK"]);
+    }
+
+    #[test]
+    fn test_layout_segment_too_big() {
+        let a = compile("
+            segment _TEXT CODE align=byte length=3
+            segment _DATA DATA align=byte length=0xfffe
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &a).unwrap();
+        linker.combine_segments().unwrap();
+        let err = linker.layout().unwrap_err();
+        assert_eq!(err.details, "segment _DATA (DATA) is larger than 64K");
+    }
+
+    #[test]
+    fn test_resolve_fixups() {
+        let fixup = |data_offset, location, frame_method, target_method, target_datum| Fixup{
+            is_seg_relative: true,
+            location,
+            data_offset,
+            frame_thread: None,
+            frame_method,
+            frame_datum: None,
+            target_thread: None,
+            target_method,
+            target_datum,
+            target_displacement: 0,
+        };
+
+        let a = raw_object(&[
+            (0x80, Record::THEADR{ name: "a".to_string() }),
+            (0x96, Record::LNAMES{ names: ["_TEXT", "CODE", "_DATA", "DATA", "DGROUP"].iter().map(|name| name.to_string()).collect() }),
+            (0x98, Record::SEGDEF{ segs: vec![
+                Segdef{ name: Some(1), class: Some(2), align: Align::Paragraph, length: 0x20, ..Segdef::empty() },
+                Segdef{ name: Some(3), class: Some(4), align: Align::Paragraph, length: 0x10, ..Segdef::empty() },
+            ] }),
+            (0x9a, Record::GRPDEF{ name: 5, components: vec![GrpComponent::Segdef{ index: 2 }] }),
+            (0x8c, Record::EXTDEF{ externs: vec![Extern{ name: "_x".to_string(), typeidx: 0 }] }),
+            (0xa0, Record::LEDATA{ seg: 1, offset: 0, data: vec![0; 0x10] }),
+            (0x9c, Record::FIXUPP{ fixups: vec![
+                FixupSubrecord::TargetThread{ method: TargetMethod::SegdefNoDisplacement, thread: 0, index: 2 },
+                FixupSubrecord::FrameThread{ method: FrameMethod::Grpdef, thread: 1, index: Some(1) },
+                FixupSubrecord::Fixup{ fixup: Fixup{ frame_thread: Some(1), target_thread: Some(0), target_displacement: 4, ..fixup(2, FixupLocation::Word, None, None, None) } },
+            ] }),
+            (0xa0, Record::LEDATA{ seg: 1, offset: 0x10, data: vec![0; 0x10] }),
+            (0x9c, Record::FIXUPP{ fixups: vec![
+                FixupSubrecord::Fixup{ fixup: Fixup{ target_thread: Some(0), ..fixup(0, FixupLocation::Word, Some(FrameMethod::PreviousDataRecord), None, None) } },
+                FixupSubrecord::Fixup{ fixup: fixup(4, FixupLocation::LongPointer, Some(FrameMethod::Target), Some(TargetMethod::ExtdefNoDisplacement), Some(1)) },
+            ] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+        let b = compile("
+            segment _DATA DATA align=para length=8
+            group DGROUP _DATA
+            public _x _DATA 6
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &a).unwrap();
+        linker.add_object("b.obj", &b).unwrap();
+        linker.combine_segments().unwrap();
+        linker.combine_groups().unwrap();
+        linker.layout().unwrap();
+
+        let resolved = |record, data_offset, location, frame, target, symbol| ResolvedFixup{ record, data_offset, location, is_seg_relative: true, frame, fixed_frame: false, target, symbol, overlay: 0 };
+        assert_eq!(linker.resolve_fixups(0).unwrap(), vec![
+            resolved(5, 2, FixupLocation::Word, 2, 0x24, None),
+            resolved(7, 0, FixupLocation::Word, 0, 0x20, None),
+            resolved(7, 4, FixupLocation::LongPointer, 2, 0x36, linker.symbols.id("_x")),
+        ]);
+    }
+
+    #[test]
+    fn test_build_image() {
+        let a = compile("
+            segment _TEXT CODE align=byte
+            segment _DATA DATA align=para
+            group DGROUP _DATA
+            extern _puts
+            data _TEXT 0 b8 00 00 e8 00 00 ea 00 00 00 00
+            fixup _TEXT 1 word seg _DATA disp 2 frame group DGROUP
+            fixup _TEXT 4 word extern _puts self
+            fixup _TEXT 7 pointer extern _puts
+            data _DATA 0 48 69 00
+        ").unwrap();
+        let b = compile("
+            segment _TEXT CODE align=byte
+            public _puts _TEXT 1
+            data _TEXT 0 90 c3
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &a).unwrap();
+        linker.add_object("b.obj", &b).unwrap();
+        linker.combine_segments().unwrap();
+        linker.combine_groups().unwrap();
+        linker.layout().unwrap();
+
+        let image = linker.build_image().unwrap();
+        assert_eq!(image.data, vec![
+            0xb8, 0x02, 0x00, 0xe8, 0x06, 0x00, 0xea, 0x0c, 0x00, 0x00, 0x00, 0x90, 0xc3, 0x00, 0x00, 0x00,
+            0x48, 0x69, 0x00,
+        ]);
+        assert_eq!(image.relocations, vec![Relocation{ segment: 0, offset: 9 }]);
+    }
+
+    #[test]
+    fn test_lidata() {
+        let object = |data_offset, location| raw_object(&[
+            (0x80, Record::THEADR{ name: "a".to_string() }),
+            (0x96, Record::LNAMES{ names: vec!["_TEXT".to_string(), "CODE".to_string()] }),
+            (0x98, Record::SEGDEF{ segs: vec![Segdef{ name: Some(1), class: Some(2), length: 8, ..Segdef::empty() }] }),
+            (0xa2, Record::LIDATA{ seg: 1, offset: 2, blocks: vec![
+                IteratedBlock{ repeat: 1, content: IteratedContent::Blocks(vec![
+                    IteratedBlock{ repeat: 1, content: IteratedContent::Data(vec![0xb8, 0x00, 0x00]) },
+                ]) },
+                IteratedBlock{ repeat: 3, content: IteratedContent::Data(vec![0x90]) },
+            ] }),
+            (0x9c, Record::FIXUPP{ fixups: vec![FixupSubrecord::Fixup{ fixup: Fixup{
+                is_seg_relative: true,
+                location,
+                data_offset,
+                frame_thread: None,
+                frame_method: Some(FrameMethod::Target),
+                frame_datum: None,
+                target_thread: None,
+                target_method: Some(TargetMethod::Segdef),
+                target_datum: Some(1),
+                target_displacement: 5,
+            } }] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+
+        let link = |obj: &[u8]| {
+            let mut linker = Linker::new(ParseOptions::default());
+            linker.add_object("a.obj", obj).unwrap();
+            linker.combine_segments().unwrap();
+            linker.layout().unwrap();
+            linker.build_image()
+        };
+
+        // the literal data starts 9 bytes in, after two block headers and
+        // its length byte
+        //
+        let image = link(&object(10, FixupLocation::Word)).unwrap();
+        assert_eq!(image.data, vec![0x00, 0x00, 0xb8, 0x05, 0x00, 0x90, 0x90, 0x90]);
+
+        let err = link(&object(17, FixupLocation::Byte)).unwrap_err();
+        assert_eq!(err.details, "fixup at 0011 is inside a repeated block of iterated data");
+    }
+
+    #[test]
+    fn test_build_exe() {
+        let main = compile("
+            module main
+            segment _TEXT CODE align=byte
+            segment STACK STACK align=para combine=stack length=100
+            extern _puts
+            data _TEXT 0 90 9a 00 00 00 00
+            fixup _TEXT 2 pointer extern _puts
+            start _TEXT 1
+        ").unwrap();
+        let puts = compile("
+            module puts
+            segment PUTS_TEXT CODE align=para
+            public _puts PUTS_TEXT 0
+            data PUTS_TEXT 0 cb
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("main.obj", &main).unwrap();
+        linker.add_object("puts.obj", &puts).unwrap();
+
+        let exe = linker.build_exe().unwrap();
+        assert!(linker.diagnostics.pending().is_empty());
+        assert_eq!((exe.cs, exe.ip), (0, 1));
+        assert_eq!((exe.ss, exe.sp), (2, 0x100));
+        assert_eq!(&exe.image[..0x11], &[0x90, 0x9a, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xcb]);
+        assert_eq!(exe.relocations, vec![Relocation{ segment: 0, offset: 4 }]);
+
+        // the stack is left for DOS to provide
+        //
+        assert_eq!(exe.image.len(), 0x11);
+        assert_eq!(exe.min_alloc, 0x11);
+
+        let parsed = MzExe::parse(&exe.write().unwrap()).unwrap();
+        assert_eq!(parsed.image, exe.image);
+    }
+
+    #[test]
+    fn test_entry_point() {
+        let main = |name: &str| compile(&format!("
+            module {}
+            segment _TEXT CODE align=byte
+            data _TEXT 0 90 c3
+            start _TEXT 1
+        ", name)).unwrap();
+        let start = compile("
+            segment _TEXT CODE align=para
+            public _start _TEXT 0
+            data _TEXT 0 c3
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", main("a")).unwrap();
+        linker.add_object("b.obj", main("b")).unwrap();
+        linker.add_object("start.obj", &start).unwrap();
+
+        let err = linker.build_exe().unwrap_err();
+        assert_eq!(err.details, "a.obj and b.obj both have a start address");
+
+        linker.entry = Some("_start".to_string());
+        assert_eq!(linker.entry_point().unwrap(), Some((0, 0x10)));
+
+        linker.entry = Some("_missing".to_string());
+        assert_eq!(linker.entry_point().unwrap_err().details, "entry point _missing is undefined");
+    }
+
+    #[test]
+    fn test_entry_from_library() {
+        let mut writer = LibWriter::new();
+        let start = compile("module start\nsegment _TEXT CODE\npublic _start _TEXT 0\ndata _TEXT 0 c3").unwrap();
+        writer.add(LibMember::from_obj("start", &start, &ParseOptions::default()).unwrap()).unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", compile("segment _TEXT CODE\ndata _TEXT 0 90").unwrap()).unwrap();
+        linker.add_library("CRT.LIB", writer.write().unwrap()).unwrap();
+        linker.entry = Some("_start".to_string());
+
+        assert_eq!(linker.resolve().unwrap(), 1);
+        let exe = linker.build_exe().unwrap();
+        assert_eq!((exe.cs, exe.ip), (0, 0x10));
+    }
+
+    #[test]
+    fn test_unresolved() {
+        let main = compile("
+            segment _TEXT CODE
+            extern _puts _exit
+            data _TEXT 0 c3
+        ").unwrap();
+        let exit = compile("
+            segment _TEXT CODE
+            extern _exit
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("main.obj", &main).unwrap();
+        linker.add_object("exit.obj", &exit).unwrap();
+
+        let unresolved = linker.unresolved();
+        assert_eq!(unresolved.len(), 2);
+        assert_eq!(unresolved[1].references.iter().map(|(origin, _)| origin.as_str()).collect::<Vec<_>>(), vec!["main.obj", "exit.obj"]);
+
+        let report = format!("{}", unresolved[0]);
+        assert!(report.starts_with("unresolved external _puts\n    referenced by main.obj at "));
+
+        assert_eq!(linker.build_exe().unwrap_err().details, "2 unresolved external(s)");
+    }
+
+    #[test]
+    fn test_local_scopes() {
+        let object = |name: &str, offset: u32| raw_object(&[
+            (0x80, Record::THEADR{ name: name.to_string() }),
+            (0x96, Record::LNAMES{ names: vec!["_TEXT".to_string(), "CODE".to_string()] }),
+            (0x98, Record::SEGDEF{ segs: vec![Segdef{ name: Some(1), class: Some(2), length: 4, ..Segdef::empty() }] }),
+            (0xb4, Record::LEXTDEF{ externs: vec![Extern{ name: "_helper".to_string(), typeidx: 0 }] }),
+            (0xb6, Record::LPUBDEF{ group: None, seg: Some(1), frame: None, publics: vec![Public{ name: "_helper".to_string(), offset, typeidx: 0 }] }),
+            (0xa0, Record::LEDATA{ seg: 1, offset: 0, data: vec![0; 2] }),
+            (0x9c, Record::FIXUPP{ fixups: vec![FixupSubrecord::Fixup{ fixup: Fixup{
+                is_seg_relative: true,
+                location: FixupLocation::Word,
+                data_offset: 0,
+                frame_thread: None,
+                frame_method: Some(FrameMethod::Target),
+                frame_datum: None,
+                target_thread: None,
+                target_method: Some(TargetMethod::ExtdefNoDisplacement),
+                target_datum: Some(1),
+                target_displacement: 0,
+            } }] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+        let global = compile("
+            segment _TEXT CODE align=byte
+            extern _helper
+            data _TEXT 0 c3
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", object("a", 2)).unwrap();
+        linker.add_object("b.obj", object("b", 3)).unwrap();
+        assert!(linker.duplicates().is_empty());
+        assert!(linker.unresolved().is_empty());
+
+        linker.combine_segments().unwrap();
+        linker.layout().unwrap();
+        let image = linker.build_image().unwrap();
+        assert_eq!(image.data, vec![0x02, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00]);
+
+        // the statics don't satisfy a global reference
+        //
+        linker.add_object("c.obj", &global).unwrap();
+        let unresolved: Vec<String> = linker.unresolved().into_iter().map(|symbol| symbol.name).collect();
+        assert_eq!(unresolved, vec!["_helper".to_string()]);
+    }
+
+    #[test]
+    fn test_link_pass_separator() {
+        let obj = raw_object(&[
+            (0x80, Record::THEADR{ name: "a".to_string() }),
+            (0x96, Record::LNAMES{ names: vec!["_TEXT".to_string(), "CODE".to_string()] }),
+            (0x98, Record::SEGDEF{ segs: vec![Segdef{ name: Some(1), class: Some(2), length: 2, ..Segdef::empty() }] }),
+            (0x90, Record::PUBDEF{ group: None, seg: Some(1), frame: None, publics: vec![Public{ name: "_main".to_string(), offset: 0, typeidx: 0 }] }),
+            (0x88, Record::COMENT{ header: ComentHeader{ comtype: 0x40, comclass: 0xa2 }, coment: Coment::LinkPassSeparator }),
+            (0xa0, Record::LEDATA{ seg: 1, offset: 0, data: vec![0x90, 0xc3] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+
+        // pass 1 stops at the separator, but has the definitions
+        //
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &obj).unwrap();
+        assert_eq!(linker.modules[0].records.len(), 5);
+        assert!(linker.modules[0].deferred.is_some());
+        assert!(linker.symbols.get("_main").unwrap().is_defined());
+
+        // and pass 2 reads the rest
+        //
+        linker.pass1().unwrap();
+        let images = linker.pass2().unwrap();
+        assert_eq!(images[0].data, vec![0x90, 0xc3]);
+        assert_eq!(linker.modules[0].records.len(), 7);
+        assert!(linker.modules[0].deferred.is_none());
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let object = |name: &str| compile(&format!("
+            module {}
+            segment _TEXT CODE
+            public _dup _TEXT 0
+            data _TEXT 0 c3
+        ", name)).unwrap();
+        let communal = raw_object(&[
+            (0x80, Record::THEADR{ name: "c".to_string() }),
+            (0xb0, Record::COMDEF{ commons: vec![Comdef{ name: "_dup".to_string(), length: 2, datatype: 0x62, typeidx: 0 }] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", object("a")).unwrap();
+        linker.add_object("c.obj", &communal).unwrap();
+        assert!(linker.duplicates().is_empty());
+
+        linker.add_object("b.obj", object("b")).unwrap();
+        linker.add_object("d.obj", object("d")).unwrap();
+        let duplicates = linker.duplicates();
+        assert_eq!(format!("{}", duplicates[0]), "_dup is defined in a.obj and b.obj, d.obj");
+        assert_eq!(linker.build_exe().unwrap_err().details, "1 public symbol(s) defined more than once");
+
+        linker.allow_duplicates = true;
+        linker.build_exe().unwrap();
+        let duplicate = linker.diagnostics.pending().iter().find(|report| report.code == "duplicate").unwrap();
+        assert_eq!(duplicate.diagnostic.details, "_dup is defined in a.obj and b.obj, d.obj; using the first definition");
+    }
+
+    #[test]
+    fn test_build_exe_warnings() {
+        let obj = compile("
+            segment _TEXT CODE
+            data _TEXT 0 c3
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &obj).unwrap();
+        let exe = linker.build_exe().unwrap();
+
+        let warnings: Vec<&str> = linker.diagnostics.pending().iter().map(|report| report.diagnostic.details.as_str()).collect();
+        assert_eq!(warnings, vec!["no starting address", "no stack segment"]);
+        assert_eq!(exe.image, vec![0xc3]);
+    }
+
+    #[test]
+    fn test_allocate_communals() {
+        let comdefs = |name: &str, commons: &[(&str, usize, u8)]| raw_object(&[
+            (0x80, Record::THEADR{ name: name.to_string() }),
+            (0xb0, Record::COMDEF{ commons: commons.iter()
+                .map(|(name, length, datatype)| Comdef{ name: name.to_string(), length: *length, datatype: *datatype, typeidx: 0 })
+                .collect() }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+
+        let a = comdefs("a", &[("_n", 4, 0x62), ("_f", 0x20, 0x61), ("_mixed", 3, 0x61), ("_p", 2, 0x62)]);
+        let b = comdefs("b", &[("_n", 10, 0x62), ("_mixed", 2, 0x62), ("_big", 0xfff0, 0x61)]);
+        let c = compile("
+            segment _DATA DATA align=word length=2
+            group DGROUP _DATA
+            public _p _DATA 0
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &a).unwrap();
+        linker.add_object("b.obj", &b).unwrap();
+        linker.add_object("c.obj", &c).unwrap();
+        linker.build_exe().unwrap();
+
+        let segments: Vec<(&str, &str, u64)> = linker.segments.iter()
+            .map(|segment| (segment.name.as_str(), segment.class.as_str(), segment.length))
+            .collect();
+        assert_eq!(segments, vec![
+            ("_DATA", "DATA", 2),
+            ("c_common", "BSS", 13),
+            ("FAR_BSS", "FAR_BSS", 0x20),
+            ("FAR_BSS", "FAR_BSS", 0xfff0),
+        ]);
+
+        let dgroup = &linker.groups[0];
+        assert_eq!(dgroup.segments, vec![0, 1]);
+
+        let address = |name: &str| linker.symbol_address(linker.symbols.id(name).unwrap()).unwrap();
+        assert_eq!(address("_p"), (dgroup.frame, 0));
+        assert_eq!(address("_n"), (dgroup.frame, 2));
+        assert_eq!(address("_mixed"), (dgroup.frame, 0xc));
+        assert_eq!(address("_f"), (1, 0x10));
+        assert_eq!(address("_big"), (3, 0x30));
+    }
+
+    #[test]
+    fn test_absolute_segments() {
+        let obj = raw_object(&[
+            (0x80, Record::THEADR{ name: "a".to_string() }),
+            (0x96, Record::LNAMES{ names: ["_TEXT", "CODE", "VIDEO", ""].iter().map(|name| name.to_string()).collect() }),
+            (0x98, Record::SEGDEF{ segs: vec![
+                Segdef{ name: Some(1), class: Some(2), length: 8, ..Segdef::empty() },
+                Segdef{ name: Some(3), class: Some(4), align: Align::Absolute, combine: Combine::Private, abs: Some(AbsoluteSeg{ frame: 0xb800, offset: 0 }), length: 0x1000, ..Segdef::empty() },
+            ] }),
+            (0x90, Record::PUBDEF{ group: None, seg: None, frame: Some(0x40), publics: vec![Public{ name: "_ticks".to_string(), offset: 0x6c, typeidx: 0 }] }),
+            (0x8c, Record::EXTDEF{ externs: vec![Extern{ name: "_ticks".to_string(), typeidx: 0 }] }),
+            (0xa0, Record::LEDATA{ seg: 1, offset: 0, data: vec![0; 8] }),
+            (0x9c, Record::FIXUPP{ fixups: vec![
+                FixupSubrecord::Fixup{ fixup: Fixup{
+                    is_seg_relative: true,
+                    location: FixupLocation::LongPointer,
+                    data_offset: 0,
+                    frame_thread: None,
+                    frame_method: Some(FrameMethod::Target),
+                    frame_datum: None,
+                    target_thread: None,
+                    target_method: Some(TargetMethod::Segdef),
+                    target_datum: Some(2),
+                    target_displacement: 0xa0,
+                } },
+                FixupSubrecord::Fixup{ fixup: Fixup{
+                    is_seg_relative: true,
+                    location: FixupLocation::LongPointer,
+                    data_offset: 4,
+                    frame_thread: None,
+                    frame_method: Some(FrameMethod::Target),
+                    frame_datum: None,
+                    target_thread: None,
+                    target_method: Some(TargetMethod::ExtdefNoDisplacement),
+                    target_datum: Some(1),
+                    target_displacement: 0,
+                } },
+            ] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &obj).unwrap();
+        linker.combine_segments().unwrap();
+        linker.layout().unwrap();
+
+        assert_eq!(linker.order, vec![0]);
+        assert_eq!(linker.segments[1].frame().unwrap(), 0xb800);
+        assert_eq!(linker.image_length(), 8);
+
+        let image = linker.build_image().unwrap();
+        assert_eq!(image.data, vec![0xa0, 0x00, 0x00, 0xb8, 0x6c, 0x00, 0x40, 0x00]);
+        assert!(image.relocations.is_empty());
+    }
+
+    #[test]
+    fn test_image_trim() {
+        let mut image = Image{ base: 0, data: vec![0x90, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], relocations: Vec::new(), loaded: 0 };
+        assert_eq!(image.trim(), 2);
+        assert_eq!(image.data, vec![0x90]);
+
+        let mut image = Image{ base: 0, data: vec![0x90, 0, 0, 0, 0, 0], relocations: vec![Relocation{ segment: 0, offset: 3 }], loaded: 0 };
+        assert_eq!(image.trim(), 1);
+        assert_eq!(image.data, vec![0x90, 0, 0, 0, 0]);
+
+        let mut image = Image{ base: 0, data: vec![0; 0x20], relocations: Vec::new(), loaded: 0 };
+        assert_eq!(image.trim(), 2);
+        assert!(image.data.is_empty());
+
+        // zeros a data record put there are kept, only those past it go
+        //
+        let mut image = Image{ base: 0, data: vec![0x90, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], relocations: Vec::new(), loaded: 3 };
+        assert_eq!(image.trim(), 2);
+        assert_eq!(image.data, vec![0x90, 0, 0]);
+    }
+
+    #[test]
+    fn test_fixup_overflow() {
+        let link = |text: &str| {
+            let near = compile("
+                segment _TEXT CODE align=byte
+                public _near _TEXT 0
+                data _TEXT 0 c3
+            ").unwrap();
+            let far = compile("
+                segment FAR_TEXT FAR align=para length=0x200
+                public _far FAR_TEXT 100
+            ").unwrap();
+
+            let mut linker = Linker::new(ParseOptions::default());
+            linker.add_object("a.obj", compile(text).unwrap()).unwrap();
+            linker.add_object("near.obj", &near).unwrap();
+            linker.add_object("far.obj", &far).unwrap();
+            linker.combine_segments().unwrap();
+            linker.layout().unwrap();
+            linker.build_image()
+        };
+
+        // a short jump to the next module's code is fine
+        //
+        link("
+            segment _TEXT CODE align=byte
+            extern _near
+            data _TEXT 0 eb 00
+            fixup _TEXT 1 byte extern _near self
+        ").unwrap();
+
+        let err = link("
+            segment _TEXT CODE align=byte length=0x100
+            extern _near
+            data _TEXT 10 eb 00
+            fixup _TEXT 11 byte extern _near self
+        ").unwrap_err();
+        assert_eq!(err.details, "fixup overflow at _TEXT:0011 (Byte fixup to _near): displacement 238 does not fit");
+        assert_eq!(err.module.as_deref(), Some("a.obj"));
+
+        let err = link("
+            segment _TEXT CODE align=byte
+            segment _DATA DATA align=para
+            extern _near
+            data _TEXT 0 b8 00 00
+            fixup _TEXT 1 word extern _near frame seg _DATA
+            data _DATA 0 00
+        ").unwrap_err();
+        assert_eq!(err.details, "fixup overflow at _TEXT:0001 (Word fixup to _near): target 00003 is outside its frame 0001");
+
+        let err = link("
+            segment _TEXT CODE align=byte
+            extern _far
+            data _TEXT 0 e8 00 00
+            fixup _TEXT 1 word extern _far self frame target
+        ").unwrap_err();
+        assert_eq!(err.details, "fixup overflow at _TEXT:0001 (Word fixup to _far): self-relative fixup is outside the target's frame 0001");
+    }
+
+    #[test]
+    fn test_sym_file() {
+        let obj = compile("
+            segment _TEXT CODE align=byte
+            segment _DATA DATA align=para
+            group DGROUP _DATA
+            public _main _TEXT 0
+            public _exit _TEXT 4
+            public _msg _DATA 2
+            data _TEXT 0 90 90 90 90 c3
+            data _DATA 0 00 00 00
+        ").unwrap();
+        let abs = raw_object(&[
+            (0x90, Record::PUBDEF{ group: None, seg: None, frame: Some(0), publics: vec![Public{ name: "ABS".to_string(), offset: 0x1234, typeidx: 0 }] }),
+        ]);
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &obj).unwrap();
+        linker.add_object("abs.obj", &abs).unwrap();
+        linker.build_exe().unwrap();
+
+        let sym = linker.sym_file("A").unwrap();
+        assert_eq!(sym.constants, vec![("ABS".to_string(), 0x1234)]);
+        assert_eq!(sym.segments, vec![
+            SymSegment{ name: "_TEXT".to_string(), frame: 0, symbols: vec![("_main".to_string(), 0), ("_exit".to_string(), 4)] },
+            SymSegment{ name: "DGROUP".to_string(), frame: 1, symbols: vec![("_msg".to_string(), 2)] },
+        ]);
+    }
+
+    #[test]
+    fn test_build_exe_keeps_loaded_zeros() {
+        let obj = compile("
+            module main
+            segment _TEXT CODE align=byte
+            segment _DATA DATA align=byte
+            segment _BSS BSS align=byte length=20
+            data _TEXT 0 cb
+            data _DATA 0 00 00 00 00
+            start _TEXT 0
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("main.obj", &obj).unwrap();
+
+        let exe = linker.build_exe().unwrap();
+        assert_eq!(exe.image, vec![0xcb, 0, 0, 0, 0]);
+        assert_eq!(exe.min_alloc, 2);
+    }
+
+    #[test]
+    fn test_image_add_carries() {
+        let mut image = Image{ base: 0, data: vec![0xff, 0xff, 0x12, 0x34], relocations: Vec::new(), loaded: 0 };
+        image.add(0, 2, 0x102);
+        assert_eq!(image.data, vec![0x01, 0x01, 0x12, 0x34]);
+        image.add(2, 1, 0xef);
+        assert_eq!(image.data, vec![0x01, 0x01, 0x01, 0x34]);
+    }
+
+    #[test]
+    fn test_fixup_thread_not_set() {
+        let obj = raw_object(&[
+            (0x80, Record::THEADR{ name: "a".to_string() }),
+            (0x96, Record::LNAMES{ names: vec!["_TEXT".to_string(), "CODE".to_string()] }),
+            (0x98, Record::SEGDEF{ segs: vec![Segdef{ name: Some(1), class: Some(2), length: 4, ..Segdef::empty() }] }),
+            (0xa0, Record::LEDATA{ seg: 1, offset: 0, data: vec![0; 4] }),
+            (0x9c, Record::FIXUPP{ fixups: vec![FixupSubrecord::Fixup{ fixup: Fixup{
+                is_seg_relative: true,
+                location: FixupLocation::Word,
+                data_offset: 0,
+                frame_thread: None,
+                frame_method: Some(FrameMethod::Target),
+                frame_datum: None,
+                target_thread: Some(2),
+                target_method: None,
+                target_datum: None,
+                target_displacement: 0,
+            } }] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &obj).unwrap();
+        linker.combine_segments().unwrap();
+        linker.layout().unwrap();
+
+        let err = linker.resolve_fixups(0).unwrap_err();
+        assert_eq!(err.details, "fixup uses target thread 2 before it is set");
+        assert_eq!(err.module.as_deref(), Some("a.obj"));
+    }
+
+    #[test]
+    fn test_combine_type_conflict() {
+        let a = compile("segment _DATA DATA combine=public length=1").unwrap();
+        let b = compile("segment _DATA DATA combine=common length=1").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &a).unwrap();
+        linker.add_object("b.obj", &b).unwrap();
+
+        let err = linker.combine_segments().unwrap_err();
+        assert_eq!(format!("{}", err), "b.obj: segment _DATA (DATA) has combine type 6 here but 2 elsewhere");
+    }
+
+    #[test]
+    fn test_communal_and_local_symbols() {
+        let obj = raw_object(&[
+            (0x80, Record::THEADR{ name: "c".to_string() }),
+            (0x96, Record::LNAMES{ names: vec!["_TEXT".to_string(), "CODE".to_string(), "_ext".to_string()] }),
+            (0x98, Record::SEGDEF{ segs: vec![Segdef{ name: Some(1), class: Some(2), ..Segdef::empty() }] }),
+            (0x8c, Record::EXTDEF{ externs: vec![Extern{ name: "_a".to_string(), typeidx: 0 }] }),
+            (0xb0, Record::COMDEF{ commons: vec![
+                Comdef{ name: "_near".to_string(), length: 10, datatype: 0x62, typeidx: 0 },
+                Comdef{ name: "_far".to_string(), length: 400, datatype: 0x61, typeidx: 0 },
+            ] }),
+            (0xbc, Record::CEXTDEF{ externs: vec![CExtern{ name: 3, typeindex: 0 }] }),
+            (0xb4, Record::LEXTDEF{ externs: vec![Extern{ name: "_static".to_string(), typeidx: 0 }] }),
+            (0xb6, Record::LPUBDEF{ group: None, seg: Some(1), frame: None, publics: vec![Public{ name: "_static".to_string(), offset: 2, typeidx: 0 }] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("c.obj", &obj).unwrap();
+        let symbols = &linker.symbols;
+
+        let externs: Vec<&str> = linker.modules[0].externs.iter().map(|id| symbols.symbols[*id].name.as_str()).collect();
+        assert_eq!(externs, vec!["_a", "_near", "_far", "_ext", "_static"]);
+
+        assert_eq!(symbols.get("_near").unwrap().commons[0], CommonDef{ module: 0, far: false, length: 10 });
+        assert_eq!(symbols.get("_far").unwrap().commons[0], CommonDef{ module: 0, far: true, length: 400 });
+        assert!(symbols.get("_static").is_none());
+        assert!(symbols.local(0, "_static").unwrap().definition().unwrap().local);
+
+        let undefined: Vec<&str> = symbols.undefined().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(undefined, vec!["_a", "_ext"]);
+    }
+
+    #[test]
+    fn test_fill_gaps() {
+        let a = compile("
+            segment _TEXT CODE align=byte
+            segment _DATA DATA align=para length=4
+            data _TEXT 0 c3
+            data _DATA 0 01
+        ").unwrap();
+        let b = compile("
+            segment _TEXT CODE align=word
+            data _TEXT 0 c3
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.fill = 0x90;
+        linker.add_object("a.obj", &a).unwrap();
+        linker.add_object("b.obj", &b).unwrap();
+        linker.pass1().unwrap();
+
+        // the gap inside _TEXT and the one before _DATA are filled, but the
+        // uninitialized end of _DATA is left alone
+        //
+        let image = linker.build_image().unwrap();
+        let mut expected = vec![0xc3, 0x90, 0xc3];
+        expected.extend([0x90; 13]);
+        expected.extend([0x01, 0, 0, 0]);
+        assert_eq!(image.data, expected);
+    }
+
+    #[test]
+    fn test_overlays() {
+        let main = compile("
+            segment _TEXT CODE
+            segment _DATA DATA
+            extern _ov1 _ov2
+            public _msg _DATA 0
+            data _TEXT 0 9a 00 00 00 00 9a 00 00 00 00 c3
+            fixup _TEXT 1 pointer extern _ov1
+            fixup _TEXT 6 pointer extern _ov2
+            data _DATA 0 48 69
+            start _TEXT 0
+        ").unwrap();
+        let ov1 = compile("
+            segment OV1_TEXT CODE
+            extern _msg
+            public _ov1 OV1_TEXT 2
+            data OV1_TEXT 0 90 90 b8 00 00 cb
+            fixup OV1_TEXT 3 selector extern _msg
+        ").unwrap();
+        let ov2 = compile("
+            segment OV2_TEXT CODE
+            public _ov2 OV2_TEXT 10
+            repeat OV2_TEXT 0 20 cb
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("main.obj", &main).unwrap();
+        linker.add_overlay("ov1.obj", &ov1, 1).unwrap();
+        linker.add_overlay("ov2.obj", &ov2, 2).unwrap();
+        let exe = linker.build_exe().unwrap();
+
+        // the overlays share the area after the root's code, which is as
+        // long as the longer of them
+        //
+        let base = |name: &str| linker.segments.iter().find(|segment| segment.name == name).unwrap().base;
+        assert_eq!((base("OVERLAY_AREA"), base("OV1_TEXT"), base("OV2_TEXT"), base("_DATA")), (0x10, 0x10, 0x10, 0x30));
+        assert_eq!(linker.symbol_address(linker.symbols.id(OVERLAY_BASE).unwrap()).unwrap(), (1, 0x10));
+
+        // calls into them go through the overlay manager
+        //
+        assert_eq!(&exe.image[0..11], &[0xcd, 0x3f, 0x01, 0x02, 0x00, 0xcd, 0x3f, 0x02, 0x10, 0x00, 0xc3]);
+        assert_eq!(&exe.image[0x30..], b"Hi");
+
+        // and each overlay is an image of its own, relocated from its start
+        //
+        assert_eq!(exe.overlays.len(), 2);
+        assert_eq!(exe.overlays[0].image, vec![0x90, 0x90, 0xb8, 0x03, 0x00, 0xcb]);
+        assert_eq!(exe.overlays[0].relocations, vec![Relocation{ segment: 0, offset: 3 }]);
+        assert_eq!(exe.overlays[1].image, vec![0xcb; 0x20]);
+    }
+
+    #[test]
+    fn test_overlay_reference_not_a_call() {
+        let main = compile("
+            segment _TEXT CODE
+            extern _ov1
+            data _TEXT 0 ea 00 00 00 00
+            fixup _TEXT 1 pointer extern _ov1
+        ").unwrap();
+        let ov1 = compile("
+            segment OV1_TEXT CODE
+            public _ov1 OV1_TEXT 0
+            data OV1_TEXT 0 cb
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("main.obj", &main).unwrap();
+        linker.add_overlay("ov1.obj", &ov1, 1).unwrap();
+        let err = linker.build_exe().unwrap_err();
+        assert!(err.to_string().contains("reference to _ov1 in overlay 1 from outside it is not a far call"), "{}", err);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut writer = LibWriter::new();
+        let puts = compile("module puts\nsegment _TEXT CODE align=byte\npublic _puts _TEXT 0\ndata _TEXT 0 c3").unwrap();
+        writer.add(LibMember::from_obj("puts", &puts, &ParseOptions::default()).unwrap()).unwrap();
+
+        let main = compile("
+            segment _TEXT CODE align=byte
+            extern _puts
+            data _TEXT 0 e8 00 00 c3
+            fixup _TEXT 1 word extern _puts self
+            start _TEXT 0
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.verbose = 2;
+        linker.add_object("main.obj", &main).unwrap();
+        linker.add_library("C.LIB", writer.write().unwrap()).unwrap();
+        linker.resolve().unwrap();
+        linker.build_exe().unwrap();
+
+        assert_eq!(linker.trace, vec![
+            "C.LIB(puts) loaded for _puts",
+            "segment _TEXT (CODE) at 00000, length 5, align 1",
+            "    00000 length 4 from main.obj",
+            "    00004 length 1 from C.LIB(puts)",
+            "main.obj: fixup at 0000:0001 Word self-relative to _puts at 00004 from frame 0000",
+        ]);
+    }
+
+    #[test]
+    fn test_control_script() {
+        let obj = compile("
+            segment _TEXT CODE align=byte
+            segment BOOT CODE align=byte
+            segment _DATA DATA align=byte
+            segment SIG DATA align=byte
+            extern _bios
+            data _TEXT 0 eb 3c 90
+            data BOOT 0 be 00 00 c3 00 00
+            fixup BOOT 1 word seg _DATA
+            fixup BOOT 4 selector extern _bios
+            data _DATA 0 41 00
+            data SIG 0 55 aa
+        ").unwrap();
+        let script = LinkScript::parse("
+            origin 0:7c00
+            reserve 7c03 3d
+            place SIG 7dfe
+            define _bios 40:0
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_script(script.clone());
+        linker.add_object("boot.obj", &obj).unwrap();
+        let image = linker.build_binary().unwrap();
+
+        // BOOT skips the reserved range, and everything is addressed from
+        // frame 0 as the origin says
+        //
+        assert_eq!(image.len(), 0x200);
+        assert_eq!(&image[..3], &[0xeb, 0x3c, 0x90]);
+        assert_eq!(&image[0x40..0x48], &[0xbe, 0x46, 0x7c, 0xc3, 0x40, 0x00, 0x41, 0x00]);
+        assert_eq!(&image[0x1fe..], &[0x55, 0xaa]);
+        assert!(linker.build_exe().is_err());
+
+        // a placement must be above the origin and clear of what's reserved
+        //
+        for (place, error) in [
+            ("place SIG 7000", "segment SIG (DATA) is placed below the origin"),
+            ("place SIG 7c10", "segment SIG (DATA) overlaps something else placed or reserved at 07C10"),
+            ("place NOWHERE 7d00", "script places NOWHERE, which is not a segment or group in the image"),
+        ] {
+            let mut linker = Linker::new(ParseOptions::default());
+            linker.add_script(LinkScript::parse(&format!("origin 0:7c00\nreserve 7c03 3d\ndefine _bios 40:0\n{}", place)).unwrap());
+            linker.add_object("boot.obj", &obj).unwrap();
+            assert_eq!(linker.build_binary().unwrap_err().details, error);
+        }
+    }
+
+    #[test]
+    fn test_memory_models() {
+        assert_eq!(memory_model("0sO"), (Some("small"), Some("8086")));
+        assert_eq!(memory_model("2l"), (Some("large"), Some("80286")));
+        assert_eq!(memory_model("O"), (None, None));
+
+        let module = |name: &str, model: &str| raw_object(&[
+            (0x80, Record::THEADR{ name: name.to_string() }),
+            (0x88, Record::COMENT{ header: ComentHeader{ comtype: 0x40, comclass: 0x9d }, coment: Coment::MemoryModel{ text: model.to_string() } }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 1 }),
+        ]);
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", module("a", "0s")).unwrap();
+        linker.add_object("b.obj", module("b", "0sO")).unwrap();
+        linker.pass1().unwrap();
+        assert!(linker.diagnostics.pending().is_empty());
+
+        linker.add_object("c.obj", module("c", "2l")).unwrap();
+        linker.add_object("d.obj", module("d", "0l")).unwrap();
+        linker.pass1().unwrap();
+        let warnings: Vec<(&str, &str)> = linker.diagnostics.pending().iter()
+            .map(|report| (report.code, report.diagnostic.details.as_str()))
+            .collect();
+        assert_eq!(warnings, vec![
+            ("model", "modules are compiled for different memory models: a.obj (small), c.obj (large)"),
+            ("cpu", "modules are compiled for different processors: a.obj (8086), c.obj (80286)"),
+        ]);
+    }
+
+    #[test]
+    fn test_near_call_to_far_symbol() {
+        let link = |group: &str| {
+            let main = compile(&format!("
+                segment _TEXT CODE align=byte
+                segment FAR_TEXT CODE align=para
+                {}
+                extern _far
+                public _main _TEXT 0
+                data _TEXT 0 90 e8 00 00 c3
+                fixup _TEXT 2 word extern _far frame location self
+            ", group)).unwrap();
+            let far = compile("
+                segment FAR_TEXT CODE align=para
+                public _far FAR_TEXT 0
+                data FAR_TEXT 0 cb
+            ").unwrap();
+
+            let mut linker = Linker::new(ParseOptions::default());
+            linker.add_object("main.obj", &main).unwrap();
+            linker.add_object("far.obj", &far).unwrap();
+            linker.build_exe().unwrap();
+            linker.diagnostics.take().into_iter()
+                .filter(|report| report.code == "nearfar")
+                .map(|report| report.to_string())
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(link(""), vec!["main.obj: error [nearfar]: near call to far symbol _far from _main+2"]);
+
+        // in one group, both are addressed from the group's frame
+        //
+        assert!(link("group CGROUP _TEXT FAR_TEXT").is_empty());
+    }
+
+    #[test]
+    fn test_fixup_past_data_record() {
+        let obj = compile("
+            segment _TEXT CODE align=byte
+            data _TEXT 0 90 90
+            fixup _TEXT 1 word seg _TEXT
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &obj).unwrap();
+        let err = linker.build_exe().unwrap_err();
+        assert_eq!(err.details, "fixup at 0000..0002 runs past the end of its data record");
+        assert_eq!(err.module.as_deref(), Some("a.obj"));
+        assert!(err.offset.is_some());
+    }
+
+    #[test]
+    fn test_combine_codes_4_and_7() {
+        let module = |raw: u8| raw_object(&[
+            (0x80, Record::THEADR{ name: "m".to_string() }),
+            (0x96, Record::LNAMES{ names: vec!["_X".to_string(), "DATA".to_string()] }),
+            (0x98, Record::SEGDEF{ segs: vec![Segdef{ name: Some(1), class: Some(2), combine: Combine::Public{ raw }, length: 4, ..Segdef::empty() }] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+        let lengths = |raw: u8, combine_4: Combine, combine_7: Combine| {
+            let mut linker = Linker::new(ParseOptions::default());
+            linker.combine_4 = combine_4;
+            linker.combine_7 = combine_7;
+            linker.add_object("a.obj", module(raw)).unwrap();
+            linker.add_object("b.obj", module(raw)).unwrap();
+            linker.combine_segments().unwrap();
+            linker.segments.iter().map(|segment| segment.length).collect::<Vec<u64>>()
+        };
+
+        // by default all three are public
+        //
+        for raw in [2, 4, 7] {
+            assert_eq!(lengths(raw, Combine::Public{ raw: 4 }, Combine::Public{ raw: 7 }), vec![8]);
+        }
+
+        assert_eq!(lengths(2, Combine::Private, Combine::Common), vec![8]);
+        assert_eq!(lengths(4, Combine::Private, Combine::Common), vec![4, 4]);
+        assert_eq!(lengths(7, Combine::Private, Combine::Common), vec![4]);
+    }
+
+    #[test]
+    fn test_windows_build_the_same_images() {
+        let a = compile("
+            segment A_TEXT CODE
+            segment _DATA DATA
+            extern _b _c
+            public _a A_TEXT 0
+            data A_TEXT 0 9a 00 00 00 00 9a 00 00 00 00 cb
+            fixup A_TEXT 1 pointer extern _b
+            fixup A_TEXT 6 pointer extern _c
+            data _DATA 0 00 00 00 00
+            fixup _DATA 0 pointer seg A_TEXT
+        ").unwrap();
+        let b = compile("
+            segment B_TEXT CODE
+            segment _DATA DATA
+            extern _a
+            public _b B_TEXT 0
+            public _c B_TEXT 5
+            data B_TEXT 0 9a 00 00 00 00 cb
+            fixup B_TEXT 1 pointer extern _a
+            data _DATA 0 00 00 00 00
+            fixup _DATA 0 pointer seg B_TEXT disp 5
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &a).unwrap();
+        linker.add_object("b.obj", &b).unwrap();
+        linker.pass1().unwrap();
+
+        let base = linker.script.origin.map_or(0, |origin| origin.linear);
+        let image = Image{ base, data: vec![0; linker.image_length() as usize], relocations: Vec::new(), loaded: 0 };
+        assert_eq!(linker.windows(&[image], true).len(), 3);
+
+        // the relocations come in the order of the fixups, not of the
+        // windows they're in
+        //
+        let fixups = linker.resolve_all_fixups().unwrap();
+        let split = linker.assemble(&fixups, true).unwrap();
+        let whole = linker.assemble(&fixups, false).unwrap();
+        assert_eq!(split[0].data, whole[0].data);
+        assert_eq!(split[0].relocations, whole[0].relocations);
+        assert_eq!(split[0].relocations.len(), 5);
+    }
+
+    #[test]
+    fn test_segment_past_1m() {
+        let obj = compile("
+            segment _TEXT CODE
+            repeat _TEXT 0 20 90
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_script(LinkScript::parse("place _TEXT ffff0").unwrap());
+        linker.add_object("high.obj", &obj).unwrap();
+
+        // its frame would be past FFFF, so it mustn't be let wrap
+        //
+        let err = linker.pass1().unwrap_err();
+        assert_eq!(err.details, "segment _TEXT (CODE) runs past 1M");
+        assert_eq!(err.module, Some("high.obj".to_string()));
+
+        linker.segments[0].base = 0x100000;
+        assert_eq!(linker.segments[0].frame().unwrap_err().details, "segment _TEXT (CODE) is above 1M");
+    }
+
+    #[test]
+    fn test_parser_diagnostics_reported() {
+        let obj = raw_object(&[
+            (0x80, Record::THEADR{ name: "a".to_string() }),
+            (0x96, Record::LNAMES{ names: vec!["_TEXT".to_string(), "CODE".to_string()] }),
+            (0x98, Record::SEGDEF{ segs: vec![Segdef{ name: Some(1), class: Some(2), length: 2, ..Segdef::empty() }] }),
+            (0xa0, Record::LEDATA{ seg: 2, offset: 0, data: vec![0x90] }),
+            (0x88, Record::COMENT{ header: ComentHeader{ comtype: 0x40, comclass: 0xa2 }, coment: Coment::LinkPassSeparator }),
+            (0xa0, Record::LEDATA{ seg: 3, offset: 0, data: vec![0x90] }),
+            (0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0 }),
+        ]);
+
+        // what the parser puts up with is reported, from either pass, as
+        // a warning about the module as the link knows it
+        //
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("LIB.LIB(a)", &obj).unwrap();
+        linker.modules[0].read_deferred(&ParseOptions::default(), &mut linker.diagnostics).unwrap();
+
+        let reports: Vec<String> = linker.diagnostics.take().iter().map(|report| report.to_string()).collect();
+        assert_eq!(reports, vec![
+            "LIB.LIB(a): 0000001f: warning [object]: SEGDEF index 2 is out of range (1 defined)",
+            "LIB.LIB(a): 0000002e: warning [object]: SEGDEF index 3 is out of range (1 defined)",
+            "LIB.LIB(a): 00000036: warning [object]: MODEND module type $00 does not have bit 5 clear and bit 0 set",
+        ]);
+    }
+}
@@ -0,0 +1,361 @@
+use std::cmp::Ordering;
+
+use crate::error::Error as LinkError;
+use crate::linker::Linker;
+use crate::objfile::Record;
+
+// An output segment, where it is in the image, and the overlay it's in
+// (0 for the root)
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct MapSegment {
+    pub start: u64,
+    pub length: u64,
+    pub name: String,
+    pub class: String,
+    pub overlay: u16,
+}
+
+// A public symbol and its address. `absolute` ones were defined with a
+// frame rather than a segment.
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct MapPublic {
+    pub name: String,
+    pub frame: u16,
+    pub offset: u32,
+    pub absolute: bool,
+}
+
+// The line numbers a module gave for its code in one segment, with where
+// each line ended up as frame and offset. Lines are addressed from the
+// group the LINNUM names, if any, as a debugger would.
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct MapLines {
+    pub origin: String,
+    pub source: String,
+    pub segment: String,
+    pub lines: Vec<(u16, u16, u32)>,
+}
+
+// A public symbol's entry in the cross-reference listing: the module
+// which defines it and every other module which refers to it, by origin
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct MapXref {
+    pub name: String,
+    pub defined: String,
+    pub references: Vec<String>,
+}
+
+// What LINK puts in a map file: the segments in the order they were laid
+// out, the groups, the publics, the entry point, any line numbers and the
+// cross-reference listing
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct LinkMap {
+    pub segments: Vec<MapSegment>,
+    pub groups: Vec<(String, u16)>,
+    pub publics: Vec<MapPublic>,
+    pub entry: Option<(u16, u16)>,
+    pub lines: Vec<MapLines>,
+    pub xref: Vec<MapXref>,
+}
+
+// Names are listed case-insensitively, as LINK does, with case breaking
+// ties so the order is stable
+//
+fn by_name(a: &str, b: &str) -> Ordering {
+    a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase()).then_with(|| a.cmp(b))
+}
+
+impl LinkMap {
+    pub fn publics_by_name(&self) -> Vec<&MapPublic> {
+        let mut publics: Vec<&MapPublic> = self.publics.iter().collect();
+        publics.sort_by(|a, b| by_name(&a.name, &b.name));
+        publics
+    }
+
+    pub fn publics_by_value(&self) -> Vec<&MapPublic> {
+        let mut publics: Vec<&MapPublic> = self.publics.iter().collect();
+        publics.sort_by(|a, b| (a.frame, a.offset).cmp(&(b.frame, b.offset)).then_with(|| by_name(&a.name, &b.name)));
+        publics
+    }
+
+    pub fn xref_by_name(&self) -> Vec<&MapXref> {
+        let mut xref: Vec<&MapXref> = self.xref.iter().collect();
+        xref.sort_by(|a, b| by_name(&a.name, &b.name));
+        xref
+    }
+}
+
+// The segments in the order they were laid out, which only takes the
+// layout to have been done
+//
+pub fn map_segments(linker: &Linker) -> Vec<MapSegment> {
+    linker.order.iter()
+        .map(|index| &linker.segments[*index])
+        .map(|segment| MapSegment{
+            start: segment.base,
+            length: segment.length,
+            name: segment.name.clone(),
+            class: segment.class.clone(),
+            overlay: segment.overlay,
+        })
+        .collect()
+}
+
+// The map of a linked program. The image must have been built.
+//
+pub fn link_map(linker: &Linker) -> Result<LinkMap, LinkError> {
+    let segments = map_segments(linker);
+
+    let groups = linker.groups.iter()
+        .filter(|group| !group.segments.is_empty())
+        .map(|group| (group.name.clone(), group.frame))
+        .collect();
+
+    let mut publics = Vec::new();
+    let mut xref = Vec::new();
+    for (id, symbol) in linker.symbols.symbols.iter().enumerate() {
+        let def = match symbol.definition() {
+            Some(def) if symbol.module.is_none() => def,
+            _ => continue,
+        };
+
+        let (frame, linear) = linker.symbol_address(id)?;
+        publics.push(MapPublic{
+            name: symbol.name.clone(),
+            frame,
+            offset: (linear - frame as u64 * 16) as u32,
+            absolute: def.seg.is_none(),
+        });
+
+        // a communal variable is referred to by every module which declares
+        // it, as well as those which have it as an extern
+        //
+        let mut modules: Vec<usize> = symbol.references.iter().map(|reference| reference.module)
+            .chain(symbol.commons.iter().map(|common| common.module))
+            .filter(|module| *module != def.module)
+            .collect();
+        modules.sort();
+        modules.dedup();
+
+        xref.push(MapXref{
+            name: symbol.name.clone(),
+            defined: linker.modules[def.module].origin.clone(),
+            references: modules.into_iter().map(|module| linker.modules[module].origin.clone()).collect(),
+        });
+    }
+
+    Ok(LinkMap{ segments, groups, publics, entry: linker.entry_point()?, lines: line_numbers(linker)?, xref })
+}
+
+// The LINNUM records of every module. Consecutive records for the same
+// segment of a module are listed together.
+//
+fn line_numbers(linker: &Linker) -> Result<Vec<MapLines>, LinkError> {
+    let mut blocks: Vec<MapLines> = Vec::new();
+
+    for (index, module) in linker.modules.iter().enumerate() {
+        let mut last = None;
+
+        for (_, _, record) in &module.records {
+            let (group, seg, lines) = match record {
+                Record::LINNUM{ group, seg, lines } => (*group, *seg, lines),
+                _ => continue,
+            };
+
+            let segment = &linker.segments[linker.placement(index, seg)?.segment];
+            if last != Some(seg) {
+                blocks.push(MapLines{
+                    origin: module.origin.clone(),
+                    source: module.name.clone(),
+                    segment: segment.name.clone(),
+                    lines: Vec::new(),
+                });
+                last = Some(seg);
+            }
+
+            let frame = match group {
+                Some(group) => Some(linker.group(index, group)?.frame),
+                None => None,
+            };
+
+            for line in lines {
+                let (seg_frame, offset) = linker.segdef_address(index, seg, line.offset as u64)?;
+                let linear = seg_frame as u64 * 16 + offset;
+                let frame = frame.unwrap_or(seg_frame);
+                let offset = linear.checked_sub(frame as u64 * 16)
+                    .ok_or_else(|| LinkError::new(&format!("line {} is below its group's frame", line.line)).in_module(&module.origin))?;
+                blocks.last_mut().unwrap().lines.push((line.line, frame, offset as u32));
+            }
+        }
+    }
+
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::libwriter::{LibMember, LibWriter};
+    use crate::objfile::ParseOptions;
+    use crate::omfgen::compile;
+
+    #[test]
+    fn test_link_map() {
+        let obj = compile("
+            segment _TEXT CODE align=byte
+            segment _DATA DATA align=para
+            group DGROUP _DATA
+            public _main _TEXT 1
+            public _exit _TEXT 0
+            public _msg _DATA 2
+            data _TEXT 0 90 c3
+            data _DATA 0 00 00 00
+            start _TEXT 1
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &obj).unwrap();
+        linker.build_exe().unwrap();
+
+        let map = link_map(&linker).unwrap();
+        assert_eq!(map.segments, vec![
+            MapSegment{ start: 0, length: 2, name: "_TEXT".to_string(), class: "CODE".to_string(), overlay: 0 },
+            MapSegment{ start: 0x10, length: 3, name: "_DATA".to_string(), class: "DATA".to_string(), overlay: 0 },
+        ]);
+        assert_eq!(map.groups, vec![("DGROUP".to_string(), 1)]);
+        assert_eq!(map.entry, Some((0, 1)));
+
+        let names: Vec<&str> = map.publics_by_name().iter().map(|public| public.name.as_str()).collect();
+        assert_eq!(names, vec!["_exit", "_main", "_msg"]);
+        let values: Vec<(u16, u32)> = map.publics_by_value().iter().map(|public| (public.frame, public.offset)).collect();
+        assert_eq!(values, vec![(0, 0), (0, 1), (1, 2)]);
+        assert!(map.lines.is_empty());
+    }
+
+    #[test]
+    fn test_xref() {
+        let main = compile("
+            segment _TEXT CODE align=byte
+            extern _puts _exit
+            public _main _TEXT 0
+            data _TEXT 0 e8 00 00 e8 00 00
+            fixup _TEXT 1 word extern _puts self
+            fixup _TEXT 4 word extern _exit self
+            start _TEXT 0
+        ").unwrap();
+        let puts = compile("
+            segment _TEXT CODE align=byte
+            extern _exit
+            public _puts _TEXT 0
+            data _TEXT 0 e8 00 00
+            fixup _TEXT 1 word extern _exit self
+        ").unwrap();
+        let exit = compile("
+            segment _TEXT CODE align=byte
+            public _exit _TEXT 0
+            public _unused _TEXT 1
+            data _TEXT 0 c3 c3
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("main.obj", &main).unwrap();
+        linker.add_object("puts.obj", &puts).unwrap();
+        linker.add_object("exit.obj", &exit).unwrap();
+        linker.build_exe().unwrap();
+
+        let map = link_map(&linker).unwrap();
+        let xref: Vec<(&str, &str, Vec<&str>)> = map.xref_by_name().iter()
+            .map(|entry| (entry.name.as_str(), entry.defined.as_str(), entry.references.iter().map(|name| name.as_str()).collect()))
+            .collect();
+        assert_eq!(xref, vec![
+            ("_exit", "exit.obj", vec!["main.obj", "puts.obj"]),
+            ("_main", "main.obj", vec![]),
+            ("_puts", "puts.obj", vec!["main.obj"]),
+            ("_unused", "exit.obj", vec![]),
+        ]);
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        let first = compile("
+            segment _TEXT CODE align=para
+            data _TEXT 0 90 90 c3
+        ").unwrap();
+        let second = compile("
+            module b.c
+            segment _TEXT CODE align=byte
+            data _TEXT 0 90 c3
+            line _TEXT 5 0
+            line _TEXT 6 1
+        ").unwrap();
+
+        let mut linker = Linker::new(ParseOptions::default());
+        linker.add_object("a.obj", &first).unwrap();
+        linker.add_object("b.obj", &second).unwrap();
+        linker.build_exe().unwrap();
+
+        let map = link_map(&linker).unwrap();
+        assert_eq!(map.lines.len(), 1);
+        assert_eq!(map.lines[0].origin, "b.obj");
+        assert_eq!(map.lines[0].source, "b.c");
+        assert_eq!(map.lines[0].segment, "_TEXT");
+        assert_eq!(map.lines[0].lines, vec![(5, 0, 3), (6, 0, 4)]);
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let main = compile("
+            segment _TEXT CODE align=byte
+            segment _DATA DATA align=word
+            segment STACK STACK combine=stack length=100
+            group DGROUP _DATA STACK
+            extern _puts _exit _write
+            public _main _TEXT 0
+            public _zeta _DATA 0
+            public _alpha _DATA 2
+            public _mid _DATA 4
+            data _TEXT 0 e8 00 00 e8 00 00 c3
+            fixup _TEXT 1 word extern _puts self
+            fixup _TEXT 4 word extern _exit self
+            start _TEXT 0
+        ").unwrap();
+
+        let mut writer = LibWriter::new();
+        for (name, text) in [
+            ("puts", "segment _TEXT CODE\nextern _write\npublic _puts _TEXT 0\ndata _TEXT 0 c3"),
+            ("write", "segment _TEXT CODE\npublic _write _TEXT 0\npublic _read _TEXT 1\ndata _TEXT 0 c3 c3"),
+            ("exit", "segment _TEXT CODE\nsegment _BSS BSS\ngroup DGROUP _BSS\npublic _exit _TEXT 0\npublic _errno _BSS 0\ndata _TEXT 0 c3"),
+        ] {
+            let obj = compile(&format!("module {}\n{}", name, text)).unwrap();
+            writer.add(LibMember::from_obj(name, &obj, &ParseOptions::default()).unwrap()).unwrap();
+        }
+        let library = writer.write().unwrap();
+
+        let link = || {
+            let mut linker = Linker::new(ParseOptions::default());
+            linker.add_object("main.obj", &main).unwrap();
+            linker.add_library("C.LIB", library.clone()).unwrap();
+            linker.resolve().unwrap();
+            let exe = linker.build_exe().unwrap().write().unwrap();
+            (exe, link_map(&linker).unwrap(), linker.sym_file("MAIN").unwrap().write().unwrap())
+        };
+
+        // every link has hash tables of its own, seeded differently, so
+        // any order taken from one would show up as a difference
+        //
+        let first = link();
+        for _ in 0..4 {
+            assert_eq!(link(), first);
+        }
+    }
+}
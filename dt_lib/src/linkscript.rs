@@ -0,0 +1,145 @@
+use crate::error::Error as ScriptError;
+
+// A linker control script, for programs which don't load the way an EXE
+// does: ROMs, boot sectors and the like, which must be at fixed places in
+// memory. One directive per line; `;` starts a comment. All numbers are
+// hex, with an optional `0x` prefix or `h` suffix.
+//
+//   origin ADDRESS
+//   place NAME ADDRESS
+//   define NAME FRAME:OFFSET
+//   reserve ADDRESS LENGTH
+//
+// An ADDRESS is either linear or FRAME:OFFSET. `origin` links the program
+// to run where it says, and has it written as a flat binary image from
+// there rather than as an EXE. `place` puts a segment, or every segment
+// of a group, at an address; `define` makes an absolute public symbol;
+// and `reserve` keeps segments out of a range of memory. Given a frame,
+// what's at an address is addressed from that frame rather than from the
+// paragraph it starts in, as a boot sector linked at 0000:7C00 must be.
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub struct LinkScript {
+    pub origin: Option<Address>,
+    pub places: Vec<(String, Address)>,
+    pub symbols: Vec<(String, u16, u16)>,
+    pub reserved: Vec<(u64, u64)>,
+}
+
+// Somewhere in memory, and the frame it's to be addressed from if the
+// script gave one
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Address {
+    pub linear: u64,
+    pub frame: Option<u16>,
+}
+
+fn number(word: &str) -> Result<u64, ScriptError> {
+    let digits = word.strip_prefix("0x")
+        .or_else(|| word.strip_suffix('h'))
+        .or_else(|| word.strip_suffix('H'))
+        .unwrap_or(word);
+
+    u64::from_str_radix(digits, 16).map_err(|_| ScriptError::new(&format!("{} is not a hex number", word)))
+}
+
+fn word(text: &str) -> Result<u16, ScriptError> {
+    u16::try_from(number(text)?).map_err(|_| ScriptError::new(&format!("{} does not fit in 16 bits", text)))
+}
+
+fn far_address(text: &str) -> Result<(u16, u16), ScriptError> {
+    match text.split_once(':') {
+        Some((frame, offset)) => Ok((word(frame)?, word(offset)?)),
+        None => Err(ScriptError::new(&format!("{} is not a FRAME:OFFSET address", text))),
+    }
+}
+
+fn address(text: &str) -> Result<Address, ScriptError> {
+    let address = match text.contains(':') {
+        true => {
+            let (frame, offset) = far_address(text)?;
+            Address{ linear: frame as u64 * 16 + offset as u64, frame: Some(frame) }
+        },
+        false => Address{ linear: number(text)?, frame: None },
+    };
+
+    match address.linear {
+        linear if linear >= 0x100000 => Err(ScriptError::new(&format!("{} is above 1M", text))),
+        _ => Ok(address),
+    }
+}
+
+impl LinkScript {
+    pub fn parse(text: &str) -> Result<LinkScript, ScriptError> {
+        let mut script = LinkScript::default();
+
+        for (line, text) in text.lines().enumerate() {
+            let text = text.split(';').next().unwrap_or("");
+            let words: Vec<&str> = text.split_whitespace().collect();
+
+            if !words.is_empty() {
+                script.directive(&words)
+                    .map_err(|err| ScriptError::new(&format!("line {}: {}", line + 1, err.details)))?;
+            }
+        }
+
+        Ok(script)
+    }
+
+    fn directive(&mut self, words: &[&str]) -> Result<(), ScriptError> {
+        match words {
+            ["origin", at] => match self.origin {
+                Some(_) => return Err(ScriptError::new("origin is given more than once")),
+                None => self.origin = Some(address(at)?),
+            },
+            ["place", name, at] => self.places.push((name.to_string(), address(at)?)),
+            ["define", name, at] => {
+                let (frame, offset) = far_address(at)?;
+                self.symbols.push((name.to_string(), frame, offset));
+            },
+            ["reserve", at, length] => {
+                let start = address(at)?.linear;
+                self.reserved.push((start, start + number(length)?));
+            },
+            [directive, ..] => return Err(ScriptError::new(&format!("bad or incomplete directive {}", directive))),
+            [] => (),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_script() {
+        let script = LinkScript::parse("
+            ; a boot sector
+            origin 0:7c00
+            place _DATA 7e00h
+            place VECTORS 0x0000:0x0000
+            define _bios_data 40:0
+            reserve 7d00 10   ; the partition table
+        ").unwrap();
+
+        assert_eq!(script.origin, Some(Address{ linear: 0x7c00, frame: Some(0) }));
+        assert_eq!(script.places, vec![
+            ("_DATA".to_string(), Address{ linear: 0x7e00, frame: None }),
+            ("VECTORS".to_string(), Address{ linear: 0, frame: Some(0) }),
+        ]);
+        assert_eq!(script.symbols, vec![("_bios_data".to_string(), 0x40, 0)]);
+        assert_eq!(script.reserved, vec![(0x7d00, 0x7d10)]);
+
+        assert_eq!(LinkScript::parse("origin 100\norigin 200").unwrap_err().details, "line 2: origin is given more than once");
+        assert_eq!(LinkScript::parse("define x 400").unwrap_err().details, "line 1: 400 is not a FRAME:OFFSET address");
+        assert_eq!(LinkScript::parse("place _TEXT 100000").unwrap_err().details, "line 1: 100000 is above 1M");
+        assert!(LinkScript::parse("reserve 100").is_err());
+    }
+}
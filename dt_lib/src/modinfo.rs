@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+
+use crate::error::Error as ObjError;
+use crate::objfile::*;
+
+// Record types which come in a 16- and a 32-bit flavor; the 32-bit one is
+// always the next (odd) type.
+//
+const SIZED_RECORDS: [u8; 13] = [
+    0x8a, 0x90, 0x94, 0x98, 0x9c, 0xa0, 0xa2, 0xb2, 0xb4, 0xb6, 0xc2, 0xc4, 0xc8,
+];
+
+// A quick summary of one module, for triage without walking every record
+//
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub struct ModuleInfo {
+    pub name: Option<String>,
+    pub uses16: bool,
+    pub uses32: bool,
+    pub translator: Option<String>,
+    pub memory_model: Option<String>,
+    pub default_libraries: Vec<String>,
+    pub debug_info: bool,
+    pub record_counts: BTreeMap<u8, usize>,
+}
+
+impl ModuleInfo {
+    fn record(&mut self, rectype: u8, easy_omf: bool) {
+        *self.record_counts.entry(rectype).or_insert(0) += 1;
+
+        if SIZED_RECORDS.contains(&rectype) {
+            if easy_omf {
+                self.uses32 = true;
+            } else {
+                self.uses16 = true;
+            }
+        } else if SIZED_RECORDS.contains(&rectype.wrapping_sub(1)) {
+            self.uses32 = true;
+        }
+
+        // LINNUM, LINSYM, and the Intel symbol records
+        //
+        if matches!(rectype, 0x94 | 0x95 | 0xc4 | 0xc5 | 0x7e | 0x8e | 0x92) {
+            self.debug_info = true;
+        }
+    }
+
+    fn coment(&mut self, coment: &Coment) {
+        match coment {
+            Coment::Translator{ text } if self.translator.is_none() => self.translator = Some(text.clone()),
+            Coment::MemoryModel{ text } => self.memory_model = Some(text.clone()),
+            Coment::DefaultLibrary{ name } => self.default_libraries.push(name.clone()),
+            Coment::NewOMF{ .. } => self.debug_info = true,
+            _ => (),
+        }
+    }
+
+    pub fn is_mixed(&self) -> bool {
+        self.uses16 && self.uses32
+    }
+}
+
+// Scan one module and summarize it. Stops at MODEND, so a library member
+// may be passed with the rest of the library following it.
+//
+pub fn module_info(obj: &[u8], options: &ParseOptions) -> Result<ModuleInfo, ObjError> {
+    let mut parser = Parser::with_options(obj, options.clone());
+    let mut info = ModuleInfo::default();
+    let mut lnames: Vec<String> = Vec::new();
+    let mut easy_omf = false;
+
+    loop {
+        let parsed = parser.next_parsed()?;
+        info.record(parsed.rectype, easy_omf);
+
+        match &parsed.record {
+            Record::None => {
+                // end of input isn't a record
+                //
+                info.record_counts.remove(&0);
+                break;
+            },
+            Record::THEADR{ name } | Record::LHEADR{ name } if info.name.is_none() => info.name = Some(name.clone()),
+            Record::LNAMES{ names } => lnames.extend(names.iter().cloned()),
+            Record::SEGDEF{ segs } => {
+                // CodeView and Borland debug info live in these segments
+                //
+                let debug = segs.iter()
+                    .filter_map(|seg| seg.name.and_then(|name| lnames.get(name.wrapping_sub(1))))
+                    .any(|name| name == "$$SYMBOLS" || name == "$$TYPES");
+                info.debug_info |= debug;
+            },
+            Record::COMENT{ coment: Coment::EasyOmf{ .. }, .. } => easy_omf = true,
+            Record::COMENT{ coment, .. } => info.coment(coment),
+            Record::MODEND{ .. } => break,
+            _ => (),
+        }
+    }
+
+    if let Some(module) = parser.module() {
+        info.name = Some(module.to_string());
+    }
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_module_info_summarizes_module() {
+        let obj = vec![
+            0x80, 0x03, 0x00, 0x01, 0x41, 0x3b,
+            // translator "TC"
+            0x88, 0x05, 0x00, 0x00, 0x00, 0x54, 0x43, 0x00,
+            // memory model "sl"
+            0x88, 0x05, 0x00, 0x00, 0x9d, 0x73, 0x6c, 0x00,
+            // default library "CL"
+            0x88, 0x05, 0x00, 0x00, 0x9f, 0x43, 0x4c, 0x00,
+            // LNAMES $$TYPES
+            0x96, 0x09, 0x00, 0x07, 0x24, 0x24, 0x54, 0x59, 0x50, 0x45, 0x53, 0x00,
+            0x98, 0x07, 0x00, 0x28, 0x00, 0x00, 0x01, 0x01, 0x01, 0x00,
+            0x8b, 0x02, 0x00, 0x00, 0x00,
+        ];
+
+        let info = module_info(&obj, &ParseOptions::default()).unwrap();
+
+        assert_eq!(info.name.as_deref(), Some("A"));
+        assert_eq!(info.translator.as_deref(), Some("TC"));
+        assert_eq!(info.memory_model.as_deref(), Some("sl"));
+        assert_eq!(info.default_libraries, vec!["CL".to_string()]);
+        assert!(info.debug_info);
+        assert!(info.is_mixed());
+        assert_eq!(info.record_counts.get(&0x88), Some(&3));
+        assert_eq!(info.record_counts.get(&0x8b), Some(&1));
+        assert_eq!(info.record_counts.len(), 5);
+    }
+}
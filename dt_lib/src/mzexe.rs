@@ -0,0 +1,340 @@
+use crate::error::Error as ExeError;
+use crate::linker::Relocation;
+
+const MZ_MAGIC: u16 = 0x5a4d;
+const HEADER_SIZE: usize = 0x1c;
+
+// The fixed part of a DOS .EXE header, as it is on disk
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub struct MzHeader {
+    pub last_page_bytes: u16,
+    pub pages: u16,
+    pub relocations: u16,
+    pub header_paragraphs: u16,
+    pub min_alloc: u16,
+    pub max_alloc: u16,
+    pub ss: u16,
+    pub sp: u16,
+    pub checksum: u16,
+    pub ip: u16,
+    pub cs: u16,
+    pub reloc_offset: u16,
+    pub overlay: u16,
+}
+
+impl MzHeader {
+    pub fn parse(exe: &[u8]) -> Result<MzHeader, ExeError> {
+        if exe.len() < HEADER_SIZE {
+            return Err(ExeError::new("file is too short to be an EXE"));
+        }
+
+        let word = |index: usize| u16::from_le_bytes([exe[index * 2], exe[index * 2 + 1]]);
+        if word(0) != MZ_MAGIC {
+            return Err(ExeError::new("file is not an MZ EXE"));
+        }
+
+        Ok(MzHeader{
+            last_page_bytes: word(1),
+            pages: word(2),
+            relocations: word(3),
+            header_paragraphs: word(4),
+            min_alloc: word(5),
+            max_alloc: word(6),
+            ss: word(7),
+            sp: word(8),
+            checksum: word(9),
+            ip: word(10),
+            cs: word(11),
+            reloc_offset: word(12),
+            overlay: word(13),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [
+            MZ_MAGIC, self.last_page_bytes, self.pages, self.relocations, self.header_paragraphs,
+            self.min_alloc, self.max_alloc, self.ss, self.sp, self.checksum, self.ip, self.cs,
+            self.reloc_offset, self.overlay,
+        ].iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    // How much of the file DOS loads: everything the page counts cover,
+    // less the header
+    //
+    pub fn image_size(&self) -> usize {
+        let file = match self.last_page_bytes {
+            0 => self.pages as usize * 512,
+            bytes => (self.pages as usize).saturating_sub(1) * 512 + bytes as usize,
+        };
+        file.saturating_sub(self.header_paragraphs as usize * 16)
+    }
+}
+
+// A program ready to be written as an EXE: the load image, the words in
+// it DOS must relocate, and what the header says about running it
+//
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct MzExe {
+    pub image: Vec<u8>,
+    pub relocations: Vec<Relocation>,
+
+    // Paragraphs of memory the program needs, and would like, past the
+    // end of the load image
+    pub min_alloc: u16,
+    pub max_alloc: u16,
+
+    pub ss: u16,
+    pub sp: u16,
+    pub cs: u16,
+    pub ip: u16,
+
+    // Overlays, written after the program as EXEs of their own, numbered
+    // from 1 in their headers' overlay field. DOS doesn't load them; the
+    // program's overlay manager does.
+    pub overlays: Vec<MzExe>,
+}
+
+impl MzExe {
+    // Ask for at most `paragraphs` past the image, as /CPARMAXALLOC does,
+    // though never less than the program needs
+    //
+    pub fn set_max_alloc(&mut self, paragraphs: u16) {
+        self.max_alloc = paragraphs.max(self.min_alloc);
+    }
+
+    // Have DOS load the program as high in memory as it can, as /HIGH
+    // does. DOS does that when the header asks for no memory past the
+    // image, so what the program needs past it is put back in the image.
+    //
+    pub fn load_high(&mut self) {
+        self.image.resize(self.image.len() + self.min_alloc as usize * 16, 0);
+        self.min_alloc = 0;
+        self.max_alloc = 0;
+    }
+
+    // The header for the EXE, which is padded out to a whole paragraph
+    // after the relocation table
+    //
+    pub fn header(&self) -> Result<MzHeader, ExeError> {
+        self.section_header(0)
+    }
+
+    fn section_header(&self, overlay: u16) -> Result<MzHeader, ExeError> {
+        let relocations = u16::try_from(self.relocations.len())
+            .map_err(|_| ExeError::new(&format!("{} relocations is too many for an EXE", self.relocations.len())))?;
+        let header_size = (HEADER_SIZE + self.relocations.len() * 4).div_ceil(16) * 16;
+        let file_size = header_size + self.image.len();
+
+        if file_size.div_ceil(512) > 0xffff {
+            return Err(ExeError::new(&format!("program is too large for an EXE ({} bytes)", file_size)));
+        }
+
+        Ok(MzHeader{
+            last_page_bytes: (file_size % 512) as u16,
+            pages: file_size.div_ceil(512) as u16,
+            relocations,
+            header_paragraphs: (header_size / 16) as u16,
+            min_alloc: self.min_alloc,
+            max_alloc: self.max_alloc,
+            ss: self.ss,
+            sp: self.sp,
+            checksum: 0,
+            ip: self.ip,
+            cs: self.cs,
+            reloc_offset: HEADER_SIZE as u16,
+            overlay,
+        })
+    }
+
+    // The EXE file: header, relocation table, padding, then the image,
+    // and then each overlay the same way
+    //
+    pub fn write(&self) -> Result<Vec<u8>, ExeError> {
+        let mut exe = self.write_section(0)?;
+        for (index, overlay) in self.overlays.iter().enumerate() {
+            let number = u16::try_from(index + 1).map_err(|_| ExeError::new("too many overlays for an EXE"))?;
+            exe.extend(overlay.write_section(number)?);
+        }
+        Ok(exe)
+    }
+
+    fn write_section(&self, overlay: u16) -> Result<Vec<u8>, ExeError> {
+        let header = self.section_header(overlay)?;
+        let mut exe = header.to_bytes();
+
+        for reloc in &self.relocations {
+            exe.extend(reloc.offset.to_le_bytes());
+            exe.extend(reloc.segment.to_le_bytes());
+        }
+
+        exe.resize(header.header_paragraphs as usize * 16, 0);
+        exe.extend(&self.image);
+        Ok(exe)
+    }
+
+    // Read an EXE back, as far as this module writes them, along with any
+    // overlays which follow it
+    //
+    pub fn parse(exe: &[u8]) -> Result<MzExe, ExeError> {
+        let (mut program, mut end) = MzExe::parse_section(exe)?;
+
+        while exe.len() - end >= HEADER_SIZE && exe[end..end + 2] == MZ_MAGIC.to_le_bytes() {
+            let (overlay, length) = MzExe::parse_section(&exe[end..])?;
+            program.overlays.push(overlay);
+            end += length;
+        }
+
+        Ok(program)
+    }
+
+    // One EXE at the start of `exe`, and how many bytes of the file it is
+    //
+    fn parse_section(exe: &[u8]) -> Result<(MzExe, usize), ExeError> {
+        let header = MzHeader::parse(exe)?;
+        let start = header.header_paragraphs as usize * 16;
+        let end = start + header.image_size();
+        let relocs = header.reloc_offset as usize;
+
+        if end > exe.len() || relocs + header.relocations as usize * 4 > exe.len() {
+            return Err(ExeError::new("EXE is truncated"));
+        }
+
+        let word = |at: usize| u16::from_le_bytes([exe[at], exe[at + 1]]);
+        let relocations = (0..header.relocations as usize)
+            .map(|index| Relocation{ offset: word(relocs + index * 4), segment: word(relocs + index * 4 + 2) })
+            .collect();
+
+        let section = MzExe{
+            image: exe[start..end].to_vec(),
+            relocations,
+            min_alloc: header.min_alloc,
+            max_alloc: header.max_alloc,
+            ss: header.ss,
+            sp: header.sp,
+            cs: header.cs,
+            ip: header.ip,
+            overlays: Vec::new(),
+        };
+        Ok((section, end))
+    }
+}
+
+// Fill in the checksum of an EXE file as written: the word which makes the
+// words of the program, header and all, add up to ffff. Overlays after it
+// aren't counted.
+//
+pub fn set_checksum(exe: &mut [u8]) -> Result<(), ExeError> {
+    let header = MzHeader::parse(exe)?;
+    let length = (header.image_size() + header.header_paragraphs as usize * 16).min(exe.len());
+
+    exe[0x12..0x14].copy_from_slice(&[0, 0]);
+    let sum = exe[..length].chunks(2)
+        .map(|word| u16::from_le_bytes([word[0], word.get(1).copied().unwrap_or(0)]))
+        .fold(0u16, |sum, word| sum.wrapping_add(word));
+    exe[0x12..0x14].copy_from_slice(&(!sum).to_le_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_exe() {
+        let exe = MzExe{
+            image: vec![0x90; 0x300],
+            relocations: vec![Relocation{ segment: 0x10, offset: 3 }],
+            min_alloc: 0x20,
+            max_alloc: 0xffff,
+            ss: 0x30,
+            sp: 0x100,
+            cs: 0,
+            ip: 0x12,
+            overlays: Vec::new(),
+        };
+
+        let bytes = exe.write().unwrap();
+        assert_eq!(bytes.len(), 0x20 + 0x300);
+        assert_eq!(&bytes[0..2], b"MZ");
+        assert_eq!(&bytes[0x1c..0x20], &[3, 0, 0x10, 0]);
+
+        let header = MzHeader::parse(&bytes).unwrap();
+        assert_eq!(header.pages, 2);
+        assert_eq!(header.last_page_bytes, 0x120);
+        assert_eq!(header.header_paragraphs, 2);
+        assert_eq!(header.image_size(), 0x300);
+
+        let parsed = MzExe::parse(&bytes).unwrap();
+        assert_eq!(parsed.image, exe.image);
+        assert_eq!(parsed.relocations, exe.relocations);
+        assert_eq!((parsed.ss, parsed.sp, parsed.cs, parsed.ip), (0x30, 0x100, 0, 0x12));
+    }
+
+    #[test]
+    fn test_write_overlays() {
+        let section = |image: Vec<u8>, relocations| MzExe{
+            image, relocations, min_alloc: 0, max_alloc: 0, ss: 0, sp: 0, cs: 0, ip: 0, overlays: Vec::new(),
+        };
+        let mut exe = section(vec![0xc3; 0x30], Vec::new());
+        exe.overlays.push(section(vec![0x90; 0x21], vec![Relocation{ segment: 1, offset: 4 }]));
+        exe.overlays.push(section(vec![0xcc; 0x10], Vec::new()));
+
+        let bytes = exe.write().unwrap();
+        assert_eq!(bytes.len(), 0x50 + 0x41 + 0x30);
+
+        let header = MzHeader::parse(&bytes[0x50..]).unwrap();
+        assert_eq!(header.overlay, 1);
+        assert_eq!(header.image_size(), 0x21);
+        let header = MzHeader::parse(&bytes[0x91..]).unwrap();
+        assert_eq!(header.overlay, 2);
+
+        let parsed = MzExe::parse(&bytes).unwrap();
+        assert_eq!(parsed.overlays.len(), 2);
+        assert_eq!(parsed.overlays[0].image, vec![0x90; 0x21]);
+        assert_eq!(parsed.overlays[0].relocations, vec![Relocation{ segment: 1, offset: 4 }]);
+        assert_eq!(parsed.overlays[1].image, vec![0xcc; 0x10]);
+    }
+
+    #[test]
+    fn test_header_tuning() {
+        let mut exe = MzExe{
+            image: vec![0x90; 0x21],
+            relocations: Vec::new(),
+            min_alloc: 0x20,
+            max_alloc: 0xffff,
+            ss: 0,
+            sp: 0,
+            cs: 0,
+            ip: 0,
+            overlays: Vec::new(),
+        };
+
+        exe.set_max_alloc(0x100);
+        assert_eq!(exe.max_alloc, 0x100);
+        exe.set_max_alloc(0x10);
+        assert_eq!(exe.max_alloc, 0x20);
+
+        exe.load_high();
+        assert_eq!((exe.min_alloc, exe.max_alloc), (0, 0));
+        assert_eq!(exe.image.len(), 0x221);
+
+        let mut bytes = exe.write().unwrap();
+        set_checksum(&mut bytes).unwrap();
+        let sum = bytes.chunks(2)
+            .map(|word| u16::from_le_bytes([word[0], word.get(1).copied().unwrap_or(0)]))
+            .fold(0u16, |sum, word| sum.wrapping_add(word));
+        assert_eq!(sum, 0xffff);
+        assert_ne!(MzHeader::parse(&bytes).unwrap().checksum, 0);
+    }
+
+    #[test]
+    fn test_parse_rejects_non_exe() {
+        assert!(MzHeader::parse(b"short").is_err());
+        assert!(MzHeader::parse(&[0u8; 0x40]).is_err());
+    }
+}
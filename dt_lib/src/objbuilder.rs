@@ -0,0 +1,808 @@
+use crate::codepage::Codepage;
+use crate::error::Error as ObjError;
+use crate::objfile::*;
+use crate::objwriter::*;
+
+// Handles to things declared in a builder. These are the 1-based indices
+// the records will use.
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct SegmentId(usize);
+
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct GroupId(usize);
+
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ExternId(usize);
+
+impl SegmentId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl GroupId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl ExternId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct SegmentSpec {
+    pub name: String,
+    pub class: String,
+    pub overlay: Option<String>,
+    pub align: Align,
+    pub combine: Combine,
+    pub use32: bool,
+
+    // The segment is at least this long; data past the end extends it
+    pub length: u64,
+}
+
+impl SegmentSpec {
+    pub fn new(name: &str, class: &str) -> SegmentSpec {
+        SegmentSpec{
+            name: name.to_string(),
+            class: class.to_string(),
+            overlay: None,
+            align: Align::Paragraph,
+            combine: Combine::Public{ raw: 2 },
+            use32: false,
+            length: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum FixupTarget {
+    Segment(SegmentId),
+    Group(GroupId),
+    Extern(ExternId),
+}
+
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum FixupFrame {
+    Segment(SegmentId),
+    Group(GroupId),
+    Extern(ExternId),
+    Location,
+    Target,
+}
+
+// A fixup in terms of the things it refers to, rather than the indices
+// and methods which encode it
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+pub struct FixupSpec {
+    pub location: FixupLocation,
+    pub self_relative: bool,
+    pub target: FixupTarget,
+    pub frame: FixupFrame,
+    pub displacement: u32,
+}
+
+impl FixupSpec {
+    // The common case: a segment-relative fixup whose frame is that of
+    // the target
+    //
+    pub fn new(location: FixupLocation, target: FixupTarget) -> FixupSpec {
+        FixupSpec{ location, self_relative: false, target, frame: FixupFrame::Target, displacement: 0 }
+    }
+
+    fn encode(&self, data_offset: usize) -> Fixup {
+        let (target_method, target_datum) = match self.target {
+            FixupTarget::Segment(seg) => (TargetMethod::Segdef, seg.0),
+            FixupTarget::Group(group) => (TargetMethod::Grpdef, group.0),
+            FixupTarget::Extern(ext) => (TargetMethod::Extdef, ext.0),
+        };
+
+        let (frame_method, frame_datum) = match self.frame {
+            FixupFrame::Segment(seg) => (FrameMethod::Segdef, Some(seg.0)),
+            FixupFrame::Group(group) => (FrameMethod::Grpdef, Some(group.0)),
+            FixupFrame::Extern(ext) => (FrameMethod::Extdef, Some(ext.0)),
+            FixupFrame::Location => (FrameMethod::PreviousDataRecord, None),
+            FixupFrame::Target => (FrameMethod::Target, None),
+        };
+
+        // the short form is used when there's nothing to add
+        //
+        let target_method = match (target_method, self.displacement) {
+            (TargetMethod::Segdef, 0) => TargetMethod::SegdefNoDisplacement,
+            (TargetMethod::Grpdef, 0) => TargetMethod::GrpdefNoDisplacement,
+            (TargetMethod::Extdef, 0) => TargetMethod::ExtdefNoDisplacement,
+            (method, _) => method,
+        };
+
+        Fixup{
+            is_seg_relative: !self.self_relative,
+            location: self.location,
+            data_offset,
+            frame_thread: None,
+            frame_method: Some(frame_method),
+            frame_datum,
+            target_thread: None,
+            target_method: Some(target_method),
+            target_datum: Some(target_datum),
+            target_displacement: self.displacement,
+        }
+    }
+}
+
+enum SegmentData {
+    Literal(Vec<u8>),
+    Repeated{ pattern: Vec<u8>, count: u32 },
+}
+
+impl SegmentData {
+    fn len(&self) -> u64 {
+        match self {
+            SegmentData::Literal(data) => data.len() as u64,
+            SegmentData::Repeated{ pattern, count } => pattern.len() as u64 * *count as u64,
+        }
+    }
+}
+
+struct Segment {
+    spec: SegmentSpec,
+    name: usize,
+    class: usize,
+    overlay: Option<usize>,
+    data: Vec<(u32, SegmentData)>,
+    fixups: Vec<(u32, FixupSpec)>,
+}
+
+impl Segment {
+    fn length(&self) -> u64 {
+        self.data.iter()
+            .map(|(offset, data)| *offset as u64 + data.len())
+            .fold(self.spec.length, u64::max)
+    }
+
+    fn has_fixup_in(&self, start: usize, end: usize) -> bool {
+        self.fixups.iter().any(|(offset, fixup)| {
+            let offset = *offset as usize;
+            offset < end && offset + fixup.location.size() > start
+        })
+    }
+}
+
+struct Group {
+    name: usize,
+    segs: Vec<SegmentId>,
+}
+
+struct Public {
+    name: String,
+    seg: SegmentId,
+    offset: u32,
+}
+
+// Builds an object module from symbolic declarations. Index assignment,
+// splitting data into records, and pairing fixups with the data record
+// they patch are all done when the object is written.
+//
+pub struct ObjBuilder {
+    pub codepage: Codepage,
+
+    // Runs of at least this many copies of one byte are written as LIDATA
+    pub repeat_threshold: Option<usize>,
+
+    pub variants: RecordVariants,
+
+    name: String,
+    lnames: LnameTable,
+    segments: Vec<Segment>,
+    groups: Vec<Group>,
+    externs: Vec<String>,
+    publics: Vec<Public>,
+    lines: Vec<(SegmentId, u16, u32)>,
+    start: Option<(SegmentId, u32)>,
+}
+
+impl ObjBuilder {
+    pub fn new(name: &str) -> ObjBuilder {
+        ObjBuilder{
+            codepage: Codepage::default(),
+            repeat_threshold: Some(64),
+            variants: RecordVariants::default(),
+            name: name.to_string(),
+            lnames: LnameTable::new(),
+            segments: Vec::new(),
+            groups: Vec::new(),
+            externs: Vec::new(),
+            publics: Vec::new(),
+            lines: Vec::new(),
+            start: None,
+        }
+    }
+
+    // The LNAMES index of `name`, shared with every other use of it
+    //
+    pub fn lname(&mut self, name: &str) -> usize {
+        self.lnames.intern(name)
+    }
+
+    // A new LNAMES entry for `name`, even if it's already been used
+    //
+    pub fn force_lname(&mut self, name: &str) -> usize {
+        self.lnames.force(name)
+    }
+
+    pub fn find_lname(&self, name: &str) -> Option<usize> {
+        self.lnames.find(name)
+    }
+
+    pub fn segment(&mut self, spec: SegmentSpec) -> SegmentId {
+        let name = self.lname(&spec.name);
+        let class = self.lname(&spec.class);
+        let overlay = spec.overlay.as_ref().map(|overlay| self.lnames.intern(overlay));
+
+        self.segments.push(Segment{ spec, name, class, overlay, data: Vec::new(), fixups: Vec::new() });
+        SegmentId(self.segments.len())
+    }
+
+    pub fn group(&mut self, name: &str, segs: &[SegmentId]) -> GroupId {
+        let name = self.lname(name);
+        self.groups.push(Group{ name, segs: segs.to_vec() });
+        GroupId(self.groups.len())
+    }
+
+    pub fn external(&mut self, name: &str) -> ExternId {
+        self.externs.push(name.to_string());
+        ExternId(self.externs.len())
+    }
+
+    pub fn public(&mut self, name: &str, seg: SegmentId, offset: u32) {
+        self.publics.push(Public{ name: name.to_string(), seg, offset });
+    }
+
+    // Make this the main module, starting at `offset` in the segment
+    //
+    pub fn start(&mut self, seg: SegmentId, offset: u32) {
+        self.start = Some((seg, offset));
+    }
+
+    pub fn data(&mut self, seg: SegmentId, offset: u32, data: &[u8]) -> Result<(), ObjError> {
+        self.segment_mut(seg)?.data.push((offset, SegmentData::Literal(data.to_vec())));
+        Ok(())
+    }
+
+    // `count` copies of `pattern` at `offset`, written as iterated data.
+    // There can be no fixups in it.
+    //
+    pub fn repeat(&mut self, seg: SegmentId, offset: u32, pattern: &[u8], count: u32) -> Result<(), ObjError> {
+        if pattern.is_empty() || pattern.len() > 0xff {
+            return Err(ObjError::new(&format!("repeated pattern is {} bytes long (must be 1 to 255)", pattern.len())));
+        }
+
+        self.segment_mut(seg)?.data.push((offset, SegmentData::Repeated{ pattern: pattern.to_vec(), count }));
+        Ok(())
+    }
+
+    // Source line `line` starts at `offset` in the segment
+    //
+    pub fn line(&mut self, seg: SegmentId, line: u16, offset: u32) -> Result<(), ObjError> {
+        self.segment_mut(seg)?;
+        self.lines.push((seg, line, offset));
+        Ok(())
+    }
+
+    // A fixup at `offset` in the segment, which must lie inside data given
+    // to `data`
+    //
+    pub fn fixup(&mut self, seg: SegmentId, offset: u32, fixup: FixupSpec) -> Result<(), ObjError> {
+        self.segment_mut(seg)?.fixups.push((offset, fixup));
+        Ok(())
+    }
+
+    fn segment_mut(&mut self, seg: SegmentId) -> Result<&mut Segment, ObjError> {
+        self.segments.get_mut(seg.0.wrapping_sub(1))
+            .ok_or_else(|| ObjError::new(&format!("segment {} was not declared", seg.0)))
+    }
+
+    fn body(&self) -> RecordBody {
+        RecordBody::with_codepage(self.codepage)
+    }
+
+    fn group_of(&self, seg: SegmentId) -> Option<GroupId> {
+        self.groups.iter()
+            .position(|group| group.segs.contains(&seg))
+            .map(|index| GroupId(index + 1))
+    }
+
+    // Write the object
+    //
+    pub fn build(self) -> Result<Vec<u8>, ObjError> {
+        let mut writer = RecordWriter::new();
+
+        writer.record(0x80, self.body().str(&self.name)?.bytes())?;
+        self.lnames.write(&mut writer, self.codepage)?;
+
+        let segdefs: Vec<Segdef> = self.segments.iter()
+            .map(|segment| Segdef{
+                align: segment.spec.align.clone(),
+                combine: segment.spec.combine.clone(),
+                use32: segment.spec.use32,
+                abs: None,
+                length: segment.length(),
+                name: Some(segment.name),
+                class: Some(segment.class),
+                overlay: segment.overlay,
+            })
+            .collect();
+
+        for segdef in segdefs.iter() {
+            // a 16-bit SEGDEF holds a 64K length with the BIG bit
+            //
+            let is32 = self.variants.is32(segdef.use32, segdef.length.saturating_sub(1))?;
+            let rectype = RecordVariants::rectype(0x98, is32);
+            writer.record(rectype, self.body().segdef(segdef, is32)?.bytes())?;
+        }
+
+        for group in self.groups.iter() {
+            let mut body = self.body();
+            body.index(group.name)?;
+            for seg in group.segs.iter() {
+                body.byte(0xff).index(seg.0)?;
+            }
+            writer.record(0x9a, body.bytes())?;
+        }
+
+        let items = self.externs.iter()
+            .map(|name| Ok(self.body().str(name)?.index(0)?.bytes().to_vec()))
+            .collect::<Result<Vec<_>, ObjError>>()?;
+        writer.list_record(0x8c, &[], &items)?;
+
+        self.write_publics(&mut writer)?;
+
+        let mut threader = FixupThreader::new();
+        for (index, segment) in self.segments.iter().enumerate() {
+            self.write_data(&mut writer, &mut threader, SegmentId(index + 1), segment)?;
+        }
+
+        self.write_lines(&mut writer)?;
+        self.write_modend(&mut writer)?;
+
+        Ok(writer.into_bytes())
+    }
+
+    // The start address is framed by the segment's group, if it has one
+    //
+    fn write_modend(&self, writer: &mut RecordWriter) -> Result<(), ObjError> {
+        let (seg, offset) = match self.start {
+            Some(start) => start,
+            None => {
+                writer.record(0x8a, self.body().modend(false, None, false)?.bytes())?;
+                return Ok(());
+            },
+        };
+
+        let segment = self.segments.get(seg.0.wrapping_sub(1))
+            .ok_or_else(|| ObjError::new(&format!("start address is in undeclared segment {}", seg.0)))?;
+
+        let (frame, frame_datum) = match self.group_of(seg) {
+            Some(group) => (FrameMethod::Grpdef, Some(group.0)),
+            None => (FrameMethod::Target, None),
+        };
+        let target = if offset == 0 { TargetMethod::SegdefNoDisplacement } else { TargetMethod::Segdef };
+        let start = start_address(frame, frame_datum, target, seg.0, offset)?;
+
+        let is32 = self.variants.is32(segment.spec.use32, offset as u64)?;
+        writer.record(RecordVariants::rectype(0x8a, is32), self.body().modend(true, Some(&start), is32)?.bytes())?;
+        Ok(())
+    }
+
+    // One LINNUM per segment with line numbers, based on its group
+    //
+    fn write_lines(&self, writer: &mut RecordWriter) -> Result<(), ObjError> {
+        for (index, segment) in self.segments.iter().enumerate() {
+            let seg = SegmentId(index + 1);
+            let lines: Vec<(u16, u32)> = self.lines.iter()
+                .filter(|(line_seg, _, _)| *line_seg == seg)
+                .map(|(_, line, offset)| (*line, *offset))
+                .collect();
+            if lines.is_empty() {
+                continue;
+            }
+
+            let largest = lines.iter().map(|(_, offset)| *offset as u64).max().unwrap_or(0);
+            let is32 = self.variants.is32(segment.spec.use32, largest)?;
+
+            let mut body = self.body();
+            body.opt_index(self.group_of(seg).map(|group| group.0))?.index(seg.0)?;
+            for (line, offset) in lines {
+                body.word(line).offset(offset, is32)?;
+            }
+            writer.record(RecordVariants::rectype(0x94, is32), body.bytes())?;
+        }
+        Ok(())
+    }
+
+    fn write_publics(&self, writer: &mut RecordWriter) -> Result<(), ObjError> {
+        for (index, segment) in self.segments.iter().enumerate() {
+            let seg = SegmentId(index + 1);
+            let largest = self.publics.iter()
+                .filter(|public| public.seg == seg)
+                .map(|public| public.offset as u64)
+                .max()
+                .unwrap_or(0);
+            let is32 = self.variants.is32(segment.spec.use32, largest)?;
+
+            let items = self.publics.iter()
+                .filter(|public| public.seg == seg)
+                .map(|public| Ok(self.body().str(&public.name)?.offset(public.offset, is32)?.index(0)?.bytes().to_vec()))
+                .collect::<Result<Vec<_>, ObjError>>()?;
+
+            let mut base = self.body();
+            base.opt_index(self.group_of(seg).map(|group| group.0))?.index(seg.0)?;
+
+            writer.list_record(RecordVariants::rectype(0x90, is32), base.bytes(), &items)?;
+        }
+
+        if let Some(public) = self.publics.iter().find(|public| public.seg.0 == 0 || public.seg.0 > self.segments.len()) {
+            return Err(ObjError::new(&format!("public {} is in an undeclared segment", public.name)));
+        }
+
+        Ok(())
+    }
+
+    fn write_data(&self, writer: &mut RecordWriter, threader: &mut FixupThreader, seg: SegmentId, segment: &Segment) -> Result<(), ObjError> {
+        let mut blocks: Vec<&(u32, SegmentData)> = segment.data.iter().collect();
+        blocks.sort_by_key(|(offset, _)| *offset);
+
+        for pair in blocks.windows(2) {
+            if pair[0].0 as u64 + pair[0].1.len() > pair[1].0 as u64 {
+                return Err(ObjError::new(&format!("data at {:08x} overlaps data at {:08x} in segment {}", pair[1].0, pair[0].0, segment.spec.name)));
+            }
+        }
+
+        for (offset, _) in segment.fixups.iter() {
+            let covered = blocks.iter().any(|(start, data)| {
+                matches!(data, SegmentData::Literal(_)) && *offset >= *start && (*offset as u64) < *start as u64 + data.len()
+            });
+            if !covered {
+                return Err(ObjError::new(&format!("fixup at {:08x} in segment {} is not in any literal data", offset, segment.spec.name)));
+            }
+        }
+
+        for (start, data) in blocks {
+            let start = *start as usize;
+
+            match data {
+                SegmentData::Repeated{ pattern, count } => self.write_repeated(writer, seg, segment, start, pattern, *count as usize)?,
+                SegmentData::Literal(data) => {
+                    // pull long runs of one byte out as iterated data
+                    //
+                    let mut pos = 0;
+                    for (run, length) in self.find_runs(segment, start, data) {
+                        self.write_literal(writer, threader, seg, segment, start + pos, &data[pos..run])?;
+                        self.write_repeated(writer, seg, segment, start + run, &data[run..run+1], length)?;
+                        pos = run + length;
+                    }
+                    self.write_literal(writer, threader, seg, segment, start + pos, &data[pos..])?;
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    // Runs of one byte long enough to be worth iterating, as (index, length)
+    //
+    fn find_runs(&self, segment: &Segment, start: usize, data: &[u8]) -> Vec<(usize, usize)> {
+        let threshold = match self.repeat_threshold {
+            Some(threshold) => threshold.max(1),
+            None => return Vec::new(),
+        };
+
+        let mut runs = Vec::new();
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let length = data[pos..].iter().take_while(|byte| **byte == data[pos]).count();
+            if length >= threshold && !segment.has_fixup_in(start + pos, start + pos + length) {
+                runs.push((pos, length));
+            }
+            pos += length;
+        }
+
+        runs
+    }
+
+    fn write_repeated(&self, writer: &mut RecordWriter, seg: SegmentId, segment: &Segment, start: usize, pattern: &[u8], count: usize) -> Result<(), ObjError> {
+        let end = start as u64 + (pattern.len() * count) as u64;
+        let is32 = self.variants.is32(segment.spec.use32, end.saturating_sub(1))?;
+        let max_repeat = if is32 { u32::MAX as usize } else { u16::MAX as usize };
+        let mut offset = start;
+        let mut left = count;
+
+        while left > 0 {
+            let repeat = left.min(max_repeat);
+            let block = IteratedBlock{ repeat, content: IteratedContent::Data(pattern.to_vec()) };
+
+            let mut body = self.body();
+            body.index(seg.0)?.offset(offset as u32, is32)?.iterated_block(&block, is32)?;
+            writer.record(RecordVariants::rectype(0xa2, is32), body.bytes())?;
+
+            offset += repeat * pattern.len();
+            left -= repeat;
+        }
+
+        Ok(())
+    }
+
+    fn write_literal(&self, writer: &mut RecordWriter, threader: &mut FixupThreader, seg: SegmentId, segment: &Segment, start: usize, data: &[u8]) -> Result<(), ObjError> {
+        let end = start + data.len();
+        let mut pos = start;
+
+        while pos < end {
+            // never split a fixup across two data records
+            //
+            let mut cut = end.min(pos + MAX_DATA);
+            while let Some((offset, _)) = segment.fixups.iter().find(|(offset, fixup)| {
+                let offset = *offset as usize;
+                offset > pos && offset < cut && offset + fixup.location.size() > cut
+            }) {
+                cut = *offset as usize;
+            }
+
+            let chunk = &data[pos - start..cut - start];
+            let is32 = self.variants.is32(segment.spec.use32, (cut as u64).saturating_sub(1))?;
+            writer.data_record(RecordVariants::rectype(0xa0, is32), seg.0, pos as u32, chunk)?;
+
+            let fixups: Vec<Fixup> = segment.fixups.iter()
+                .filter(|(offset, _)| (pos..cut).contains(&(*offset as usize)))
+                .map(|(offset, fixup)| fixup.encode(*offset as usize - pos))
+                .collect();
+
+            // the FIXUPP matches its data record unless a displacement
+            // needs more room
+            //
+            let largest = fixups.iter().map(|fixup| fixup.target_displacement as u64).max().unwrap_or(0);
+            let is32 = is32 || self.variants.is32(false, largest)?;
+
+            let items = threader.thread(fixups)?.iter()
+                .map(|sub| Ok(self.body().fixup(sub, is32)?.bytes().to_vec()))
+                .collect::<Result<Vec<_>, ObjError>>()?;
+            writer.list_record(RecordVariants::rectype(0x9c, is32), &[], &items)?;
+
+            pos = cut;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_all(obj: &[u8]) -> Vec<Record> {
+        let options = ParseOptions{ strict: true, checksum: ChecksumPolicy::Strict, ..Default::default() };
+        let mut parser = Parser::with_options(obj, options);
+        let mut records = Vec::new();
+
+        loop {
+            match parser.next() {
+                Ok(Record::None) => break,
+                Ok(record) => records.push(record),
+                Err(err) => assert!(false, "parser returned {}", err),
+            }
+        }
+
+        assert!(parser.diagnostics().is_empty(), "{:?}", parser.diagnostics());
+        records
+    }
+
+    #[test]
+    fn test_builder_emits_valid_object() {
+        let mut builder = ObjBuilder::new("hello");
+        let text = builder.segment(SegmentSpec::new("_TEXT", "CODE"));
+        let data = builder.segment(SegmentSpec{ align: Align::Word, ..SegmentSpec::new("_DATA", "DATA") });
+        let dgroup = builder.group("DGROUP", &[data]);
+        let puts = builder.external("_puts");
+
+        builder.public("_main", text, 0);
+        builder.data(text, 0, &[0xb8, 0x00, 0x00, 0xe8, 0x00, 0x00, 0xc3]).unwrap();
+        builder.fixup(text, 1, FixupSpec{ frame: FixupFrame::Group(dgroup), ..FixupSpec::new(FixupLocation::Word, FixupTarget::Segment(data)) }).unwrap();
+        builder.fixup(text, 4, FixupSpec{ self_relative: true, displacement: 0xfffe, ..FixupSpec::new(FixupLocation::Word, FixupTarget::Extern(puts)) }).unwrap();
+        builder.data(data, 0, b"hi\0").unwrap();
+
+        let records = parse_all(&builder.build().unwrap());
+
+        assert_eq!(records[0], Record::THEADR{ name: "hello".to_string() });
+        assert!(matches!(&records[1], Record::LNAMES{ names } if names.len() == 5));
+        assert!(matches!(&records[2], Record::SEGDEF{ segs } if segs[0].name == Some(1) && segs[0].length == 7));
+        assert!(matches!(&records[4], Record::GRPDEF{ name: 5, components } if components.len() == 1));
+        assert!(matches!(&records[6], Record::PUBDEF{ group: None, seg: Some(1), .. }));
+
+        match &records[8] {
+            Record::FIXUPP{ fixups } => {
+                assert_eq!(fixups.len(), 2);
+                match &fixups[1] {
+                    FixupSubrecord::Fixup{ fixup } => {
+                        assert_eq!(fixup.data_offset, 4);
+                        assert!(!fixup.is_seg_relative);
+                        assert_eq!(fixup.target_method, Some(TargetMethod::Extdef));
+                        assert_eq!(fixup.target_displacement, 0xfffe);
+                    },
+                    x => assert!(false, "fixup was {:x?}", x),
+                }
+            },
+            x => assert!(false, "record was {:x?}", x),
+        }
+
+        assert!(matches!(records.last(), Some(Record::MODEND{ main: false, start_address: None, .. })));
+    }
+
+    #[test]
+    fn test_builder_shares_lnames() {
+        let mut builder = ObjBuilder::new("names");
+        builder.segment(SegmentSpec::new("_DATA", "DATA"));
+        builder.segment(SegmentSpec::new("CONST", "CONST"));
+        builder.segment(SegmentSpec::new("_BSS", "BSS"));
+        builder.segment(SegmentSpec::new("_DATA2", "DATA"));
+        assert_eq!(builder.find_lname("DATA"), Some(2));
+        assert_eq!(builder.force_lname("DATA"), 7);
+
+        let records = parse_all(&builder.build().unwrap());
+        assert_eq!(records[1], Record::LNAMES{ names: vec![
+            "_DATA", "DATA", "CONST", "_BSS", "BSS", "_DATA2", "DATA",
+        ].into_iter().map(|name| name.to_string()).collect() });
+        assert!(matches!(&records[5], Record::SEGDEF{ segs } if segs[0].name == Some(6) && segs[0].class == Some(2)));
+    }
+
+    #[test]
+    fn test_builder_keeps_fixups_within_one_record() {
+        let mut builder = ObjBuilder::new("big");
+        let text = builder.segment(SegmentSpec::new("_TEXT", "CODE"));
+        builder.data(text, 0, &[0x90; 2000]).unwrap();
+        builder.fixup(text, 1023, FixupSpec::new(FixupLocation::Word, FixupTarget::Segment(text))).unwrap();
+
+        let records = parse_all(&builder.build().unwrap());
+        let ledata: Vec<(u32, usize)> = records.iter()
+            .filter_map(|record| match record {
+                Record::LEDATA{ offset, data, .. } => Some((*offset, data.len())),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(ledata, vec![(0, 1023), (1023, 977)]);
+    }
+
+    #[test]
+    fn test_builder_iterates_long_runs() {
+        let mut builder = ObjBuilder::new("table");
+        let data = builder.segment(SegmentSpec::new("_DATA", "DATA"));
+
+        let mut table = vec![0x11, 0x22];
+        table.extend([0u8; 1000]);
+        table.extend([0x33]);
+        builder.data(data, 0, &table).unwrap();
+        builder.repeat(data, 0x1000, &[0xde, 0xad], 0x100).unwrap();
+
+        let records = parse_all(&builder.build().unwrap());
+        let mut image = vec![0; 0x1200];
+
+        for record in records.iter() {
+            match record {
+                Record::LEDATA{ offset, data, .. } => {
+                    assert!(data.len() < 4);
+                    image[*offset as usize..*offset as usize + data.len()].copy_from_slice(data);
+                },
+                Record::LIDATA{ offset, blocks, .. } => {
+                    let data = expand_iterated(blocks, 0x10000).unwrap();
+                    image[*offset as usize..*offset as usize + data.len()].copy_from_slice(&data);
+                },
+                _ => (),
+            }
+        }
+
+        assert_eq!(&image[..table.len()], &table[..]);
+        assert_eq!(&image[0x1000..0x1004], &[0xde, 0xad, 0xde, 0xad]);
+        assert_eq!(records.iter().filter(|record| matches!(record, Record::LIDATA{ .. })).count(), 2);
+    }
+
+    #[test]
+    fn test_builder_keeps_fixups_out_of_runs() {
+        let mut builder = ObjBuilder::new("zeros");
+        let text = builder.segment(SegmentSpec::new("_TEXT", "CODE"));
+        builder.data(text, 0, &[0; 100]).unwrap();
+        builder.fixup(text, 50, FixupSpec::new(FixupLocation::Word, FixupTarget::Segment(text))).unwrap();
+
+        let records = parse_all(&builder.build().unwrap());
+        assert!(!records.iter().any(|record| matches!(record, Record::LIDATA{ .. })));
+    }
+
+    #[test]
+    fn test_builder_rejects_fixup_outside_data() {
+        let mut builder = ObjBuilder::new("bad");
+        let text = builder.segment(SegmentSpec::new("_TEXT", "CODE"));
+        builder.data(text, 0, &[0x90; 4]).unwrap();
+        builder.fixup(text, 8, FixupSpec::new(FixupLocation::Word, FixupTarget::Segment(text))).unwrap();
+
+        assert!(builder.build().is_err());
+    }
+
+    fn rectypes(obj: &[u8]) -> Vec<u8> {
+        let mut parser = Parser::new(obj);
+        let mut rectypes = Vec::new();
+
+        loop {
+            match parser.next_parsed() {
+                Ok(ParsedRecord{ record: Record::None, .. }) => break,
+                Ok(parsed) => rectypes.push(parsed.rectype),
+                Err(err) => assert!(false, "parser returned {}", err),
+            }
+        }
+
+        rectypes
+    }
+
+    #[test]
+    fn test_builder_picks_32_bit_records_when_needed() {
+        let mut builder = ObjBuilder::new("big");
+        let small = builder.segment(SegmentSpec::new("_SMALL", "DATA"));
+        let big = builder.segment(SegmentSpec::new("_BIG", "DATA"));
+        builder.data(small, 0, &[1]).unwrap();
+        builder.data(big, 0, &[2]).unwrap();
+        builder.data(big, 0x12340, &[3]).unwrap();
+        builder.public("_far", big, 0x12340);
+
+        let rectypes = rectypes(&builder.build().unwrap());
+        assert_eq!(rectypes, vec![0x80, 0x96, 0x98, 0x99, 0x91, 0xa0, 0xa0, 0xa1, 0x8a]);
+    }
+
+    #[test]
+    fn test_builder_forced_16_bit_records() {
+        let mut spec = SegmentSpec::new("_TEXT", "CODE");
+        spec.use32 = true;
+
+        let mut builder = ObjBuilder::new("small");
+        builder.variants = RecordVariants::Force16;
+        let text = builder.segment(spec.clone());
+        builder.data(text, 0xfff0, &[0x90; 0x10]).unwrap();
+        builder.public("_x", text, 0xfff0);
+
+        let rectypes = rectypes(&builder.build().unwrap());
+        assert_eq!(rectypes, vec![0x80, 0x96, 0x98, 0x90, 0xa0, 0x8a]);
+
+        let mut builder = ObjBuilder::new("small");
+        builder.variants = RecordVariants::Force16;
+        let text = builder.segment(spec);
+        builder.data(text, 0xfff0, &[0x90; 0x11]).unwrap();
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn test_builder_writes_start_address() {
+        let mut builder = ObjBuilder::new("main");
+        let text = builder.segment(SegmentSpec::new("_TEXT", "CODE"));
+        builder.group("CGROUP", &[text]);
+        builder.data(text, 0, &[0x90; 0x20]).unwrap();
+        builder.start(text, 0x10);
+
+        let records = parse_all(&builder.build().unwrap());
+        let start = StartAddress{ fix_data: 0x10, frame_datum: Some(1), target_datum: Some(1), target_disp: Some(0x10) };
+        assert_eq!(records.last(), Some(&Record::MODEND{ main: true, start_address: Some(start), modtype: 0xc1 }));
+    }
+}
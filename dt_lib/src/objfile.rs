@@ -1,5 +1,10 @@
+use std::collections::VecDeque;
+
+use crate::codepage::Codepage;
+use crate::error::Diagnostic;
 use crate::error::Error as ObjError;
 
+#[derive(Clone, Copy)]
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub enum FrameMethod {
@@ -27,13 +32,26 @@ impl TryFrom<u8> for FrameMethod {
 }
 
 impl FrameMethod {
-    fn has_datum(&self) -> bool {
+    // The value as encoded in fix data and thread subrecords
+    //
+    pub fn raw(&self) -> u8 {
+        match self {
+            FrameMethod::Segdef => 0,
+            FrameMethod::Grpdef => 1,
+            FrameMethod::Extdef => 2,
+            FrameMethod::PreviousDataRecord => 4,
+            FrameMethod::Target => 5,
+        }
+    }
+
+    pub fn has_datum(&self) -> bool {
         *self == FrameMethod::Segdef ||
         *self == FrameMethod::Grpdef || 
         *self == FrameMethod::Extdef
     }
 }
 
+#[derive(Clone, Copy)]
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub enum TargetMethod {
@@ -62,6 +80,27 @@ impl TryFrom<u8> for TargetMethod {
     }
 }
 
+impl TargetMethod {
+    // The value as encoded in fix data, including the P (no displacement)
+    // bit
+    //
+    pub fn raw(&self) -> u8 {
+        match self {
+            TargetMethod::Segdef => 0,
+            TargetMethod::Grpdef => 1,
+            TargetMethod::Extdef => 2,
+            TargetMethod::SegdefNoDisplacement => 4,
+            TargetMethod::GrpdefNoDisplacement => 5,
+            TargetMethod::ExtdefNoDisplacement => 6,
+        }
+    }
+
+    pub fn has_displacement(&self) -> bool {
+        self.raw() < 4
+    }
+}
+
+#[derive(Clone, Copy)]
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub enum FixupLocation {
@@ -85,6 +124,7 @@ impl TryFrom<u8> for FixupLocation {
             1 => Ok(FixupLocation::Word),
             2 => Ok(FixupLocation::Selector),
             3 => Ok(FixupLocation::LongPointer),
+            4 => Ok(FixupLocation::HighOrderByte),
             5 => Ok(FixupLocation::LoaderWord),
             9 => Ok(FixupLocation::Offset32),
             11 => Ok(FixupLocation::Pointer48),
@@ -95,6 +135,33 @@ impl TryFrom<u8> for FixupLocation {
     }
 }
 
+impl FixupLocation {
+    pub fn raw(&self) -> u8 {
+        match self {
+            FixupLocation::Byte => 0,
+            FixupLocation::Word => 1,
+            FixupLocation::Selector => 2,
+            FixupLocation::LongPointer => 3,
+            FixupLocation::HighOrderByte => 4,
+            FixupLocation::LoaderWord => 5,
+            FixupLocation::Offset32 => 9,
+            FixupLocation::Pointer48 => 11,
+            FixupLocation::LoaderOffset32 => 13,
+        }
+    }
+
+    // The number of bytes the fixup patches
+    //
+    pub fn size(&self) -> usize {
+        match self {
+            FixupLocation::Byte | FixupLocation::HighOrderByte => 1,
+            FixupLocation::Word | FixupLocation::Selector | FixupLocation::LoaderWord => 2,
+            FixupLocation::LongPointer | FixupLocation::Offset32 | FixupLocation::LoaderOffset32 => 4,
+            FixupLocation::Pointer48 => 6,
+        }
+    }
+}
+
 // NB most enum cases have the data directly embedded, but fixup has enough
 // fields that it's unwieldy
 //
@@ -199,12 +266,27 @@ impl TryFrom<u8> for Align {
     }
 }
 
+impl Align {
+    pub fn raw(&self) -> u8 {
+        match self {
+            Align::Absolute => 0,
+            Align::Byte => 1,
+            Align::Word => 2,
+            Align::Paragraph => 3,
+            Align::Page => 4,
+            Align::Dword => 5,
+        }
+    }
+}
+
 #[derive(Clone)]
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub enum Combine {
     Private,
-    Public,
+    // 2, 4, and 7 all mean public; the original value is kept so the
+    // SEGDEF can be reproduced exactly
+    Public{ raw: u8 },
     Stack,
     Common,
 }
@@ -215,7 +297,7 @@ impl TryFrom<u8> for Combine {
     fn try_from(val: u8) -> Result<Self, Self::Error> {
         match val {
             0 => Ok(Combine::Private),
-            2|4|7 => Ok(Combine::Public),
+            2|4|7 => Ok(Combine::Public{ raw: val }),
             5 => Ok(Combine::Stack),
             6 => Ok(Combine::Common),
 
@@ -224,6 +306,19 @@ impl TryFrom<u8> for Combine {
     }
 }
 
+impl Combine {
+    // The value as encoded in the ACBP byte
+    //
+    pub fn raw(&self) -> u8 {
+        match self {
+            Combine::Private => 0,
+            Combine::Public{ raw } => *raw,
+            Combine::Stack => 5,
+            Combine::Common => 6,
+        }
+    }
+}
+
 #[derive(Clone)]
 #[derive(Debug)]
 #[derive(PartialEq)]
@@ -250,7 +345,7 @@ impl Segdef {
     pub fn empty() -> Segdef {
         Segdef {
             align: Align::Byte,
-            combine: Combine::Public,
+            combine: Combine::Public{ raw: 2 },
             use32: false,
             abs: None,
             length: 0,
@@ -261,6 +356,20 @@ impl Segdef {
     }
 }
 
+// A member of a group. Modern objects only use segment indexes; the
+// other forms come from the original Intel spec.
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum GrpComponent {
+    Segdef{ index: usize },
+    Extdef{ index: usize },
+    SegClassOverlay{ name: usize, class: usize, overlay: usize },
+    Ltl{ data: u8, max_length: u16, length: u16 },
+    Absolute{ frame: u16, offset: u8 },
+}
+
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub struct Extern {
@@ -321,7 +430,84 @@ pub enum Coment {
     NewOMF{ text: String },
     Libmod{ name: String },
     WeakExtern{ externs: Vec<WeakExtern> },
+    LazyExtern{ externs: Vec<WeakExtern> },
+    DosVersion{ version: u16 },
+    EasyOmf{ text: String },
     User{ text: String },
+    Impdef{ internal: String, module: String, entry: ImportEntry },
+
+    // Flags: bit 7 ordinal given, bit 6 resident name, bit 5 no data,
+    // bits 4-0 word count of parameters. An empty internal name means it
+    // matches the exported one.
+    Expdef{ flags: u8, exported: String, internal: String, ordinal: Option<u16> },
+
+    // Incremental compilation: how many EXTDEFs and LINNUMs the module
+    // gained or lost since the last compile
+    Incdef{ extdef_delta: i16, linnum_delta: i16 },
+
+    // Microsoft C 7 linker directives. Flags: bit 0 new .EXE, bit 1 omit
+    // CodeView publics, bit 2 run MPC.
+    Lnkdir{ flags: u8, pcode_version: u8, cv_version: u8 },
+    ExeStr{ text: String },
+    IncErr,
+    NoPad{ segs: Vec<usize> },
+
+    // Borland source dependency. An empty comment ends the list.
+    Dependency{ name: String, timestamp: DosTimestamp },
+    DependencyEnd,
+}
+
+// A packed DOS file date and time, as stored in directory entries
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct DosTimestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DosTimestamp {
+    pub fn new(date: u16, time: u16) -> DosTimestamp {
+        DosTimestamp {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0x0f) as u8,
+            day: (date & 0x1f) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3f) as u8,
+            second: ((time & 0x1f) * 2) as u8,
+        }
+    }
+}
+
+impl DosTimestamp {
+    // The packed (date, time) words
+    //
+    pub fn to_dos(&self) -> (u16, u16) {
+        let date = ((self.year.saturating_sub(1980)) << 9) | ((self.month as u16) << 5) | self.day as u16;
+        let time = ((self.hour as u16) << 11) | ((self.minute as u16) << 5) | (self.second as u16 / 2);
+        (date, time)
+    }
+}
+
+impl std::fmt::Display for DosTimestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+// How an imported function is found in its DLL. An empty name means the
+// entry has the same name as the internal one.
+//
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum ImportEntry {
+    Ordinal(u16),
+    Name(String),
 }
 
 #[derive(Debug)]
@@ -346,6 +532,167 @@ impl TryFrom<u8> for BakpatLocation {
     }
 }
 
+impl BakpatLocation {
+    // NB 9 also means a dword (it's what MS LINK writes); this gives the
+    // spec's 2
+    //
+    pub fn raw(&self) -> u8 {
+        match self {
+            BakpatLocation::Byte => 0,
+            BakpatLocation::Word => 1,
+            BakpatLocation::Dword => 2,
+        }
+    }
+}
+
+// The rest of the original Intel 8086 record set. Microsoft's tools never
+// emitted these, but objects from Intel's own toolchains do; the payload
+// is kept as-is.
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum IntelRecord {
+    Rheadr,
+    Regint,
+    Redata,
+    Ridata,
+    Ovldef,
+    Endrec,
+    Blkdef,
+    Blkend,
+    Debsym,
+    Pedata,
+    Pidata,
+    Typdef,
+    Locsym,
+    Libhed,
+    Libnam,
+    Libloc,
+    Libdic,
+}
+
+impl TryFrom<u8> for IntelRecord {
+    type Error = ObjError;
+
+    fn try_from(val: u8) -> Result<Self, Self::Error> {
+        match val {
+            0x6e => Ok(IntelRecord::Rheadr),
+            0x70 => Ok(IntelRecord::Regint),
+            0x72 => Ok(IntelRecord::Redata),
+            0x74 => Ok(IntelRecord::Ridata),
+            0x76 => Ok(IntelRecord::Ovldef),
+            0x78 => Ok(IntelRecord::Endrec),
+            0x7a => Ok(IntelRecord::Blkdef),
+            0x7c => Ok(IntelRecord::Blkend),
+            0x7e => Ok(IntelRecord::Debsym),
+            0x84 => Ok(IntelRecord::Pedata),
+            0x86 => Ok(IntelRecord::Pidata),
+            0x8e => Ok(IntelRecord::Typdef),
+            0x92 => Ok(IntelRecord::Locsym),
+            0xa4 => Ok(IntelRecord::Libhed),
+            0xa6 => Ok(IntelRecord::Libnam),
+            0xa8 => Ok(IntelRecord::Libloc),
+            0xaa => Ok(IntelRecord::Libdic),
+
+            val => Err(ObjError::new(&format!("${:02x} is not an Intel record type", val))),
+        }
+    }
+}
+
+impl IntelRecord {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IntelRecord::Rheadr => "RHEADR",
+            IntelRecord::Regint => "REGINT",
+            IntelRecord::Redata => "REDATA",
+            IntelRecord::Ridata => "RIDATA",
+            IntelRecord::Ovldef => "OVLDEF",
+            IntelRecord::Endrec => "ENDREC",
+            IntelRecord::Blkdef => "BLKDEF",
+            IntelRecord::Blkend => "BLKEND",
+            IntelRecord::Debsym => "DEBSYM",
+            IntelRecord::Pedata => "PEDATA",
+            IntelRecord::Pidata => "PIDATA",
+            IntelRecord::Typdef => "TYPDEF",
+            IntelRecord::Locsym => "LOCSYM",
+            IntelRecord::Libhed => "LIBHED",
+            IntelRecord::Libnam => "LIBNAM",
+            IntelRecord::Libloc => "LIBLOC",
+            IntelRecord::Libdic => "LIBDIC",
+        }
+    }
+}
+
+// Iterated data (LIDATA, and COMDATs with the iterated bit) is a tree of
+// blocks, each repeated some number of times. A leaf holds literal bytes.
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum IteratedContent {
+    Data(Vec<u8>),
+    Blocks(Vec<IteratedBlock>),
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct IteratedBlock {
+    pub repeat: usize,
+    pub content: IteratedContent,
+}
+
+impl IteratedBlock {
+    // The number of bytes the block expands to, saturating rather than
+    // overflowing on hostile repeat counts
+    //
+    pub fn expanded_len(&self) -> u64 {
+        let once = match &self.content {
+            IteratedContent::Data(data) => data.len() as u64,
+            IteratedContent::Blocks(blocks) => blocks.iter().fold(0u64, |sum, block| sum.saturating_add(block.expanded_len())),
+        };
+
+        once.saturating_mul(self.repeat as u64)
+    }
+
+    // A block which expands to nothing is skipped outright, as however
+    // often it's repeated there's nothing to show for it, and the limit
+    // on the expanded length doesn't bound the work of going through it
+    //
+    fn expand_into(&self, out: &mut Vec<u8>) {
+        if self.expanded_len() == 0 {
+            return;
+        }
+
+        for _ in 0..self.repeat {
+            match &self.content {
+                IteratedContent::Data(data) => out.extend(data),
+                IteratedContent::Blocks(blocks) => for block in blocks {
+                    block.expand_into(out);
+                },
+            }
+        }
+    }
+}
+
+// Flatten iterated data into the bytes it describes. A few bytes of
+// iterated data can describe gigabytes, so refuse to expand past `limit`.
+//
+pub fn expand_iterated(blocks: &[IteratedBlock], limit: u64) -> Result<Vec<u8>, ObjError> {
+    let len = blocks.iter().fold(0u64, |sum, block| sum.saturating_add(block.expanded_len()));
+    if len > limit {
+        return Err(ObjError::new(&format!("iterated data expands to {} bytes (limit is {})", len, limit)));
+    }
+
+    let mut out = Vec::with_capacity(len as usize);
+    for block in blocks {
+        block.expand_into(&mut out);
+    }
+
+    Ok(out)
+}
+
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub struct BakpatFixup {
@@ -353,6 +700,13 @@ pub struct BakpatFixup {
     pub value: u32,
 }
 
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct LineNumber {
+    pub line: u16,
+    pub offset: u32,
+}
+
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub struct Alias {
@@ -391,6 +745,17 @@ impl TryFrom<u8> for ComdatSelection {
     }
 }
 
+impl ComdatSelection {
+    pub fn raw(&self) -> u8 {
+        match self {
+            ComdatSelection::NoMatch => 0x00,
+            ComdatSelection::PickAny => 0x10,
+            ComdatSelection::SameSize => 0x20,
+            ComdatSelection::ExactMatch => 0x30,
+        }
+    }
+}
+
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub enum ComdatAllocation {
@@ -417,6 +782,18 @@ impl TryFrom<u8> for ComdatAllocation {
     }
 }
 
+impl ComdatAllocation {
+    pub fn raw(&self) -> u8 {
+        match self {
+            ComdatAllocation::Explicit => 0x00,
+            ComdatAllocation::FarCode => 0x01,
+            ComdatAllocation::FarData => 0x02,
+            ComdatAllocation::Code32 => 0x03,
+            ComdatAllocation::Data32 => 0x04,
+        }
+    }
+}
+
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub enum ComdatAlign {
@@ -445,6 +822,19 @@ impl TryFrom<u8> for ComdatAlign {
     }
 }
 
+impl ComdatAlign {
+    pub fn raw(&self) -> u8 {
+        match self {
+            ComdatAlign::Segdef => 0x00,
+            ComdatAlign::Byte => 0x01,
+            ComdatAlign::Word => 0x02,
+            ComdatAlign::Paragraph => 0x03,
+            ComdatAlign::Page => 0x04,
+            ComdatAlign::Dword => 0x05,
+        }
+    }
+}
+
 
 #[derive(Debug)]
 #[derive(PartialEq)]
@@ -459,10 +849,24 @@ pub struct Comdat {
     pub base_seg: Option<usize>,
     pub base_frame: Option<u16>,
     pub name: usize,
+
+    // Exactly one of these is used, depending on iterated_data()
     pub data: Vec<u8>,
+    pub blocks: Vec<IteratedBlock>,
 }
 
 impl Comdat {
+    // The bytes the COMDAT contributes, whether they were given directly
+    // or as iterated data
+    //
+    pub fn expand(&self, limit: u64) -> Result<Vec<u8>, ObjError> {
+        if self.iterated_data() {
+            expand_iterated(&self.blocks, limit)
+        } else {
+            Ok(self.data.clone())
+        }
+    }
+
     pub fn continuation(&self) -> bool {
         (self.flags & 0x01) != 0
     }
@@ -487,16 +891,20 @@ pub enum Record {
     Unknown{ rectype: u8 },
 
     THEADR{ name: String },
-    MODEND{ main: bool, start_address: Option<StartAddress> },
+    LHEADR{ name: String },
+    // The module type byte is kept whole; main and start_address decide
+    // bits 7 and 6 of it
+    MODEND{ main: bool, start_address: Option<StartAddress>, modtype: u8 },
     LNAMES{ names: Vec<String> },
     SEGDEF{ segs: Vec<Segdef> },
-    GRPDEF{ name: usize, segs: Vec<usize> },
+    GRPDEF{ name: usize, components: Vec<GrpComponent> },
     EXTDEF{ externs: Vec<Extern> },
     PUBDEF{ group: Option<usize>, seg: Option<usize>, frame: Option<u16>, publics: Vec<Public> },
     COMENT{ header: ComentHeader, coment: Coment },
     LEDATA{ seg: usize, offset: u32, data: Vec<u8> },
-    LIDATA{ seg: usize, offset: u32, data: Vec<u8> },
+    LIDATA{ seg: usize, offset: u32, blocks: Vec<IteratedBlock> },
     BAKPAT{ seg: usize, location: BakpatLocation, fixups: Vec<BakpatFixup> },
+    NBKPAT{ name: usize, location: BakpatLocation, fixups: Vec<BakpatFixup> },
     FIXUPP{ fixups: Vec<FixupSubrecord >},
     COMDEF { commons: Vec<Comdef> },
     CEXTDEF { externs: Vec<CExtern> },
@@ -504,6 +912,94 @@ pub enum Record {
     LPUBDEF{ group: Option<usize>, seg: Option<usize>, frame: Option<u16>, publics: Vec<Public> },
     ALIAS { aliases: Vec<Alias> },
     COMDAT { comdat: Comdat },
+    LINNUM{ group: Option<usize>, seg: usize, lines: Vec<LineNumber> },
+    LINSYM{ flags: u8, name: usize, lines: Vec<LineNumber> },
+
+    // Intel 8086 records with no Microsoft equivalent
+    INTEL{ kind: IntelRecord, data: Vec<u8> },
+
+    // Library framing records, which Borland's TLIB also leaves inside
+    // extracted modules
+    LIBHDR{ page_size: usize, dict_offset: u32, dict_blocks: u16, flags: u8 },
+    LIBEND,
+
+    // The rest of the 0xf0 range is private to Borland tools; keep the
+    // payload since there is no public description of it
+    BORLAND{ rectype: u8, data: Vec<u8> },
+}
+
+// A record along with where it came from. `raw` is the whole record,
+// header and checksum included. A merged COMDAT spans all the records it
+// was built from; the FIXUPP which follows it is attributed to the first
+// FIXUPP which was merged.
+//
+#[derive(Debug)]
+pub struct ParsedRecord<'a> {
+    pub offset: usize,
+    pub rectype: u8,
+    pub raw: &'a [u8],
+    pub record: Record,
+}
+
+// What to do when a record's checksum doesn't match its contents
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub enum ChecksumPolicy {
+    // Fail the record
+    #[default]
+    Enforce,
+
+    // Record a diagnostic and parse the record anyway
+    Warn,
+
+    // Don't look at checksums at all
+    Ignore,
+
+    // Fail the record, and don't accept a zero byte as meaning "no
+    // checksum" either
+    Strict,
+}
+
+impl TryFrom<&str> for ChecksumPolicy {
+    type Error = ObjError;
+
+    fn try_from(val: &str) -> Result<Self, Self::Error> {
+        match val.to_lowercase().as_str() {
+            "enforce" => Ok(ChecksumPolicy::Enforce),
+            "warn" => Ok(ChecksumPolicy::Warn),
+            "ignore" => Ok(ChecksumPolicy::Ignore),
+            "strict" => Ok(ChecksumPolicy::Strict),
+
+            val => Err(ObjError::new(&format!("unknown checksum policy {}", val))),
+        }
+    }
+}
+
+// Settings which control how the parser interprets an object
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(Default)]
+pub struct ParseOptions {
+    // How to decode names and comment text
+    pub codepage: Codepage,
+    pub checksum: ChecksumPolicy,
+
+    // Deliver each COMDAT with its continuation records already appended,
+    // followed by a single FIXUPP covering all of the merged data
+    pub merge_comdats: bool,
+
+    // Reject spec violations which real-world tools are known to commit,
+    // instead of noting them as diagnostics. Useful for validating the
+    // output of a writer.
+    pub strict: bool,
+
+    // Skip records which fail to parse, noting them as diagnostics, so
+    // the rest of a damaged object can still be read
+    pub recover: bool,
 }
 
 pub struct Parser<'a> {
@@ -511,15 +1007,165 @@ pub struct Parser<'a> {
     start: usize,
     ptr: usize,
     next: usize,
+    options: ParseOptions,
+    diagnostics: Vec<Diagnostic>,
+
+    // declared lengths of the SEGDEFs seen so far, and the number of
+    // other indexable definitions
+    seg_lengths: Vec<u64>,
+    lnames: usize,
+    grpdefs: usize,
+    externs: usize,
+
+    // payload length of the data record just parsed, and of the one
+    // immediately before the current record, for pairing FIXUPPs
+    last_data: Option<usize>,
+    prev_data: Option<usize>,
+
+    // name of the COMDAT which a continuation may follow, and of the one
+    // visible to the current record
+    last_comdat: Option<usize>,
+    prev_comdat: Option<usize>,
+
+    // records already parsed but not yet returned, when merging COMDATs
+    pending: VecDeque<ParsedRecord<'a>>,
+
+    // set once MODEND has been parsed; anything after it is not part of
+    // the module
+    ended: bool,
+
+    // the module's name, from THEADR or a LIBMOD comment
+    module: Option<String>,
+
+    // set by PharLap's Easy OMF-386 comment, after which the 16-bit
+    // record types carry 32-bit fields
+    easy_omf: bool,
+}
+
+// What a parser has to remember of an object to go on parsing it: where
+// the next record starts, and what's been defined so far for later
+// records to be checked against
+//
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct ParserState {
+    next: usize,
+    seg_lengths: Vec<u64>,
+    lnames: usize,
+    grpdefs: usize,
+    externs: usize,
+    last_data: Option<usize>,
+    last_comdat: Option<usize>,
+    ended: bool,
+    module: Option<String>,
+    easy_omf: bool,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(obj: &'a [u8]) -> Parser<'a> {
-        Parser{ obj, start: 0, ptr: 0, next: 0 }
+        Self::with_options(obj, ParseOptions::default())
+    }
+
+    pub fn with_options(obj: &'a [u8], options: ParseOptions) -> Parser<'a> {
+        Parser{
+            obj,
+            start: 0,
+            ptr: 0,
+            next: 0,
+            options,
+            diagnostics: Vec::new(),
+            seg_lengths: Vec::new(),
+            lnames: 0,
+            grpdefs: 0,
+            externs: 0,
+            last_data: None,
+            prev_data: None,
+            last_comdat: None,
+            prev_comdat: None,
+            pending: VecDeque::new(),
+            ended: false,
+            module: None,
+            easy_omf: false,
+        }
+    }
+
+    // Pick up an object where a parser left off, with what it had seen of
+    // it, rather than going over it again from the start
+    //
+    pub fn resume(obj: &'a [u8], options: ParseOptions, state: ParserState) -> Parser<'a> {
+        Parser{
+            next: state.next,
+            seg_lengths: state.seg_lengths,
+            lnames: state.lnames,
+            grpdefs: state.grpdefs,
+            externs: state.externs,
+            last_data: state.last_data,
+            last_comdat: state.last_comdat,
+            ended: state.ended,
+            module: state.module,
+            easy_omf: state.easy_omf,
+            ..Self::with_options(obj, options)
+        }
+    }
+
+    // Where the parser is and what it's seen, to resume from later. Any
+    // records read ahead, to merge COMDATs, aren't kept, so it should be
+    // taken only after a record which can't be merged with anything.
+    //
+    pub fn state(&self) -> ParserState {
+        ParserState{
+            next: self.next,
+            seg_lengths: self.seg_lengths.clone(),
+            lnames: self.lnames,
+            grpdefs: self.grpdefs,
+            externs: self.externs,
+            last_data: self.last_data,
+            last_comdat: self.last_comdat,
+            ended: self.ended,
+            module: self.module.clone(),
+            easy_omf: self.easy_omf,
+        }
+    }
+
+    // Problems which were tolerated rather than failing the parse
+    //
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    // The name of the module, as far as it's known. A LIBMOD comment
+    // (left by the librarian) wins over THEADR, which is often the
+    // source file name.
+    //
+    pub fn module(&self) -> Option<&str> {
+        self.module.as_deref()
+    }
+
+    fn warn(&mut self, details: &str) {
+        let mut diag = Diagnostic::with_offset(details, self.start);
+        diag.module = self.module.clone();
+        self.diagnostics.push(diag);
+    }
+
+    // A spec violation: an error in strict mode, else a diagnostic
+    //
+    fn violation(&mut self, details: &str) -> Result<(), ObjError> {
+        if self.options.strict {
+            Err(self.err(details))
+        } else {
+            self.warn(details);
+            Ok(())
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<String, ObjError> {
+        self.options.codepage.decode(bytes).map_err(|err| self.err(&err.details))
     }
 
     fn err(&self, err: &str) -> ObjError {
-        ObjError::with_offset(err, self.start)
+        let mut err = ObjError::with_offset(err, self.start);
+        err.module = self.module.clone();
+        err
     }
 
     fn endrec(&self) -> usize {
@@ -562,15 +1208,23 @@ impl<'a> Parser<'a> {
                 let s = &self.obj[self.ptr..self.ptr+len];
                 self.ptr += len;
         
-                String::from_utf8(s.to_vec()).map_err(|err| self.err(&format!("{:x?}", err)))
+                self.decode(s)
             }
         }
     }
 
-    fn rest_str(&mut self) -> Result<String, ObjError> {
-        let bytes = &self.obj[self.ptr..self.endrec()];
+    // The rest of the record's body, up to its checksum
+    //
+    fn rest(&mut self) -> Result<&'a [u8], ObjError> {
+        let obj: &'a [u8] = self.obj;
+        let bytes = obj.get(self.ptr..self.endrec()).ok_or_else(|| self.err("record body truncated"))?;
         self.ptr = self.endrec();
-        String::from_utf8(bytes.to_vec()).map_err(|err| self.err(&format!("{:x?}", err)))
+        Ok(bytes)
+    }
+
+    fn rest_str(&mut self) -> Result<String, ObjError> {
+        let bytes = self.rest()?;
+        self.decode(bytes)
     }
 
     fn next_index(&mut self) -> Result<usize, ObjError> {
@@ -592,8 +1246,8 @@ impl<'a> Parser<'a> {
         })
     }
 
-    fn checksum(bytes: &[u8]) -> bool {
-        if *bytes.last().unwrap() == 0 {
+    fn checksum(bytes: &[u8], allow_zero: bool) -> bool {
+        if allow_zero && *bytes.last().unwrap() == 0 {
             true
         } else {
             let mut sum = 0;
@@ -612,7 +1266,11 @@ impl<'a> Parser<'a> {
         let has_start = (modtype & 0x40) != 0;
 
         // NB the spec claims that bit 5 (0x20) must be zero and bit 0 (0x01) 
-        // must be 1, but real-life objects from MS tools don't obey this.
+        // must be 1, but real-life objects from MS tools don't obey this, so
+        // it's only an error in strict mode.
+        if (modtype & 0x21) != 0x01 {
+            self.violation(&format!("MODEND module type ${:02x} does not have bit 5 clear and bit 0 set", modtype))?;
+        }
 
         let bytes = if is32 { 4 } else { 2 };
 
@@ -629,7 +1287,7 @@ impl<'a> Parser<'a> {
             Some(StartAddress{ fix_data, frame_datum, target_datum, target_disp })
         };
 
-        Ok(Record::MODEND{ main, start_address })
+        Ok(Record::MODEND{ main, start_address, modtype: modtype as u8 })
     }
 
     fn lnames(&mut self) -> Result<Record, ObjError> {
@@ -673,10 +1331,22 @@ impl<'a> Parser<'a> {
                 length = 1 << if is32 { 32 } else { 16 };
             }
 
-            let class = self.next_opt_index()?;
             let name = self.next_opt_index()?;
+            let class = self.next_opt_index()?;
             let overlay = self.next_opt_index()?;
+
+            // Easy OMF-386 appends an access byte, which also carries the
+            // USE32 bit
+            //
+            let use32 = if self.easy_omf {
+                let access = self.next_uint(1)?;
+                (access & 0x04) != 0
+            } else {
+                use32
+            };
             
+            self.seg_lengths.push(length as u64);
+
             segs.push(Segdef{
                 align,
                 combine,
@@ -694,20 +1364,39 @@ impl<'a> Parser<'a> {
 
     fn grpdef(&mut self) -> Result<Record, ObjError> {
         let name = self.next_index()?;
-        let mut segs = Vec::new();
+        let mut components = Vec::new();
 
         while self.ptr < self.endrec() {
             let typ = self.next_uint(1)?;
-            let index = self.next_index()?;
-            
+
+            let component = match typ {
+                0xff => GrpComponent::Segdef{ index: self.next_index()? },
+                0xfe => GrpComponent::Extdef{ index: self.next_index()? },
+                0xfd => GrpComponent::SegClassOverlay{
+                    name: self.next_index()?,
+                    class: self.next_index()?,
+                    overlay: self.next_index()?,
+                },
+                0xfb => GrpComponent::Ltl{
+                    data: self.next_uint(1)? as u8,
+                    max_length: self.next_uint(2)? as u16,
+                    length: self.next_uint(2)? as u16,
+                },
+                0xfa => GrpComponent::Absolute{
+                    frame: self.next_uint(2)? as u16,
+                    offset: self.next_uint(1)? as u8,
+                },
+                typ => return Err(self.err(&format!("invalid grpdef component type ${:02x}", typ))),
+            };
+
             if typ != 0xff {
-                return Err(self.err("grpdef segment with type other than FF"));
+                self.violation(&format!("grpdef component with obsolete type ${:02x}", typ))?;
             }
 
-            segs.push(index);
+            components.push(component);
         }
 
-        Ok(Record::GRPDEF{ name, segs })
+        Ok(Record::GRPDEF{ name, components })
     }
 
     fn make_externs(&mut self, rec: &dyn Fn(Vec<Extern>) -> Record) -> Result<Record, ObjError> {
@@ -731,6 +1420,39 @@ impl<'a> Parser<'a> {
         self.make_externs(&|externs| Record::LEXTDEF{ externs })
     }
 
+    fn line_numbers(&mut self, is32: bool) -> Result<Vec<LineNumber>, ObjError> {
+        let bytes = if is32 { 4 } else { 2 };
+        let mut lines = Vec::new();
+        while self.ptr < self.endrec() {
+            let line = self.next_uint(2)? as u16;
+            let offset = self.next_uint(bytes)? as u32;
+            lines.push(LineNumber{ line, offset });
+        }
+        Ok(lines)
+    }
+
+    fn linnum(&mut self, is32: bool) -> Result<Record, ObjError> {
+        // Line numbers for code in a segment. The group is only there for
+        // debuggers, which address the lines from it.
+        //
+        let group = self.next_opt_index()?;
+        let seg = self.next_index()?;
+        let lines = self.line_numbers(is32)?;
+
+        Ok(Record::LINNUM{ group, seg, lines })
+    }
+
+    fn linsym(&mut self, is32: bool) -> Result<Record, ObjError> {
+        // Line numbers for code in a COMDAT, which is named by its LNAMES
+        // index. Bit 0 of the flags marks a continuation.
+        //
+        let flags = self.next_uint(1)? as u8;
+        let name = self.next_index()?;
+        let lines = self.line_numbers(is32)?;
+
+        Ok(Record::LINSYM{ flags, name, lines })
+    }
+
     fn alias(&mut self) -> Result<Record, ObjError> {
         let mut aliases = Vec::new();
 
@@ -794,66 +1516,108 @@ impl<'a> Parser<'a> {
         Ok(Record::LPUBDEF{ group, seg, frame, publics })
     }
 
+    // The spec limits the data in a data record to 1024 bytes
+    //
+    const MAX_DATA_RECORD: usize = 1024;
+
+    // Check a data record's payload size and that the data it describes 
+    // fits in its segment, and remember it for the FIXUPP which may follow.
+    //
+    fn check_data(&mut self, seg: usize, offset: u32, payload: usize, expanded: u64) -> Result<(), ObjError> {
+        if payload > Self::MAX_DATA_RECORD {
+            self.violation(&format!("data record has {} bytes of data (limit is {})", payload, Self::MAX_DATA_RECORD))?;
+        }
+
+        if seg > 0 && seg <= self.seg_lengths.len() {
+            let length = self.seg_lengths[seg - 1];
+            let end = (offset as u64).saturating_add(expanded);
+            if end > length {
+                self.violation(&format!(
+                    "data at {:08x}..{:08x} is past the end of segment {} (length {:08x})",
+                    offset, end, seg, length
+                ))?;
+            }
+        }
+
+        self.last_data = Some(payload);
+        Ok(())
+    }
+
+    fn libhdr(&mut self) -> Result<Record, ObjError> {
+        // The page size is implied by the record length; the rest of the
+        // page is padding.
+        //
+        let page_size = self.next - self.start;
+        let dict_offset = self.next_uint(4)? as u32;
+        let dict_blocks = self.next_uint(2)? as u16;
+        let flags = if self.ptr < self.endrec() { self.next_uint(1)? as u8 } else { 0 };
+
+        Ok(Record::LIBHDR{ page_size, dict_offset, dict_blocks, flags })
+    }
+
     fn ledata(&mut self, is32: bool) -> Result<Record, ObjError> {
         let seg = self.next_index()?;
         let bytes = if is32 { 4 } else { 2 };
         let offset = self.next_uint(bytes)? as u32;
         let data = &self.obj[self.ptr..self.endrec()];
 
+        self.check_data(seg, offset, data.len(), data.len() as u64)?;
+
         Ok(Record::LEDATA{ seg, offset, data: data.to_vec() })
     }
 
-    fn build_li_data(out: &mut Vec<u8>, input: &[u8], is32: bool) -> Result<usize, ObjError> {
-        let bytes = if is32 { 4 } else { 2 };
-        if bytes + 2 > input.len() {
-            return Err(ObjError::truncated());
+    // An iterated data block, `depth` blocks in. Each level costs only a
+    // few bytes, so a hostile record could nest deep enough to run out of
+    // stack; no translator nests anything like as deep as the limit.
+    //
+    fn iterated_block(&mut self, is32: bool, depth: usize) -> Result<IteratedBlock, ObjError> {
+        const MAX_DEPTH: usize = 16;
+        if depth > MAX_DEPTH {
+            return Err(self.err("iterated data is nested too deeply"));
         }
 
-        let repeat = Self::uint(&input[0..bytes]);
-        let block_count = Self::uint(&input[bytes..bytes+2]);
-        let mut next = bytes + 2;
-        
-        if block_count == 0 {
-            if next == input.len() {
-                return Err(ObjError::truncated());
-            }
-            let count = input[next] as usize;
-            next += 1;
-            if next + count > input.len() {
-                return Err(ObjError::truncated());
-            }
+        let bytes = if is32 { 4 } else { 2 };
+        let repeat = self.next_uint(bytes)?;
+        let block_count = self.next_uint(2)?;
 
-            for _ in 0..repeat {
-                for by in &input[next..next+count] {
-                    out.push(*by);
-                }
+        let content = if block_count == 0 {
+            let count = self.next_uint(1)?;
+            if self.ptr + count > self.endrec() {
+                return Err(self.err("iterated data block is truncated"));
             }
 
-            Ok(next + count)
+            let data = self.obj[self.ptr..self.ptr+count].to_vec();
+            self.ptr += count;
+            IteratedContent::Data(data)
         } else {
-            for _ in 0..repeat {
-                next = bytes + 2;
-                for _ in 0..block_count {
-                    next += Self::build_li_data(out, &input[next..], is32)?;
-                }
+            let mut blocks = Vec::new();
+            for _ in 0..block_count {
+                blocks.push(self.iterated_block(is32, depth + 1)?);
             }
-            Ok(next)
-        }
+            IteratedContent::Blocks(blocks)
+        };
+
+        Ok(IteratedBlock{ repeat, content })
     }
 
     fn lidata(&mut self, is32: bool) -> Result<Record, ObjError> {
         let seg = self.next_index()?;
         let bytes = if is32 { 4 } else { 2 };
         let offset = self.next_uint(bytes)? as u32;
-        let mut data = Vec::new();
-        Self::build_li_data(&mut data, &self.obj[self.ptr..self.endrec()], is32)?;
+        let payload = self.endrec() - self.ptr;
 
-        Ok(Record::LIDATA{ seg, offset, data: data.to_vec() })
-    }
-    fn bakpat(&mut self, is32: bool) -> Result<Record, ObjError> {
-        let seg = self.next_index()?;
-        let location = (self.next_uint(1)? as u8).try_into()?;
+        let mut blocks = Vec::new();
+        while self.ptr < self.endrec() {
+            blocks.push(self.iterated_block(is32, 0)?);
+        }
+
+        let expanded = blocks.iter().fold(0u64, |sum, block| sum.saturating_add(block.expanded_len()));
+        self.check_data(seg, offset, payload, expanded)?;
 
+        Ok(Record::LIDATA{ seg, offset, blocks })
+    }
+
+    fn bakpat_fixups(&mut self, is32: bool) -> Result<Vec<BakpatFixup>, ObjError> {
         let mut fixups = Vec::new();
 
         let bytes = if is32 { 4 } else { 2 };
@@ -863,12 +1627,65 @@ impl<'a> Parser<'a> {
             fixups.push(BakpatFixup{ offset, value });
         }
 
+        Ok(fixups)
+    }
+
+    fn bakpat(&mut self, is32: bool) -> Result<Record, ObjError> {
+        let seg = self.next_index()?;
+        let location = (self.next_uint(1)? as u8).try_into()?;
+        let fixups = self.bakpat_fixups(is32)?;
+
         Ok(Record::BAKPAT{ seg, location, fixups })
     }
 
+    fn nbkpat(&mut self, is32: bool) -> Result<Record, ObjError> {
+        // Like BAKPAT, but patching a COMDAT named by its LNAMES index
+        // rather than a segment. Note the location comes first here.
+        //
+        let location = (self.next_uint(1)? as u8).try_into()?;
+        let name = self.next_index()?;
+        let fixups = self.bakpat_fixups(is32)?;
+
+        Ok(Record::NBKPAT{ name, location, fixups })
+    }
+
+    // A fixup (as opposed to a thread definition) patches the data record
+    // immediately before the FIXUPP, and must lie within it.
+    //
+    fn check_fixup_location(&mut self, fixup: &Fixup) -> Result<(), ObjError> {
+        match self.prev_data {
+            None => self.violation(&format!("fixup at {:04x} does not follow a data record", fixup.data_offset)),
+            Some(length) => {
+                let end = fixup.data_offset + fixup.location.size();
+                if end > length {
+                    self.violation(&format!(
+                        "fixup at {:04x}..{:04x} is past the end of the data record (length {:04x})",
+                        fixup.data_offset, end, length
+                    ))
+                } else {
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    fn fixup_location(&self, location: u8) -> Result<FixupLocation, ObjError> {
+        // Easy OMF-386 predates the Microsoft 32-bit locations and puts
+        // them in the slots of the 16-bit loader-resolved ones
+        //
+        match location {
+            5 if self.easy_omf => Ok(FixupLocation::Offset32),
+            6 if self.easy_omf => Ok(FixupLocation::Pointer48),
+            location => location.try_into(),
+        }
+    }
+
     fn fixupp(&mut self, is32: bool) -> Result<Record, ObjError> {
         let mut fixups = Vec::new();
 
+        // a COMDAT's fixups may sit between it and its continuation
+        self.last_comdat = self.prev_comdat;
+
         while self.ptr < self.endrec() {
             let lead = self.next_uint(1)? as u8;
 
@@ -883,7 +1700,11 @@ impl<'a> Parser<'a> {
                     //    derived from the P bit in the Fix Data field of FIXUP 
                     //    subrecords that refer to this thread."
                     //
-                    let method: TargetMethod = ((lead >> 2) & 3).try_into()?;
+                    // MS tools set the high bit anyway. It's kept so the
+                    // subrecord can be written back as it was, but it says
+                    // nothing about the fixups which use the thread.
+                    //
+                    let method: TargetMethod = ((lead >> 2) & 7).try_into()?;
                     let index = self.next_index()?;
                     fixups.push(FixupSubrecord::TargetThread{ method, thread, index })
                 } else {
@@ -898,11 +1719,16 @@ impl<'a> Parser<'a> {
                     fixups.push(FixupSubrecord::FrameThread{ method, thread, index })
                 }
             } else {
-                //
-                // TODO if not seg_relative is entire fixdata section missing?
+                // NB the fix data byte is always present, for self-relative
+                // fixups as well as segment-relative ones (e.g. MSC's near
+                // calls are self-relative with full fix data). Only the
+                // fields it governs are optional: the frame datum (F=0 and
+                // a method which takes one), the target datum (T=0) and the
+                // displacement (P=0), which is present even when the target
+                // comes from a thread.
                 //
                 let is_seg_relative = (lead & 0x40) != 0;
-                let location: FixupLocation = ((lead >> 2) & 0x0f).try_into()?;
+                let location = self.fixup_location((lead >> 2) & 0x0f)?;
                 let low = self.next_uint(1)?;
                 let data_offset = (((lead as usize) & 3) << 8) | low;
                 let fixdata = self.next_uint(1)?;
@@ -961,6 +1787,8 @@ impl<'a> Parser<'a> {
                     target_displacement,
                 };
 
+                self.check_fixup_location(&fixup)?;
+
                 fixups.push(FixupSubrecord::Fixup{ fixup });
             }
         }
@@ -975,8 +1803,8 @@ impl<'a> Parser<'a> {
         } else {
             match byte {
                 0x81 => Ok(self.next_uint(2)?),
-                0x82 => Ok(self.next_uint(3)?),
-                0x83 => Ok(self.next_uint(4)?),
+                0x82 | 0x84 => Ok(self.next_uint(3)?),
+                0x83 | 0x88 => Ok(self.next_uint(4)?),
                 x => return Err(self.err(&format!("invalid encoded length lead byte {:02x}", x))),
             }
         }
@@ -1039,12 +1867,22 @@ impl<'a> Parser<'a> {
 
         let name = self.next_index()?;
 
+        if (flags & 0x01) != 0 && self.prev_comdat != Some(name) {
+            self.violation(&format!("continuation COMDAT (name {}) does not follow a COMDAT with the same name", name))?;
+        }
+        self.last_comdat = Some(name);
+
         let mut data = Vec::new();
+        let mut blocks = Vec::new();
 
         let is_iterated = (flags & 0x02) != 0;
 
+        self.last_data = Some(self.endrec() - self.ptr);
+
         if is_iterated {
-            Self::build_li_data(&mut data, &self.obj[self.ptr..self.endrec()], is32)?;
+            while self.ptr < self.endrec() {
+                blocks.push(self.iterated_block(is32, 0)?);
+            }
         } else {
             while self.ptr < self.endrec() {
                 data.push(self.next_uint(1)? as u8);
@@ -1064,6 +1902,7 @@ impl<'a> Parser<'a> {
                 base_frame,
                 name,
                 data,    
+                blocks,
             }
         })
     }
@@ -1076,6 +1915,103 @@ impl<'a> Parser<'a> {
         })
     }
 
+    fn coment_omf_extension(&mut self, header: ComentHeader) -> Result<Record, ObjError> {
+        let subtype = self.next_uint(1)? as u8;
+
+        match subtype {
+            0x01 => self.coment_impdef(header),
+            0x02 => self.coment_expdef(header),
+            0x03 => self.coment_incdef(header),
+            0x05 => self.coment_lnkdir(header),
+            _ => Ok(Record::COMENT{ header, coment: Coment::Unknown }),
+        }
+    }
+
+    fn coment_impdef(&mut self, header: ComentHeader) -> Result<Record, ObjError> {
+        let by_ordinal = self.next_uint(1)? != 0;
+        let internal = self.next_str()?;
+        let module = self.next_str()?;
+
+        let entry = if by_ordinal {
+            ImportEntry::Ordinal(self.next_uint(2)? as u16)
+        } else {
+            ImportEntry::Name(self.next_str()?)
+        };
+
+        Ok(Record::COMENT{
+            header,
+            coment: Coment::Impdef{ internal, module, entry }
+        })
+    }
+
+    fn coment_expdef(&mut self, header: ComentHeader) -> Result<Record, ObjError> {
+        let flags = self.next_uint(1)? as u8;
+        let exported = self.next_str()?;
+        let internal = self.next_str()?;
+
+        let ordinal = if (flags & 0x80) != 0 {
+            Some(self.next_uint(2)? as u16)
+        } else {
+            None
+        };
+
+        Ok(Record::COMENT{
+            header,
+            coment: Coment::Expdef{ flags, exported, internal, ordinal }
+        })
+    }
+
+    fn coment_incdef(&mut self, header: ComentHeader) -> Result<Record, ObjError> {
+        // Anything after the deltas is padding
+        //
+        let extdef_delta = self.next_uint(2)? as i16;
+        let linnum_delta = self.next_uint(2)? as i16;
+
+        Ok(Record::COMENT{
+            header,
+            coment: Coment::Incdef{ extdef_delta, linnum_delta }
+        })
+    }
+
+    fn coment_lnkdir(&mut self, header: ComentHeader) -> Result<Record, ObjError> {
+        let flags = self.next_uint(1)? as u8;
+        let pcode_version = self.next_uint(1)? as u8;
+        let cv_version = self.next_uint(1)? as u8;
+
+        Ok(Record::COMENT{
+            header,
+            coment: Coment::Lnkdir{ flags, pcode_version, cv_version }
+        })
+    }
+
+    fn coment_nopad(&mut self, header: ComentHeader) -> Result<Record, ObjError> {
+        let mut segs = Vec::new();
+
+        while self.ptr < self.endrec() {
+            segs.push(self.next_index()?);
+        }
+
+        Ok(Record::COMENT{
+            header,
+            coment: Coment::NoPad{ segs }
+        })
+    }
+
+    fn coment_dependency(&mut self, header: ComentHeader) -> Result<Record, ObjError> {
+        if self.ptr == self.endrec() {
+            return Ok(Record::COMENT{ header, coment: Coment::DependencyEnd });
+        }
+
+        let time = self.next_uint(2)? as u16;
+        let date = self.next_uint(2)? as u16;
+        let name = self.next_str()?;
+
+        Ok(Record::COMENT{
+            header,
+            coment: Coment::Dependency{ name, timestamp: DosTimestamp::new(date, time) }
+        })
+    }
+
     fn coment_new_omf(&mut self, header: ComentHeader) -> Result<Record, ObjError> {
         let text = self.rest_str()?;
         Ok(Record::COMENT{
@@ -1110,7 +2046,10 @@ impl<'a> Parser<'a> {
         })
     }
 
-    fn coment_weak_extern(&mut self, header: ComentHeader) -> Result<Record, ObjError> {
+    fn coment_weak_extern(&mut self, header: ComentHeader, lazy: bool) -> Result<Record, ObjError> {
+        // Lazy externs have the same layout, but the default is only used
+        // if the weak symbol isn't found by searching libraries either
+        //
         let mut externs = Vec::new();
 
         while self.ptr < self.endrec() {
@@ -1120,11 +2059,13 @@ impl<'a> Parser<'a> {
             externs.push(WeakExtern{ weak, default });
         }
         
+        let coment = if lazy {
+            Coment::LazyExtern{ externs }
+        } else {
+            Coment::WeakExtern{ externs }
+        };
         
-        Ok(Record::COMENT{ 
-            header,
-            coment: Coment::WeakExtern{ externs }
-        })
+        Ok(Record::COMENT{ header, coment })
     }
 
     fn coment_user(&mut self, header: ComentHeader) -> Result<Record, ObjError> {
@@ -1143,36 +2084,49 @@ impl<'a> Parser<'a> {
 
         match comclass {
             0x00 => self.coment_translator(header),
+            0xa0 => self.coment_omf_extension(header),
+            0x9c => Ok(Record::COMENT{ header, coment: Coment::DosVersion{ version: self.next_uint(2)? as u16 } }),
             0x9d => self.coment_memory_model(header),
             0x9e => Ok(Record::COMENT{ header, coment: Coment::DosSeg }),
             0x9f => self.coment_default_library(header),
             0xa1 => self.coment_new_omf(header),
             0xa2 => Ok(Record::COMENT{ header, coment: Coment::LinkPassSeparator }),
             0xa3 => self.coment_libmod(header),
-            0xa8 => self.coment_weak_extern(header),
+            0xa4 => Ok(Record::COMENT{ header, coment: Coment::ExeStr{ text: self.rest_str()? } }),
+            0xa6 => Ok(Record::COMENT{ header, coment: Coment::IncErr }),
+            0xa7 => self.coment_nopad(header),
+            0xa8 => self.coment_weak_extern(header, false),
+            0xaa => Ok(Record::COMENT{ header, coment: Coment::EasyOmf{ text: self.rest_str()? } }),
+            0xa9 => self.coment_weak_extern(header, true),
             0xdf => self.coment_user(header),
+            0xe9 => self.coment_dependency(header),
             _ => Ok(Record::COMENT{ header, coment: Coment::Unknown }), 
         }
     }
 
     fn record(&mut self, rectype: u8) -> Result<Record, ObjError> {
+        let easy = self.easy_omf;
+
         match rectype {
             0x80 => Ok(Record::THEADR{ name: self.next_str()? }),
+            0x82 => Ok(Record::LHEADR{ name: self.next_str()? }),
             0x88 => self.coment(),
-            0x8a => self.modend(false),
+            0x8a => self.modend(easy),
             0x8b => self.modend(true),
             0x8c => self.extdef(),
-            0x90 => self.pubdef(false),
+            0x90 => self.pubdef(easy),
             0x91 => self.pubdef(true),
+            0x94 => self.linnum(easy),
+            0x95 => self.linnum(true),
             0x96 => self.lnames(),
-            0x98 => self.segdef(false),
+            0x98 => self.segdef(easy),
             0x99 => self.segdef(true),
             0x9a => self.grpdef(),
-            0x9c => self.fixupp(false),
+            0x9c => self.fixupp(easy),
             0x9d => self.fixupp(true),
-            0xa0 => self.ledata(false),
+            0xa0 => self.ledata(easy),
             0xa1 => self.ledata(true),
-            0xa2 => self.lidata(false),
+            0xa2 => self.lidata(easy),
             0xa3 => self.lidata(true),
             0xb0 => self.comdef(),
             0xb2 => self.bakpat(false),
@@ -1184,32 +2138,319 @@ impl<'a> Parser<'a> {
             0xbc => self.cextdef(),
             0xc2 => self.comdat(false),
             0xc3 => self.comdat(true),
+            0xc4 => self.linsym(false),
+            0xc5 => self.linsym(true),
             0xc6 => self.alias(),
-            rectype => Ok(Record::Unknown{ rectype }),
+            0xc8 => self.nbkpat(false),
+            0xc9 => self.nbkpat(true),
+            0xf0 => self.libhdr(),
+            0xf1 => Ok(Record::LIBEND),
+            0xf2..=0xff => Ok(Record::BORLAND{ rectype, data: self.rest()?.to_vec() }),
+            rectype => match IntelRecord::try_from(rectype) {
+                Ok(kind) => Ok(Record::INTEL{ kind, data: self.rest()?.to_vec() }),
+                Err(_) => Ok(Record::Unknown{ rectype }),
+            },
+        }
+    }
+
+    fn check_index(&mut self, kind: &str, index: usize, count: usize) -> Result<(), ObjError> {
+        if index == 0 || index > count {
+            self.violation(&format!("{} index {} is out of range ({} defined)", kind, index, count))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_opt_index(&mut self, kind: &str, index: Option<usize>, count: usize) -> Result<(), ObjError> {
+        match index {
+            Some(index) => self.check_index(kind, index, count),
+            None => Ok(()),
+        }
+    }
+
+    fn check_target(&mut self, method: &TargetMethod, index: usize) -> Result<(), ObjError> {
+        match method {
+            TargetMethod::Segdef | TargetMethod::SegdefNoDisplacement => self.check_index("SEGDEF", index, self.seg_lengths.len()),
+            TargetMethod::Grpdef | TargetMethod::GrpdefNoDisplacement => self.check_index("GRPDEF", index, self.grpdefs),
+            TargetMethod::Extdef | TargetMethod::ExtdefNoDisplacement => self.check_index("EXTDEF", index, self.externs),
+        }
+    }
+
+    fn check_frame(&mut self, method: &FrameMethod, index: usize) -> Result<(), ObjError> {
+        match method {
+            FrameMethod::Segdef => self.check_index("SEGDEF", index, self.seg_lengths.len()),
+            FrameMethod::Grpdef => self.check_index("GRPDEF", index, self.grpdefs),
+            FrameMethod::Extdef => self.check_index("EXTDEF", index, self.externs),
+            _ => Ok(()),
+        }
+    }
+
+    // Check every index in a record against the number of names, segments,
+    // groups and externs declared so far, then account for any the record
+    // itself declares.
+    //
+    fn check_indices(&mut self, record: &Record) -> Result<(), ObjError> {
+        let segs = self.seg_lengths.len();
+
+        match record {
+            Record::LNAMES{ names } => self.lnames += names.len(),
+            Record::SEGDEF{ segs } => {
+                for seg in segs {
+                    self.check_opt_index("LNAMES", seg.name, self.lnames)?;
+                    self.check_opt_index("LNAMES", seg.class, self.lnames)?;
+                    self.check_opt_index("LNAMES", seg.overlay, self.lnames)?;
+                }
+            },
+            Record::GRPDEF{ name, components } => {
+                self.check_index("LNAMES", *name, self.lnames)?;
+                for component in components {
+                    match component {
+                        GrpComponent::Segdef{ index } => self.check_index("SEGDEF", *index, segs)?,
+                        GrpComponent::Extdef{ index } => self.check_index("EXTDEF", *index, self.externs)?,
+                        GrpComponent::SegClassOverlay{ name, class, overlay } => {
+                            self.check_index("LNAMES", *name, self.lnames)?;
+                            self.check_index("LNAMES", *class, self.lnames)?;
+                            self.check_index("LNAMES", *overlay, self.lnames)?;
+                        },
+                        _ => (),
+                    }
+                }
+                self.grpdefs += 1;
+            },
+            Record::EXTDEF{ externs } | Record::LEXTDEF{ externs } => self.externs += externs.len(),
+            Record::COMDEF{ commons } => self.externs += commons.len(),
+            Record::CEXTDEF{ externs } => {
+                for ext in externs {
+                    self.check_index("LNAMES", ext.name, self.lnames)?;
+                }
+                self.externs += externs.len();
+            },
+            Record::PUBDEF{ group, seg, .. } | Record::LPUBDEF{ group, seg, .. } => {
+                self.check_opt_index("GRPDEF", *group, self.grpdefs)?;
+                self.check_opt_index("SEGDEF", *seg, segs)?;
+            },
+            Record::LEDATA{ seg, .. } | Record::LIDATA{ seg, .. } | Record::BAKPAT{ seg, .. } => {
+                self.check_index("SEGDEF", *seg, segs)?;
+            },
+            Record::FIXUPP{ fixups } => {
+                for fixup in fixups {
+                    match fixup {
+                        FixupSubrecord::TargetThread{ method, index, .. } => self.check_target(method, *index)?,
+                        FixupSubrecord::FrameThread{ method, index: Some(index), .. } => self.check_frame(method, *index)?,
+                        FixupSubrecord::FrameThread{ .. } => (),
+                        FixupSubrecord::Fixup{ fixup } => {
+                            if let (Some(method), Some(index)) = (&fixup.frame_method, fixup.frame_datum) {
+                                self.check_frame(method, index)?;
+                            }
+                            if let (Some(method), Some(index)) = (&fixup.target_method, fixup.target_datum) {
+                                self.check_target(method, index)?;
+                            }
+                        },
+                    }
+                }
+            },
+            Record::COMENT{ coment: Coment::WeakExtern{ externs }, .. } | 
+            Record::COMENT{ coment: Coment::LazyExtern{ externs }, .. } => {
+                for ext in externs {
+                    self.check_index("EXTDEF", ext.weak, self.externs)?;
+                    self.check_index("EXTDEF", ext.default, self.externs)?;
+                }
+            },
+            Record::COMENT{ coment: Coment::NoPad{ segs: nopad }, .. } => {
+                for seg in nopad {
+                    self.check_index("SEGDEF", *seg, segs)?;
+                }
+            },
+            Record::COMDAT{ comdat } => {
+                self.check_opt_index("GRPDEF", comdat.base_group, self.grpdefs)?;
+                self.check_opt_index("SEGDEF", comdat.base_seg, segs)?;
+                self.check_index("LNAMES", comdat.name, self.lnames)?;
+            },
+            Record::LINNUM{ group, seg, .. } => {
+                self.check_opt_index("GRPDEF", *group, self.grpdefs)?;
+                self.check_index("SEGDEF", *seg, segs)?;
+            },
+            Record::LINSYM{ name, .. } | Record::NBKPAT{ name, .. } => self.check_index("LNAMES", *name, self.lnames)?,
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    // The type and first body byte of the next physical record, if any
+    //
+    fn peek(&self) -> Option<(u8, u8)> {
+        if self.next + 3 < self.obj.len() {
+            Some((self.obj[self.next], self.obj[self.next + 3]))
+        } else {
+            None
+        }
+    }
+
+    // Gather the continuations of a COMDAT, and the fixups which apply to
+    // each piece, into one logical COMDAT. The combined FIXUPP is queued
+    // to be returned next, with its data offsets rebased onto the merged
+    // data.
+    //
+    fn merge_comdat(&mut self, first: ParsedRecord<'a>) -> Result<ParsedRecord<'a>, ObjError> {
+        let mut comdat = match first.record {
+            Record::COMDAT{ comdat } => comdat,
+            _ => unreachable!(),
+        };
+
+        let mut fixups = Vec::new();
+        let mut fixupp: Option<ParsedRecord<'a>> = None;
+        let mut base = 0;
+        let mut end = first.offset + first.raw.len();
+
+        loop {
+            match self.peek() {
+                Some((0x9c, _)) | Some((0x9d, _)) => {
+                    let mut parsed = self.next_record()?;
+                    end = parsed.offset + parsed.raw.len();
+
+                    if let Record::FIXUPP{ fixups: more } = std::mem::replace(&mut parsed.record, Record::None) {
+                        for mut sub in more {
+                            if let FixupSubrecord::Fixup{ ref mut fixup } = sub {
+                                fixup.data_offset += base;
+                            }
+                            fixups.push(sub);
+                        }
+                    }
+
+                    fixupp.get_or_insert(parsed);
+                },
+                Some((0xc2, flags)) | Some((0xc3, flags)) if (flags & 0x03) == 0x01 => {
+                    let parsed = self.next_record()?;
+                    match parsed.record {
+                        Record::COMDAT{ comdat: ref next } 
+                            if next.name == comdat.name && next.offset as usize == comdat.offset as usize + comdat.data.len() => {
+                            base = comdat.data.len();
+                            comdat.data.extend(&next.data);
+                            end = parsed.offset + parsed.raw.len();
+                        },
+                        _ => {
+                            self.pending.push_back(parsed);
+                            break;
+                        },
+                    }
+                },
+                _ => break,
+            }
+        }
+
+        if let Some(mut fixupp) = fixupp {
+            fixupp.record = Record::FIXUPP{ fixups };
+            self.pending.push_front(fixupp);
         }
+
+        Ok(ParsedRecord{
+            offset: first.offset,
+            rectype: first.rectype,
+            raw: &self.obj[first.offset..end],
+            record: Record::COMDAT{ comdat },
+        })
     }
 
     pub fn next(&mut self) -> Result<Record, ObjError> {
+        self.next_parsed().map(|parsed| parsed.record)
+    }
+
+    // Like next, but also says where the record came from
+    //
+    pub fn next_parsed(&mut self) -> Result<ParsedRecord<'a>, ObjError> {
+        if let Some(parsed) = self.pending.pop_front() {
+            return Ok(parsed);
+        }
+
+        let parsed = loop {
+            match self.next_record() {
+                Ok(parsed) => break parsed,
+                Err(err) if self.options.recover => {
+                    // self.next is already past the record if its header
+                    // was sound; if not, the next call will find the end
+                    //
+                    self.warn(&format!("skipped record: {}", err.details));
+                },
+                Err(err) => match &self.module {
+                    Some(module) => return Err(err.in_module(module)),
+                    None => return Err(err),
+                },
+            }
+        };
+
+        match &parsed.record {
+            Record::COMDAT{ comdat } if self.options.merge_comdats && !comdat.continuation() && !comdat.iterated_data() => 
+                self.merge_comdat(parsed),
+            _ => Ok(parsed),
+        }
+    }
+
+    fn next_record(&mut self) -> Result<ParsedRecord<'a>, ObjError> {
         self.ptr = self.next;
+        self.start = self.ptr;
         self.next = self.obj.len();
+        self.prev_data = self.last_data.take();
+        self.prev_comdat = self.last_comdat.take();
+
+        let end = ParsedRecord{ offset: self.start, rectype: 0, raw: &[], record: Record::None };
 
         if self.ptr >= self.obj.len() {
-            Ok(Record::None)
+            Ok(end)
+        } else if self.ended {
+            // Padding to a block boundary, librarian slack or a ^Z EOF mark.
+            // None of it is a record, so don't try to parse it.
+            //
+            let trailing = self.obj.len() - self.ptr;
+            self.warn(&format!("{} bytes of trailing data after MODEND", trailing));
+            Ok(end)
         } else if self.next - self.ptr < 3  {
             Err(self.err("record header truncated"))
         } else {
             let typ = self.next_uint(1)?;
             let len = self.next_uint(2)?;
             
-            if self.ptr + len > self.obj.len() {
+            // the length counts the checksum byte, which is always there
+            //
+            if len < 1 || self.ptr + len > self.obj.len() {
                 Err(self.err("record body truncated"))
             } else {
                 self.next = self.ptr + len;
-                if !Self::checksum(&self.obj[self.start..self.next]) {
-                    Err(self.err("checksum failed"))
-                } else {
-                    self.record(typ as u8)
-                }    
+                let record_bytes = &self.obj[self.start..self.next];
+                let valid = match self.options.checksum {
+                    ChecksumPolicy::Ignore => true,
+                    ChecksumPolicy::Strict => Self::checksum(record_bytes, false),
+                    _ => Self::checksum(record_bytes, true),
+                };
+
+                if !valid {
+                    if self.options.checksum == ChecksumPolicy::Warn {
+                        self.warn("checksum failed");
+                    } else {
+                        return Err(self.err("checksum failed"));
+                    }
+                }
+
+                let record = self.record(typ as u8)?;
+                self.check_indices(&record)?;
+                self.ended = matches!(record, Record::MODEND{ .. });
+
+                if let Record::COMENT{ coment: Coment::EasyOmf{ .. }, .. } = &record {
+                    self.easy_omf = true;
+                }
+
+                match &record {
+                    Record::THEADR{ name } | Record::LHEADR{ name } if self.module.is_none() => self.module = Some(name.clone()),
+                    Record::COMENT{ coment: Coment::Libmod{ name }, .. } => self.module = Some(name.clone()),
+                    _ => (),
+                }
+
+                Ok(ParsedRecord{
+                    offset: self.start,
+                    rectype: typ as u8,
+                    raw: &self.obj[self.start..self.next],
+                    record,
+                })
             }
         }
     }
@@ -1258,7 +2499,7 @@ mod test {
 
     #[test]
     fn test_undefined_rectype_returns_unknown() {
-        let obj = vec![0x42, 0x00, 0x00, 0x00];
+        let obj = vec![0x42, 0x01, 0x00, 0x00];
         let mut parser = Parser::new(&obj);
 
         let p = parser.next();
@@ -1278,75 +2519,365 @@ mod test {
     }
 
     #[test]
-    fn test_truncated_record_fails() {
+    fn test_bad_checksum_warns_when_asked() {
         let obj = vec![
             0x80, 0x0e, 0x00, 0x0c,  0x64, 0x6f, 0x73, 0x5c, 
-            0xdc];
+            0x63, 0x72, 0x74, 0x30,  0x2e, 0x61, 0x73, 0x6d, 
+            0xdd,
+            0x8a, 0x02, 0x00, 0x01, 0x73];
+        let options = ParseOptions{ checksum: ChecksumPolicy::Warn, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+
+        match parser.next() {
+            Ok(Record::THEADR{ name }) => assert_eq!(name, "dos\\crt0.asm"),
+            x => assert!(false, "parser returned {:x?}", x),
+        };
+        assert_eq!(parser.diagnostics(), &[Diagnostic::with_offset("checksum failed", 0)]);
+
+        // the checksum of the following record is judged on its own bytes
+        assert!(matches!(parser.next(), Ok(Record::MODEND{ .. })));
+        assert_eq!(parser.diagnostics().len(), 1);
+    }
+
+    #[test]
+    fn test_ignored_checksum_is_not_checked() {
+        let obj = vec![0x8a, 0x02, 0x00, 0x01, 0x55];
+        let options = ParseOptions{ checksum: ChecksumPolicy::Ignore, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+
+        assert!(matches!(parser.next(), Ok(Record::MODEND{ .. })));
+        assert!(parser.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_zero_checksum_fails_when_strict() {
+        let obj = vec![0x8a, 0x02, 0x00, 0x01, 0x00];
+
         let mut parser = Parser::new(&obj);
+        assert!(parser.next().is_ok());
 
+        let options = ParseOptions{ checksum: ChecksumPolicy::Strict, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
         assert!(parser.next().is_err());
     }
 
-    //
-    // THEADR
-    //
     #[test]
-    fn test_theadr_succeeds() {
+    fn test_checksum_policy_names_parse() {
+        assert_eq!(ChecksumPolicy::try_from("Ignore").unwrap(), ChecksumPolicy::Ignore);
+        assert!(ChecksumPolicy::try_from("sometimes").is_err());
+    }
+
+    #[test]
+    fn test_checksum_error_reports_record_offset() {
         let obj = vec![
-            0x80, 0x0e, 0x00, 0x0c,  0x64, 0x6f, 0x73, 0x5c, 
-            0x63, 0x72, 0x74, 0x30,  0x2e, 0x61, 0x73, 0x6d, 
-            0xdc];
+            0x96, 0x02, 0x00, 0x00, 0x68,
+            0x8a, 0x02, 0x00, 0x00, 0x75];
         let mut parser = Parser::new(&obj);
 
+        assert!(parser.next().is_ok());
         match parser.next() {
-            Ok(Record::THEADR{ name }) => assert_eq!(name, "dos\\crt0.asm"),
+            Err(err) => assert_eq!(err.offset, Some(5)),
             x => assert!(false, "parser returned {:x?}", x),
-        };
+        }
     }
 
-    //
-    // LNAMES
-    //
     #[test]
-    fn test_lnames_succeeds() {
+    fn test_bytes_after_modend_are_not_parsed() {
         let obj = vec![
-            0x96, 0x09, 0x00, 0x03,  0x41, 0x42, 0x43, 0x03, 
-            0x44, 0x45, 0x46, 0x00];
+            0x8a, 0x02, 0x00, 0x01, 0x73,
+            0x00, 0x00, 0x1a];
+        let mut parser = Parser::new(&obj);
+
+        assert!(matches!(parser.next(), Ok(Record::MODEND{ .. })));
+        assert!(matches!(parser.next(), Ok(Record::None)));
+        assert_eq!(parser.diagnostics(), &[Diagnostic::with_offset("3 bytes of trailing data after MODEND", 5)]);
+    }
+
+    #[test]
+    fn test_libhdr_succeeds() {
+        let obj = vec![
+            0xf0, 0x0d, 0x00,
+            0x00, 0x04, 0x00, 0x00,
+            0x01, 0x00,
+            0x01,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
         let mut parser = Parser::new(&obj);
 
         match parser.next() {
-            Ok(Record::LNAMES{ names }) => {
-                assert_eq!(names.len(), 2);
-                assert_eq!(names[0], "ABC");
-                assert_eq!(names[1], "DEF");
+            Ok(Record::LIBHDR{ page_size, dict_offset, dict_blocks, flags }) => {
+                assert_eq!(page_size, 16);
+                assert_eq!(dict_offset, 0x400);
+                assert_eq!(dict_blocks, 1);
+                assert_eq!(flags, 1);
             },
             x => assert!(false, "parser returned {:x?}", x),
-        };
+        }
     }
 
-    //
-    // SEGDEF
-    //
     #[test]
-    fn test_segdef_relocatable_succeeds() {
-        let obj = vec![
-            0x98, 0x0d, 0x00,
-            0b01001000, 0x34, 0x12, 0x01, 0x02, 0x03,
-            0b01100011, 0x00, 0x00, 0x05, 0x06, 0x00,
-            0x00];
+    fn test_libend_succeeds() {
+        let obj = vec![0xf1, 0x01, 0x00, 0x00];
+        let mut parser = Parser::new(&obj);
+
+        assert!(matches!(parser.next(), Ok(Record::LIBEND)));
+    }
+
+    #[test]
+    fn test_borland_record_keeps_payload() {
+        let obj = vec![0xf8, 0x03, 0x00, 0x12, 0x34, 0x00];
         let mut parser = Parser::new(&obj);
 
         match parser.next() {
-            Ok(Record::SEGDEF{ segs }) => {
-                assert_eq!(segs.len(), 2);
-                assert_eq!(segs[0], Segdef{
-                    align: Align::Word,
-                    combine: Combine::Public,
-                    use32: false,
+            Ok(Record::BORLAND{ rectype, data }) => {
+                assert_eq!(rectype, 0xf8);
+                assert_eq!(data, vec![0x12, 0x34]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_zero_length_record_fails() {
+        let obj = vec![0xf2, 0x00, 0x00, 0x00];
+        let mut parser = Parser::new(&obj);
+
+        assert_eq!(parser.next().unwrap_err().details, "record body truncated");
+    }
+
+    #[test]
+    fn test_lheadr_names_the_module() {
+        let obj = vec![0x82, 0x03, 0x00, 0x01, 0x41, 0x39];
+        let mut parser = Parser::new(&obj);
+
+        assert_eq!(parser.next().unwrap(), Record::LHEADR{ name: "A".to_string() });
+        assert_eq!(parser.module(), Some("A"));
+    }
+
+    #[test]
+    fn test_intel_record_keeps_payload() {
+        let obj = vec![0x70, 0x04, 0x00, 0x01, 0x02, 0x03, 0x00];
+        let mut parser = Parser::new(&obj);
+
+        match parser.next() {
+            Ok(Record::INTEL{ kind, data }) => {
+                assert_eq!(kind, IntelRecord::Regint);
+                assert_eq!(data, vec![0x01, 0x02, 0x03]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_zero_length_intel_record_fails() {
+        let obj = vec![0x6e, 0x00, 0x00, 0x00];
+        let mut parser = Parser::new(&obj);
+
+        assert_eq!(parser.next().unwrap_err().details, "record body truncated");
+    }
+
+    #[test]
+    fn test_errors_name_the_module() {
+        let obj = vec![
+            0x80, 0x07, 0x00, 0x05, 0x66, 0x6f, 0x70, 0x65, 0x6e, 0x00,
+            0x8a, 0x02, 0x00, 0x01, 0x00,
+            0x00];
+        let options = ParseOptions{ strict: true, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+
+        assert!(matches!(parser.next(), Ok(Record::THEADR{ .. })));
+        assert_eq!(parser.module(), Some("fopen"));
+        assert!(matches!(parser.next(), Ok(Record::MODEND{ .. })));
+        assert!(matches!(parser.next(), Ok(Record::None)));
+
+        let diag = &parser.diagnostics()[0];
+        assert_eq!(diag.module.as_deref(), Some("fopen"));
+        assert_eq!(format!("{}", diag), "fopen: 0000000f: warning: 1 bytes of trailing data after MODEND");
+    }
+
+    #[test]
+    fn test_libmod_overrides_theadr_name() {
+        let obj = vec![
+            0x80, 0x07, 0x00, 0x05, 0x66, 0x6f, 0x70, 0x65, 0x6e, 0x00,
+            0x88, 0x08, 0x00, 0x00, 0xa3, 0x04, 0x6d, 0x61, 0x69, 0x6e, 0x00,
+            0x8a, 0x02, 0x00, 0x00, 0x00];
+        let options = ParseOptions{ strict: true, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+
+        assert!(matches!(parser.next(), Ok(Record::THEADR{ .. })));
+        assert!(matches!(parser.next(), Ok(Record::COMENT{ .. })));
+        assert_eq!(parser.module(), Some("main"));
+
+        match parser.next() {
+            Err(err) => assert_eq!(err.module.as_deref(), Some("main")),
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_next_parsed_returns_record_location() {
+        let obj = vec![
+            0x96, 0x02, 0x00, 0x00, 0x68,
+            0x8a, 0x02, 0x00, 0x01, 0x73];
+        let mut parser = Parser::new(&obj);
+
+        let parsed = parser.next_parsed().unwrap();
+        assert_eq!(parsed.offset, 0);
+        assert_eq!(parsed.rectype, 0x96);
+        assert_eq!(parsed.raw, &obj[0..5]);
+        assert!(matches!(parsed.record, Record::LNAMES{ .. }));
+
+        let parsed = parser.next_parsed().unwrap();
+        assert_eq!(parsed.offset, 5);
+        assert_eq!(parsed.rectype, 0x8a);
+        assert_eq!(parsed.raw, &obj[5..10]);
+
+        let parsed = parser.next_parsed().unwrap();
+        assert_eq!(parsed.offset, 10);
+        assert!(parsed.raw.is_empty());
+        assert_eq!(parsed.record, Record::None);
+    }
+
+    #[test]
+    fn test_recover_skips_bad_records() {
+        let obj = vec![
+            0x96, 0x02, 0x00, 0x00, 0x55,   // bad checksum
+            0x9a, 0x03, 0x00, 0x01, 0xf0, 0x00,   // bad component type
+            0x8a, 0x02, 0x00, 0x01, 0x73];
+        let options = ParseOptions{ recover: true, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+
+        assert!(matches!(parser.next(), Ok(Record::MODEND{ .. })));
+        assert!(matches!(parser.next(), Ok(Record::None)));
+        assert_eq!(parser.diagnostics(), &[
+            Diagnostic::with_offset("skipped record: checksum failed", 0),
+            Diagnostic::with_offset("skipped record: invalid grpdef component type $f0", 5),
+        ]);
+    }
+
+    #[test]
+    fn test_recover_stops_at_truncated_record() {
+        let obj = vec![
+            0x96, 0x02, 0x00, 0x00, 0x68,
+            0x96, 0x08, 0x00, 0x00];
+        let options = ParseOptions{ recover: true, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+
+        assert!(matches!(parser.next(), Ok(Record::LNAMES{ .. })));
+        assert!(matches!(parser.next(), Ok(Record::None)));
+        assert_eq!(parser.diagnostics(), &[Diagnostic::with_offset("skipped record: record body truncated", 5)]);
+    }
+
+    #[test]
+    fn test_truncated_record_fails() {
+        let obj = vec![
+            0x80, 0x0e, 0x00, 0x0c,  0x64, 0x6f, 0x73, 0x5c, 
+            0xdc];
+        let mut parser = Parser::new(&obj);
+
+        assert!(parser.next().is_err());
+    }
+
+    //
+    // THEADR
+    //
+    #[test]
+    fn test_theadr_succeeds() {
+        let obj = vec![
+            0x80, 0x0e, 0x00, 0x0c,  0x64, 0x6f, 0x73, 0x5c, 
+            0x63, 0x72, 0x74, 0x30,  0x2e, 0x61, 0x73, 0x6d, 
+            0xdc];
+        let mut parser = Parser::new(&obj);
+
+        match parser.next() {
+            Ok(Record::THEADR{ name }) => assert_eq!(name, "dos\\crt0.asm"),
+            x => assert!(false, "parser returned {:x?}", x),
+        };
+    }
+
+    //
+    // LNAMES
+    //
+    #[test]
+    fn test_lnames_succeeds() {
+        let obj = vec![
+            0x96, 0x09, 0x00, 0x03,  0x41, 0x42, 0x43, 0x03, 
+            0x44, 0x45, 0x46, 0x00];
+        let mut parser = Parser::new(&obj);
+
+        match parser.next() {
+            Ok(Record::LNAMES{ names }) => {
+                assert_eq!(names.len(), 2);
+                assert_eq!(names[0], "ABC");
+                assert_eq!(names[1], "DEF");
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        };
+    }
+
+    #[test]
+    fn test_lnames_decodes_cp437_by_default() {
+        let obj = vec![
+            0x96, 0x05, 0x00, 0x03,  0x41, 0x81, 0x42, 0x00];
+        let mut parser = Parser::new(&obj);
+
+        match parser.next() {
+            Ok(Record::LNAMES{ names }) => assert_eq!(names, vec!["AüB".to_string()]),
+            x => assert!(false, "parser returned {:x?}", x),
+        };
+    }
+
+    #[test]
+    fn test_lnames_strict_utf8_fails_on_high_bytes() {
+        let obj = vec![
+            0x96, 0x05, 0x00, 0x03,  0x41, 0x81, 0x42, 0x00];
+        let options = ParseOptions{ codepage: Codepage::Utf8, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+
+        assert!(parser.next().is_err());
+    }
+
+    //
+    // SEGDEF
+    //
+    #[test]
+    fn test_segdef_keeps_raw_combine() {
+        let obj = vec![
+            0x98, 0x0d, 0x00,
+            0b00110000, 0x00, 0x00, 0x01, 0x01, 0x01,
+            0b00111100, 0x00, 0x00, 0x01, 0x01, 0x01,
+            0x00];
+        let mut parser = Parser::new(&obj);
+
+        match parser.next() {
+            Ok(Record::SEGDEF{ segs }) => {
+                assert_eq!(segs[0].combine, Combine::Public{ raw: 4 });
+                assert_eq!(segs[1].combine, Combine::Public{ raw: 7 });
+                assert_eq!(segs[1].combine.raw(), 7);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_segdef_relocatable_succeeds() {
+        let obj = vec![
+            0x98, 0x0d, 0x00,
+            0b01001000, 0x34, 0x12, 0x01, 0x02, 0x03,
+            0b01100011, 0x00, 0x00, 0x05, 0x06, 0x00,
+            0x00];
+        let mut parser = Parser::new(&obj);
+
+        match parser.next() {
+            Ok(Record::SEGDEF{ segs }) => {
+                assert_eq!(segs.len(), 2);
+                assert_eq!(segs[0], Segdef{
+                    align: Align::Word,
+                    combine: Combine::Public{ raw: 2 },
+                    use32: false,
                     abs: None,
                     length: 0x1234,
-                    class: Some(1),
-                    name: Some(2),
+                    name: Some(1),
+                    class: Some(2),
                     overlay: Some(3),                
                 });
                 assert_eq!(segs[1], Segdef{
@@ -1355,8 +2886,8 @@ mod test {
                     use32: true,
                     abs: None,
                     length: 0x10000,
-                    class: Some(5),
-                    name: Some(6),
+                    name: Some(5),
+                    class: Some(6),
                     overlay: None,                
                 });
             },
@@ -1384,8 +2915,8 @@ mod test {
                         offset: 0x73,
                     }),
                     length: 0x1234,
-                    class: Some(1),
-                    name: Some(2),
+                    name: Some(1),
+                    class: Some(2),
                     overlay: Some(3),                
                 });
             },
@@ -1412,8 +2943,8 @@ mod test {
                     use32: false,
                     abs: None,
                     length: 0x12345678,
-                    class: Some(1),
-                    name: Some(2),
+                    name: Some(1),
+                    class: Some(2),
                     overlay: Some(3),                
                 });
                 assert_eq!(segs[1], Segdef{
@@ -1425,8 +2956,8 @@ mod test {
                         offset: 0x73,
                     }),
                     length: 0x12345678,
-                    class: Some(1),
-                    name: Some(2),
+                    name: Some(1),
+                    class: Some(2),
                     overlay: Some(3),                
                 });
                 assert_eq!(segs[2], Segdef{
@@ -1435,8 +2966,8 @@ mod test {
                     use32: false,
                     abs: None,
                     length: 0x1_0000_0000,
-                    class: Some(1),
-                    name: Some(2),
+                    name: Some(1),
+                    class: Some(2),
                     overlay: Some(3),                
                 });
             },
@@ -1457,12 +2988,70 @@ mod test {
         let mut parser = Parser::new(&obj);
 
         match parser.next() {
-            Ok(Record::GRPDEF{ name, segs }) => {
+            Ok(Record::GRPDEF{ name, components }) => {
                 assert_eq!(name, 0x0123);
-                assert_eq!(segs, vec![1, 2]);
+                assert_eq!(components, vec![
+                    GrpComponent::Segdef{ index: 1 },
+                    GrpComponent::Segdef{ index: 2 },
+                ]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_grpdef_legacy_components_warn() {
+        let obj = vec![
+            0x9a, 0x0c, 0x00,
+            0x01,
+            0xfe, 0x01,
+            0xfd, 0x01, 0x02, 0x03,
+            0xfa, 0x00, 0xf0, 0x10,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+
+        match parser.next() {
+            Ok(Record::GRPDEF{ name, components }) => {
+                assert_eq!(name, 1);
+                assert_eq!(components, vec![
+                    GrpComponent::Extdef{ index: 1 },
+                    GrpComponent::SegClassOverlay{ name: 1, class: 2, overlay: 3 },
+                    GrpComponent::Absolute{ frame: 0xf000, offset: 0x10 },
+                ]);
             },
             x => assert!(false, "parser returned {:x?}", x),
         }
+
+        let warnings = parser.diagnostics().iter().filter(|d| d.details.contains("obsolete")).count();
+        assert_eq!(warnings, 3);
+    }
+
+    #[test]
+    fn test_grpdef_legacy_components_fail_when_strict() {
+        let obj = vec![
+            0x9a, 0x04, 0x00,
+            0x01,
+            0xfe, 0x01,
+            0x00];
+
+        let options = ParseOptions{ strict: true, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+
+        assert!(parser.next().is_err());
+    }
+
+    #[test]
+    fn test_grpdef_unknown_component_fails() {
+        let obj = vec![
+            0x9a, 0x04, 0x00,
+            0x01,
+            0xf0, 0x01,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+
+        assert!(parser.next().is_err());
     }
 
     //
@@ -1671,7 +3260,7 @@ mod test {
         let mut parser = Parser::new(&obj);
 
         match parser.next() {
-            Ok(Record::MODEND{ main, start_address }) => {
+            Ok(Record::MODEND{ main, start_address, .. }) => {
                 assert_eq!(main, false);
                 assert_eq!(start_address, None);
             },
@@ -1679,6 +3268,29 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_modend_nonconforming_type_warns() {
+        let obj = vec![
+            0x8a, 0x02, 0x00, 0x80, 0x00];
+
+        let mut parser = Parser::new(&obj);
+
+        assert!(matches!(parser.next(), Ok(Record::MODEND{ main: true, .. })));
+        assert_eq!(parser.diagnostics().len(), 1);
+        assert!(parser.diagnostics()[0].details.contains("$80"));
+    }
+
+    #[test]
+    fn test_modend_nonconforming_type_fails_when_strict() {
+        let obj = vec![
+            0x8a, 0x02, 0x00, 0xa1, 0x00];
+
+        let options = ParseOptions{ strict: true, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+
+        assert!(parser.next().is_err());
+    }
+
     #[test]
     fn test_modend_with_main_succeeds() {
         let obj = vec![
@@ -1687,7 +3299,7 @@ mod test {
         let mut parser = Parser::new(&obj);
 
         match parser.next() {
-            Ok(Record::MODEND{ main, start_address }) => {
+            Ok(Record::MODEND{ main, start_address, .. }) => {
                 assert_eq!(main, true);
                 assert_eq!(start_address, None);
             },
@@ -1704,7 +3316,7 @@ mod test {
 
         let mut parser = Parser::new(&obj);
         match parser.next() {
-            Ok(Record::MODEND{ main, start_address }) => {
+            Ok(Record::MODEND{ main, start_address, .. }) => {
                 assert_eq!(main, true);
                 match start_address {
                     None => assert!(false, "modend missing start address"),
@@ -1729,7 +3341,7 @@ mod test {
 
         let mut parser = Parser::new(&obj);
         match parser.next() {
-            Ok(Record::MODEND{ main, start_address }) => {
+            Ok(Record::MODEND{ main, start_address, .. }) => {
                 assert_eq!(main, true);
                 match start_address {
                     None => assert!(false, "modend missing start address"),
@@ -1772,6 +3384,22 @@ mod test {
         }
     }
 
+    #[test]
+    pub fn test_coment_translator_uses_codepage() {
+        let obj = vec![
+            0x88, 0x05, 0x00,
+            0x00, 0x00,
+            0x41, 0x9b,
+            0x00];
+
+        let options = ParseOptions{ codepage: Codepage::Cp850, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+        match parser.next() {
+            Ok(Record::COMENT{ coment: Coment::Translator{ text }, .. }) => assert_eq!(text, "Aø"),
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
     #[test]
     pub fn test_coment_new_omf_succeeds() {
         let obj = vec![
@@ -1905,6 +3533,283 @@ mod test {
     }
 
 
+    #[test]
+    pub fn test_coment_impdef_by_name_succeeds() {
+        let obj = vec![
+            0x88, 0x14, 0x00,
+            0x00, 0xa0, 
+            0x01,           // IMPDEF
+            0x00,           // by name
+            0x05, 0x5f, 0x4f, 0x50, 0x45, 0x4e,
+            0x03, 0x44, 0x4f, 0x53,
+            0x04, 0x4f, 0x50, 0x45, 0x4e,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::COMENT{ coment: Coment::Impdef{ internal, module, entry }, .. }) => {
+                assert_eq!(internal, "_OPEN");
+                assert_eq!(module, "DOS");
+                assert_eq!(entry, ImportEntry::Name("OPEN".to_string()));
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_coment_impdef_by_ordinal_succeeds() {
+        let obj = vec![
+            0x88, 0x11, 0x00,
+            0x00, 0xa0, 
+            0x01,           // IMPDEF
+            0x01,           // by ordinal
+            0x05, 0x5f, 0x4f, 0x50, 0x45, 0x4e,
+            0x03, 0x44, 0x4f, 0x53,
+            0x46, 0x00,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::COMENT{ coment: Coment::Impdef{ entry, .. }, .. }) => {
+                assert_eq!(entry, ImportEntry::Ordinal(70));
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_coment_expdef_succeeds() {
+        let obj = vec![
+            0x88, 0x12, 0x00,
+            0x00, 0xa0, 
+            0x02,           // EXPDEF
+            0xc2,           // ordinal, resident, 2 parameter words
+            0x04, 0x4f, 0x50, 0x45, 0x4e,
+            0x05, 0x5f, 0x6f, 0x70, 0x65, 0x6e,
+            0x03, 0x00,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::COMENT{ coment: Coment::Expdef{ flags, exported, internal, ordinal }, .. }) => {
+                assert_eq!(flags, 0xc2);
+                assert_eq!(exported, "OPEN");
+                assert_eq!(internal, "_open");
+                assert_eq!(ordinal, Some(3));
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_coment_expdef_without_ordinal_succeeds() {
+        let obj = vec![
+            0x88, 0x0b, 0x00,
+            0x00, 0xa0, 
+            0x02,           // EXPDEF
+            0x00,
+            0x04, 0x4f, 0x50, 0x45, 0x4e,
+            0x00,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::COMENT{ coment: Coment::Expdef{ internal, ordinal, .. }, .. }) => {
+                assert_eq!(internal, "");
+                assert_eq!(ordinal, None);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_coment_incdef_succeeds() {
+        let obj = vec![
+            0x88, 0x08, 0x00,
+            0x00, 0xa0, 
+            0x03,           // INCDEF
+            0x02, 0x00,
+            0xff, 0xff,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::COMENT{ coment: Coment::Incdef{ extdef_delta, linnum_delta }, .. }) => {
+                assert_eq!(extdef_delta, 2);
+                assert_eq!(linnum_delta, -1);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_coment_lnkdir_succeeds() {
+        let obj = vec![
+            0x88, 0x07, 0x00,
+            0x00, 0xa0, 
+            0x05,           // LNKDIR
+            0x01, 0x02, 0x04,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::COMENT{ coment, .. }) => {
+                assert_eq!(coment, Coment::Lnkdir{ flags: 1, pcode_version: 2, cv_version: 4 });
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_coment_exestr_succeeds() {
+        let obj = vec![
+            0x88, 0x06, 0x00,
+            0x00, 0xa4, 
+            0x41, 0x42, 0x43,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::COMENT{ coment, .. }) => assert_eq!(coment, Coment::ExeStr{ text: "ABC".to_string() }),
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_coment_incerr_succeeds() {
+        let obj = vec![
+            0x88, 0x03, 0x00,
+            0x00, 0xa6, 
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        assert!(matches!(parser.next(), Ok(Record::COMENT{ coment: Coment::IncErr, .. })));
+    }
+
+    #[test]
+    pub fn test_coment_nopad_succeeds() {
+        let mut obj = seg_preamble(0x10);
+        obj.extend([
+            0x88, 0x04, 0x00,
+            0x00, 0xa7, 
+            0x01,
+            0x00]);
+
+        let mut parser = Parser::new(&obj);
+        skip_preamble(&mut parser);
+
+        match parser.next() {
+            Ok(Record::COMENT{ coment, .. }) => assert_eq!(coment, Coment::NoPad{ segs: vec![1] }),
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+        assert!(parser.diagnostics().is_empty());
+    }
+
+    #[test]
+    pub fn test_coment_dependency_succeeds() {
+        let obj = vec![
+            0x88, 0x0b, 0x00,
+            0x00, 0xe9, 
+            0x6a, 0x5c,     // 11:35:20
+            0x4f, 0x1a,     // 1993-02-15
+            0x03, 0x41, 0x2e, 0x48,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::COMENT{ coment: Coment::Dependency{ name, timestamp }, .. }) => {
+                assert_eq!(name, "A.H");
+                assert_eq!(format!("{}", timestamp), "1993-02-15 11:35:20");
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_coment_dependency_end_succeeds() {
+        let obj = vec![
+            0x88, 0x03, 0x00,
+            0x80, 0xe9, 
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        assert!(matches!(parser.next(), Ok(Record::COMENT{ coment: Coment::DependencyEnd, .. })));
+    }
+
+    #[test]
+    pub fn test_coment_lazy_extern_succeeds() {
+        let obj = vec![
+            0x88, 0x05, 0x00,
+            0x00, 0xa9, 
+            0x01, 0x02,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::COMENT{ coment: Coment::LazyExtern{ externs }, .. }) => {
+                assert_eq!(externs, vec![WeakExtern{ weak: 1, default: 2 }]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_coment_dos_version_succeeds() {
+        let obj = vec![
+            0x88, 0x05, 0x00,
+            0x00, 0x9c, 
+            0x03, 0x00,
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::COMENT{ coment, .. }) => assert_eq!(coment, Coment::DosVersion{ version: 3 }),
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    pub fn test_easy_omf_uses_32_bit_fields() {
+        let obj = vec![
+            0x88, 0x08, 0x00, 0x80, 0xaa, 0x38, 0x30, 0x33, 0x38, 0x36, 0x00,
+            0x96, 0x03, 0x00, 0x01, 0x41, 0x00,
+            // SEGDEF with a 32-bit length and an access byte (USE32, RW)
+            0x98, 0x0a, 0x00, 0x48, 0x00, 0x00, 0x01, 0x00, 0x01, 0x01, 0x01, 0x07, 0x00,
+            // LEDATA with a 32-bit offset
+            0xa0, 0x07, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0x90, 0x00,
+            // FIXUPP, location 5 is a 32-bit offset
+            0x9c, 0x05, 0x00, 0xd4, 0x00, 0x54, 0x01, 0x00,
+        ];
+
+        let mut parser = Parser::new(&obj);
+        assert!(matches!(parser.next(), Ok(Record::COMENT{ coment: Coment::EasyOmf{ .. }, .. })));
+        assert!(matches!(parser.next(), Ok(Record::LNAMES{ .. })));
+
+        match parser.next() {
+            Ok(Record::SEGDEF{ segs }) => {
+                assert_eq!(segs[0].length, 0x10000);
+                assert!(segs[0].use32);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+
+        match parser.next() {
+            Ok(Record::LEDATA{ offset, data, .. }) => {
+                assert_eq!(offset, 0x10000);
+                assert_eq!(data, vec![0x90]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+
+        match parser.next() {
+            Ok(Record::FIXUPP{ fixups }) => match &fixups[0] {
+                FixupSubrecord::Fixup{ fixup } => assert_eq!(fixup.location, FixupLocation::Offset32),
+                x => assert!(false, "fixup was {:x?}", x),
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
     #[test]
     pub fn test_coment_weak_extern_succeeds() {
         let obj = vec![
@@ -1963,35 +3868,204 @@ mod test {
         ];
 
         let mut parser = Parser::new(&obj);
-        match parser.next() {
-            Ok(Record::LEDATA{ seg, offset, data }) => {
-                assert_eq!(seg, 1);
-                assert_eq!(offset, 0x1234);
-                assert_eq!(data, vec![0x02, 0x78, 0x56, 0x34, 0x12]);
-            },
-            x => assert!(false, "parser returned {:x?}", x),
-        }
+        match parser.next() {
+            Ok(Record::LEDATA{ seg, offset, data }) => {
+                assert_eq!(seg, 1);
+                assert_eq!(offset, 0x1234);
+                assert_eq!(data, vec![0x02, 0x78, 0x56, 0x34, 0x12]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_ledata32_succeeds() {
+        let obj = vec![
+            0xa1, 0x0b, 0x00, 
+            0x01, 
+            0x78, 0x56, 0x34, 0x12, 
+            0x02, 0x78, 0x56, 0x34, 0x12, 
+            0x00
+        ];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::LEDATA{ seg, offset, data }) => {
+                assert_eq!(seg, 1);
+                assert_eq!(offset, 0x12345678);
+                assert_eq!(data, vec![0x02, 0x78, 0x56, 0x34, 0x12]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    // LNAMES and a single SEGDEF of the given length, for tests which need
+    // a segment to refer to
+    //
+    fn seg_preamble(length: u16) -> Vec<u8> {
+        vec![
+            0x96, 0x03, 0x00, 0x01, 0x41, 0x00,
+            0x98, 0x07, 0x00, 0x48, length as u8, (length >> 8) as u8, 0x01, 0x01, 0x01, 0x00,
+        ]
+    }
+
+    fn skip_preamble(parser: &mut Parser) {
+        assert!(matches!(parser.next(), Ok(Record::LNAMES{ .. })));
+        assert!(matches!(parser.next(), Ok(Record::SEGDEF{ .. })));
+    }
+
+    #[test]
+    fn test_oversized_ledata_warns() {
+        let mut obj = seg_preamble(0x1000);
+        obj.extend(vec![0xa0, 0x05, 0x04, 0x01, 0x00, 0x00]);
+        obj.extend(vec![0x90; 1025]);
+        obj.push(0x00);
+
+        let mut parser = Parser::new(&obj);
+        skip_preamble(&mut parser);
+        assert!(matches!(parser.next(), Ok(Record::LEDATA{ .. })));
+        assert_eq!(parser.diagnostics().len(), 1);
+
+        let options = ParseOptions{ strict: true, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+        skip_preamble(&mut parser);
+        assert!(parser.next().is_err());
+    }
+
+    #[test]
+    fn test_ledata_past_end_of_segment_warns() {
+        let mut obj = seg_preamble(4);
+        obj.extend(vec![
+            0xa0, 0x08, 0x00, 0x01, 0x02, 0x00, 0x90, 0x90, 0x90, 0x90, 0x00,
+        ]);
+
+        let mut parser = Parser::new(&obj);
+        skip_preamble(&mut parser);
+        assert!(matches!(parser.next(), Ok(Record::LEDATA{ .. })));
+        assert_eq!(parser.diagnostics(), &[
+            Diagnostic::with_offset("data at 00000002..00000006 is past the end of segment 1 (length 00000004)", 16),
+        ]);
+    }
+
+    #[test]
+    fn test_fixup_without_data_record_warns() {
+        let mut obj = seg_preamble(4);
+        obj.extend(vec![
+            0x9c, 0x08, 0x00, 0xc4, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00,
+        ]);
+
+        let mut parser = Parser::new(&obj);
+        skip_preamble(&mut parser);
+        assert!(matches!(parser.next(), Ok(Record::FIXUPP{ .. })));
+        assert_eq!(parser.diagnostics(), &[
+            Diagnostic::with_offset("fixup at 0000 does not follow a data record", 16),
+        ]);
+    }
+
+    #[test]
+    fn test_fixup_threads_need_no_data_record() {
+        let mut obj = seg_preamble(4);
+        obj.extend(vec![
+            0x9c, 0x03, 0x00, 0x00, 0x01, 0x00,
+        ]);
+
+        let mut parser = Parser::new(&obj);
+        skip_preamble(&mut parser);
+        assert!(matches!(parser.next(), Ok(Record::FIXUPP{ .. })));
+        assert!(parser.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_fixup_past_end_of_data_record_warns() {
+        let mut obj = seg_preamble(4);
+        obj.extend(vec![
+            0xa0, 0x06, 0x00, 0x01, 0x00, 0x00, 0x90, 0x90, 0x00,
+            0x9c, 0x08, 0x00, 0xc4, 0x01, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00,
+        ]);
+
+        let mut parser = Parser::new(&obj);
+        skip_preamble(&mut parser);
+        assert!(matches!(parser.next(), Ok(Record::LEDATA{ .. })));
+        assert!(matches!(parser.next(), Ok(Record::FIXUPP{ .. })));
+        assert_eq!(parser.diagnostics(), &[
+            Diagnostic::with_offset("fixup at 0001..0003 is past the end of the data record (length 0002)", 25),
+        ]);
+    }
+
+    //
+    // Index checking
+    //
+    #[test]
+    fn test_undeclared_segment_index_warns() {
+        let mut obj = seg_preamble(4);
+        obj.extend(vec![
+            0xa0, 0x06, 0x00, 0x02, 0x00, 0x00, 0x90, 0x90, 0x00,
+        ]);
+
+        let mut parser = Parser::new(&obj);
+        skip_preamble(&mut parser);
+        assert!(matches!(parser.next(), Ok(Record::LEDATA{ .. })));
+        assert_eq!(parser.diagnostics(), &[
+            Diagnostic::with_offset("SEGDEF index 2 is out of range (1 defined)", 16),
+        ]);
+    }
+
+    #[test]
+    fn test_resumed_parser_keeps_definitions() {
+        let mut obj = seg_preamble(4);
+        obj.extend(vec![
+            0xa0, 0x06, 0x00, 0x02, 0x00, 0x00, 0x90, 0x90, 0x00,
+        ]);
+
+        let mut parser = Parser::new(&obj);
+        skip_preamble(&mut parser);
+        let state = parser.state();
+
+        // it goes on from the LEDATA, still knowing of the one SEGDEF
+        //
+        let mut parser = Parser::resume(&obj, ParseOptions::default(), state);
+        assert!(matches!(parser.next(), Ok(Record::LEDATA{ .. })));
+        assert!(matches!(parser.next(), Ok(Record::None)));
+        assert_eq!(parser.diagnostics(), &[
+            Diagnostic::with_offset("SEGDEF index 2 is out of range (1 defined)", 16),
+        ]);
     }
 
     #[test]
-    fn test_ledata32_succeeds() {
+    fn test_undeclared_lname_fails_when_strict() {
         let obj = vec![
-            0xa1, 0x0b, 0x00, 
-            0x01, 
-            0x78, 0x56, 0x34, 0x12, 
-            0x02, 0x78, 0x56, 0x34, 0x12, 
-            0x00
+            0x96, 0x03, 0x00, 0x01, 0x41, 0x00,
+            0x9a, 0x02, 0x00, 0x02, 0x00,
         ];
 
+        let options = ParseOptions{ strict: true, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+        assert!(parser.next().is_ok());
+        assert!(parser.next().is_err());
+    }
+
+    #[test]
+    fn test_fixup_extern_index_counts_all_extern_records() {
+        let mut obj = seg_preamble(4);
+        obj.extend(vec![
+            0x8c, 0x04, 0x00, 0x01, 0x42, 0x00, 0x00,
+            0xb0, 0x06, 0x00, 0x01, 0x43, 0x00, 0x62, 0x02, 0x00,
+            0xa0, 0x06, 0x00, 0x01, 0x00, 0x00, 0x90, 0x90, 0x00,
+            0x9c, 0x05, 0x00, 0xc4, 0x00, 0x56, 0x02, 0x00,
+            0xa0, 0x06, 0x00, 0x01, 0x00, 0x00, 0x90, 0x90, 0x00,
+            0x9c, 0x05, 0x00, 0xc4, 0x00, 0x56, 0x03, 0x00,
+        ]);
+
         let mut parser = Parser::new(&obj);
-        match parser.next() {
-            Ok(Record::LEDATA{ seg, offset, data }) => {
-                assert_eq!(seg, 1);
-                assert_eq!(offset, 0x12345678);
-                assert_eq!(data, vec![0x02, 0x78, 0x56, 0x34, 0x12]);
-            },
-            x => assert!(false, "parser returned {:x?}", x),
+        skip_preamble(&mut parser);
+        for _ in 0..4 {
+            assert!(parser.next().is_ok());
         }
+        assert!(parser.diagnostics().is_empty());
+
+        assert!(parser.next().is_ok());
+        assert!(parser.next().is_ok());
+        assert_eq!(parser.diagnostics().len(), 1);
     }
 
     //
@@ -2045,6 +4119,54 @@ mod test {
         }
     }
 
+    //
+    // NBKPAT
+    //
+    #[test]
+    fn test_nbkpat_succeeds() {
+        let obj = vec![
+            0x96, 0x03, 0x00, 0x01, 0x41, 0x00,
+            0xc8, 0x07, 0x00, 
+            0x01,           // location
+            0x01,           // name
+            0x02, 0x00, 0x34, 0x12,
+            0x00
+        ];
+
+        let mut parser = Parser::new(&obj);
+        assert!(matches!(parser.next(), Ok(Record::LNAMES{ .. })));
+
+        match parser.next() {
+            Ok(Record::NBKPAT{ name, location, fixups }) => {
+                assert_eq!(name, 1);
+                assert_eq!(location, BakpatLocation::Word);
+                assert_eq!(fixups, vec![BakpatFixup{ offset: 0x0002, value: 0x1234 }]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+        assert!(parser.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_nbkpat32_succeeds() {
+        let obj = vec![
+            0xc9, 0x0b, 0x00, 
+            0x02,           // location
+            0x01,           // name
+            0x02, 0x00, 0x01, 0x00, 0x34, 0x12, 0x55, 0xaa,
+            0x00
+        ];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::NBKPAT{ location, fixups, .. }) => {
+                assert_eq!(location, BakpatLocation::Dword);
+                assert_eq!(fixups, vec![BakpatFixup{ offset: 0x00010002, value: 0xaa551234 }]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
     //
     // FIXUPP
     //
@@ -2052,7 +4174,7 @@ mod test {
     fn test_fixup_frame_thread_succeeds() {
         let obj = vec![
             0x9c, 0x03, 0x00, 
-            0b010_001_01,
+            0x45,           // 010 001 01
             0x07,
             0x00
         ];
@@ -2076,7 +4198,7 @@ mod test {
     fn test_fixup_frame_thread_no_datum_succeeds() {
         let obj = vec![
             0x9c, 0x02, 0x00, 
-            0b010_101_01,
+            0x55,           // 010 101 01
             0x00
         ];
 
@@ -2099,7 +4221,7 @@ mod test {
     fn test_fixup_target_thread_succeeds() {
         let obj = vec![
             0x9c, 0x03, 0x00, 
-            0b000_010_10,
+            0x0a,           // 000 010 10
             0x06,
             0x00
         ];
@@ -2123,8 +4245,8 @@ mod test {
     fn test_fixup_succeeds() {
         let obj = vec![
             0x9c, 0x08, 0x00, 
-            0b1_1_0001_00, 0x67,
-            0b0_001_0_000,
+            0xc4, 0x67,     // 1 1 0001 00
+            0x10,           // 0 001 0 000
             0x01,
             0x02,
             0x34, 0x12,
@@ -2159,8 +4281,8 @@ mod test {
     fn test_fixup_using_thread_succeeds() {
         let obj = vec![
             0x9c, 0x06, 0x00, 
-            0b1_1_0001_00, 0x67,
-            0b1_001_1_010,
+            0xc4, 0x67,     // 1 1 0001 00
+            0x9a,           // 1 001 1 010
             0x34, 0x12,
             0x00
         ];
@@ -2194,8 +4316,8 @@ mod test {
     fn test_fixup_no_displacement_succeeds() {
         let obj = vec![
             0x9c, 0x04, 0x00, 
-            0b1_1_0001_00, 0x67,
-            0b1_001_1_110,
+            0xc4, 0x67,     // 1 1 0001 00
+            0x9e,           // 1 001 1 110
             0x00
         ];
 
@@ -2223,12 +4345,120 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_masm_fixupp_succeeds() {
+        // as MASM 5.1 writes the fixups for
+        //
+        //   mov ax, DGROUP
+        //   call far ptr _exit
+        //   call _puts
+        //   mov bx, offset _msg
+        //
+        let obj = vec![
+            0x9c, 0x13, 0x00,
+            0xc8, 0x01,     // segment-relative selector at 0x001
+            0x15,           // frame GRPDEF, target GRPDEF, no displacement
+            0x01, 0x01,     // frame and target datums
+            0xcc, 0x04,     // segment-relative far pointer at 0x004
+            0x56,           // frame target, target EXTDEF, no displacement
+            0x01,           // target datum
+            0x84, 0x0a,     // self-relative offset at 0x00a
+            0x56,           // frame target, target EXTDEF, no displacement
+            0x02,           // target datum
+            0xc4, 0x0d,     // segment-relative offset at 0x00d
+            0x14,           // frame GRPDEF, target SEGDEF, no displacement
+            0x01, 0x02,     // frame and target datums
+            0x00
+        ];
+
+        let fixup = |is_seg_relative, location, data_offset, frame_method, frame_datum, target_method, target_datum| {
+            FixupSubrecord::Fixup{
+                fixup: Fixup {
+                    is_seg_relative,
+                    location,
+                    data_offset,
+                    frame_thread: None,
+                    frame_method: Some(frame_method),
+                    frame_datum,
+                    target_thread: None,
+                    target_method: Some(target_method),
+                    target_datum: Some(target_datum),
+                    target_displacement: 0,
+                }
+            }
+        };
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::FIXUPP{ fixups }) => {
+                assert_eq!(fixups, vec![
+                    fixup(true, FixupLocation::Selector, 0x001, FrameMethod::Grpdef, Some(1), TargetMethod::GrpdefNoDisplacement, 1),
+                    fixup(true, FixupLocation::LongPointer, 0x004, FrameMethod::Target, None, TargetMethod::ExtdefNoDisplacement, 1),
+                    fixup(false, FixupLocation::Word, 0x00a, FrameMethod::Target, None, TargetMethod::ExtdefNoDisplacement, 2),
+                    fixup(true, FixupLocation::Word, 0x00d, FrameMethod::Grpdef, Some(1), TargetMethod::SegdefNoDisplacement, 2),
+                ]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_self_relative_fixup_has_fix_data() {
+        let obj = vec![
+            0x9c, 0x0a, 0x00,
+            0x84, 0x07,     // self-relative word at 0x007
+            0x56,           // frame target, extern target, no displacement
+            0x02,           // target datum
+            0x90, 0x10,     // self-relative high byte at 0x010
+            0x89,           // frame thread 0, target thread 1
+            0x34, 0x12,     // displacement
+            0x00
+        ];
+
+        let mut parser = Parser::new(&obj);
+        match parser.next() {
+            Ok(Record::FIXUPP{ fixups }) => {
+                assert_eq!(fixups, vec![
+                    FixupSubrecord::Fixup{
+                        fixup: Fixup {
+                            is_seg_relative: false,
+                            location: FixupLocation::Word,
+                            data_offset: 0x0007,
+                            frame_thread: None,
+                            frame_method: Some(FrameMethod::Target),
+                            frame_datum: None,
+                            target_thread: None,
+                            target_method: Some(TargetMethod::ExtdefNoDisplacement),
+                            target_datum: Some(2),
+                            target_displacement: 0,
+                        }
+                    },
+                    FixupSubrecord::Fixup{
+                        fixup: Fixup {
+                            is_seg_relative: false,
+                            location: FixupLocation::HighOrderByte,
+                            data_offset: 0x0010,
+                            frame_thread: Some(0),
+                            frame_method: None,
+                            frame_datum: None,
+                            target_thread: Some(1),
+                            target_method: None,
+                            target_datum: None,
+                            target_displacement: 0x1234,
+                        }
+                    },
+                ]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
     #[test]
     fn test_32_bit_fixup_succeeds() {
         let obj = vec![
             0x9d, 0x0a, 0x00, 
-            0b1_1_0001_00, 0x67,
-            0b0_001_0_000,
+            0xc4, 0x67,     // 1 1 0001 00
+            0x10,           // 0 001 0 000
             0x01,
             0x02,
             0x78, 0x56, 0x34, 0x12,
@@ -2418,6 +4648,7 @@ mod test {
                         base_frame: None,
                         name: 3,
                         data: vec![0x55, 0x66],
+                        blocks: vec![],
                     }
                 );
             },
@@ -2458,6 +4689,7 @@ mod test {
                         base_frame: None,
                         name: 3,
                         data: vec![0x55, 0x66],
+                        blocks: vec![],
                     }
                 );
             },
@@ -2499,6 +4731,7 @@ mod test {
                         base_frame: Some(0xf000),
                         name: 3,
                         data: vec![0x55, 0x66],
+                        blocks: vec![],
                     }
                 );
             },
@@ -2539,6 +4772,7 @@ mod test {
                         base_frame: None,
                         name: 3,
                         data: vec![0x55, 0x66],
+                        blocks: vec![],
                     }
                 );
             },
@@ -2579,12 +4813,15 @@ mod test {
                         base_seg: Some(2),
                         base_frame: None,
                         name: 3,
-                        data: vec![
-                            0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
-                            0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
-                        ],
+                        data: vec![],
+                        blocks: lidata_test_blocks(),
                     }
                 );
+                assert_eq!(comdat.expand(0x10000).unwrap(),
+                    vec![
+                        0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
+                        0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
+                    ]);
             },
             x => assert!(false, "parser returned {:x?}", x),
         }
@@ -2630,20 +4867,113 @@ mod test {
                         base_seg: Some(2),
                         base_frame: None,
                         name: 3,
-                        data: vec![
-                            0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
-                            0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
-                        ],
+                        data: vec![],
+                        blocks: lidata_test_blocks(),
                     }
                 );
+                assert_eq!(comdat.expand(0x10000).unwrap(),
+                    vec![
+                        0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
+                        0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
+                    ]);
             },
             x => assert!(false, "parser returned {:x?}", x),
         }
     }
 
+    #[test]
+    fn test_misplaced_comdat_continuation_warns() {
+        let mut obj = seg_preamble(4);
+        obj.extend(vec![
+            0xc2, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0xaa, 0xbb, 0x00,
+            0x8a, 0x02, 0x00, 0x01, 0x00,
+            0xc2, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x01, 0xcc, 0xdd, 0x00,
+        ]);
+
+        let mut parser = Parser::new(&obj);
+        skip_preamble(&mut parser);
+        assert!(parser.next().is_ok());
+        assert!(parser.next().is_ok());
+        assert!(parser.diagnostics().is_empty());
+        assert!(parser.next().is_ok());
+        assert_eq!(parser.diagnostics().len(), 1);
+    }
+
+    #[test]
+    fn test_comdat_merge_on_parse() {
+        let mut obj = seg_preamble(4);
+        obj.extend(vec![
+            0xc2, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0xaa, 0xbb, 0x00,
+            0x9c, 0x05, 0x00, 0xc4, 0x00, 0x54, 0x01, 0x00,
+            0xc2, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x01, 0xcc, 0xdd, 0x00,
+            0x9c, 0x05, 0x00, 0xc4, 0x00, 0x54, 0x01, 0x00,
+            0x8a, 0x02, 0x00, 0x01, 0x00,
+        ]);
+
+        let options = ParseOptions{ merge_comdats: true, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+        skip_preamble(&mut parser);
+
+        match parser.next() {
+            Ok(Record::COMDAT{ comdat }) => {
+                assert_eq!(comdat.offset, 0);
+                assert_eq!(comdat.data, vec![0xaa, 0xbb, 0xcc, 0xdd]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+
+        match parser.next() {
+            Ok(Record::FIXUPP{ fixups }) => {
+                let offsets: Vec<Option<usize>> = fixups.iter().map(|sub| match sub {
+                    FixupSubrecord::Fixup{ fixup } => Some(fixup.data_offset),
+                    _ => None,
+                }).collect();
+                assert_eq!(offsets, vec![Some(0), Some(2)]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+
+        assert!(matches!(parser.next(), Ok(Record::MODEND{ .. })));
+        assert!(matches!(parser.next(), Ok(Record::None)));
+        assert!(parser.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_merged_comdat_spans_its_records() {
+        let mut obj = seg_preamble(4);
+        obj.extend(vec![
+            0xc2, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0xaa, 0xbb, 0x00,
+            0x9c, 0x05, 0x00, 0xc4, 0x00, 0x54, 0x01, 0x00,
+            0xc2, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x01, 0xcc, 0xdd, 0x00,
+            0x9c, 0x05, 0x00, 0xc4, 0x00, 0x54, 0x01, 0x00,
+        ]);
+
+        let options = ParseOptions{ merge_comdats: true, ..Default::default() };
+        let mut parser = Parser::with_options(&obj, options);
+        skip_preamble(&mut parser);
+
+        let comdat = parser.next_parsed().unwrap();
+        assert_eq!(comdat.offset, 16);
+        assert_eq!(comdat.raw.len(), 46);
+
+        let fixupp = parser.next_parsed().unwrap();
+        assert_eq!(fixupp.offset, 31);
+        assert_eq!(fixupp.rectype, 0x9c);
+        assert_eq!(fixupp.raw.len(), 8);
+    }
+
     //
     // LIDATA
     //
+    fn lidata_test_blocks() -> Vec<IteratedBlock> {
+        vec![
+            IteratedBlock{ repeat: 2, content: IteratedContent::Blocks(vec![
+                IteratedBlock{ repeat: 3, content: IteratedContent::Data(vec![0x40, 0x41]) },
+                IteratedBlock{ repeat: 2, content: IteratedContent::Data(vec![0x50, 0x51]) },
+            ])},
+        ]
+    }
+
     #[test]
     fn test_lidata_succeeds() {
         let obj = vec![
@@ -2657,14 +4987,11 @@ mod test {
         let mut parser = Parser::new(&obj);
 
         match parser.next() {
-            Ok(Record::LIDATA{ seg, offset, data }) => {
+            Ok(Record::LIDATA{ seg, offset, blocks }) => {
                 assert_eq!(seg, 1);
                 assert_eq!(offset, 0x1234);
-                assert_eq!(data,
-                    vec![
-                        0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
-                        0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
-                    ]);
+                assert_eq!(blocks, lidata_test_blocks());
+                assert_eq!(blocks[0].expanded_len(), 20);
             },
             x => assert!(false, "parser returned {:x?}", x),
         }
@@ -2689,17 +5016,211 @@ mod test {
         let mut parser = Parser::new(&obj);
 
         match parser.next() {
-            Ok(Record::LIDATA{ seg, offset, data }) => {
+            Ok(Record::LIDATA{ seg, offset, blocks }) => {
                 assert_eq!(seg, 1);
                 assert_eq!(offset, 0x12345678);
-                assert_eq!(data,
-                    vec![
-                        0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
-                        0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
-                    ]);
+                assert_eq!(blocks, lidata_test_blocks());
+                assert_eq!(blocks[0].expanded_len(), 20);
             },
             x => assert!(false, "parser returned {:x?}", x),
         }
     }
-}
 
+    #[test]
+    fn test_lidata_multiple_blocks_succeeds() {
+        let obj = vec![
+            0xa2, 0x12, 0x00,
+            0x01,           // segment
+            0x00, 0x00,     // offset
+            0x04, 0x00,     // repeat 4
+            0x00, 0x00,     // block 0
+            0x01, 0x90,     // -data-
+            0x02, 0x00,     // repeat 2
+            0x00, 0x00,     // block 0
+            0x03, 0x01, 0x02, 0x03, // -data-
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+
+        match parser.next() {
+            Ok(Record::LIDATA{ blocks, .. }) => {
+                assert_eq!(blocks, vec![
+                    IteratedBlock{ repeat: 4, content: IteratedContent::Data(vec![0x90]) },
+                    IteratedBlock{ repeat: 2, content: IteratedContent::Data(vec![0x01, 0x02, 0x03]) },
+                ]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_lidata_truncated_block_fails() {
+        let obj = vec![
+            0xa2, 0x0a, 0x00,
+            0x01,           // segment
+            0x00, 0x00,     // offset
+            0x01, 0x00,     // repeat 1
+            0x00, 0x00,     // block 0
+            0x04, 0x90,     // -data- claims 4 bytes
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+
+        assert!(parser.next().is_err());
+    }
+
+    #[test]
+    fn test_lidata_nested_too_deeply_fails() {
+        let nested = |depth: usize| {
+            let mut body = vec![0x01, 0x00, 0x00];
+            for _ in 0..depth {
+                body.extend([0x01, 0x00, 0x01, 0x00]);
+            }
+            body.extend([0x01, 0x00, 0x00, 0x00, 0x01, 0x90]);
+
+            let mut obj = vec![0xa2, (body.len() + 1) as u8, 0x00];
+            obj.extend(body);
+            obj.push(0x00);
+            obj
+        };
+
+        let obj = nested(16);
+        assert!(matches!(Parser::new(&obj).next(), Ok(Record::LIDATA{ .. })));
+
+        let obj = nested(17);
+        assert_eq!(Parser::new(&obj).next().unwrap_err().details, "iterated data is nested too deeply");
+    }
+
+    #[test]
+    fn test_expand_iterated_succeeds() {
+        let data = expand_iterated(&lidata_test_blocks(), 0x10000).unwrap();
+        assert_eq!(data,
+            vec![
+                0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
+                0x40, 0x41, 0x40, 0x41, 0x40, 0x41, 0x50, 0x51, 0x50, 0x51,   
+            ]);
+    }
+
+    #[test]
+    fn test_expand_iterated_enforces_limit() {
+        assert!(expand_iterated(&lidata_test_blocks(), 20).is_ok());
+        assert!(expand_iterated(&lidata_test_blocks(), 19).is_err());
+
+        let bomb = IteratedBlock{ repeat: 0xffff, content: IteratedContent::Blocks(vec![
+            IteratedBlock{ repeat: 0xffff, content: IteratedContent::Data(vec![0; 0xff]) },
+        ])};
+        assert!(expand_iterated(&[bomb], 0x100000).is_err());
+    }
+
+    #[test]
+    fn test_expand_iterated_skips_empty_blocks() {
+        let empty = IteratedBlock{ repeat: 0xffffffff, content: IteratedContent::Blocks(vec![
+            IteratedBlock{ repeat: 0xffffffff, content: IteratedContent::Blocks(vec![
+                IteratedBlock{ repeat: 0xffffffff, content: IteratedContent::Data(vec![]) },
+            ])},
+        ])};
+        let blocks = vec![
+            IteratedBlock{ repeat: 2, content: IteratedContent::Blocks(vec![
+                empty,
+                IteratedBlock{ repeat: 1, content: IteratedContent::Data(vec![0x90]) },
+            ])},
+        ];
+
+        assert_eq!(expand_iterated(&blocks, 0x10000).unwrap(), vec![0x90, 0x90]);
+    }
+
+    #[test]
+    fn test_iterated_block_length_saturates() {
+        let block = IteratedBlock{ repeat: usize::MAX, content: IteratedContent::Blocks(vec![
+            IteratedBlock{ repeat: usize::MAX, content: IteratedContent::Data(vec![0; 4]) },
+        ])};
+
+        assert_eq!(block.expanded_len(), u64::MAX);
+    }
+
+    //
+    // LINNUM
+    //
+    #[test]
+    fn test_linnum_succeeds() {
+        let obj = vec![
+            0x96, 0x08, 0x00, 0x00, 0x05, 0x5f, 0x54, 0x45, 0x58, 0x54, 0x00,
+            0x98, 0x07, 0x00, 0x28, 0x10, 0x00, 0x02, 0x01, 0x01, 0x00,
+            0x94, 0x0b, 0x00,
+            0x00,           // group
+            0x01,           // segment
+            0x0a, 0x00, 0x00, 0x00,   // line 10 @ 0000
+            0x0c, 0x00, 0x07, 0x00,   // line 12 @ 0007
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        assert!(matches!(parser.next(), Ok(Record::LNAMES{ .. })));
+        assert!(matches!(parser.next(), Ok(Record::SEGDEF{ .. })));
+
+        match parser.next() {
+            Ok(Record::LINNUM{ group, seg, lines }) => {
+                assert_eq!(group, None);
+                assert_eq!(seg, 1);
+                assert_eq!(lines, vec![
+                    LineNumber{ line: 10, offset: 0 },
+                    LineNumber{ line: 12, offset: 7 },
+                ]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+        assert!(parser.diagnostics().is_empty());
+    }
+
+    //
+    // LINSYM
+    //
+    #[test]
+    fn test_linsym_succeeds() {
+        let obj = vec![
+            0x96, 0x03, 0x00, 0x01, 0x41, 0x00,
+            0xc4, 0x0b, 0x00,
+            0x01,           // flags
+            0x01,           // name
+            0x0a, 0x00, 0x00, 0x00,   // line 10 @ 0000
+            0x0b, 0x00, 0x05, 0x00,   // line 11 @ 0005
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+        assert!(matches!(parser.next(), Ok(Record::LNAMES{ .. })));
+
+        match parser.next() {
+            Ok(Record::LINSYM{ flags, name, lines }) => {
+                assert_eq!(flags, 1);
+                assert_eq!(name, 1);
+                assert_eq!(lines, vec![
+                    LineNumber{ line: 10, offset: 0 },
+                    LineNumber{ line: 11, offset: 5 },
+                ]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+        assert!(parser.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_linsym_32_succeeds() {
+        let obj = vec![
+            0xc5, 0x09, 0x00,
+            0x00,           // flags
+            0x01,           // name
+            0x0a, 0x00, 0x78, 0x56, 0x34, 0x12, 
+            0x00];
+
+        let mut parser = Parser::new(&obj);
+
+        match parser.next() {
+            Ok(Record::LINSYM{ lines, .. }) => {
+                assert_eq!(lines, vec![LineNumber{ line: 10, offset: 0x12345678 }]);
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+
+        // no LNAMES were declared
+        assert_eq!(parser.diagnostics().len(), 1);
+    }
+}
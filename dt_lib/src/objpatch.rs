@@ -0,0 +1,271 @@
+use crate::codepage::Codepage;
+use crate::error::Error as ObjError;
+use crate::objfile::*;
+use crate::objwriter::*;
+
+// One record of an object being patched. Records the parser doesn't
+// understand keep their original body, which is written back as is.
+//
+#[derive(Debug)]
+pub struct PatchRecord {
+    pub rectype: u8,
+    pub record: Record,
+    raw: Option<Vec<u8>>,
+}
+
+impl PatchRecord {
+    pub fn new(rectype: u8, record: Record) -> PatchRecord {
+        PatchRecord{ rectype, record, raw: None }
+    }
+}
+
+// An object loaded as a list of records which may be edited in place and
+// then written back out. Lengths and checksums are recomputed on write,
+// so edits may change the size of a record freely.
+//
+#[derive(Debug)]
+pub struct ObjPatcher {
+    pub records: Vec<PatchRecord>,
+    pub codepage: Codepage,
+}
+
+impl ObjPatcher {
+    // Load every record in `obj`. COMDATs are never merged here, since a
+    // merged COMDAT can't be written back as the records it came from.
+    //
+    pub fn load(obj: &[u8], options: &ParseOptions) -> Result<ObjPatcher, ObjError> {
+        let options = ParseOptions{ merge_comdats: false, ..options.clone() };
+        let codepage = options.codepage;
+        let mut parser = Parser::with_options(obj, options);
+        let mut records = Vec::new();
+
+        loop {
+            let parsed = parser.next_parsed()?;
+
+            let raw = match &parsed.record {
+                Record::None => break,
+                Record::Unknown{ .. } | Record::COMENT{ coment: Coment::Unknown, .. } => Some(parsed.raw.to_vec()),
+                _ => None,
+            };
+
+            records.push(PatchRecord{ rectype: parsed.rectype, record: parsed.record, raw });
+        }
+
+        Ok(ObjPatcher{ records, codepage })
+    }
+
+    // All the names defined by LNAMES, in index order (the first is 1)
+    //
+    pub fn lnames(&self) -> Vec<&str> {
+        self.records.iter()
+            .filter_map(|rec| match &rec.record {
+                Record::LNAMES{ names } => Some(names),
+                _ => None,
+            })
+            .flatten()
+            .map(|name| name.as_str())
+            .collect()
+    }
+
+    // The 1-based LNAMES index of `name`, adding it to the last LNAMES
+    // record if it isn't already defined. Appending never moves an
+    // existing name; it fails if there's no LNAMES record to add to, or if
+    // the last one comes after `before` (the position of the record which
+    // will use the name).
+    //
+    pub fn lname(&mut self, name: &str, before: usize) -> Result<usize, ObjError> {
+        if let Some(index) = self.lnames().iter().position(|lname| *lname == name) {
+            return Ok(index + 1);
+        }
+
+        let count = self.lnames().len();
+        let last = self.records.iter_mut()
+            .enumerate()
+            .rev()
+            .find(|(_, rec)| matches!(rec.record, Record::LNAMES{ .. }));
+
+        match last {
+            Some((at, PatchRecord{ record: Record::LNAMES{ names }, .. })) if at < before => {
+                names.push(name.to_string());
+                Ok(count + 1)
+            },
+            _ => Err(ObjError::new(&format!("no LNAMES record precedes record {} to add {} to", before, name))),
+        }
+    }
+
+    // Rename a public symbol, returning how many definitions were changed
+    //
+    pub fn rename_public(&mut self, old: &str, new: &str) -> usize {
+        let mut count = 0;
+
+        for rec in self.records.iter_mut() {
+            if let Record::PUBDEF{ publics, .. } | Record::LPUBDEF{ publics, .. } = &mut rec.record {
+                for public in publics.iter_mut().filter(|public| public.name == old) {
+                    public.name = new.to_string();
+                    count += 1;
+                }
+            }
+        }
+
+        count
+    }
+
+    // Rename an external reference, returning how many were changed
+    //
+    pub fn rename_extern(&mut self, old: &str, new: &str) -> usize {
+        let mut count = 0;
+
+        for rec in self.records.iter_mut() {
+            if let Record::EXTDEF{ externs } | Record::LEXTDEF{ externs } = &mut rec.record {
+                for ext in externs.iter_mut().filter(|ext| ext.name == old) {
+                    ext.name = new.to_string();
+                    count += 1;
+                }
+            }
+        }
+
+        count
+    }
+
+    // Set the class of every segment named `segment`, returning how many
+    // were changed
+    //
+    pub fn set_segment_class(&mut self, segment: &str, class: &str) -> Result<usize, ObjError> {
+        let mut count = 0;
+
+        for at in 0..self.records.len() {
+            let matching: Vec<usize> = match &self.records[at].record {
+                Record::SEGDEF{ segs } => {
+                    let lnames = self.lnames();
+                    segs.iter()
+                        .enumerate()
+                        .filter(|(_, seg)| seg.name.and_then(|name| lnames.get(name.wrapping_sub(1))) == Some(&segment))
+                        .map(|(i, _)| i)
+                        .collect()
+                },
+                _ => continue,
+            };
+
+            if matching.is_empty() {
+                continue;
+            }
+
+            let index = self.lname(class, at)?;
+            if let Record::SEGDEF{ segs } = &mut self.records[at].record {
+                for i in matching {
+                    segs[i].class = Some(index);
+                    count += 1;
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    // Write the object back out
+    //
+    pub fn write(&self) -> Result<Vec<u8>, ObjError> {
+        let mut writer = RecordWriter::new();
+
+        for rec in &self.records {
+            match &rec.raw {
+                Some(raw) => {
+                    let body = &raw[3..raw.len() - 1];
+                    writer.record(rec.rectype, body)?;
+                },
+                None => {
+                    let body = encode_record(rec.rectype, &rec.record, self.codepage)?;
+                    writer.record(rec.rectype, &body)?;
+                },
+            }
+        }
+
+        Ok(writer.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::objbuilder::*;
+
+    fn hello() -> Vec<u8> {
+        let mut builder = ObjBuilder::new("hello");
+        let text = builder.segment(SegmentSpec::new("_TEXT", "CODE"));
+        let data = builder.segment(SegmentSpec::new("_DATA", "DATA"));
+        builder.external("_puts");
+        builder.public("_main", text, 0);
+        builder.data(text, 0, &[0xc3]).unwrap();
+        builder.data(data, 0, b"hi\0").unwrap();
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_unchanged_object_writes_back_the_same() {
+        let obj = hello();
+        let patcher = ObjPatcher::load(&obj, &ParseOptions::default()).unwrap();
+        assert_eq!(patcher.write().unwrap(), obj);
+    }
+
+    #[test]
+    fn test_rename_public_and_extern() {
+        let mut patcher = ObjPatcher::load(&hello(), &ParseOptions::default()).unwrap();
+        assert_eq!(patcher.rename_public("_main", "_start"), 1);
+        assert_eq!(patcher.rename_extern("_puts", "_write"), 1);
+        assert_eq!(patcher.rename_public("_nothing", "_x"), 0);
+
+        let obj = patcher.write().unwrap();
+        let options = ParseOptions{ checksum: ChecksumPolicy::Strict, ..Default::default() };
+        let patched = ObjPatcher::load(&obj, &options).unwrap();
+
+        let publics: Vec<&str> = patched.records.iter()
+            .filter_map(|rec| match &rec.record {
+                Record::PUBDEF{ publics, .. } => Some(publics),
+                _ => None,
+            })
+            .flatten()
+            .map(|public| public.name.as_str())
+            .collect();
+        assert_eq!(publics, vec!["_start"]);
+
+        match &patched.records.iter().find(|rec| rec.rectype == 0x8c).map(|rec| &rec.record) {
+            Some(Record::EXTDEF{ externs }) if externs[0].name == "_write" => (),
+            x => assert!(false, "patched object has {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_set_segment_class_adds_lname() {
+        let mut patcher = ObjPatcher::load(&hello(), &ParseOptions::default()).unwrap();
+        let before = patcher.lnames().len();
+
+        assert_eq!(patcher.set_segment_class("_DATA", "CONST").unwrap(), 1);
+        assert_eq!(patcher.lnames().len(), before + 1);
+
+        // an existing name is reused
+        //
+        assert_eq!(patcher.set_segment_class("_TEXT", "CONST").unwrap(), 1);
+        assert_eq!(patcher.lnames().len(), before + 1);
+
+        let obj = patcher.write().unwrap();
+        let options = ParseOptions{ strict: true, ..Default::default() };
+        let patched = ObjPatcher::load(&obj, &options).unwrap();
+        let lnames = patched.lnames();
+
+        match patched.records.iter().find(|rec| rec.rectype == 0x98).map(|rec| &rec.record) {
+            Some(Record::SEGDEF{ segs }) => assert!(segs.iter().all(|seg| lnames[seg.class.unwrap() - 1] == "CONST")),
+            x => assert!(false, "patched object has {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_unknown_records_are_kept() {
+        let mut obj = hello();
+        obj.splice(0..0, [0x88, 0x04, 0x00, 0x00, 0xfe, 0x55, 0x00]);
+
+        let patcher = ObjPatcher::load(&obj, &ParseOptions::default()).unwrap();
+        let written = patcher.write().unwrap();
+        assert_eq!(&written[..7], &[0x88, 0x04, 0x00, 0x00, 0xfe, 0x55, 0x21]);
+        assert_eq!(&written[7..], &obj[7..]);
+    }
+}
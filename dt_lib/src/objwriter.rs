@@ -0,0 +1,1429 @@
+use std::collections::HashMap;
+
+use crate::codepage::Codepage;
+use crate::error::Error as ObjError;
+use crate::objfile::*;
+
+// The record length field covers the body plus the checksum byte
+//
+const MAX_LENGTH: usize = 0xffff;
+
+// The spec's limits: a data record may carry 1024 bytes of data, and any
+// other record may be 1024 bytes long
+//
+pub const MAX_DATA: usize = 1024;
+pub const MAX_RECORD: usize = 1024;
+
+// Which form of the records which come in 16- and 32-bit flavors to write
+//
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub enum RecordVariants {
+    // The 32-bit form for USE32 segments and wherever a value won't fit
+    // in 16 bits
+    #[default]
+    Auto,
+
+    // Always the 16-bit form; a value which doesn't fit is an error
+    Force16,
+}
+
+impl RecordVariants {
+    // Whether a record should be the 32-bit form, given whether it's for
+    // a USE32 segment and the largest offset or length it has to hold
+    //
+    pub fn is32(&self, use32: bool, largest: u64) -> Result<bool, ObjError> {
+        match self {
+            RecordVariants::Auto => Ok(use32 || largest > 0xffff),
+            RecordVariants::Force16 if largest > 0xffff => {
+                Err(ObjError::new(&format!("value {:x} does not fit in a 16-bit record", largest)))
+            },
+            RecordVariants::Force16 => Ok(false),
+        }
+    }
+
+    // The record type to write, given the 16-bit one
+    //
+    pub fn rectype(rectype: u8, is32: bool) -> u8 {
+        rectype | is32 as u8
+    }
+}
+
+// Writes OMF records: a type byte, a 16-bit length, the body, and the
+// checksum which makes the bytes of the whole record sum to zero.
+//
+#[derive(Default)]
+pub struct RecordWriter {
+    out: Vec<u8>,
+}
+
+impl RecordWriter {
+    pub fn new() -> RecordWriter {
+        RecordWriter::default()
+    }
+
+    // Where the next record will start
+    //
+    pub fn offset(&self) -> usize {
+        self.out.len()
+    }
+
+    // Append one record, returning its offset
+    //
+    pub fn record(&mut self, rectype: u8, body: &[u8]) -> Result<usize, ObjError> {
+        let length = body.len() + 1;
+        if length > MAX_LENGTH {
+            return Err(ObjError::with_offset(&format!("record type ${:02x} body is {} bytes long", rectype, body.len()), self.offset()));
+        }
+
+        let start = self.offset();
+        self.out.push(rectype);
+        self.out.extend((length as u16).to_le_bytes());
+        self.out.extend(body);
+        self.out.push(checksum(&self.out[start..]));
+
+        Ok(start)
+    }
+
+    // Write a logical record made of a list of items (names, publics,
+    // externs) as many physical records as it takes to stay under the
+    // limit. Each one starts with the same base fields, e.g. PUBDEF's
+    // group and segment.
+    //
+    pub fn list_record(&mut self, rectype: u8, base: &[u8], items: &[Vec<u8>]) -> Result<Vec<usize>, ObjError> {
+        let mut offsets = Vec::new();
+        let mut body = base.to_vec();
+
+        for item in items {
+            if base.len() + item.len() + 1 > MAX_RECORD {
+                return Err(ObjError::with_offset(&format!("record type ${:02x} item is too long to fit in any record", rectype), self.offset()));
+            }
+
+            if body.len() + item.len() + 1 > MAX_RECORD {
+                offsets.push(self.record(rectype, &body)?);
+                body.truncate(base.len());
+            }
+
+            body.extend(item);
+        }
+
+        if body.len() > base.len() {
+            offsets.push(self.record(rectype, &body)?);
+        }
+
+        Ok(offsets)
+    }
+
+    // Write an LEDATA (or LEDATA32) as enough records to stay under the
+    // data limit, advancing the offset in each.
+    //
+    pub fn data_record(&mut self, rectype: u8, seg: usize, offset: u32, data: &[u8]) -> Result<Vec<usize>, ObjError> {
+        let is32 = (rectype & 1) != 0;
+        let limit: u64 = if is32 { 1 << 32 } else { 1 << 16 };
+        if offset as u64 + data.len() as u64 > limit {
+            return Err(ObjError::with_offset(&format!("data at {:08x} runs past the end of the segment", offset), self.offset()));
+        }
+
+        let mut offsets = Vec::new();
+        let mut chunk_offset = offset;
+
+        // an empty data record is legal and still has to be written
+        //
+        let chunks: Vec<&[u8]> = if data.is_empty() { vec![data] } else { data.chunks(MAX_DATA).collect() };
+
+        for chunk in chunks {
+            let mut body = RecordBody::new();
+            body.index(seg)?.offset(chunk_offset, is32)?.raw(chunk);
+            offsets.push(self.record(rectype, body.bytes())?);
+
+            chunk_offset = chunk_offset.wrapping_add(chunk.len() as u32);
+        }
+
+        Ok(offsets)
+    }
+
+    // Write a record as it was parsed. Records the parser doesn't
+    // understand are copied through as they were read.
+    //
+    pub fn parsed(&mut self, parsed: &ParsedRecord, codepage: Codepage) -> Result<usize, ObjError> {
+        match &parsed.record {
+            Record::Unknown{ .. } | Record::COMENT{ coment: Coment::Unknown, .. } => {
+                let start = self.offset();
+                self.out.extend(parsed.raw);
+                Ok(start)
+            },
+            record => {
+                let body = encode_record(parsed.rectype, record, codepage)?;
+                self.record(parsed.rectype, &body)
+            },
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.out
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.out
+    }
+}
+
+// The names a module's LNAMES records will hold. Asking for a name again
+// gives back the same 1-based index, so indices are stable from the time
+// a name is first used.
+//
+#[derive(Default)]
+pub struct LnameTable {
+    names: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl LnameTable {
+    pub fn new() -> LnameTable {
+        LnameTable::default()
+    }
+
+    // The index of `name`, adding it if it's new
+    //
+    pub fn intern(&mut self, name: &str) -> usize {
+        match self.index.get(name) {
+            Some(index) => *index,
+            None => self.force(name),
+        }
+    }
+
+    // Add `name` even if it's already present; lookups keep finding the
+    // first copy
+    //
+    pub fn force(&mut self, name: &str) -> usize {
+        self.names.push(name.to_string());
+        let index = self.names.len();
+        self.index.entry(name.to_string()).or_insert(index);
+        index
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+
+    pub fn name(&self, index: usize) -> Option<&str> {
+        self.names.get(index.wrapping_sub(1)).map(|name| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    // Write the names as one or more LNAMES records
+    //
+    pub fn write(&self, writer: &mut RecordWriter, codepage: Codepage) -> Result<(), ObjError> {
+        let items = self.names.iter()
+            .map(|name| Ok(RecordBody::with_codepage(codepage).str(name)?.bytes().to_vec()))
+            .collect::<Result<Vec<_>, ObjError>>()?;
+        writer.list_record(0x96, &[], &items)?;
+        Ok(())
+    }
+}
+
+// Rewrites fixups to use THREAD subrecords where a frame or target is
+// shared, as MASM and LINK do. Threads stay defined from one FIXUPP to the
+// next, so one threader should see all of a module's fixups in order.
+//
+#[derive(Default)]
+pub struct FixupThreader {
+    frames: [Option<(u8, usize)>; 4],
+    targets: [Option<(u8, usize)>; 4],
+    next_frame: usize,
+    next_target: usize,
+}
+
+impl FixupThreader {
+    pub fn new() -> FixupThreader {
+        FixupThreader::default()
+    }
+
+    fn frame_key(fixup: &Fixup) -> Option<(u8, usize)> {
+        match (&fixup.frame_method, fixup.frame_datum) {
+            (Some(method), Some(datum)) if fixup.frame_thread.is_none() && method.has_datum() => Some((method.raw(), datum)),
+            _ => None,
+        }
+    }
+
+    // Target threads hold only the low two bits of the method; whether
+    // there's a displacement is up to each fixup
+    //
+    fn target_key(fixup: &Fixup) -> Option<(u8, usize)> {
+        match (&fixup.target_method, fixup.target_datum) {
+            (Some(method), Some(datum)) if fixup.target_thread.is_none() => Some((method.raw() & 3, datum)),
+            _ => None,
+        }
+    }
+
+    fn slot(slots: &mut [Option<(u8, usize)>; 4], next: &mut usize, key: (u8, usize)) -> (usize, bool) {
+        match slots.iter().position(|slot| *slot == Some(key)) {
+            Some(thread) => (thread, false),
+            None => {
+                let thread = *next;
+                slots[thread] = Some(key);
+                *next = (*next + 1) % 4;
+                (thread, true)
+            },
+        }
+    }
+
+    // Thread one FIXUPP's worth of fixups. A thread is only defined for a
+    // frame or target which is used more than once in the batch or is
+    // still defined from an earlier one.
+    //
+    pub fn thread(&mut self, fixups: Vec<Fixup>) -> Result<Vec<FixupSubrecord>, ObjError> {
+        let mut frame_uses: HashMap<(u8, usize), usize> = HashMap::new();
+        let mut target_uses: HashMap<(u8, usize), usize> = HashMap::new();
+
+        for fixup in fixups.iter() {
+            if let Some(key) = Self::frame_key(fixup) {
+                *frame_uses.entry(key).or_insert(0) += 1;
+            }
+            if let Some(key) = Self::target_key(fixup) {
+                *target_uses.entry(key).or_insert(0) += 1;
+            }
+        }
+
+        let mut subrecords = Vec::new();
+
+        for mut fixup in fixups {
+            if let Some(key) = Self::frame_key(&fixup) {
+                if frame_uses[&key] > 1 || self.frames.contains(&Some(key)) {
+                    let (thread, new) = Self::slot(&mut self.frames, &mut self.next_frame, key);
+                    if new {
+                        let method = key.0.try_into()?;
+                        subrecords.push(FixupSubrecord::FrameThread{ method, thread, index: Some(key.1) });
+                    }
+                    fixup.frame_thread = Some(thread);
+                    fixup.frame_method = None;
+                    fixup.frame_datum = None;
+                }
+            }
+
+            if let Some(key) = Self::target_key(&fixup) {
+                if target_uses[&key] > 1 || self.targets.contains(&Some(key)) {
+                    let (thread, new) = Self::slot(&mut self.targets, &mut self.next_target, key);
+                    if new {
+                        let method = key.0.try_into()?;
+                        subrecords.push(FixupSubrecord::TargetThread{ method, thread, index: key.1 });
+                    }
+                    fixup.target_thread = Some(thread);
+                    fixup.target_method = None;
+                    fixup.target_datum = None;
+                }
+            }
+
+            subrecords.push(FixupSubrecord::Fixup{ fixup });
+        }
+
+        Ok(subrecords)
+    }
+}
+
+// The byte which brings the sum of `bytes` to zero
+//
+pub fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg()
+}
+
+// Encodes the fields which make up a record body, in the same forms the
+// parser reads them.
+//
+#[derive(Default)]
+pub struct RecordBody {
+    bytes: Vec<u8>,
+    codepage: Codepage,
+}
+
+impl RecordBody {
+    pub fn new() -> RecordBody {
+        RecordBody::default()
+    }
+
+    pub fn with_codepage(codepage: Codepage) -> RecordBody {
+        RecordBody{ bytes: Vec::new(), codepage }
+    }
+
+    pub fn byte(&mut self, value: u8) -> &mut Self {
+        self.bytes.push(value);
+        self
+    }
+
+    pub fn word(&mut self, value: u16) -> &mut Self {
+        self.bytes.extend(value.to_le_bytes());
+        self
+    }
+
+    pub fn dword(&mut self, value: u32) -> &mut Self {
+        self.bytes.extend(value.to_le_bytes());
+        self
+    }
+
+    // An offset or length field, which is a word or a dword depending on
+    // whether the record is the 32-bit variant
+    //
+    pub fn offset(&mut self, value: u32, is32: bool) -> Result<&mut Self, ObjError> {
+        if is32 {
+            Ok(self.dword(value))
+        } else {
+            let value = u16::try_from(value)
+                .map_err(|_| ObjError::new(&format!("offset {:08x} does not fit in a 16-bit record", value)))?;
+            Ok(self.word(value))
+        }
+    }
+
+    // Indices take one byte below 0x80, else two with the high bit set
+    //
+    pub fn index(&mut self, index: usize) -> Result<&mut Self, ObjError> {
+        match index {
+            0..=0x7f => Ok(self.byte(index as u8)),
+            0x80..=0x7fff => Ok(self.byte(0x80 | (index >> 8) as u8).byte(index as u8)),
+            _ => Err(ObjError::new(&format!("index {} is too large to encode", index))),
+        }
+    }
+
+    pub fn opt_index(&mut self, index: Option<usize>) -> Result<&mut Self, ObjError> {
+        self.index(index.unwrap_or(0))
+    }
+
+    // A length-prefixed name
+    //
+    pub fn str(&mut self, text: &str) -> Result<&mut Self, ObjError> {
+        let bytes = self.codepage.encode(text)?;
+        if bytes.len() > 0xff {
+            return Err(ObjError::new(&format!("name {} is longer than 255 bytes", text)));
+        }
+
+        self.bytes.push(bytes.len() as u8);
+        self.bytes.extend(bytes);
+        Ok(self)
+    }
+
+    // Text which runs to the end of the record, as in COMENTs
+    //
+    pub fn rest_str(&mut self, text: &str) -> Result<&mut Self, ObjError> {
+        let bytes = self.codepage.encode(text)?;
+        self.bytes.extend(bytes);
+        Ok(self)
+    }
+
+    // One segment definition, as in SEGDEF
+    //
+    pub fn segdef(&mut self, seg: &Segdef, is32: bool) -> Result<&mut Self, ObjError> {
+        let limit: u64 = if is32 { 1 << 32 } else { 1 << 16 };
+        if seg.length > limit {
+            return Err(ObjError::new(&format!("segment length {:x} does not fit in a {}-bit SEGDEF", seg.length, if is32 { 32 } else { 16 })));
+        }
+
+        // a segment of exactly the maximum length sets BIG with a zero length
+        //
+        let big = seg.length == limit;
+        let acbp = (seg.align.raw() << 5) | (seg.combine.raw() << 2) | ((big as u8) << 1) | (seg.use32 as u8);
+        self.byte(acbp);
+
+        if seg.align == Align::Absolute {
+            let abs = seg.abs.as_ref()
+                .ok_or_else(|| ObjError::new("absolute segment has no frame"))?;
+            self.word(abs.frame).byte(abs.offset);
+        }
+
+        let length = if big { 0 } else { seg.length as u32 };
+        self.offset(length, is32)?
+            .opt_index(seg.name)?
+            .opt_index(seg.class)?
+            .opt_index(seg.overlay)
+    }
+
+    // One block of iterated data, as in LIDATA
+    //
+    pub fn iterated_block(&mut self, block: &IteratedBlock, is32: bool) -> Result<&mut Self, ObjError> {
+        let repeat = u32::try_from(block.repeat)
+            .map_err(|_| ObjError::new(&format!("repeat count {} is too large", block.repeat)))?;
+        self.offset(repeat, is32)?;
+
+        match &block.content {
+            IteratedContent::Data(data) => {
+                if data.len() > 0xff {
+                    return Err(ObjError::new(&format!("iterated data block is {} bytes long (limit is 255)", data.len())));
+                }
+                self.word(0).byte(data.len() as u8).raw(data);
+            },
+            IteratedContent::Blocks(blocks) => {
+                let count = u16::try_from(blocks.len())
+                    .ok().filter(|count| *count > 0)
+                    .ok_or_else(|| ObjError::new(&format!("iterated block has {} nested blocks", blocks.len())))?;
+                self.word(count);
+                for block in blocks {
+                    self.iterated_block(block, is32)?;
+                }
+            },
+        }
+
+        Ok(self)
+    }
+
+    // One FIXUPP subrecord
+    //
+    pub fn fixup(&mut self, sub: &FixupSubrecord, is32: bool) -> Result<&mut Self, ObjError> {
+        match sub {
+            FixupSubrecord::TargetThread{ method, thread, index } => {
+                self.byte((method.raw() << 2) | (*thread as u8 & 3)).index(*index)
+            },
+            FixupSubrecord::FrameThread{ method, thread, index } => {
+                self.byte(0x40 | (method.raw() << 2) | (*thread as u8 & 3));
+                match (method.has_datum(), index) {
+                    (true, Some(index)) => self.index(*index),
+                    (true, None) => Err(ObjError::new(&format!("frame method {:?} needs an index", method))),
+                    (false, _) => Ok(self),
+                }
+            },
+            FixupSubrecord::Fixup{ fixup } => self.fixup_subrecord(fixup, is32),
+        }
+    }
+
+    fn fixup_subrecord(&mut self, fixup: &Fixup, is32: bool) -> Result<&mut Self, ObjError> {
+        if fixup.data_offset > 0x3ff {
+            return Err(ObjError::new(&format!("fixup data offset {:x} is out of range", fixup.data_offset)));
+        }
+
+        let lead = 0x80 | ((fixup.is_seg_relative as u8) << 6) | (fixup.location.raw() << 2) | (fixup.data_offset >> 8) as u8;
+
+        let frame = match (fixup.frame_thread, &fixup.frame_method) {
+            (Some(thread), _) => 0x80 | ((thread as u8 & 3) << 4),
+            (None, Some(method)) => method.raw() << 4,
+            (None, None) => return Err(ObjError::new("fixup has neither a frame thread nor a frame method")),
+        };
+
+        // NB a thread can't say whether there's a displacement, so one is
+        // written only if it's needed
+        //
+        let (target, has_displacement) = match (fixup.target_thread, &fixup.target_method) {
+            (Some(thread), _) if fixup.target_displacement == 0 => (0x0c | (thread as u8 & 3), false),
+            (Some(thread), _) => (0x08 | (thread as u8 & 3), true),
+            (None, Some(method)) => (method.raw(), method.has_displacement()),
+            (None, None) => return Err(ObjError::new("fixup has neither a target thread nor a target method")),
+        };
+
+        if !has_displacement && fixup.target_displacement != 0 {
+            return Err(ObjError::new("fixup has a displacement but its target method does not allow one"));
+        }
+
+        self.byte(lead).byte(fixup.data_offset as u8).byte(frame | target);
+
+        if let Some(method) = &fixup.frame_method {
+            if fixup.frame_thread.is_none() && method.has_datum() {
+                let datum = fixup.frame_datum
+                    .ok_or_else(|| ObjError::new(&format!("frame method {:?} needs an index", method)))?;
+                self.index(datum)?;
+            }
+        }
+
+        if fixup.target_thread.is_none() {
+            let datum = fixup.target_datum
+                .ok_or_else(|| ObjError::new("fixup target needs an index"))?;
+            self.index(datum)?;
+        }
+
+        if has_displacement {
+            self.offset(fixup.target_displacement, is32)?;
+        }
+
+        Ok(self)
+    }
+
+    // The group, segment, and (if both are zero) frame which PUBDEF and
+    // COMDAT use to say where something lives
+    //
+    pub fn base(&mut self, group: Option<usize>, seg: Option<usize>, frame: Option<u16>) -> Result<&mut Self, ObjError> {
+        self.opt_index(group)?.opt_index(seg)?;
+        if group.is_none() && seg.is_none() {
+            self.word(frame.unwrap_or(0));
+        }
+        Ok(self)
+    }
+
+    // The whole of a MODEND: the module type, then the start address if
+    // there is one
+    //
+    pub fn modend(&mut self, main: bool, start: Option<&StartAddress>, is32: bool) -> Result<&mut Self, ObjError> {
+        self.byte(((main as u8) << 7) | ((start.is_some() as u8) << 6) | 0x01);
+        match start {
+            Some(start) => self.start_address(start, is32),
+            None => Ok(self),
+        }
+    }
+
+    // A MODEND start address, which is a fixup without the location
+    //
+    pub fn start_address(&mut self, start: &StartAddress, is32: bool) -> Result<&mut Self, ObjError> {
+        self.byte(start.fix_data);
+
+        if let Some(method) = start.fmethod()? {
+            if method.has_datum() {
+                self.opt_index(start.frame_datum)?;
+            }
+        }
+
+        if !start.tthread() {
+            self.opt_index(start.target_datum)?;
+        }
+
+        if (start.fix_data & 0x04) == 0 {
+            self.offset(start.target_disp.unwrap_or(0), is32)?;
+        }
+
+        Ok(self)
+    }
+
+    pub fn grp_component(&mut self, component: &GrpComponent) -> Result<&mut Self, ObjError> {
+        match component {
+            GrpComponent::Segdef{ index } => self.byte(0xff).index(*index),
+            GrpComponent::Extdef{ index } => self.byte(0xfe).index(*index),
+            GrpComponent::SegClassOverlay{ name, class, overlay } => {
+                self.byte(0xfd).index(*name)?.index(*class)?.index(*overlay)
+            },
+            GrpComponent::Ltl{ data, max_length, length } => Ok(self.byte(0xfb).byte(*data).word(*max_length).word(*length)),
+            GrpComponent::Absolute{ frame, offset } => Ok(self.byte(0xfa).word(*frame).byte(*offset)),
+        }
+    }
+
+    // A COMDEF length, in the shortest form which holds it
+    //
+    pub fn comlength(&mut self, length: usize) -> Result<&mut Self, ObjError> {
+        match length {
+            0..=0x80 => Ok(self.byte(length as u8)),
+            0x81..=0xffff => Ok(self.byte(0x81).word(length as u16)),
+            0x10000..=0xffffff => Ok(self.byte(0x84).word(length as u16).byte((length >> 16) as u8)),
+            0x1000000..=0xffffffff => Ok(self.byte(0x88).dword(length as u32)),
+            _ => Err(ObjError::new(&format!("common length {:x} is too large to encode", length))),
+        }
+    }
+
+    pub fn bakpat_fixups(&mut self, fixups: &[BakpatFixup], is32: bool) -> Result<&mut Self, ObjError> {
+        for fixup in fixups {
+            self.offset(fixup.offset, is32)?.offset(fixup.value, is32)?;
+        }
+        Ok(self)
+    }
+
+    // The content of a COMENT, after the type and class bytes
+    //
+    pub fn coment(&mut self, coment: &Coment) -> Result<&mut Self, ObjError> {
+        match coment {
+            Coment::Translator{ text } | Coment::MemoryModel{ text } | Coment::NewOMF{ text } |
+            Coment::EasyOmf{ text } | Coment::User{ text } | Coment::ExeStr{ text } => self.rest_str(text),
+            Coment::DefaultLibrary{ name } => self.rest_str(name),
+            Coment::DosSeg | Coment::IncErr | Coment::DependencyEnd => Ok(self),
+            Coment::LinkPassSeparator => Ok(self.byte(0x01)),
+            Coment::Libmod{ name } => self.str(name),
+            Coment::WeakExtern{ externs } | Coment::LazyExtern{ externs } => {
+                for ext in externs {
+                    self.index(ext.weak)?.index(ext.default)?;
+                }
+                Ok(self)
+            },
+            Coment::DosVersion{ version } => Ok(self.word(*version)),
+            Coment::Impdef{ internal, module, entry } => {
+                self.byte(0x01).byte(matches!(entry, ImportEntry::Ordinal(_)) as u8)
+                    .str(internal)?.str(module)?;
+                match entry {
+                    ImportEntry::Ordinal(ordinal) => Ok(self.word(*ordinal)),
+                    ImportEntry::Name(name) => self.str(name),
+                }
+            },
+            Coment::Expdef{ flags, exported, internal, ordinal } => {
+                self.byte(0x02).byte(*flags).str(exported)?.str(internal)?;
+                match ((flags & 0x80) != 0, ordinal) {
+                    (true, Some(ordinal)) => Ok(self.word(*ordinal)),
+                    (true, None) => Err(ObjError::new(&format!("export {} is flagged as having an ordinal but has none", exported))),
+                    (false, _) => Ok(self),
+                }
+            },
+            Coment::Incdef{ extdef_delta, linnum_delta } => {
+                Ok(self.byte(0x03).word(*extdef_delta as u16).word(*linnum_delta as u16))
+            },
+            Coment::Lnkdir{ flags, pcode_version, cv_version } => {
+                Ok(self.byte(0x05).byte(*flags).byte(*pcode_version).byte(*cv_version))
+            },
+            Coment::NoPad{ segs } => {
+                for seg in segs {
+                    self.index(*seg)?;
+                }
+                Ok(self)
+            },
+            Coment::Dependency{ name, timestamp } => {
+                let (date, time) = timestamp.to_dos();
+                self.word(time).word(date).str(name)
+            },
+            Coment::Unknown => Err(ObjError::new("comment class has no known encoding")),
+        }
+    }
+
+    pub fn raw(&mut self, bytes: &[u8]) -> &mut Self {
+        self.bytes.extend(bytes);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+// Pack a MODEND start address from the methods which locate it. A frame
+// thread can't be used here, and F4 (the location's frame) means nothing
+// at the end of a module. The NoDisplacement target methods set the P
+// bit, so `displacement` must be zero with them.
+//
+pub fn start_address(frame: FrameMethod, frame_datum: Option<usize>, target: TargetMethod, target_datum: usize, displacement: u32) -> Result<StartAddress, ObjError> {
+    if frame == FrameMethod::PreviousDataRecord {
+        return Err(ObjError::new("a start address cannot use the frame of the previous data record"));
+    }
+
+    let frame_datum = match (frame.has_datum(), frame_datum) {
+        (true, Some(datum)) => Some(datum),
+        (true, None) => return Err(ObjError::new(&format!("frame method {:?} needs an index", frame))),
+        (false, _) => None,
+    };
+
+    let target_disp = if target.has_displacement() {
+        Some(displacement)
+    } else if displacement != 0 {
+        return Err(ObjError::new(&format!("target method {:?} cannot carry a displacement", target)));
+    } else {
+        None
+    };
+
+    Ok(StartAddress{
+        fix_data: (frame.raw() << 4) | target.raw(),
+        frame_datum,
+        target_datum: Some(target_datum),
+        target_disp,
+    })
+}
+
+// Encode the body of one parsed record, such that parsing it again gives
+// the same record. `rectype` picks the 16- or 32-bit form. This is exact
+// for what tools actually write, except where the parser folds several
+// encodings into one value; those come back in the canonical form:
+//
+// - indices below 0x80 written in the two-byte form
+// - a FIXUP whose target thread has the P bit clear but a zero displacement
+// - a far COMDEF length not factored as (length, 1)
+// - BAKPAT location 9, which is written as 2
+// - LIBHDR's padding, which is written as zeros
+//
+// Easy OMF objects use 32-bit fields in 16-bit record types; pass the
+// 32-bit type to get them.
+//
+pub fn encode_record(rectype: u8, record: &Record, codepage: Codepage) -> Result<Vec<u8>, ObjError> {
+    let is32 = (rectype & 1) != 0;
+    let mut body = RecordBody::with_codepage(codepage);
+
+    match record {
+        Record::THEADR{ name } | Record::LHEADR{ name } => { body.str(name)?; },
+        Record::MODEND{ main, start_address, modtype } => {
+            let modtype = (modtype & 0x3f) | ((*main as u8) << 7) | ((start_address.is_some() as u8) << 6);
+            body.byte(modtype);
+            if let Some(start) = start_address {
+                body.start_address(start, is32)?;
+            }
+        },
+        Record::LNAMES{ names } => for name in names {
+            body.str(name)?;
+        },
+        Record::SEGDEF{ segs } => for seg in segs {
+            body.segdef(seg, is32)?;
+        },
+        Record::GRPDEF{ name, components } => {
+            body.index(*name)?;
+            for component in components {
+                body.grp_component(component)?;
+            }
+        },
+        Record::EXTDEF{ externs } | Record::LEXTDEF{ externs } => for ext in externs {
+            body.str(&ext.name)?.index(ext.typeidx)?;
+        },
+        Record::PUBDEF{ group, seg, frame, publics } | Record::LPUBDEF{ group, seg, frame, publics } => {
+            body.base(*group, *seg, *frame)?;
+            for public in publics {
+                body.str(&public.name)?.offset(public.offset, is32)?.index(public.typeidx)?;
+            }
+        },
+        Record::COMENT{ header, coment } => {
+            body.byte(header.comtype).byte(header.comclass);
+            body.coment(coment)?;
+        },
+        Record::LEDATA{ seg, offset, data } => {
+            body.index(*seg)?.offset(*offset, is32)?.raw(data);
+        },
+        Record::LIDATA{ seg, offset, blocks } => {
+            body.index(*seg)?.offset(*offset, is32)?;
+            for block in blocks {
+                body.iterated_block(block, is32)?;
+            }
+        },
+        Record::BAKPAT{ seg, location, fixups } => {
+            body.index(*seg)?.byte(location.raw());
+            body.bakpat_fixups(fixups, is32)?;
+        },
+        Record::NBKPAT{ name, location, fixups } => {
+            body.byte(location.raw()).index(*name)?;
+            body.bakpat_fixups(fixups, is32)?;
+        },
+        Record::FIXUPP{ fixups } => for fixup in fixups {
+            body.fixup(fixup, is32)?;
+        },
+        Record::COMDEF{ commons } => for common in commons {
+            body.str(&common.name)?.index(common.typeidx)?.byte(common.datatype);
+            body.comlength(common.length)?;
+            if common.datatype == 0x61 {
+                body.comlength(1)?;
+            }
+        },
+        Record::CEXTDEF{ externs } => for ext in externs {
+            body.index(ext.name)?.index(ext.typeindex)?;
+        },
+        Record::ALIAS{ aliases } => for alias in aliases {
+            body.str(&alias.alias)?.str(&alias.substitute)?;
+        },
+        Record::COMDAT{ comdat } => {
+            body.byte(comdat.flags)
+                .byte(comdat.selection.raw() | comdat.allocation.raw())
+                .byte(comdat.align.raw())
+                .offset(comdat.offset, is32)?
+                .index(comdat.typeindex)?
+                .base(comdat.base_group, comdat.base_seg, comdat.base_frame)?
+                .index(comdat.name)?;
+
+            if comdat.iterated_data() {
+                for block in &comdat.blocks {
+                    body.iterated_block(block, is32)?;
+                }
+            } else {
+                body.raw(&comdat.data);
+            }
+        },
+        Record::LINNUM{ group, seg, lines } => {
+            body.opt_index(*group)?.index(*seg)?;
+            for line in lines {
+                body.word(line.line).offset(line.offset, is32)?;
+            }
+        },
+        Record::LINSYM{ flags, name, lines } => {
+            body.byte(*flags).index(*name)?;
+            for line in lines {
+                body.word(line.line).offset(line.offset, is32)?;
+            }
+        },
+        Record::INTEL{ data, .. } | Record::BORLAND{ data, .. } => { body.raw(data); },
+        Record::LIBHDR{ page_size, dict_offset, dict_blocks, flags } => {
+            // the record fills the first page, checksum included
+            //
+            if *page_size < 11 {
+                return Err(ObjError::new(&format!("library page size {} is too small", page_size)));
+            }
+            body.dword(*dict_offset).word(*dict_blocks).byte(*flags);
+            body.raw(&vec![0; page_size - 11]);
+        },
+        Record::LIBEND => (),
+        Record::None | Record::Unknown{ .. } => {
+            return Err(ObjError::new(&format!("record type ${:02x} has no known encoding", rectype)));
+        },
+    }
+
+    Ok(body.into_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_writer_emits_header_and_checksum() {
+        let mut writer = RecordWriter::new();
+        assert_eq!(writer.record(0x80, &[0x01, 0x41]).unwrap(), 0);
+        assert_eq!(writer.record(0x8a, &[0x00]).unwrap(), 6);
+
+        assert_eq!(writer.bytes(), &[
+            0x80, 0x03, 0x00, 0x01, 0x41, 0x3b,
+            0x8a, 0x02, 0x00, 0x00, 0x74,
+        ]);
+    }
+
+    #[test]
+    fn test_written_records_parse() {
+        let mut body = RecordBody::new();
+        body.str("CODE").unwrap().str("_TEXT").unwrap();
+
+        let mut writer = RecordWriter::new();
+        writer.record(0x96, body.bytes()).unwrap();
+
+        let options = ParseOptions{ checksum: ChecksumPolicy::Strict, ..Default::default() };
+        let mut parser = Parser::with_options(writer.bytes(), options);
+        assert_eq!(parser.next().unwrap(), Record::LNAMES{ names: vec!["CODE".to_string(), "_TEXT".to_string()] });
+    }
+
+    #[test]
+    fn test_record_body_encodes_fields() {
+        let mut body = RecordBody::new();
+        body.index(0x05).unwrap()
+            .index(0x1234).unwrap()
+            .opt_index(None).unwrap()
+            .offset(0x5678, false).unwrap()
+            .offset(0x9abcdef0, true).unwrap();
+
+        assert_eq!(body.bytes(), &[0x05, 0x92, 0x34, 0x00, 0x78, 0x56, 0xf0, 0xde, 0xbc, 0x9a]);
+        assert!(body.index(0x8000).is_err());
+        assert!(body.offset(0x10000, false).is_err());
+    }
+
+    #[test]
+    fn test_list_record_splits_at_limit() {
+        let names: Vec<String> = (0..300).map(|i| format!("N{:03}", i)).collect();
+        let items: Vec<Vec<u8>> = names.iter()
+            .map(|name| RecordBody::new().str(name).unwrap().bytes().to_vec())
+            .collect();
+
+        let mut writer = RecordWriter::new();
+        let offsets = writer.list_record(0x96, &[], &items).unwrap();
+        assert_eq!(offsets.len(), 2);
+
+        let mut parser = Parser::new(writer.bytes());
+        let mut parsed = Vec::new();
+        for _ in 0..2 {
+            let record = parser.next_parsed().unwrap();
+            assert!(record.raw.len() - 3 <= MAX_RECORD);
+            match record.record {
+                Record::LNAMES{ names } => parsed.extend(names),
+                x => assert!(false, "parser returned {:x?}", x),
+            }
+        }
+        assert_eq!(parsed, names);
+    }
+
+    #[test]
+    fn test_list_record_repeats_base_fields() {
+        let base = [0x00, 0x01];
+        let items: Vec<Vec<u8>> = (0..200)
+            .map(|i| RecordBody::new().str(&format!("_public{:03}", i)).unwrap().word(i).index(0).unwrap().bytes().to_vec())
+            .collect();
+
+        let mut writer = RecordWriter::new();
+        assert_eq!(writer.list_record(0x90, &base, &items).unwrap().len(), 3);
+
+        let bytes = writer.bytes();
+        let mut ptr = 0;
+        while ptr < bytes.len() {
+            assert_eq!(&bytes[ptr+3..ptr+5], &base);
+            ptr += 3 + u16::from_le_bytes([bytes[ptr+1], bytes[ptr+2]]) as usize;
+        }
+    }
+
+    #[test]
+    fn test_data_record_advances_offset() {
+        let data: Vec<u8> = (0..2500).map(|i| i as u8).collect();
+
+        let mut writer = RecordWriter::new();
+        assert_eq!(writer.data_record(0xa0, 1, 0x100, &data).unwrap().len(), 3);
+
+        let mut parser = Parser::new(writer.bytes());
+        let mut expected = 0x100;
+        let mut rebuilt = Vec::new();
+        for _ in 0..3 {
+            match parser.next() {
+                Ok(Record::LEDATA{ seg, offset, data }) => {
+                    assert_eq!(seg, 1);
+                    assert_eq!(offset, expected);
+                    expected += data.len() as u32;
+                    rebuilt.extend(data);
+                },
+                x => assert!(false, "parser returned {:x?}", x),
+            }
+        }
+        assert_eq!(rebuilt, data);
+    }
+
+    #[test]
+    fn test_data_record_rejects_16_bit_overflow() {
+        let mut writer = RecordWriter::new();
+        assert!(writer.data_record(0xa0, 1, 0xff00, &[0; 0x200]).is_err());
+        assert!(writer.data_record(0xa1, 1, 0xff00, &[0; 0x200]).is_ok());
+    }
+
+    #[test]
+    fn test_segdef_encoding_parses() {
+        let segs = vec![
+            Segdef{ align: Align::Paragraph, combine: Combine::Public{ raw: 2 }, length: 0x1234, name: Some(1), class: Some(2), ..Segdef::empty() },
+            Segdef{ align: Align::Byte, combine: Combine::Stack, length: 0x10000, name: Some(3), ..Segdef::empty() },
+            Segdef{ align: Align::Absolute, abs: Some(AbsoluteSeg{ frame: 0xb800, offset: 0 }), combine: Combine::Private, ..Segdef::empty() },
+        ];
+
+        let mut body = RecordBody::new();
+        for seg in segs.iter() {
+            body.segdef(seg, false).unwrap();
+        }
+
+        let mut writer = RecordWriter::new();
+        writer.record(0x96, RecordBody::new().str("A").unwrap().str("B").unwrap().str("C").unwrap().bytes()).unwrap();
+        writer.record(0x98, body.bytes()).unwrap();
+
+        let mut parser = Parser::new(writer.bytes());
+        parser.next().unwrap();
+        assert_eq!(parser.next().unwrap(), Record::SEGDEF{ segs });
+
+        assert!(RecordBody::new().segdef(&Segdef{ length: 0x10001, ..Segdef::empty() }, false).is_err());
+    }
+
+    #[test]
+    fn test_fixup_encoding() {
+        let fixup = Fixup{
+            is_seg_relative: true,
+            location: FixupLocation::Offset32,
+            data_offset: 0x123,
+            frame_thread: None,
+            frame_method: Some(FrameMethod::Grpdef),
+            frame_datum: Some(1),
+            target_thread: None,
+            target_method: Some(TargetMethod::Extdef),
+            target_datum: Some(0x81),
+            target_displacement: 0x10,
+        };
+
+        let mut body = RecordBody::new();
+        body.fixup(&FixupSubrecord::Fixup{ fixup }, true).unwrap();
+        assert_eq!(body.bytes(), &[0xe5, 0x23, 0x12, 0x01, 0x80, 0x81, 0x10, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_lname_table_interns_names() {
+        let mut lnames = LnameTable::new();
+        assert_eq!(lnames.intern("CODE"), 1);
+        assert_eq!(lnames.intern("_TEXT"), 2);
+        assert_eq!(lnames.intern("CODE"), 1);
+        assert_eq!(lnames.force("CODE"), 3);
+        assert_eq!(lnames.find("CODE"), Some(1));
+        assert_eq!(lnames.find("code"), None);
+        assert_eq!(lnames.name(3), Some("CODE"));
+
+        let mut writer = RecordWriter::new();
+        lnames.write(&mut writer, Codepage::default()).unwrap();
+
+        let mut parser = Parser::new(writer.bytes());
+        assert_eq!(parser.next().unwrap(), Record::LNAMES{ names: lnames.names().to_vec() });
+    }
+
+    #[test]
+    fn test_threader_shares_frame_and_target() {
+        let fixup = |data_offset, target_displacement| Fixup{
+            is_seg_relative: true,
+            location: FixupLocation::Word,
+            data_offset,
+            frame_thread: None,
+            frame_method: Some(FrameMethod::Grpdef),
+            frame_datum: Some(1),
+            target_thread: None,
+            target_method: Some(if target_displacement == 0 { TargetMethod::ExtdefNoDisplacement } else { TargetMethod::Extdef }),
+            target_datum: Some(2),
+            target_displacement,
+        };
+
+        let mut threader = FixupThreader::new();
+        let subrecords = threader.thread(vec![fixup(0, 0), fixup(2, 4), fixup(4, 0)]).unwrap();
+        assert_eq!(subrecords.len(), 5);
+        assert_eq!(subrecords[0], FixupSubrecord::FrameThread{ method: FrameMethod::Grpdef, thread: 0, index: Some(1) });
+        assert_eq!(subrecords[1], FixupSubrecord::TargetThread{ method: TargetMethod::Extdef, thread: 0, index: 2 });
+
+        // the threads are still live for the next record
+        //
+        let next = threader.thread(vec![fixup(6, 0)]).unwrap();
+        assert_eq!(next.len(), 1);
+
+        let mut body = RecordBody::new();
+        for sub in subrecords.iter().chain(next.iter()) {
+            body.fixup(sub, false).unwrap();
+        }
+
+        let mut writer = RecordWriter::new();
+        writer.record(0x9c, body.bytes()).unwrap();
+
+        match Parser::new(writer.bytes()).next() {
+            Ok(Record::FIXUPP{ fixups }) => {
+                assert_eq!(fixups, subrecords.into_iter().chain(next).collect::<Vec<_>>());
+                match &fixups[3] {
+                    FixupSubrecord::Fixup{ fixup } => assert_eq!(fixup.target_displacement, 4),
+                    x => assert!(false, "fixup was {:x?}", x),
+                }
+            },
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_threader_leaves_single_use_alone() {
+        let fixup = Fixup{
+            is_seg_relative: true,
+            location: FixupLocation::Word,
+            data_offset: 0,
+            frame_thread: None,
+            frame_method: Some(FrameMethod::Target),
+            frame_datum: None,
+            target_thread: None,
+            target_method: Some(TargetMethod::SegdefNoDisplacement),
+            target_datum: Some(1),
+            target_displacement: 0,
+        };
+
+        let subrecords = FixupThreader::new().thread(vec![fixup]).unwrap();
+        assert!(matches!(&subrecords[..], [FixupSubrecord::Fixup{ fixup }] if fixup.target_thread.is_none()));
+    }
+
+    #[test]
+    fn test_iterated_block_encoding() {
+        let blocks = vec![
+            IteratedBlock{ repeat: 2, content: IteratedContent::Blocks(vec![
+                IteratedBlock{ repeat: 3, content: IteratedContent::Data(vec![0x40, 0x41]) },
+                IteratedBlock{ repeat: 2, content: IteratedContent::Data(vec![0x50, 0x51]) },
+            ])},
+        ];
+
+        let mut body = RecordBody::new();
+        body.index(1).unwrap().word(0x1234);
+        for block in blocks.iter() {
+            body.iterated_block(block, false).unwrap();
+        }
+
+        let mut writer = RecordWriter::new();
+        writer.record(0xa2, body.bytes()).unwrap();
+
+        let mut parser = Parser::new(writer.bytes());
+        assert_eq!(parser.next().unwrap(), Record::LIDATA{ seg: 1, offset: 0x1234, blocks });
+    }
+
+    #[test]
+    fn test_record_writer_rejects_oversized_body() {
+        let mut writer = RecordWriter::new();
+        assert!(writer.record(0xa0, &vec![0; MAX_LENGTH]).is_err());
+        assert!(writer.bytes().is_empty());
+    }
+
+    fn round_trip(rectype: u8, record: Record) {
+        let body = encode_record(rectype, &record, Codepage::default()).unwrap();
+
+        let mut writer = RecordWriter::new();
+        writer.record(rectype, &body).unwrap();
+
+        let mut parser = Parser::new(writer.bytes());
+        match parser.next() {
+            Ok(parsed) if parsed == record => (),
+            x => assert!(false, "parser returned {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_encode_round_trips_header_records() {
+        round_trip(0x80, Record::THEADR{ name: "hello.c".to_string() });
+        round_trip(0x82, Record::LHEADR{ name: "HELLO".to_string() });
+        round_trip(0x96, Record::LNAMES{ names: vec!["".to_string(), "_TEXT".to_string(), "CODE".to_string()] });
+        round_trip(0xf1, Record::LIBEND);
+        round_trip(0xf0, Record::LIBHDR{ page_size: 16, dict_offset: 0x400, dict_blocks: 1, flags: 1 });
+    }
+
+    #[test]
+    fn test_encode_round_trips_modend() {
+        round_trip(0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0x00 });
+        round_trip(0x8a, Record::MODEND{ main: false, start_address: None, modtype: 0x01 });
+
+        let start_address = Some(StartAddress{ fix_data: 0x00, frame_datum: Some(1), target_datum: Some(1), target_disp: Some(0x10) });
+        round_trip(0x8a, Record::MODEND{ main: true, start_address, modtype: 0xc1 });
+
+        let start_address = Some(StartAddress{ fix_data: 0x54, frame_datum: None, target_datum: Some(2), target_disp: None });
+        round_trip(0x8b, Record::MODEND{ main: true, start_address, modtype: 0xc1 });
+    }
+
+    #[test]
+    fn test_encode_round_trips_definitions() {
+        let mut seg = Segdef::empty();
+        seg.name = Some(2);
+        seg.class = Some(3);
+        seg.overlay = Some(1);
+        seg.length = 0x1234;
+        round_trip(0x98, Record::SEGDEF{ segs: vec![seg] });
+
+        round_trip(0x9a, Record::GRPDEF{ name: 4, components: vec![
+            GrpComponent::Segdef{ index: 1 },
+            GrpComponent::Extdef{ index: 2 },
+            GrpComponent::SegClassOverlay{ name: 1, class: 2, overlay: 3 },
+            GrpComponent::Ltl{ data: 0x80, max_length: 0x1000, length: 0x0800 },
+            GrpComponent::Absolute{ frame: 0xb800, offset: 0 },
+        ]});
+
+        round_trip(0x8c, Record::EXTDEF{ externs: vec![Extern{ name: "_printf".to_string(), typeidx: 0 }] });
+        round_trip(0xb4, Record::LEXTDEF{ externs: vec![Extern{ name: "_local".to_string(), typeidx: 1 }] });
+
+        let publics = vec![Public{ name: "_main".to_string(), offset: 0x10, typeidx: 0 }];
+        round_trip(0x90, Record::PUBDEF{ group: Some(1), seg: Some(1), frame: None, publics });
+
+        let publics = vec![Public{ name: "_abs".to_string(), offset: 0x12345, typeidx: 0 }];
+        round_trip(0xb7, Record::LPUBDEF{ group: None, seg: None, frame: Some(0x40), publics });
+
+        round_trip(0xb0, Record::COMDEF{ commons: vec![
+            Comdef{ name: "_near".to_string(), length: 0x80, datatype: 0x62, typeidx: 0 },
+            Comdef{ name: "_word".to_string(), length: 0xc8, datatype: 0x62, typeidx: 0 },
+            Comdef{ name: "_big".to_string(), length: 0x12345, datatype: 0x62, typeidx: 0 },
+            Comdef{ name: "_far".to_string(), length: 0x1234567, datatype: 0x61, typeidx: 0 },
+        ]});
+
+        round_trip(0xbc, Record::CEXTDEF{ externs: vec![CExtern{ name: 1, typeindex: 0 }] });
+        round_trip(0xc6, Record::ALIAS{ aliases: vec![Alias{ alias: "_a".to_string(), substitute: "_b".to_string() }] });
+    }
+
+    #[test]
+    fn test_encode_round_trips_data_and_fixups() {
+        round_trip(0xa0, Record::LEDATA{ seg: 1, offset: 0x10, data: vec![0xb8, 0x00, 0x00] });
+        round_trip(0xa1, Record::LEDATA{ seg: 0x90, offset: 0x12345, data: vec![0xcc] });
+        round_trip(0xa2, Record::LIDATA{ seg: 1, offset: 0, blocks: vec![
+            IteratedBlock{ repeat: 0x20, content: IteratedContent::Data(vec![0x90]) },
+        ]});
+
+        let fixups = vec![
+            FixupSubrecord::TargetThread{ method: TargetMethod::SegdefNoDisplacement, thread: 0, index: 1 },
+            FixupSubrecord::FrameThread{ method: FrameMethod::Grpdef, thread: 1, index: Some(1) },
+            FixupSubrecord::FrameThread{ method: FrameMethod::Target, thread: 2, index: None },
+            FixupSubrecord::Fixup{ fixup: Fixup{
+                is_seg_relative: true,
+                location: FixupLocation::Word,
+                data_offset: 0x101,
+                frame_thread: None,
+                frame_method: Some(FrameMethod::Grpdef),
+                frame_datum: Some(1),
+                target_thread: None,
+                target_method: Some(TargetMethod::Extdef),
+                target_datum: Some(2),
+                target_displacement: 4,
+            }},
+            FixupSubrecord::Fixup{ fixup: Fixup{
+                is_seg_relative: false,
+                location: FixupLocation::Word,
+                data_offset: 1,
+                frame_thread: Some(1),
+                frame_method: None,
+                frame_datum: None,
+                target_thread: Some(0),
+                target_method: None,
+                target_datum: None,
+                target_displacement: 0,
+            }},
+        ];
+        round_trip(0x9c, Record::FIXUPP{ fixups });
+
+        let fixups = vec![BakpatFixup{ offset: 2, value: 0x10 }];
+        round_trip(0xb2, Record::BAKPAT{ seg: 1, location: BakpatLocation::Word, fixups });
+        let fixups = vec![BakpatFixup{ offset: 2, value: 0x12345 }];
+        round_trip(0xc9, Record::NBKPAT{ name: 3, location: BakpatLocation::Dword, fixups });
+
+        round_trip(0x94, Record::LINNUM{ group: Some(1), seg: 2, lines: vec![LineNumber{ line: 7, offset: 0x20 }] });
+        round_trip(0xc4, Record::LINSYM{ flags: 1, name: 2, lines: vec![LineNumber{ line: 10, offset: 4 }] });
+    }
+
+    #[test]
+    fn test_encode_round_trips_comdat() {
+        let comdat = |flags: u8, data: Vec<u8>, blocks: Vec<IteratedBlock>| Comdat{
+            flags,
+            selection: ComdatSelection::PickAny,
+            allocation: ComdatAllocation::FarCode,
+            align: ComdatAlign::Word,
+            offset: 0,
+            typeindex: 0,
+            base_group: None,
+            base_seg: None,
+            base_frame: Some(0),
+            name: 1,
+            data,
+            blocks,
+        };
+
+        round_trip(0xc2, Record::COMDAT{ comdat: comdat(0x00, vec![0xc3], vec![]) });
+        let blocks = vec![IteratedBlock{ repeat: 4, content: IteratedContent::Data(vec![0]) }];
+        round_trip(0xc3, Record::COMDAT{ comdat: comdat(0x02, vec![], blocks) });
+    }
+
+    #[test]
+    fn test_encode_round_trips_coments() {
+        let coment = |comclass: u8, coment: Coment| Record::COMENT{ header: ComentHeader{ comtype: 0x80, comclass }, coment };
+
+        round_trip(0x88, coment(0x00, Coment::Translator{ text: "MS C".to_string() }));
+        round_trip(0x88, coment(0x9c, Coment::DosVersion{ version: 0x0303 }));
+        round_trip(0x88, coment(0x9d, Coment::MemoryModel{ text: "0sO".to_string() }));
+        round_trip(0x88, coment(0x9e, Coment::DosSeg));
+        round_trip(0x88, coment(0x9f, Coment::DefaultLibrary{ name: "SLIBCE".to_string() }));
+        round_trip(0x88, coment(0xa1, Coment::NewOMF{ text: "\x01CV".to_string() }));
+        round_trip(0x88, coment(0xa2, Coment::LinkPassSeparator));
+        round_trip(0x88, coment(0xa3, Coment::Libmod{ name: "hello".to_string() }));
+        round_trip(0x88, coment(0xa4, Coment::ExeStr{ text: "(C)".to_string() }));
+        round_trip(0x88, coment(0xa6, Coment::IncErr));
+        round_trip(0x88, coment(0xa7, Coment::NoPad{ segs: vec![1, 2] }));
+        round_trip(0x88, coment(0xa8, Coment::WeakExtern{ externs: vec![WeakExtern{ weak: 1, default: 2 }] }));
+        round_trip(0x88, coment(0xa9, Coment::LazyExtern{ externs: vec![WeakExtern{ weak: 1, default: 2 }] }));
+        round_trip(0x88, coment(0xdf, Coment::User{ text: "hi".to_string() }));
+        round_trip(0x88, coment(0xe9, Coment::DependencyEnd));
+
+        let timestamp = DosTimestamp::new(0x1a21, 0x6000);
+        round_trip(0x88, coment(0xe9, Coment::Dependency{ name: "hello.c".to_string(), timestamp }));
+
+        round_trip(0x88, coment(0xa0, Coment::Impdef{
+            internal: "_Beep".to_string(),
+            module: "KERNEL".to_string(),
+            entry: ImportEntry::Ordinal(7),
+        }));
+        round_trip(0x88, coment(0xa0, Coment::Impdef{
+            internal: "_Beep".to_string(),
+            module: "KERNEL".to_string(),
+            entry: ImportEntry::Name("BEEP".to_string()),
+        }));
+        round_trip(0x88, coment(0xa0, Coment::Expdef{
+            flags: 0x80,
+            exported: "FOO".to_string(),
+            internal: "".to_string(),
+            ordinal: Some(3),
+        }));
+        round_trip(0x88, coment(0xa0, Coment::Incdef{ extdef_delta: -1, linnum_delta: 2 }));
+        round_trip(0x88, coment(0xa0, Coment::Lnkdir{ flags: 1, pcode_version: 2, cv_version: 3 }));
+    }
+
+    #[test]
+    fn test_encode_unknown_records_fails() {
+        assert!(encode_record(0x70, &Record::Unknown{ rectype: 0x70 }, Codepage::default()).is_err());
+
+        let coment = Record::COMENT{ header: ComentHeader{ comtype: 0, comclass: 0xfe }, coment: Coment::Unknown };
+        assert!(encode_record(0x88, &coment, Codepage::default()).is_err());
+    }
+
+    // The first module of a library built by MS LIB from MSC output. The
+    // tools left every checksum zero.
+    //
+    const HELLO: [u8; 401] = [
+            0x80, 0x09, 0x00, 0x07, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2e, 0x63, 0xcb, 0x88, 0x09, 0x00, 0x00,
+            0xa3, 0x05, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0xb3, 0x88, 0x06, 0x00, 0x00, 0xa1, 0x01, 0x43, 0x56,
+            0x00, 0x96, 0x3f, 0x00, 0x00, 0x04, 0x43, 0x4f, 0x44, 0x45, 0x04, 0x44, 0x41, 0x54, 0x41, 0x05,
+            0x43, 0x4f, 0x4e, 0x53, 0x54, 0x03, 0x42, 0x53, 0x53, 0x08, 0x46, 0x41, 0x52, 0x5f, 0x44, 0x41,
+            0x54, 0x41, 0x09, 0x24, 0x24, 0x53, 0x59, 0x4d, 0x42, 0x4f, 0x4c, 0x53, 0x06, 0x44, 0x45, 0x42,
+            0x53, 0x59, 0x4d, 0x07, 0x24, 0x24, 0x54, 0x59, 0x50, 0x45, 0x53, 0x06, 0x44, 0x45, 0x42, 0x54,
+            0x59, 0x50, 0x00, 0x96, 0x1f, 0x00, 0x05, 0x5f, 0x54, 0x45, 0x58, 0x54, 0x05, 0x5f, 0x44, 0x41,
+            0x54, 0x41, 0x05, 0x43, 0x4f, 0x4e, 0x53, 0x54, 0x04, 0x5f, 0x42, 0x53, 0x53, 0x06, 0x44, 0x47,
+            0x52, 0x4f, 0x55, 0x50, 0x00, 0x98, 0x07, 0x00, 0x48, 0x1b, 0x00, 0x0b, 0x02, 0x01, 0x00, 0x98,
+            0x07, 0x00, 0x48, 0x0f, 0x00, 0x0c, 0x03, 0x01, 0x00, 0x98, 0x07, 0x00, 0x48, 0x00, 0x00, 0x0d,
+            0x04, 0x01, 0x00, 0x98, 0x07, 0x00, 0x48, 0x00, 0x00, 0x0e, 0x05, 0x01, 0x00, 0x99, 0x09, 0x00,
+            0x21, 0x00, 0x00, 0x00, 0x00, 0x07, 0x08, 0x01, 0x00, 0x99, 0x09, 0x00, 0x21, 0x00, 0x00, 0x00,
+            0x00, 0x09, 0x0a, 0x01, 0x00, 0x9a, 0x08, 0x00, 0x0f, 0xff, 0x02, 0xff, 0x03, 0xff, 0x04, 0x00,
+            0x9c, 0x0b, 0x00, 0x10, 0x01, 0x11, 0x02, 0x12, 0x03, 0x13, 0x04, 0x44, 0x01, 0x00, 0x88, 0x05,
+            0x00, 0x00, 0x9d, 0x30, 0x73, 0x00, 0x88, 0x0f, 0x00, 0x00, 0x9f, 0x4f, 0x4c, 0x44, 0x4e, 0x41,
+            0x4d, 0x45, 0x53, 0x2e, 0x4c, 0x49, 0x42, 0x00, 0x88, 0x09, 0x00, 0x00, 0x9f, 0x53, 0x4c, 0x49,
+            0x42, 0x43, 0x45, 0x00, 0xb0, 0x0b, 0x00, 0x04, 0x5f, 0x66, 0x6f, 0x6f, 0x00, 0x62, 0x81, 0xc8,
+            0x00, 0x00, 0x8c, 0x16, 0x00, 0x0a, 0x5f, 0x5f, 0x61, 0x4e, 0x63, 0x68, 0x6b, 0x73, 0x74, 0x6b,
+            0x00, 0x07, 0x5f, 0x70, 0x72, 0x69, 0x6e, 0x74, 0x66, 0x00, 0x00, 0xa0, 0x1f, 0x00, 0x01, 0x00,
+            0x00, 0x55, 0x8b, 0xec, 0xb8, 0x00, 0x00, 0xe8, 0x00, 0x00, 0x56, 0x57, 0xb8, 0x00, 0x00, 0x50,
+            0xe8, 0x00, 0x00, 0x83, 0xc4, 0x02, 0x5f, 0x5e, 0x8b, 0xe5, 0x5d, 0xc3, 0x00, 0x9c, 0x0c, 0x00,
+            0x84, 0x07, 0x56, 0x02, 0xc4, 0x0c, 0x8d, 0x84, 0x10, 0x56, 0x03, 0x00, 0xa0, 0x13, 0x00, 0x02,
+            0x00, 0x00, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x21, 0x0a,
+            0x00, 0x00, 0x90, 0x0d, 0x00, 0x00, 0x01, 0x06, 0x5f, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x00, 0x00,
+            0x00, 0x00, 0xb2, 0x07, 0x00, 0x01, 0x01, 0x04, 0x00, 0x02, 0x00, 0x00, 0x8a, 0x02, 0x00, 0x30,
+            0x00,
+    ];
+
+    fn without_checksums(obj: &[u8]) -> Vec<u8> {
+        let mut out = obj.to_vec();
+        let mut at = 0;
+        while at + 3 <= out.len() {
+            let length = u16::from_le_bytes([out[at + 1], out[at + 2]]) as usize;
+            at += 3 + length;
+            out[at - 1] = 0;
+        }
+        out
+    }
+
+    #[test]
+    fn test_real_module_round_trips_byte_for_byte() {
+        let mut parser = Parser::new(&HELLO);
+        let mut writer = RecordWriter::new();
+
+        loop {
+            let parsed = parser.next_parsed().unwrap();
+            if parsed.record == Record::None {
+                break;
+            }
+            writer.parsed(&parsed, Codepage::default()).unwrap();
+        }
+
+        assert_eq!(without_checksums(writer.bytes()), without_checksums(&HELLO));
+    }
+
+    #[test]
+    fn test_parsed_unknown_record_is_copied() {
+        let obj = [0x70, 0x02, 0x00, 0x55, 0x39];
+        let mut parser = Parser::new(&obj);
+        let parsed = parser.next_parsed().unwrap();
+
+        let mut writer = RecordWriter::new();
+        writer.parsed(&parsed, Codepage::default()).unwrap();
+        assert_eq!(writer.bytes(), obj);
+    }
+
+    #[test]
+    fn test_start_address_encodes_16_and_32_bits() {
+        let start = start_address(FrameMethod::Grpdef, Some(1), TargetMethod::Segdef, 2, 0x10).unwrap();
+        assert_eq!(start.fix_data, 0x10);
+
+        let mut body = RecordBody::new();
+        body.modend(true, Some(&start), false).unwrap();
+        assert_eq!(body.bytes(), &[0xc1, 0x10, 0x01, 0x02, 0x10, 0x00]);
+
+        let mut writer = RecordWriter::new();
+        writer.record(0x8a, body.bytes()).unwrap();
+        let mut parser = Parser::new(writer.bytes());
+        assert_eq!(parser.next().unwrap(), Record::MODEND{ main: true, start_address: Some(start), modtype: 0xc1 });
+
+        let start = start_address(FrameMethod::Target, None, TargetMethod::SegdefNoDisplacement, 1, 0).unwrap();
+        let mut body = RecordBody::new();
+        body.modend(true, Some(&start), true).unwrap();
+        assert_eq!(body.bytes(), &[0xc1, 0x54, 0x01]);
+
+        let start = start_address(FrameMethod::Segdef, Some(1), TargetMethod::Segdef, 1, 0x12345).unwrap();
+        let mut body = RecordBody::new();
+        body.modend(true, Some(&start), true).unwrap();
+        assert_eq!(body.bytes(), &[0xc1, 0x00, 0x01, 0x01, 0x45, 0x23, 0x01, 0x00]);
+        assert!(RecordBody::new().modend(true, Some(&start), false).is_err());
+    }
+
+    #[test]
+    fn test_start_address_rejects_bad_methods() {
+        assert!(start_address(FrameMethod::PreviousDataRecord, None, TargetMethod::Segdef, 1, 0).is_err());
+        assert!(start_address(FrameMethod::Grpdef, None, TargetMethod::Segdef, 1, 0).is_err());
+        assert!(start_address(FrameMethod::Target, None, TargetMethod::SegdefNoDisplacement, 1, 4).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+
+use crate::error::Error as ObjError;
+use crate::objbuilder::*;
+use crate::objfile::*;
+
+// Compiles a small text description of an object module, for test
+// fixtures and stubs too simple to be worth an assembler. One directive
+// per line; `;` starts a comment. All numbers are hex, as in a listing,
+// with an optional `0x` prefix or `h` suffix.
+//
+//   module NAME
+//   segment NAME CLASS [align=byte|word|para|page|dword]
+//           [combine=private|public|stack|common] [overlay=NAME]
+//           [length=N] [use32]
+//   group NAME SEGMENT...
+//   extern NAME...
+//   public NAME SEGMENT OFFSET
+//   data SEGMENT OFFSET BYTE...
+//   repeat SEGMENT OFFSET COUNT BYTE...
+//   line SEGMENT LINE OFFSET
+//   fixup SEGMENT OFFSET LOCATION seg|group|extern NAME
+//         [disp N] [frame seg|group|extern NAME | frame location|target]
+//         [self]
+//   start SEGMENT OFFSET
+//
+// LOCATION is one of byte, word, selector, pointer, hibyte, loaderword,
+// offset32, loaderoffset32 or pointer48. Names must be declared before
+// they're used. The module name defaults to "omfgen".
+//
+pub fn compile(text: &str) -> Result<Vec<u8>, ObjError> {
+    let mut compiler = Compiler::default();
+
+    for (line, text) in text.lines().enumerate() {
+        let text = text.split(';').next().unwrap_or("");
+        let words: Vec<&str> = text.split_whitespace().collect();
+
+        if !words.is_empty() {
+            compiler.directive(&words)
+                .map_err(|err| ObjError::new(&format!("line {}: {}", line + 1, err.details)))?;
+        }
+    }
+
+    compiler.builder.unwrap_or_else(|| ObjBuilder::new("omfgen")).build()
+}
+
+#[derive(Default)]
+struct Compiler {
+    builder: Option<ObjBuilder>,
+    segments: HashMap<String, SegmentId>,
+    groups: HashMap<String, GroupId>,
+    externs: HashMap<String, ExternId>,
+}
+
+fn number(word: &str) -> Result<u64, ObjError> {
+    let digits = word.strip_prefix("0x")
+        .or_else(|| word.strip_suffix('h'))
+        .or_else(|| word.strip_suffix('H'))
+        .unwrap_or(word);
+
+    u64::from_str_radix(digits, 16).map_err(|_| ObjError::new(&format!("{} is not a hex number", word)))
+}
+
+fn number32(word: &str) -> Result<u32, ObjError> {
+    u32::try_from(number(word)?).map_err(|_| ObjError::new(&format!("{} does not fit in 32 bits", word)))
+}
+
+fn bytes(words: &[&str]) -> Result<Vec<u8>, ObjError> {
+    words.iter()
+        .map(|word| u8::try_from(number(word)?).map_err(|_| ObjError::new(&format!("{} is not a byte", word))))
+        .collect()
+}
+
+fn location(word: &str) -> Result<FixupLocation, ObjError> {
+    match word {
+        "byte" => Ok(FixupLocation::Byte),
+        "word" => Ok(FixupLocation::Word),
+        "selector" => Ok(FixupLocation::Selector),
+        "pointer" => Ok(FixupLocation::LongPointer),
+        "hibyte" => Ok(FixupLocation::HighOrderByte),
+        "loaderword" => Ok(FixupLocation::LoaderWord),
+        "offset32" => Ok(FixupLocation::Offset32),
+        "loaderoffset32" => Ok(FixupLocation::LoaderOffset32),
+        "pointer48" => Ok(FixupLocation::Pointer48),
+        _ => Err(ObjError::new(&format!("unknown fixup location {}", word))),
+    }
+}
+
+impl Compiler {
+    fn builder(&mut self) -> &mut ObjBuilder {
+        self.builder.get_or_insert_with(|| ObjBuilder::new("omfgen"))
+    }
+
+    fn segment(&self, name: &str) -> Result<SegmentId, ObjError> {
+        self.segments.get(name).copied().ok_or_else(|| ObjError::new(&format!("segment {} is not declared", name)))
+    }
+
+    fn group(&self, name: &str) -> Result<GroupId, ObjError> {
+        self.groups.get(name).copied().ok_or_else(|| ObjError::new(&format!("group {} is not declared", name)))
+    }
+
+    fn external(&self, name: &str) -> Result<ExternId, ObjError> {
+        self.externs.get(name).copied().ok_or_else(|| ObjError::new(&format!("extern {} is not declared", name)))
+    }
+
+    fn args<'a>(words: &[&'a str], min: usize) -> Result<Vec<&'a str>, ObjError> {
+        if words.len() < min + 1 {
+            return Err(ObjError::new(&format!("{} needs at least {} arguments", words[0], min)));
+        }
+        Ok(words[1..].to_vec())
+    }
+
+    fn directive(&mut self, words: &[&str]) -> Result<(), ObjError> {
+        match words[0] {
+            "module" => {
+                let args = Self::args(words, 1)?;
+                if self.builder.is_some() {
+                    return Err(ObjError::new("module must come before anything else"));
+                }
+                self.builder = Some(ObjBuilder::new(args[0]));
+            },
+            "segment" => {
+                let args = Self::args(words, 2)?;
+                let spec = Self::segment_spec(&args)?;
+                let seg = self.builder().segment(spec);
+                self.segments.insert(args[0].to_string(), seg);
+            },
+            "group" => {
+                let args = Self::args(words, 1)?;
+                let segs = args[1..].iter()
+                    .map(|name| self.segment(name))
+                    .collect::<Result<Vec<_>, ObjError>>()?;
+                let group = self.builder().group(args[0], &segs);
+                self.groups.insert(args[0].to_string(), group);
+            },
+            "extern" => {
+                for name in Self::args(words, 1)? {
+                    let ext = self.builder().external(name);
+                    self.externs.insert(name.to_string(), ext);
+                }
+            },
+            "public" => {
+                let args = Self::args(words, 3)?;
+                let seg = self.segment(args[1])?;
+                let offset = number32(args[2])?;
+                self.builder().public(args[0], seg, offset);
+            },
+            "data" => {
+                let args = Self::args(words, 3)?;
+                let seg = self.segment(args[0])?;
+                let offset = number32(args[1])?;
+                let data = bytes(&args[2..])?;
+                self.builder().data(seg, offset, &data)?;
+            },
+            "repeat" => {
+                let args = Self::args(words, 4)?;
+                let seg = self.segment(args[0])?;
+                let offset = number32(args[1])?;
+                let count = number32(args[2])?;
+                let pattern = bytes(&args[3..])?;
+                self.builder().repeat(seg, offset, &pattern, count)?;
+            },
+            "line" => {
+                let args = Self::args(words, 3)?;
+                let seg = self.segment(args[0])?;
+                let line = u16::try_from(number(args[1])?).map_err(|_| ObjError::new(&format!("line number {} is too large", args[1])))?;
+                let offset = number32(args[2])?;
+                self.builder().line(seg, line, offset)?;
+            },
+            "fixup" => {
+                let args = Self::args(words, 5)?;
+                let seg = self.segment(args[0])?;
+                let offset = number32(args[1])?;
+                let fixup = self.fixup_spec(&args[2..])?;
+                self.builder().fixup(seg, offset, fixup)?;
+            },
+            "start" => {
+                let args = Self::args(words, 2)?;
+                let seg = self.segment(args[0])?;
+                let offset = number32(args[1])?;
+                self.builder().start(seg, offset);
+            },
+            word => return Err(ObjError::new(&format!("unknown directive {}", word))),
+        }
+
+        Ok(())
+    }
+
+    fn segment_spec(args: &[&str]) -> Result<SegmentSpec, ObjError> {
+        let mut spec = SegmentSpec::new(args[0], args[1]);
+
+        for option in &args[2..] {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            match (key, value) {
+                ("use32", "") => spec.use32 = true,
+                ("align", value) => spec.align = match value {
+                    "byte" => Align::Byte,
+                    "word" => Align::Word,
+                    "para" => Align::Paragraph,
+                    "page" => Align::Page,
+                    "dword" => Align::Dword,
+                    _ => return Err(ObjError::new(&format!("unknown alignment {}", value))),
+                },
+                ("combine", value) => spec.combine = match value {
+                    "private" => Combine::Private,
+                    "public" => Combine::Public{ raw: 2 },
+                    "stack" => Combine::Stack,
+                    "common" => Combine::Common,
+                    _ => return Err(ObjError::new(&format!("unknown combine type {}", value))),
+                },
+                ("overlay", value) if !value.is_empty() => spec.overlay = Some(value.to_string()),
+                ("length", value) => spec.length = number(value)?,
+                _ => return Err(ObjError::new(&format!("unknown segment option {}", option))),
+            }
+        }
+
+        Ok(spec)
+    }
+
+    fn fixup_spec(&self, args: &[&str]) -> Result<FixupSpec, ObjError> {
+        let target = match args[1] {
+            "seg" => FixupTarget::Segment(self.segment(args[2])?),
+            "group" => FixupTarget::Group(self.group(args[2])?),
+            "extern" => FixupTarget::Extern(self.external(args[2])?),
+            kind => return Err(ObjError::new(&format!("unknown fixup target kind {}", kind))),
+        };
+
+        let mut fixup = FixupSpec::new(location(args[0])?, target);
+        let mut rest = args[3..].iter();
+
+        while let Some(word) = rest.next() {
+            let mut operand = || rest.next().copied().ok_or_else(|| ObjError::new(&format!("{} needs an operand", word)));
+
+            match *word {
+                "self" => fixup.self_relative = true,
+                "disp" => fixup.displacement = number32(operand()?)?,
+                "frame" => fixup.frame = match operand()? {
+                    "location" => FixupFrame::Location,
+                    "target" => FixupFrame::Target,
+                    "seg" => FixupFrame::Segment(self.segment(operand()?)?),
+                    "group" => FixupFrame::Group(self.group(operand()?)?),
+                    "extern" => FixupFrame::Extern(self.external(operand()?)?),
+                    kind => return Err(ObjError::new(&format!("unknown fixup frame kind {}", kind))),
+                },
+                word => return Err(ObjError::new(&format!("unknown fixup option {}", word))),
+            }
+        }
+
+        Ok(fixup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_all(obj: &[u8]) -> Vec<Record> {
+        let options = ParseOptions{ strict: true, checksum: ChecksumPolicy::Strict, ..Default::default() };
+        let mut parser = Parser::with_options(obj, options);
+        let mut records = Vec::new();
+
+        loop {
+            match parser.next() {
+                Ok(Record::None) => break,
+                Ok(record) => records.push(record),
+                Err(err) => assert!(false, "parser returned {}", err),
+            }
+        }
+
+        records
+    }
+
+    #[test]
+    fn test_compile_hello() {
+        let obj = compile("
+            ; DOS hello world
+            module hello
+            segment _TEXT CODE align=byte
+            segment _DATA DATA align=word
+            group DGROUP _DATA
+            extern _puts
+            public _main _TEXT 0
+
+            data _TEXT 0 b8 00 00 50 e8 00 00 58 c3
+            fixup _TEXT 1 word seg _DATA frame group DGROUP
+            fixup _TEXT 5 word extern _puts self
+            data _DATA 0 48 69 00
+            repeat _DATA 10h 100 00
+            line _TEXT 3 0
+            line _TEXT 4 7
+            start _TEXT 0
+        ").unwrap();
+
+        let records = parse_all(&obj);
+        assert_eq!(records[0], Record::THEADR{ name: "hello".to_string() });
+        assert!(matches!(records.last(), Some(Record::MODEND{ main: true, start_address: Some(_), .. })));
+
+        match &records[2] {
+            Record::SEGDEF{ segs } => assert_eq!(segs[0].align, Align::Byte),
+            x => assert!(false, "compiled object has {:x?}", x),
+        }
+
+        let data: Vec<&Vec<u8>> = records.iter()
+            .filter_map(|record| match record {
+                Record::LEDATA{ data, .. } => Some(data),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(data, vec![&vec![0xb8, 0, 0, 0x50, 0xe8, 0, 0, 0x58, 0xc3], &vec![0x48, 0x69, 0]]);
+
+        match records.iter().find(|record| matches!(record, Record::FIXUPP{ .. })) {
+            Some(Record::FIXUPP{ fixups }) => assert_eq!(fixups.len(), 2),
+            x => assert!(false, "compiled object has {:x?}", x),
+        }
+
+        match records.iter().find(|record| matches!(record, Record::LIDATA{ .. })) {
+            Some(Record::LIDATA{ offset: 0x10, blocks, .. }) => assert_eq!(blocks[0].repeat, 0x100),
+            x => assert!(false, "compiled object has {:x?}", x),
+        }
+
+        match records.iter().find(|record| matches!(record, Record::LINNUM{ .. })) {
+            Some(Record::LINNUM{ group: None, seg: 1, lines }) => assert_eq!(lines, &vec![
+                LineNumber{ line: 3, offset: 0 },
+                LineNumber{ line: 4, offset: 7 },
+            ]),
+            x => assert!(false, "compiled object has {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_compile_reports_line() {
+        let err = compile("module m\nsegment _TEXT CODE\ndata _DATA 0 90\n").unwrap_err();
+        assert_eq!(err.details, "line 3: segment _DATA is not declared");
+
+        let err = compile("bogus\n").unwrap_err();
+        assert_eq!(err.details, "line 1: unknown directive bogus");
+
+        let err = compile("segment _TEXT CODE\ndata _TEXT 0 100\n").unwrap_err();
+        assert_eq!(err.details, "line 2: 100 is not a byte");
+    }
+}
@@ -0,0 +1,207 @@
+use crate::error::Error as SymError;
+
+// The version of MAPSYM whose format this writes, which is what SYMDEB
+// and CodeView expect
+//
+const SYM_MAJOR: u8 = 4;
+const SYM_MINOR: u8 = 0;
+
+// Symbol values in a segment are 32 bits wide rather than 16
+//
+const SEG_32BIT: u8 = 0x01;
+
+// The symbols of one segment of a program, as offsets from the frame the
+// segment is addressed from
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct SymSegment {
+    pub name: String,
+    pub frame: u16,
+    pub symbols: Vec<(String, u32)>,
+}
+
+// The symbols of a program the way MAPSYM writes them to a .SYM file:
+// absolute symbols, which it calls constants, and the rest grouped by the
+// segment they're in
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct SymFile {
+    pub module: String,
+    pub entry_segment: u16,
+    pub constants: Vec<(String, u16)>,
+    pub segments: Vec<SymSegment>,
+}
+
+fn word(out: &mut Vec<u8>, value: usize, what: &str) -> Result<(), SymError> {
+    let value = u16::try_from(value).map_err(|_| SymError::new(&format!("{} is too large for a .SYM file", what)))?;
+    out.extend(value.to_le_bytes());
+    Ok(())
+}
+
+fn name(out: &mut Vec<u8>, name: &str) -> Result<(), SymError> {
+    let len = u8::try_from(name.len()).map_err(|_| SymError::new(&format!("name {} is too long for a .SYM file", name)))?;
+    out.push(len);
+    out.extend(name.as_bytes());
+    Ok(())
+}
+
+fn pad_to_paragraph(out: &mut Vec<u8>) {
+    out.resize(out.len().div_ceil(16) * 16, 0);
+}
+
+impl SymFile {
+    // Write the file. It's a map header and its constants, then each
+    // segment on a paragraph boundary, then an end marker holding the
+    // version. Each part is followed by a table of pointers to its
+    // symbols, sorted by value, which is what a debugger searches.
+    //
+    pub fn write(&self) -> Result<Vec<u8>, SymError> {
+        let max_symbol = self.constants.iter().map(|(name, _)| name)
+            .chain(self.segments.iter().flat_map(|segment| segment.symbols.iter().map(|(name, _)| name)))
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0);
+
+        let mut out = Vec::new();
+
+        // map header; the pointers to the end marker, the constant table
+        // and the first segment are filled in once they're known
+        //
+        word(&mut out, 0, "")?;
+        out.extend([0, 0]);
+        word(&mut out, self.entry_segment as usize, "")?;
+        word(&mut out, self.constants.len(), "number of constants")?;
+        word(&mut out, 0, "")?;
+        word(&mut out, self.segments.len(), "number of segments")?;
+        word(&mut out, 0, "")?;
+        out.push(max_symbol.min(0xff) as u8);
+        name(&mut out, &self.module)?;
+
+        let mut constants: Vec<(&str, u16)> = self.constants.iter().map(|(name, value)| (name.as_str(), *value)).collect();
+        constants.sort_by_key(|(_, value)| *value);
+
+        let mut pointers = Vec::new();
+        for (symbol, value) in constants {
+            pointers.push(out.len());
+            word(&mut out, value as usize, "")?;
+            name(&mut out, symbol)?;
+        }
+
+        let table = out.len();
+        out[8..10].copy_from_slice(&(table as u16).to_le_bytes());
+        for pointer in pointers {
+            word(&mut out, pointer, "constant table")?;
+        }
+        pad_to_paragraph(&mut out);
+
+        let first = out.len();
+        out[12..14].copy_from_slice(&((first / 16) as u16).to_le_bytes());
+
+        for (index, segment) in self.segments.iter().enumerate() {
+            let start = out.len();
+            let wide = segment.symbols.iter().any(|(_, value)| *value > 0xffff);
+
+            // segment header. The segments are chained in a ring: the last
+            // one points back to the first.
+            //
+            word(&mut out, 0, "")?;
+            word(&mut out, segment.symbols.len(), "number of symbols")?;
+            word(&mut out, 0, "")?;
+            word(&mut out, segment.frame as usize, "")?;
+            out.extend([0; 6]);
+            out.push(if wide { SEG_32BIT } else { 0 });
+            out.push(0);
+            word(&mut out, 0, "")?;
+            out.extend([0, 0]);
+            name(&mut out, &segment.name)?;
+
+            let mut symbols: Vec<&(String, u32)> = segment.symbols.iter().collect();
+            symbols.sort_by_key(|(_, value)| *value);
+
+            let mut pointers = Vec::new();
+            for (symbol, value) in symbols {
+                pointers.push(out.len() - start);
+                match wide {
+                    true => out.extend(value.to_le_bytes()),
+                    false => word(&mut out, *value as usize, "")?,
+                }
+                name(&mut out, symbol)?;
+            }
+
+            let table = out.len() - start;
+            let table = u16::try_from(table).map_err(|_| SymError::new(&format!("segment {} has too many symbols for a .SYM file", segment.name)))?;
+            out[start + 4..start + 6].copy_from_slice(&table.to_le_bytes());
+            for pointer in pointers {
+                word(&mut out, pointer, &format!("segment {}", segment.name))?;
+            }
+            pad_to_paragraph(&mut out);
+
+            let next = match index + 1 == self.segments.len() {
+                true => first,
+                false => out.len(),
+            };
+            let next = u16::try_from(next / 16).map_err(|_| SymError::new("too many symbols for a .SYM file"))?;
+            out[start..start + 2].copy_from_slice(&next.to_le_bytes());
+        }
+
+        let end = u16::try_from(out.len() / 16).map_err(|_| SymError::new("too many symbols for a .SYM file"))?;
+        out[0..2].copy_from_slice(&end.to_le_bytes());
+        out.extend([0, 0, SYM_MINOR, SYM_MAJOR]);
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_sym() {
+        let sym = SymFile{
+            module: "HELLO".to_string(),
+            entry_segment: 0,
+            constants: vec![("ABS".to_string(), 0x1234)],
+            segments: vec![
+                SymSegment{ name: "_TEXT".to_string(), frame: 0, symbols: vec![("_puts".to_string(), 0x20), ("_main".to_string(), 0x10)] },
+                SymSegment{ name: "DGROUP".to_string(), frame: 3, symbols: vec![("_msg".to_string(), 0x42)] },
+            ],
+        };
+        let bytes = sym.write().unwrap();
+
+        let word = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize;
+
+        // header: a constant, first segment in paragraph 2, max name 5
+        //
+        assert_eq!(word(6), 1);
+        assert_eq!(word(10), 2);
+        assert_eq!(word(12), 2);
+        assert_eq!(bytes[14], 5);
+        assert_eq!(&bytes[15..21], b"\x05HELLO");
+        assert_eq!(&bytes[word(word(8))..word(word(8)) + 6], b"\x34\x12\x03ABS");
+
+        // the first segment, with its symbols by value
+        //
+        let seg = 0x20;
+        assert_eq!(word(seg + 2), 2);
+        assert_eq!(&bytes[seg + 20..seg + 26], b"\x05_TEXT");
+        let table = seg + word(seg + 4);
+        assert_eq!(&bytes[seg + word(table)..seg + word(table) + 8], b"\x10\x00\x05_main");
+        assert_eq!(&bytes[seg + word(table + 2)..seg + word(table + 2) + 8], b"\x20\x00\x05_puts");
+
+        // the second segment points back to the first
+        //
+        let seg = word(seg) * 16;
+        assert_eq!(word(seg + 6), 3);
+        assert_eq!(word(seg), 2);
+
+        // and the end marker
+        //
+        let end = word(0) * 16;
+        assert_eq!(&bytes[end..], &[0, 0, SYM_MINOR, SYM_MAJOR]);
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use crate::error::Error as ObjError;
+use crate::objbuilder::*;
+use crate::objfile::*;
+
+// How a thunk gets to its target
+//
+#[derive(Clone)]
+#[derive(Debug)]
+pub enum ThunkKind {
+    // JMP FAR target
+    Jump,
+
+    // Hand off to an overlay or bank manager: AX holds `selector`, DX:BX
+    // the target, and control goes to JMP FAR dispatcher. The dispatcher
+    // is expected to map the target in and jump to it.
+    Dispatch{ dispatcher: String, selector: u16 },
+}
+
+// A public entry point `name` which passes control on to `target`
+//
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Thunk {
+    pub name: String,
+    pub target: String,
+    pub kind: ThunkKind,
+}
+
+impl Thunk {
+    pub fn jump(name: &str, target: &str) -> Thunk {
+        Thunk{ name: name.to_string(), target: target.to_string(), kind: ThunkKind::Jump }
+    }
+
+    pub fn dispatch(name: &str, target: &str, dispatcher: &str, selector: u16) -> Thunk {
+        Thunk{
+            name: name.to_string(),
+            target: target.to_string(),
+            kind: ThunkKind::Dispatch{ dispatcher: dispatcher.to_string(), selector },
+        }
+    }
+}
+
+// Add `thunks` to a segment of `builder` one after another starting at
+// `offset`, declaring an extern for each target and dispatcher (once
+// each). Returns the offset just past the last thunk.
+//
+pub fn add_thunks(builder: &mut ObjBuilder, seg: SegmentId, offset: u32, thunks: &[Thunk]) -> Result<u32, ObjError> {
+    let mut externs: HashMap<String, ExternId> = HashMap::new();
+    let mut extern_id = |builder: &mut ObjBuilder, name: &str| {
+        *externs.entry(name.to_string()).or_insert_with(|| builder.external(name))
+    };
+
+    // the code goes in as one block, so it packs into as few LEDATAs as
+    // it can
+    //
+    let mut code = Vec::new();
+    let mut fixups = Vec::new();
+
+    for thunk in thunks {
+        let at = offset.checked_add(code.len() as u32)
+            .ok_or_else(|| ObjError::new("thunks overflow the segment"))?;
+        builder.public(&thunk.name, seg, at);
+        let target = FixupTarget::Extern(extern_id(builder, &thunk.target));
+
+        match &thunk.kind {
+            ThunkKind::Jump => {
+                code.extend([0xea, 0x00, 0x00, 0x00, 0x00]);
+                fixups.push((at + 1, FixupSpec::new(FixupLocation::LongPointer, target)));
+            },
+            ThunkKind::Dispatch{ dispatcher, selector } => {
+                let dispatcher = FixupTarget::Extern(extern_id(builder, dispatcher));
+                let [lo, hi] = selector.to_le_bytes();
+
+                code.extend([
+                    0xb8, lo, hi,                   // MOV AX, selector
+                    0xbb, 0x00, 0x00,               // MOV BX, OFFSET target
+                    0xba, 0x00, 0x00,               // MOV DX, SEG target
+                    0xea, 0x00, 0x00, 0x00, 0x00,   // JMP FAR dispatcher
+                ]);
+                fixups.push((at + 4, FixupSpec::new(FixupLocation::Word, target)));
+                fixups.push((at + 7, FixupSpec::new(FixupLocation::Selector, target)));
+                fixups.push((at + 10, FixupSpec::new(FixupLocation::LongPointer, dispatcher)));
+            },
+        }
+    }
+
+    let end = (offset as u64) + code.len() as u64;
+    let end = u32::try_from(end).map_err(|_| ObjError::new("thunks overflow the segment"))?;
+
+    if !code.is_empty() {
+        builder.data(seg, offset, &code)?;
+    }
+    for (at, fixup) in fixups {
+        builder.fixup(seg, at, fixup)?;
+    }
+
+    Ok(end)
+}
+
+// An object named `module` holding nothing but `thunks`, in a segment
+// described by `spec`
+//
+pub fn thunk_object(module: &str, spec: SegmentSpec, thunks: &[Thunk]) -> Result<Vec<u8>, ObjError> {
+    let mut builder = ObjBuilder::new(module);
+    let seg = builder.segment(spec);
+    add_thunks(&mut builder, seg, 0, thunks)?;
+    builder.build()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_all(obj: &[u8]) -> Vec<Record> {
+        let options = ParseOptions{ strict: true, checksum: ChecksumPolicy::Strict, ..Default::default() };
+        let mut parser = Parser::with_options(obj, options);
+        let mut records = Vec::new();
+
+        loop {
+            match parser.next() {
+                Ok(Record::None) => break,
+                Ok(record) => records.push(record),
+                Err(err) => assert!(false, "parser returned {}", err),
+            }
+        }
+
+        records
+    }
+
+    #[test]
+    fn test_jump_thunks() {
+        let thunks = vec![Thunk::jump("_f", "_f_impl"), Thunk::jump("_g", "_g_impl")];
+        let obj = thunk_object("thunks", SegmentSpec::new("THUNK_TEXT", "CODE"), &thunks).unwrap();
+        let records = parse_all(&obj);
+
+        let externs: Vec<&str> = records.iter()
+            .filter_map(|record| match record {
+                Record::EXTDEF{ externs } => Some(externs),
+                _ => None,
+            })
+            .flatten()
+            .map(|ext| ext.name.as_str())
+            .collect();
+        assert_eq!(externs, vec!["_f_impl", "_g_impl"]);
+
+        let publics: Vec<(&str, u32)> = records.iter()
+            .filter_map(|record| match record {
+                Record::PUBDEF{ publics, .. } => Some(publics),
+                _ => None,
+            })
+            .flatten()
+            .map(|public| (public.name.as_str(), public.offset))
+            .collect();
+        assert_eq!(publics, vec![("_f", 0), ("_g", 5)]);
+
+        match records.iter().find(|record| matches!(record, Record::LEDATA{ .. })) {
+            Some(Record::LEDATA{ data, .. }) => assert_eq!(data, &vec![0xea, 0, 0, 0, 0, 0xea, 0, 0, 0, 0]),
+            x => assert!(false, "thunk object has {:x?}", x),
+        }
+
+        match records.iter().find(|record| matches!(record, Record::FIXUPP{ .. })) {
+            Some(Record::FIXUPP{ fixups }) => {
+                let locations: Vec<(usize, Option<usize>)> = fixups.iter()
+                    .filter_map(|sub| match sub {
+                        FixupSubrecord::Fixup{ fixup } => Some((fixup.data_offset, fixup.target_datum)),
+                        _ => None,
+                    })
+                    .collect();
+                assert_eq!(locations, vec![(1, Some(1)), (6, Some(2))]);
+            },
+            x => assert!(false, "thunk object has {:x?}", x),
+        }
+    }
+
+    #[test]
+    fn test_dispatch_thunks_share_the_dispatcher() {
+        let thunks = vec![
+            Thunk::dispatch("_f", "_f_impl", "__ovlmgr", 1),
+            Thunk::dispatch("_g", "_g_impl", "__ovlmgr", 2),
+        ];
+
+        let mut builder = ObjBuilder::new("thunks");
+        let seg = builder.segment(SegmentSpec::new("THUNK_TEXT", "CODE"));
+        assert_eq!(add_thunks(&mut builder, seg, 0x10, &thunks).unwrap(), 0x10 + 28);
+        let records = parse_all(&builder.build().unwrap());
+
+        let externs: Vec<&str> = records.iter()
+            .filter_map(|record| match record {
+                Record::EXTDEF{ externs } => Some(externs),
+                _ => None,
+            })
+            .flatten()
+            .map(|ext| ext.name.as_str())
+            .collect();
+        assert_eq!(externs, vec!["_f_impl", "__ovlmgr", "_g_impl"]);
+
+        match records.iter().find(|record| matches!(record, Record::LEDATA{ .. })) {
+            Some(Record::LEDATA{ offset: 0x10, data, .. }) => {
+                assert_eq!(&data[..3], &[0xb8, 0x01, 0x00]);
+                assert_eq!(&data[14..17], &[0xb8, 0x02, 0x00]);
+            },
+            x => assert!(false, "thunk object has {:x?}", x),
+        }
+    }
+}
@@ -0,0 +1,410 @@
+use crate::error::Error as ObjError;
+use crate::objfile::*;
+use crate::objpatch::*;
+
+// A DOS-style wildcard pattern: `*` matches any run of characters and `?`
+// any one character. Matching is case sensitive, as OMF names are.
+//
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Pattern {
+    pattern: Vec<char>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Pattern {
+        Pattern{ pattern: pattern.chars().collect() }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.captures(name).is_some()
+    }
+
+    // What each `*` in the pattern matched, or None if it doesn't match.
+    // Stars match as little as they can.
+    //
+    pub fn captures(&self, name: &str) -> Option<Vec<String>> {
+        let name: Vec<char> = name.chars().collect();
+        let mut captures = Vec::new();
+
+        if Self::match_at(&self.pattern, &name, &mut captures) {
+            Some(captures)
+        } else {
+            None
+        }
+    }
+
+    fn match_at(pattern: &[char], name: &[char], captures: &mut Vec<String>) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => {
+                for len in 0..=name.len() {
+                    captures.push(name[..len].iter().collect());
+                    if Self::match_at(rest, &name[len..], captures) {
+                        return true;
+                    }
+                    captures.pop();
+                }
+                false
+            },
+            Some((ch, rest)) => match name.split_first() {
+                Some((first, name)) if *ch == '?' || ch == first => Self::match_at(rest, name, captures),
+                _ => false,
+            },
+        }
+    }
+}
+
+// Rename whatever matches a pattern. Each `*` in the replacement is
+// filled in with what the corresponding `*` in the pattern matched, so
+// `_TEXT*` to `CODE*` turns `_TEXT2` into `CODE2`.
+//
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Rename {
+    pub pattern: Pattern,
+    pub replacement: String,
+}
+
+impl Rename {
+    pub fn new(pattern: &str, replacement: &str) -> Rename {
+        Rename{ pattern: Pattern::new(pattern), replacement: replacement.to_string() }
+    }
+
+    pub fn apply(&self, name: &str) -> Option<String> {
+        let captures = self.pattern.captures(name)?;
+        let mut captures = captures.into_iter();
+        let mut out = String::new();
+
+        for ch in self.replacement.chars() {
+            match ch {
+                '*' => out.push_str(&captures.next().unwrap_or_default()),
+                ch => out.push(ch),
+            }
+        }
+
+        Some(out)
+    }
+}
+
+// The first rule which matches decides the new name
+//
+fn rename(rules: &[Rename], name: &str) -> Option<String> {
+    rules.iter().find_map(|rule| rule.apply(name))
+}
+
+// What to change in a module. Segments are renamed before their classes
+// are set, so `segment_classes` sees the new names; a segment it matches
+// isn't also subject to `classes`.
+//
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(Default)]
+pub struct Transform {
+    pub segments: Vec<Rename>,
+    pub classes: Vec<Rename>,
+    pub symbols: Vec<Rename>,
+
+    // Set the class of segments whose name matches
+    pub segment_classes: Vec<(Pattern, String)>,
+
+    // Comments which the linker doesn't need; ones marked no-purge and
+    // ones which change how the module links are always kept
+    pub strip_comments: bool,
+
+    // LINNUM and LINSYM
+    pub strip_line_numbers: bool,
+
+    // LPUBDEF. Local externs are left alone, since fixups refer to them.
+    pub strip_locals: bool,
+}
+
+impl Transform {
+    pub fn apply(&self, patcher: &mut ObjPatcher) -> Result<(), ObjError> {
+        self.strip(patcher);
+        self.rename_segments(patcher)?;
+        self.rename_symbols(patcher)?;
+        Ok(())
+    }
+
+    fn strip(&self, patcher: &mut ObjPatcher) {
+        patcher.records.retain(|rec| {
+            match (rec.rectype, &rec.record) {
+                (0x94 | 0x95 | 0xc4 | 0xc5, _) => !self.strip_line_numbers,
+                (0xb6 | 0xb7, _) => !self.strip_locals,
+                (_, Record::COMENT{ header, .. }) => !self.strip_comments || header.nopurge() || Self::links(header.comclass),
+                _ => true,
+            }
+        });
+    }
+
+    // Comment classes which carry information the linker acts on
+    //
+    fn links(comclass: u8) -> bool {
+        matches!(comclass, 0x9e | 0x9f | 0xa0 | 0xa2 | 0xa7 | 0xa8 | 0xa9 | 0xaa)
+    }
+
+    fn rename_segments(&self, patcher: &mut ObjPatcher) -> Result<(), ObjError> {
+        for at in 0..patcher.records.len() {
+            let segs = match &patcher.records[at].record {
+                Record::SEGDEF{ segs } => segs.clone(),
+                _ => continue,
+            };
+
+            let mut changed = Vec::new();
+            for seg in segs {
+                let lnames = patcher.lnames();
+                let lname = |index: Option<usize>| index.and_then(|index| lnames.get(index.wrapping_sub(1))).map(|name| name.to_string());
+                let name = lname(seg.name);
+                let class = lname(seg.class);
+
+                let new_name = name.as_deref().and_then(|name| rename(&self.segments, name));
+                let seg_name = new_name.clone().or(name);
+
+                let new_class = seg_name.as_deref()
+                    .and_then(|name| self.segment_classes.iter().find(|(pattern, _)| pattern.matches(name)))
+                    .map(|(_, class)| class.clone())
+                    .or_else(|| class.as_deref().and_then(|class| rename(&self.classes, class)));
+
+                let mut seg = seg;
+                if let Some(name) = new_name {
+                    seg.name = Some(patcher.lname(&name, at)?);
+                }
+                if let Some(class) = new_class {
+                    seg.class = Some(patcher.lname(&class, at)?);
+                }
+                changed.push(seg);
+            }
+
+            patcher.records[at].record = Record::SEGDEF{ segs: changed };
+        }
+
+        Ok(())
+    }
+
+    fn rename_symbols(&self, patcher: &mut ObjPatcher) -> Result<(), ObjError> {
+        if self.symbols.is_empty() {
+            return Ok(());
+        }
+
+        for at in 0..patcher.records.len() {
+            match &mut patcher.records[at].record {
+                Record::PUBDEF{ publics, .. } | Record::LPUBDEF{ publics, .. } => for public in publics {
+                    if let Some(name) = rename(&self.symbols, &public.name) {
+                        public.name = name;
+                    }
+                },
+                Record::EXTDEF{ externs } | Record::LEXTDEF{ externs } => for ext in externs {
+                    if let Some(name) = rename(&self.symbols, &ext.name) {
+                        ext.name = name;
+                    }
+                },
+                Record::COMDEF{ commons } => for common in commons {
+                    if let Some(name) = rename(&self.symbols, &common.name) {
+                        common.name = name;
+                    }
+                },
+                Record::ALIAS{ aliases } => for alias in aliases {
+                    if let Some(name) = rename(&self.symbols, &alias.alias) {
+                        alias.alias = name;
+                    }
+                    if let Some(name) = rename(&self.symbols, &alias.substitute) {
+                        alias.substitute = name;
+                    }
+                },
+                Record::CEXTDEF{ .. } | Record::COMDAT{ .. } => self.rename_lname_symbols(patcher, at)?,
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    // COMDAT and CEXTDEF name their symbols by LNAMES index
+    //
+    fn rename_lname_symbols(&self, patcher: &mut ObjPatcher, at: usize) -> Result<(), ObjError> {
+        let renamed = |patcher: &mut ObjPatcher, index: usize| -> Result<usize, ObjError> {
+            let name = patcher.lnames().get(index.wrapping_sub(1)).and_then(|name| rename(&self.symbols, name));
+            match name {
+                Some(name) => patcher.lname(&name, at),
+                None => Ok(index),
+            }
+        };
+
+        match &patcher.records[at].record {
+            Record::CEXTDEF{ externs } => {
+                let names: Vec<usize> = externs.iter().map(|ext| ext.name).collect();
+                let mut new_names = Vec::new();
+                for name in names {
+                    new_names.push(renamed(patcher, name)?);
+                }
+                if let Record::CEXTDEF{ externs } = &mut patcher.records[at].record {
+                    for (ext, name) in externs.iter_mut().zip(new_names) {
+                        ext.name = name;
+                    }
+                }
+            },
+            Record::COMDAT{ comdat } => {
+                let name = renamed(patcher, comdat.name)?;
+                if let Record::COMDAT{ comdat } = &mut patcher.records[at].record {
+                    comdat.name = name;
+                }
+            },
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+// Load a module, transform it, and write it back out
+//
+pub fn transform(obj: &[u8], options: &ParseOptions, transform: &Transform) -> Result<Vec<u8>, ObjError> {
+    let mut patcher = ObjPatcher::load(obj, options)?;
+    transform.apply(&mut patcher)?;
+    patcher.write()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::objbuilder::*;
+
+    fn module() -> Vec<u8> {
+        let mut builder = ObjBuilder::new("m");
+        let text = builder.segment(SegmentSpec::new("_TEXT", "CODE"));
+        let data = builder.segment(SegmentSpec::new("_DATA", "DATA"));
+        builder.external("_puts");
+        builder.public("_main", text, 0);
+        builder.public("_msg", data, 0);
+        builder.data(text, 0, &[0xc3]).unwrap();
+        builder.data(data, 0, b"hi\0").unwrap();
+        let mut obj = builder.build().unwrap();
+
+        // a translator comment, a no-purge comment, and a default library
+        // after THEADR
+        //
+        let at = 3 + obj[1] as usize;
+        obj.splice(at..at, [
+            0x88, 0x05, 0x00, 0x00, 0x00, 0x4d, 0x53, 0x00,
+            0x88, 0x04, 0x00, 0x80, 0xdf, 0x58, 0x00,
+            0x88, 0x05, 0x00, 0x00, 0x9f, 0x43, 0x4c, 0x00,
+        ]);
+        obj
+    }
+
+    fn records(obj: &[u8]) -> ObjPatcher {
+        let options = ParseOptions{ strict: true, ..Default::default() };
+        ObjPatcher::load(obj, &options).unwrap()
+    }
+
+    fn segments(patcher: &ObjPatcher) -> Vec<(String, String)> {
+        let lnames = patcher.lnames();
+        patcher.records.iter()
+            .filter_map(|rec| match &rec.record {
+                Record::SEGDEF{ segs } => Some(segs),
+                _ => None,
+            })
+            .flatten()
+            .map(|seg| (lnames[seg.name.unwrap() - 1].to_string(), lnames[seg.class.unwrap() - 1].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_pattern_matches_wildcards() {
+        assert!(Pattern::new("_TEXT").matches("_TEXT"));
+        assert!(!Pattern::new("_TEXT").matches("_TEXT2"));
+        assert!(Pattern::new("*").matches(""));
+        assert!(Pattern::new("_?EXT*").matches("_TEXT_A"));
+        assert!(!Pattern::new("_?EXT*").matches("TEXT"));
+        assert_eq!(Pattern::new("a*b*").captures("axxbyy"), Some(vec!["xx".to_string(), "yy".to_string()]));
+    }
+
+    #[test]
+    fn test_rename_fills_in_stars() {
+        assert_eq!(Rename::new("_TEXT*", "CODE*").apply("_TEXT2").as_deref(), Some("CODE2"));
+        assert_eq!(Rename::new("_*", "*_").apply("_main").as_deref(), Some("main_"));
+        assert_eq!(Rename::new("_*", "*_").apply("main"), None);
+    }
+
+    #[test]
+    fn test_transform_renames_segments_and_classes() {
+        let transform = Transform{
+            segments: vec![Rename::new("_TEXT", "MYCODE")],
+            classes: vec![Rename::new("DATA", "FAR_DATA")],
+            ..Default::default()
+        };
+
+        let obj = super::transform(&module(), &ParseOptions::default(), &transform).unwrap();
+        assert_eq!(segments(&records(&obj)), vec![
+            ("MYCODE".to_string(), "CODE".to_string()),
+            ("_DATA".to_string(), "FAR_DATA".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_transform_remaps_segment_classes() {
+        let transform = Transform{
+            segments: vec![Rename::new("_DATA", "_CONST")],
+            segment_classes: vec![(Pattern::new("_CONST"), "CONST".to_string())],
+            ..Default::default()
+        };
+
+        let obj = super::transform(&module(), &ParseOptions::default(), &transform).unwrap();
+        assert_eq!(segments(&records(&obj))[1], ("_CONST".to_string(), "CONST".to_string()));
+    }
+
+    #[test]
+    fn test_transform_renames_symbols() {
+        let transform = Transform{
+            symbols: vec![Rename::new("_*", "__*")],
+            ..Default::default()
+        };
+
+        let obj = super::transform(&module(), &ParseOptions::default(), &transform).unwrap();
+        let patcher = records(&obj);
+
+        let mut names = Vec::new();
+        for rec in &patcher.records {
+            match &rec.record {
+                Record::PUBDEF{ publics, .. } => names.extend(publics.iter().map(|public| public.name.clone())),
+                Record::EXTDEF{ externs } => names.extend(externs.iter().map(|ext| ext.name.clone())),
+                _ => (),
+            }
+        }
+
+        assert_eq!(names, vec!["__puts", "__main", "__msg"]);
+    }
+
+    #[test]
+    fn test_transform_strips_comments() {
+        let transform = Transform{ strip_comments: true, ..Default::default() };
+
+        let obj = super::transform(&module(), &ParseOptions::default(), &transform).unwrap();
+        let classes: Vec<u8> = records(&obj).records.iter()
+            .filter_map(|rec| match &rec.record {
+                Record::COMENT{ header, .. } => Some(header.comclass),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(classes, vec![0xdf, 0x9f]);
+    }
+
+    #[test]
+    fn test_transform_strips_line_numbers_and_locals() {
+        let mut obj = module();
+        let at = obj.len() - 5;
+        obj.splice(at..at, [
+            // LINNUM for _TEXT, line 1 at 0
+            0x94, 0x07, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00,
+            // LPUBDEF _x in _TEXT
+            0xb6, 0x09, 0x00, 0x00, 0x01, 0x02, 0x5f, 0x78, 0x00, 0x00, 0x00, 0x00,
+        ]);
+
+        let transform = Transform{ strip_line_numbers: true, strip_locals: true, ..Default::default() };
+        let obj = super::transform(&obj, &ParseOptions::default(), &transform).unwrap();
+
+        assert!(records(&obj).records.iter().all(|rec| !matches!(rec.rectype, 0x94 | 0xb6)));
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use crate::error::Diagnostic;
+use crate::error::Error as ObjError;
+use crate::objfile::*;
+
+// Spec-conformance checks over a whole module. The parser already notes
+// problems it can see one record at a time (index ranges, data record
+// sizes, fixup pairing); this adds the ones which need the whole module
+// and the merely suspicious.
+//
+const MAX_RECORD: usize = 1024;
+
+struct Validator<'a> {
+    module: Option<&'a str>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    fn warn(&mut self, details: &str, offset: usize) {
+        let mut diag = Diagnostic::with_offset(details, offset);
+        diag.module = self.module.map(|module| module.to_string());
+        self.diagnostics.push(diag);
+    }
+
+    fn headers(&mut self, records: &[ParsedRecord]) {
+        match records.first() {
+            Some(first) if first.rectype == 0x80 || first.rectype == 0x82 => (),
+            Some(first) => self.warn("module does not start with THEADR", first.offset),
+            None => self.warn("module is empty", 0),
+        }
+
+        for parsed in records.iter().filter(|parsed| parsed.rectype == 0x80).skip(1) {
+            self.warn("more than one THEADR in module", parsed.offset);
+        }
+
+        match records.last() {
+            Some(ParsedRecord{ record: Record::MODEND{ .. }, .. }) => (),
+            Some(last) => self.warn("module does not end with MODEND", last.offset + last.raw.len()),
+            None => (),
+        }
+    }
+
+    fn record_size(&mut self, parsed: &ParsedRecord) {
+        // data records are measured by the parser, against their payload
+        //
+        if (0xa0..=0xa3).contains(&parsed.rectype) || parsed.raw.len() < 3 {
+            return;
+        }
+
+        let length = parsed.raw.len() - 3;
+        if length > MAX_RECORD {
+            self.warn(&format!("record type ${:02x} is {} bytes long (limit is {})", parsed.rectype, length, MAX_RECORD), parsed.offset);
+        }
+    }
+
+    fn segdef(&mut self, segs: &[Segdef], offset: usize) {
+        for seg in segs {
+            if seg.length > 0xffffffff {
+                self.warn("BIG bit set in a 32-bit SEGDEF (4 GB segment)", offset);
+            } else if !seg.use32 && seg.length > 0x10000 {
+                self.warn(&format!("USE16 segment is {:08x} bytes long", seg.length), offset);
+            }
+        }
+    }
+
+    fn modend(&mut self, main: bool, start_address: &Option<StartAddress>, offset: usize) {
+        match (main, start_address) {
+            (true, None) => self.warn("main module has no start address", offset),
+            (false, Some(_)) => self.warn("start address in a module not marked main", offset),
+            _ => (),
+        }
+    }
+
+    fn publics(&mut self, records: &[ParsedRecord]) {
+        let mut seen = HashMap::new();
+
+        for parsed in records {
+            if let Record::PUBDEF{ publics, .. } = &parsed.record {
+                for public in publics {
+                    if seen.insert(public.name.clone(), parsed.offset).is_some() {
+                        self.warn(&format!("public {} is defined more than once", public.name), parsed.offset);
+                    }
+                }
+            }
+        }
+    }
+
+    fn check(&mut self, records: &[ParsedRecord]) {
+        self.headers(records);
+        self.publics(records);
+
+        for parsed in records {
+            self.record_size(parsed);
+
+            match &parsed.record {
+                Record::SEGDEF{ segs } => self.segdef(segs, parsed.offset),
+                Record::MODEND{ main, start_address, .. } => self.modend(*main, start_address, parsed.offset),
+                _ => (),
+            }
+        }
+    }
+}
+
+// Lint records which have already been parsed from one module
+//
+pub fn validate_records(records: &[ParsedRecord], module: Option<&str>) -> Vec<Diagnostic> {
+    let mut validator = Validator{ module, diagnostics: Vec::new() };
+    validator.check(records);
+    validator.diagnostics
+}
+
+// Parse a module and lint it. Problems the parser tolerated are included;
+// anything it can't get past is an error.
+//
+pub fn validate(obj: &[u8], options: &ParseOptions) -> Result<Vec<Diagnostic>, ObjError> {
+    let options = ParseOptions{ strict: false, ..options.clone() };
+    let mut parser = Parser::with_options(obj, options);
+    let mut records = Vec::new();
+
+    loop {
+        let parsed = parser.next_parsed()?;
+        if parsed.record == Record::None {
+            break;
+        }
+        records.push(parsed);
+    }
+
+    let mut diagnostics = parser.diagnostics().to_vec();
+    diagnostics.extend(validate_records(&records, parser.module()));
+    diagnostics.sort_by_key(|diag| diag.offset);
+
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const THEADR: [u8; 6] = [0x80, 0x03, 0x00, 0x01, 0x41, 0x3b];
+    const MODEND: [u8; 5] = [0x8a, 0x02, 0x00, 0x01, 0x73];
+
+    fn details(diagnostics: &[Diagnostic]) -> Vec<&str> {
+        diagnostics.iter().map(|diag| diag.details.as_str()).collect()
+    }
+
+    #[test]
+    fn test_clean_module_has_no_diagnostics() {
+        let obj = [&THEADR[..], &MODEND[..]].concat();
+        assert!(validate(&obj, &ParseOptions::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_missing_theadr_and_modend() {
+        let obj = vec![0x96, 0x02, 0x00, 0x00, 0x68];
+        let diagnostics = validate(&obj, &ParseOptions::default()).unwrap();
+
+        assert_eq!(details(&diagnostics), vec![
+            "module does not start with THEADR",
+            "module does not end with MODEND",
+        ]);
+        assert_eq!(diagnostics[1].offset, Some(5));
+    }
+
+    #[test]
+    fn test_oversized_record() {
+        let mut obj = THEADR.to_vec();
+        obj.extend([0x96, 0x03, 0x04]);
+        obj.extend([0x00; 0x403]);
+        obj.extend(MODEND);
+
+        let diagnostics = validate(&obj, &ParseOptions::default()).unwrap();
+        assert_eq!(details(&diagnostics), vec!["record type $96 is 1027 bytes long (limit is 1024)"]);
+    }
+
+    #[test]
+    fn test_modend_start_without_main() {
+        let obj = [
+            &THEADR[..],
+            // not main, but with a start address
+            &[0x8a, 0x04, 0x00, 0x41, 0x54, 0x01, 0x00][..],
+        ].concat();
+
+        let diagnostics = validate(&obj, &ParseOptions::default()).unwrap();
+        assert_eq!(details(&diagnostics), vec!["start address in a module not marked main"]);
+        assert_eq!(diagnostics[0].module.as_deref(), Some("A"));
+    }
+
+    #[test]
+    fn test_big_32_bit_segment() {
+        let obj = [
+            &THEADR[..],
+            &[0x96, 0x03, 0x00, 0x01, 0x41, 0x00][..],
+            &[0x99, 0x09, 0x00, 0x63, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01, 0x00][..],
+            &MODEND[..],
+        ].concat();
+
+        let diagnostics = validate(&obj, &ParseOptions::default()).unwrap();
+        assert_eq!(details(&diagnostics), vec!["BIG bit set in a 32-bit SEGDEF (4 GB segment)"]);
+    }
+
+    #[test]
+    fn test_duplicate_public() {
+        let obj = [
+            &THEADR[..],
+            &[0x90, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x58, 0x00, 0x00, 0x00, 0x01, 0x58, 0x02, 0x00, 0x00, 0x00][..],
+            &MODEND[..],
+        ].concat();
+
+        let diagnostics = validate(&obj, &ParseOptions::default()).unwrap();
+        assert_eq!(details(&diagnostics), vec!["public X is defined more than once"]);
+    }
+}